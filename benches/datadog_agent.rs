@@ -0,0 +1,160 @@
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use bytes::Bytes;
+use codecs::decoding::{DeserializerConfig, FramingConfig};
+use criterion::{
+    criterion_group, criterion_main,
+    measurement::{Measurement, ValueFormatter},
+    BatchSize, BenchmarkId, Criterion, Throughput,
+};
+use vector::sources::datadog_agent::logs::LogsBodyDecoder;
+
+/// Counts the bytes allocated by the process, to measure the allocations of decoding.
+struct CountingAllocator;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATED.fetch_add(new_size, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+/// Measures the bytes allocated over an iteration, rather than its duration.
+struct AllocatedBytes;
+
+impl Measurement for AllocatedBytes {
+    type Intermediate = usize;
+    type Value = usize;
+
+    fn start(&self) -> usize {
+        ALLOCATED.load(Ordering::Relaxed)
+    }
+
+    fn end(&self, start: usize) -> usize {
+        ALLOCATED.load(Ordering::Relaxed) - start
+    }
+
+    fn add(&self, v1: &usize, v2: &usize) -> usize {
+        v1 + v2
+    }
+
+    fn zero(&self) -> usize {
+        0
+    }
+
+    fn to_f64(&self, value: &usize) -> f64 {
+        *value as f64
+    }
+
+    fn formatter(&self) -> &dyn ValueFormatter {
+        &BytesFormatter
+    }
+}
+
+struct BytesFormatter;
+
+impl ValueFormatter for BytesFormatter {
+    fn scale_values(&self, _typical_value: f64, _values: &mut [f64]) -> &'static str {
+        "B"
+    }
+
+    fn scale_throughputs(
+        &self,
+        _typical_value: f64,
+        throughput: &Throughput,
+        values: &mut [f64],
+    ) -> &'static str {
+        match throughput {
+            Throughput::Bytes(bytes) | Throughput::BytesDecimal(bytes) => {
+                for value in values {
+                    *value /= *bytes as f64;
+                }
+                "B/input byte"
+            }
+            Throughput::Elements(elements) => {
+                for value in values {
+                    *value /= *elements as f64;
+                }
+                "B/message"
+            }
+        }
+    }
+
+    fn scale_for_machines(&self, _values: &mut [f64]) -> &'static str {
+        "B"
+    }
+}
+
+/// A logs payload of `messages` messages of `message_size` bytes each.
+fn payload(messages: usize, message_size: usize) -> Bytes {
+    let message = format!("{{\"n\": \"{}\"}}", "x".repeat(message_size - 9));
+    let messages = (0..messages)
+        .map(|_| {
+            serde_json::json!({
+                "message": message,
+                "status": "info",
+                "timestamp": 1_234_567_890,
+                "hostname": "host",
+                "service": "service",
+                "ddsource": "source",
+                "ddtags": "env:bench",
+            })
+        })
+        .collect::<Vec<_>>();
+    Bytes::from(serde_json::to_vec(&messages).unwrap())
+}
+
+fn decode_logs(c: &mut Criterion<AllocatedBytes>) {
+    let messages = 10_000;
+    let body = payload(messages, 128);
+
+    let mut group = c.benchmark_group("datadog_agent/logs/decode");
+    group.throughput(Throughput::Elements(messages as u64));
+    for (slug, framing, decoding) in [
+        // The default, which passes each message through as it is.
+        ("bytes", FramingConfig::Bytes, DeserializerConfig::Bytes),
+        ("json", FramingConfig::Bytes, DeserializerConfig::Json),
+        // Framed out of a buffer shared by the messages.
+        (
+            "newline_delimited",
+            FramingConfig::NewlineDelimited {
+                newline_delimited: Default::default(),
+            },
+            DeserializerConfig::Json,
+        ),
+    ] {
+        let decoder = LogsBodyDecoder::new(framing, decoding);
+        group.bench_function(BenchmarkId::from_parameter(slug), |b| {
+            b.iter_batched(
+                || body.clone(),
+                |body| decoder.decode(body),
+                BatchSize::SmallInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(
+    name = benches;
+    config = Criterion::default().with_measurement(AllocatedBytes);
+    targets = decode_logs
+);
+criterion_main!(benches);
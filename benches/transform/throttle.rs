@@ -0,0 +1,102 @@
+use core::fmt;
+use std::{num::NonZeroUsize, time::Duration};
+
+use criterion::{
+    criterion_group, measurement::WallTime, BatchSize, BenchmarkGroup, BenchmarkId, Criterion,
+    SamplingMode, Throughput,
+};
+use futures::StreamExt;
+use vector::{
+    config::{TransformConfig, TransformContext},
+    test_util::runtime,
+    transforms::throttle::ThrottleConfig,
+};
+
+use crate::common::FixedLogStream;
+
+#[derive(Debug)]
+struct Param {
+    slug: &'static str,
+    input: FixedLogStream,
+    throttle_config: ThrottleConfig,
+}
+
+impl fmt::Display for Param {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.slug)
+    }
+}
+
+fn throttle(c: &mut Criterion) {
+    let mut group: BenchmarkGroup<WallTime> =
+        c.benchmark_group("vector::transforms::throttle::Throttle");
+    group.sampling_mode(SamplingMode::Flat);
+
+    let fixed_stream = FixedLogStream::new(
+        NonZeroUsize::new(1_000_000).unwrap(),
+        NonZeroUsize::new(128).unwrap(),
+    );
+    for param in &[
+        // Measurement where every event counts against the same bucket, most of them dropped.
+        Param {
+            slug: "single_key",
+            input: fixed_stream.clone(),
+            throttle_config: toml::from_str::<ThrottleConfig>(
+                r#"
+            threshold = 1000
+            window_secs = 1
+        "#,
+            )
+            .unwrap(),
+        },
+        // Measurement where the events are spread over 128 buckets, most of them admitted.
+        Param {
+            slug: "many_keys",
+            input: fixed_stream.clone(),
+            throttle_config: toml::from_str::<ThrottleConfig>(
+                r#"
+            threshold = 10000
+            window_secs = 1
+            key_field = "{{ message }}"
+        "#,
+            )
+            .unwrap(),
+        },
+    ] {
+        group.throughput(Throughput::Elements(param.input.len() as u64));
+        group.bench_with_input(BenchmarkId::new("transform", param), &param, |b, param| {
+            b.iter_batched(
+                || {
+                    let rt = runtime();
+                    let throttle = rt
+                        .block_on(param.throttle_config.build(&TransformContext::default()))
+                        .unwrap()
+                        .into_multi_output_task();
+                    (rt, throttle, Box::pin(param.input.clone()))
+                },
+                |(rt, throttle, input)| {
+                    rt.block_on(throttle.transform_events(input).for_each(|_| async {}))
+                },
+                BatchSize::LargeInput,
+            )
+        });
+    }
+}
+
+criterion_group!(
+    name = benches;
+    config = Criterion::default()
+        .warm_up_time(Duration::from_secs(5))
+        .measurement_time(Duration::from_secs(60))
+        // degree of noise to ignore in measurements, here 1%
+        .noise_threshold(0.01)
+        // likelihood of noise registering as difference, here 5%
+        .significance_level(0.05)
+        // likelihood of capturing the true runtime, here 95%
+        .confidence_level(0.95)
+        // total number of bootstrap resamples, higher is less noisy but slower
+        .nresamples(100_000)
+        // total samples to collect within the set measurement time
+        .sample_size(20);
+    targets = throttle
+);
@@ -26,6 +26,7 @@ pub enum Transform {
     Function(Box<dyn FunctionTransform>),
     Synchronous(Box<dyn SyncTransform>),
     Task(Box<dyn TaskTransform<EventArray>>),
+    MultiOutputTask(Box<dyn MultiOutputTaskTransform>),
 }
 
 impl Transform {
@@ -104,6 +105,14 @@ impl Transform {
         Transform::Task(Box::new(WrapEventTask(v)))
     }
 
+    /// Create a new task transform writing to multiple outputs.
+    ///
+    /// Like [`Transform::event_task`], but the transform is allowed to write to multiple outputs.
+    /// Those outputs must be known in advance and returned via `TransformConfig::outputs`.
+    pub fn multi_output_task(v: impl MultiOutputTaskTransform + 'static) -> Self {
+        Transform::MultiOutputTask(Box::new(v))
+    }
+
     /// Mutably borrow the inner transform as a task transform.
     ///
     /// # Panics
@@ -131,6 +140,20 @@ impl Transform {
             }
         }
     }
+
+    /// Transmute the inner transform into a multi-output task transform.
+    ///
+    /// # Panics
+    ///
+    /// If the transform is not a [`MultiOutputTaskTransform`] this will panic.
+    pub fn into_multi_output_task(self) -> Box<dyn MultiOutputTaskTransform> {
+        match self {
+            Transform::MultiOutputTask(t) => t,
+            _ => {
+                panic!("Called `Transform::into_multi_output_task` on something that was not a multi-output task variant.")
+            }
+        }
+    }
 }
 
 /// Transforms that are simple, and don't require attention to coordination.
@@ -176,6 +199,30 @@ pub trait TaskTransform<T: EventContainer + 'static>: Send + 'static {
     }
 }
 
+/// Broader than [`TaskTransform`], this trait allows task transforms to write to multiple outputs.
+/// Those outputs must be known in advanced and returned via `TransformConfig::outputs`. Attempting
+/// to send to any output not registered in advance is considered a bug and will cause a panic.
+///
+/// Each item of the returned stream holds the events written to each output since the previous
+/// item.
+pub trait MultiOutputTaskTransform: Send + 'static {
+    fn transform(
+        self: Box<Self>,
+        task: Pin<Box<dyn Stream<Item = Event> + Send>>,
+    ) -> Pin<Box<dyn Stream<Item = TransformOutputsBuf> + Send>>;
+
+    /// Wrap the transform task to emit the individual events written to the default output. This
+    /// is used to simplify testing task transforms.
+    fn transform_events(
+        self: Box<Self>,
+        task: Pin<Box<dyn Stream<Item = Event> + Send>>,
+    ) -> Pin<Box<dyn Stream<Item = Event> + Send>> {
+        self.transform(task)
+            .flat_map(|mut buf| futures::stream::iter(buf.take_primary().into_events()))
+            .boxed()
+    }
+}
+
 /// Broader than the simple [`FunctionTransform`], this trait allows transforms to write to
 /// multiple outputs. Those outputs must be known in advanced and returned via
 /// `TransformConfig::outputs`. Attempting to send to any output not registered in advance is
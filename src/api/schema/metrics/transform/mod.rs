@@ -1,4 +1,5 @@
 mod generic;
+pub mod throttle;
 
 use async_graphql::Interface;
 
@@ -27,6 +28,7 @@ use crate::event::Metric;
 )]
 pub enum TransformMetrics {
     GenericTransformMetrics(generic::GenericTransformMetrics),
+    ThrottleTransformMetrics(throttle::ThrottleTransformMetrics),
 }
 
 pub trait IntoTransformMetrics {
@@ -34,7 +36,14 @@ pub trait IntoTransformMetrics {
 }
 
 impl IntoTransformMetrics for Vec<Metric> {
-    fn into_transform_metrics(self, _component_type: &str) -> TransformMetrics {
-        TransformMetrics::GenericTransformMetrics(generic::GenericTransformMetrics::new(self))
+    fn into_transform_metrics(self, component_type: &str) -> TransformMetrics {
+        match component_type {
+            "throttle" => TransformMetrics::ThrottleTransformMetrics(
+                throttle::ThrottleTransformMetrics::new(self),
+            ),
+            _ => TransformMetrics::GenericTransformMetrics(generic::GenericTransformMetrics::new(
+                self,
+            )),
+        }
     }
 }
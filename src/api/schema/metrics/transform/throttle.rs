@@ -0,0 +1,260 @@
+use std::{cmp::Ordering, collections::BTreeMap};
+
+use async_graphql::Object;
+
+use crate::{
+    api::schema::metrics::{self, MetricsFilter},
+    event::{Metric, MetricValue},
+};
+
+/// The events discarded for a single key of a throttle transform.
+#[derive(Clone, Debug)]
+pub struct ThrottleTransformMetricKey {
+    key: String,
+    discarded_events_total: f64,
+}
+
+#[Object]
+impl ThrottleTransformMetricKey {
+    /// Key, as rendered from `key_field`
+    async fn key(&self) -> &str {
+        &*self.key
+    }
+
+    /// Total events discarded for the current key
+    async fn discarded_events_total(&self) -> f64 {
+        self.discarded_events_total
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ThrottleTransformMetrics(Vec<Metric>);
+
+impl ThrottleTransformMetrics {
+    pub fn new(metrics: Vec<Metric>) -> Self {
+        Self(metrics)
+    }
+
+    /// Returns the total number of events discarded by the throttle, whatever the reason.
+    ///
+    /// The events a throttle in dry run would have discarded aren't counted.
+    pub fn get_discarded_events_total(&self) -> f64 {
+        self.0
+            .iter()
+            .filter(|m| m.name() == "component_discarded_events_total" && !is_simulated(m))
+            .map(counter_value)
+            .sum()
+    }
+
+    /// Returns the total number of events a throttle in dry run would have discarded.
+    pub fn get_simulated_discarded_events_total(&self) -> f64 {
+        self.0
+            .iter()
+            .filter(|m| m.name() == "events_discarded_total" && is_simulated(m))
+            .map(counter_value)
+            .sum()
+    }
+
+    /// Returns the `limit` keys with the most discarded events, most discarded first.
+    ///
+    /// Only the discards a throttle in dry run simulated are counted if `simulated` is set, and
+    /// only the actual ones otherwise.
+    pub fn get_top_discarded_keys(
+        &self,
+        limit: usize,
+        simulated: bool,
+    ) -> Vec<ThrottleTransformMetricKey> {
+        let mut keys = self
+            .0
+            .iter()
+            .filter(|m| m.name() == "events_discarded_total" && is_simulated(m) == simulated)
+            .filter_map(|m| m.tag_value("key").map(|key| (key, counter_value(m))))
+            .fold(BTreeMap::new(), |mut map, (key, value)| {
+                *map.entry(key).or_insert(0.0) += value;
+                map
+            })
+            .into_iter()
+            .map(|(key, discarded_events_total)| ThrottleTransformMetricKey {
+                key,
+                discarded_events_total,
+            })
+            .collect::<Vec<_>>();
+
+        // Ties are broken by key, so that the result is stable from one query to the next.
+        keys.sort_by(|a, b| {
+            b.discarded_events_total
+                .partial_cmp(&a.discarded_events_total)
+                .unwrap_or(Ordering::Equal)
+                .then_with(|| a.key.cmp(&b.key))
+        });
+        keys.truncate(limit);
+        keys
+    }
+}
+
+/// Whether `metric` counts the events a throttle in dry run would have discarded.
+fn is_simulated(metric: &Metric) -> bool {
+    metric.tag_value("simulated").as_deref() == Some("true")
+}
+
+fn counter_value(metric: &Metric) -> f64 {
+    match metric.value() {
+        MetricValue::Counter { value } => *value,
+        _ => 0.00,
+    }
+}
+
+#[Object]
+impl ThrottleTransformMetrics {
+    /// Events processed for the current throttle transform
+    pub async fn processed_events_total(&self) -> Option<metrics::ProcessedEventsTotal> {
+        self.0.processed_events_total()
+    }
+
+    /// Bytes processed for the current throttle transform
+    pub async fn processed_bytes_total(&self) -> Option<metrics::ProcessedBytesTotal> {
+        self.0.processed_bytes_total()
+    }
+
+    /// Total incoming events for the current throttle transform
+    pub async fn events_in_total(&self) -> Option<metrics::EventsInTotal> {
+        self.0.events_in_total()
+    }
+
+    /// Total received events for the current throttle transform
+    pub async fn received_events_total(&self) -> Option<metrics::ReceivedEventsTotal> {
+        self.0.received_events_total()
+    }
+
+    /// Total outgoing events for the current throttle transform
+    pub async fn events_out_total(&self) -> Option<metrics::EventsOutTotal> {
+        self.0.events_out_total()
+    }
+
+    /// Total outgoing events, admitted by the current throttle transform
+    pub async fn sent_events_total(&self) -> Option<metrics::SentEventsTotal> {
+        self.0.sent_events_total()
+    }
+
+    /// Total events discarded by the current throttle transform
+    pub async fn discarded_events_total(&self) -> f64 {
+        self.get_discarded_events_total()
+    }
+
+    /// Keys with the most events discarded by the current throttle transform, most discarded first
+    pub async fn top_discarded_keys(
+        &self,
+        #[graphql(default = 10, validator(minimum = 1, maximum = 1000))] limit: i32,
+    ) -> Vec<ThrottleTransformMetricKey> {
+        self.get_top_discarded_keys(limit as usize, false)
+    }
+
+    /// Total events the current throttle transform would have discarded, in dry run
+    pub async fn simulated_discarded_events_total(&self) -> f64 {
+        self.get_simulated_discarded_events_total()
+    }
+
+    /// Keys with the most events the current throttle transform would have discarded, in dry run,
+    /// most discarded first
+    pub async fn top_simulated_discarded_keys(
+        &self,
+        #[graphql(default = 10, validator(minimum = 1, maximum = 1000))] limit: i32,
+    ) -> Vec<ThrottleTransformMetricKey> {
+        self.get_top_discarded_keys(limit as usize, true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use async_graphql::{EmptyMutation, EmptySubscription, Schema};
+    use vector_core::metric_tags;
+
+    use super::*;
+    use crate::event::MetricKind;
+
+    fn metric(name: &str, value: f64, key: Option<&str>) -> Metric {
+        let metric = Metric::new(name, MetricKind::Absolute, MetricValue::Counter { value });
+        match key {
+            Some(key) => metric.with_tags(Some(metric_tags!("key" => key))),
+            None => metric,
+        }
+    }
+
+    fn simulated(name: &str, value: f64, key: &str) -> Metric {
+        Metric::new(name, MetricKind::Absolute, MetricValue::Counter { value })
+            .with_tags(Some(metric_tags!("key" => key, "simulated" => "true")))
+    }
+
+    fn throttle_metrics() -> ThrottleTransformMetrics {
+        ThrottleTransformMetrics::new(vec![
+            metric("component_sent_events_total", 7.0, None),
+            metric("component_discarded_events_total", 12.0, None),
+            metric("events_discarded_total", 2.0, Some("a")),
+            metric("events_discarded_total", 6.0, Some("b")),
+            metric("events_discarded_total", 1.0, Some("c")),
+            metric("events_discarded_total", 2.0, Some("d")),
+            metric("events_discarded_total", 1.0, Some("overflow")),
+            simulated("events_discarded_total", 9.0, "c"),
+            simulated("events_discarded_total", 3.0, "e"),
+        ])
+    }
+
+    #[test]
+    fn discarded_events_total() {
+        let metrics = throttle_metrics();
+        assert_eq!(metrics.get_discarded_events_total(), 12.0);
+        assert_eq!(
+            metrics
+                .0
+                .sent_events_total()
+                .unwrap()
+                .get_sent_events_total(),
+            7.0
+        );
+    }
+
+    #[test]
+    fn top_discarded_keys() {
+        let keys = throttle_metrics()
+            .get_top_discarded_keys(3, false)
+            .into_iter()
+            .map(|key| (key.key, key.discarded_events_total))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            keys,
+            vec![
+                ("b".to_string(), 6.0),
+                ("a".to_string(), 2.0),
+                ("d".to_string(), 2.0),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn simulated_discards_are_reported_apart() {
+        let schema = Schema::new(throttle_metrics(), EmptyMutation, EmptySubscription);
+        let response = schema
+            .execute(
+                "{
+                    discardedEventsTotal
+                    simulatedDiscardedEventsTotal
+                    topDiscardedKeys(limit: 1) { key discardedEventsTotal }
+                    topSimulatedDiscardedKeys { key discardedEventsTotal }
+                }",
+            )
+            .await;
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+        assert_eq!(
+            response.data.into_json().unwrap(),
+            serde_json::json!({
+                "discardedEventsTotal": 12.0,
+                "simulatedDiscardedEventsTotal": 12.0,
+                "topDiscardedKeys": [{ "key": "b", "discardedEventsTotal": 6.0 }],
+                "topSimulatedDiscardedKeys": [
+                    { "key": "c", "discardedEventsTotal": 9.0 },
+                    { "key": "e", "discardedEventsTotal": 3.0 },
+                ],
+            })
+        );
+    }
+}
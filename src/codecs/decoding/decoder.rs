@@ -48,6 +48,22 @@ impl Decoder {
         self
     }
 
+    /// Returns `true` if the framing passes a whole message through as a single frame.
+    pub const fn frames_whole_messages(&self) -> bool {
+        matches!(self.framer, Framer::Bytes(_))
+    }
+
+    /// Deserializes a frame without going through the framing, so that it isn't copied into a
+    /// buffer first.
+    ///
+    /// Only equivalent to decoding the frame when [`Decoder::frames_whole_messages`] is `true`.
+    pub fn decode_frame(
+        &mut self,
+        frame: Bytes,
+    ) -> Result<Option<(SmallVec<[Event; 1]>, usize)>, Error> {
+        self.handle_framing_result(Ok(Some(frame)))
+    }
+
     /// Handles the framing result and parses it into a structured event, if
     /// possible.
     ///
@@ -0,0 +1,101 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+/// How often reloadable files are checked for changes.
+pub(crate) const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The contents read from a file, which are reloaded once the file is modified.
+///
+/// Changes are detected from the modification time of the file, which is meant to be checked every
+/// [`CHECK_INTERVAL`].
+#[derive(Clone, Debug)]
+pub(crate) struct ReloadableFile<T> {
+    path: PathBuf,
+    /// When the file was last modified as of when it was read.
+    last_modified: Option<SystemTime>,
+    contents: T,
+}
+
+impl<T> ReloadableFile<T> {
+    pub(crate) fn load<E>(
+        path: PathBuf,
+        read: impl FnOnce(&Path) -> Result<T, E>,
+    ) -> Result<Self, E> {
+        let last_modified = modified(&path);
+        let contents = read(&path)?;
+        Ok(Self {
+            path,
+            last_modified,
+            contents,
+        })
+    }
+
+    pub(crate) const fn contents(&self) -> &T {
+        &self.contents
+    }
+
+    /// Rereads the contents with `read` if the file changed since it was last read.
+    ///
+    /// Returns `Ok(true)` if new contents were swapped in. If `read` fails, the previous contents
+    /// are kept and the error is returned. The same change is not retried until the file is
+    /// modified again.
+    pub(crate) fn reload<E>(
+        &mut self,
+        read: impl FnOnce(&Path) -> Result<T, E>,
+    ) -> Result<bool, E> {
+        let modified = modified(&self.path);
+        if modified == self.last_modified {
+            return Ok(false);
+        }
+        self.last_modified = modified;
+
+        self.contents = read(&self.path)?;
+        Ok(true)
+    }
+
+    /// Forces the next reload to reread the file, even on file systems with coarse timestamps.
+    #[cfg(test)]
+    pub(crate) fn forget_modified(&mut self) {
+        self.last_modified = None;
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(path: &Path) -> Result<u32, String> {
+        let contents = fs::read_to_string(path).map_err(|error| error.to_string())?;
+        contents.trim().parse().map_err(|_| contents)
+    }
+
+    #[test]
+    fn keeps_previous_contents_until_modified_again() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file");
+        fs::write(&path, "1").unwrap();
+
+        let mut file = ReloadableFile::load(path.clone(), read).unwrap();
+        assert!(!file.reload(read).unwrap());
+
+        file.forget_modified();
+        fs::write(&path, "not a number").unwrap();
+        assert!(file.reload(read).is_err());
+        assert_eq!(*file.contents(), 1);
+        assert!(!file.reload(read).unwrap());
+
+        file.forget_modified();
+        fs::write(&path, "2").unwrap();
+        assert!(file.reload(read).unwrap());
+        assert_eq!(*file.contents(), 2);
+    }
+}
@@ -7,16 +7,23 @@ pub(self) mod datadog_search;
 pub(crate) mod is_log;
 pub(crate) mod is_metric;
 pub(crate) mod is_trace;
+mod throttle_exceeded;
 mod vrl;
 
-pub use self::vrl::VrlConfig;
 use self::{
     datadog_search::{DatadogSearchConfig, DatadogSearchRunner},
     is_log::{check_is_log, check_is_log_with_context},
     is_metric::{check_is_metric, check_is_metric_with_context},
     is_trace::{check_is_trace, check_is_trace_with_context},
+    throttle_exceeded::ThrottleExceeded,
     vrl::Vrl,
 };
+pub use self::{
+    throttle_exceeded::{
+        register_throttle, ThrottleBudget, ThrottleExceededConfig, ThrottleRegistration,
+    },
+    vrl::VrlConfig,
+};
 
 #[derive(Debug, Clone)]
 #[allow(clippy::large_enum_variant)]
@@ -36,6 +43,9 @@ pub enum Condition {
     /// Matches an event with a [Datadog Search](https://docs.datadoghq.com/logs/explorer/search_syntax/) query.
     DatadogSearch(DatadogSearchRunner),
 
+    /// Matches an event whose key is over the budget of a `throttle` transform.
+    ThrottleExceeded(ThrottleExceeded),
+
     /// Matches any event.
     ///
     /// Used only for internal testing.
@@ -59,11 +69,27 @@ impl Condition {
             Condition::IsTrace => check_is_trace(e),
             Condition::Vrl(x) => x.check(e),
             Condition::DatadogSearch(x) => x.check(e),
+            Condition::ThrottleExceeded(x) => x.check(e),
             Condition::AlwaysPass => (true, e),
             Condition::AlwaysFail => (false, e),
         }
     }
 
+    /// Checks if a condition is true, returning an error if it fails to evaluate.
+    ///
+    /// Unlike [`Condition::check`], which treats evaluation errors as a false result, this lets
+    /// callers decide what to do with events the condition can't be evaluated for. Only VRL
+    /// conditions can fail to evaluate.
+    pub(crate) fn check_fallible(&self, e: Event) -> (Result<bool, String>, Event) {
+        match self {
+            Condition::Vrl(x) => x.check_fallible(e),
+            _ => {
+                let (result, e) = self.check(e);
+                (Ok(result), e)
+            }
+        }
+    }
+
     /// Checks if a condition is true, with a `Result`-oriented return for easier composition.
     ///
     /// This can be mildly expensive for conditions that do not often match, as it allocates a string for the error
@@ -75,6 +101,7 @@ impl Condition {
             Condition::IsTrace => check_is_trace_with_context(e),
             Condition::Vrl(x) => x.check_with_context(e),
             Condition::DatadogSearch(x) => x.check_with_context(e),
+            Condition::ThrottleExceeded(x) => x.check_with_context(e),
             Condition::AlwaysPass => (Ok(()), e),
             Condition::AlwaysFail => (Ok(()), e),
         }
@@ -113,6 +140,9 @@ pub enum ConditionConfig {
 
     /// Matches an event with a [Datadog Search](https://docs.datadoghq.com/logs/explorer/search_syntax/) query.
     DatadogSearch(DatadogSearchConfig),
+
+    /// Matches an event whose key is over the budget of a `throttle` transform.
+    ThrottleExceeded(ThrottleExceededConfig),
 }
 
 impl ConditionConfig {
@@ -123,6 +153,7 @@ impl ConditionConfig {
             ConditionConfig::IsTrace => Ok(Condition::IsTrace),
             ConditionConfig::Vrl(x) => x.build(enrichment_tables),
             ConditionConfig::DatadogSearch(x) => x.build(enrichment_tables),
+            ConditionConfig::ThrottleExceeded(x) => x.build(enrichment_tables),
         }
     }
 }
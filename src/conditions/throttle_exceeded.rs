@@ -0,0 +1,171 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+
+use once_cell::sync::Lazy;
+use vector_config::configurable_component;
+use vector_core::event::Event;
+
+use crate::{
+    conditions::{Condition, Conditional, ConditionalConfig},
+    internal_events::TemplateRenderingError,
+    template::Template,
+};
+
+/// The state of the buckets of a `throttle` transform.
+pub trait ThrottleBudget: Send + Sync {
+    /// Returns whether the bucket of `key` is currently out of budget, without counting against it.
+    fn exceeded(&self, key: Option<&str>) -> bool;
+}
+
+/// The throttles published with their `register_as` option, by name.
+static THROTTLES: Lazy<RwLock<HashMap<String, Arc<dyn ThrottleBudget>>>> =
+    Lazy::new(|| RwLock::new(HashMap::new()));
+
+/// Publishes the state of a throttle under `name`, replacing the throttle previously published
+/// under it, if any, as when the throttle is rebuilt.
+///
+/// The throttle is published until the returned registration is dropped. Names are unique within
+/// a config, as each is claimed as a [`crate::config::Resource`] by its throttle.
+pub fn register_throttle(name: &str, budget: Arc<dyn ThrottleBudget>) -> ThrottleRegistration {
+    THROTTLES
+        .write()
+        .expect("poisoned lock")
+        .insert(name.to_owned(), Arc::clone(&budget));
+    ThrottleRegistration {
+        name: name.to_owned(),
+        budget,
+    }
+}
+
+/// Unpublishes a throttle once dropped, unless another throttle was published under its name
+/// since.
+pub struct ThrottleRegistration {
+    name: String,
+    budget: Arc<dyn ThrottleBudget>,
+}
+
+impl Drop for ThrottleRegistration {
+    fn drop(&mut self) {
+        let mut throttles = THROTTLES.write().expect("poisoned lock");
+        // Only the data pointers are compared, as the vtables of the same type may differ.
+        let registered = throttles.get(&self.name).map_or(false, |budget| {
+            std::ptr::eq(
+                Arc::as_ptr(budget) as *const (),
+                Arc::as_ptr(&self.budget) as *const (),
+            )
+        });
+        if registered {
+            throttles.remove(&self.name);
+        }
+    }
+}
+
+/// A condition matching events whose key is currently over the budget of a `throttle` transform.
+#[configurable_component]
+#[derive(Clone, Debug, Default)]
+pub struct ThrottleExceededConfig {
+    /// The name the `throttle` transform publishes the state of its buckets under, with its
+    /// `register_as` option.
+    throttle: String,
+
+    /// The key to check, rendered for each event like the `key_field` of the throttle.
+    ///
+    /// If unset, or if it fails to render, the bucket of events without a key is checked.
+    key: Option<Template>,
+}
+
+impl_generate_config_from_default!(ThrottleExceededConfig);
+
+/// Checks the budget of the throttle published under a name.
+///
+/// The throttle is looked up for each event, so the condition follows the throttle when it's
+/// rebuilt, and matches no event until it's published.
+#[derive(Clone, Debug)]
+pub struct ThrottleExceeded {
+    throttle: String,
+    key: Option<Template>,
+}
+
+impl Conditional for ThrottleExceeded {
+    fn check(&self, e: Event) -> (bool, Event) {
+        let key = self.key.as_ref().and_then(|template| {
+            template
+                .render_string(&e)
+                .map_err(|error| {
+                    emit!(TemplateRenderingError {
+                        error,
+                        field: Some("key"),
+                        drop_event: false,
+                    })
+                })
+                .ok()
+        });
+        let exceeded = THROTTLES
+            .read()
+            .expect("poisoned lock")
+            .get(&self.throttle)
+            .map_or(false, |budget| budget.exceeded(key.as_deref()));
+        (exceeded, e)
+    }
+}
+
+impl ConditionalConfig for ThrottleExceededConfig {
+    fn build(&self, _enrichment_tables: &enrichment::TableRegistry) -> crate::Result<Condition> {
+        Ok(Condition::ThrottleExceeded(ThrottleExceeded {
+            throttle: self.throttle.clone(),
+            key: self.key.clone(),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Exhausted;
+
+    impl ThrottleBudget for Exhausted {
+        fn exceeded(&self, _key: Option<&str>) -> bool {
+            true
+        }
+    }
+
+    fn condition(throttle: &str) -> ThrottleExceeded {
+        ThrottleExceeded {
+            throttle: throttle.to_owned(),
+            key: None,
+        }
+    }
+
+    fn matches(condition: &ThrottleExceeded) -> bool {
+        condition
+            .check(Event::from(vector_core::event::LogEvent::default()))
+            .0
+    }
+
+    #[test]
+    fn registration_is_removed_once_dropped() {
+        let name = "registration_is_removed_once_dropped";
+        let condition = condition(name);
+
+        let registration = register_throttle(name, Arc::new(Exhausted));
+        assert!(matches(&condition));
+        drop(registration);
+        assert!(!matches(&condition));
+    }
+
+    #[test]
+    fn registration_of_a_rebuilt_throttle_outlives_the_previous_one() {
+        let name = "registration_of_a_rebuilt_throttle_outlives_the_previous_one";
+        let condition = condition(name);
+
+        let previous = register_throttle(name, Arc::new(Exhausted));
+        let rebuilt = register_throttle(name, Arc::new(Exhausted));
+        drop(previous);
+        assert!(matches(&condition));
+        drop(rebuilt);
+        assert!(!matches(&condition));
+    }
+}
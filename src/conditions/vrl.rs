@@ -93,6 +93,18 @@ impl Vrl {
         };
         (original_event, result)
     }
+
+    /// Checks the condition, returning the error if the program fails at runtime.
+    ///
+    /// As with [`Conditional::check`], a non-boolean result is treated as false.
+    pub(super) fn check_fallible(&self, event: Event) -> (Result<bool, String>, Event) {
+        let (event, result) = self.run(event);
+
+        let result = result
+            .map(|value| matches!(value, Value::Boolean(true)))
+            .map_err(|err| err.to_string());
+        (result, event)
+    }
 }
 
 impl Conditional for Vrl {
@@ -251,4 +263,19 @@ mod test {
             }
         }
     }
+
+    #[test]
+    fn check_fallible_reports_runtime_errors() {
+        let condition = VrlConfig {
+            source: "to_int!(.count) > 5".to_owned(),
+            runtime: Default::default(),
+        }
+        .build(&Default::default())
+        .unwrap();
+
+        let check = |event: Event| condition.check_fallible(event).0;
+        assert_eq!(check(log_event!["count" => 10]), Ok(true));
+        assert_eq!(check(log_event!["count" => 1]), Ok(false));
+        assert!(check(log_event!["count" => "many"]).is_err());
+    }
 }
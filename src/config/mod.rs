@@ -242,6 +242,8 @@ pub enum Resource {
     SystemFdOffset(usize),
     Fd(u32),
     DiskBuffer(String),
+    /// The name a `throttle` transform publishes its state under.
+    ThrottleName(String),
 }
 
 #[derive(Clone, Debug, Eq, PartialEq, Hash, Ord, PartialOrd, Copy)]
@@ -320,6 +322,7 @@ impl Display for Resource {
             Resource::SystemFdOffset(offset) => write!(fmt, "systemd {}th socket", offset + 1),
             Resource::Fd(fd) => write!(fmt, "file descriptor: {}", fd),
             Resource::DiskBuffer(name) => write!(fmt, "disk buffer {:?}", name),
+            Resource::ThrottleName(name) => write!(fmt, "throttle name {:?}", name),
         }
     }
 }
@@ -18,7 +18,7 @@ use vector_core::{
 
 use super::schema::Options as SchemaOptions;
 use super::OutputId;
-use super::{id::Inputs, ComponentKey};
+use super::{id::Inputs, ComponentKey, Resource};
 
 pub type BoxedTransform = Box<dyn TransformConfig>;
 
@@ -210,6 +210,14 @@ pub trait TransformConfig: DynClone + NamedComponent + core::fmt::Debug + Send +
         Ok(())
     }
 
+    /// Gets the list of resources, if any, used by this transform.
+    ///
+    /// Resources are claimed by a single component, so conflicting claims are rejected when the
+    /// configuration is validated.
+    fn resources(&self) -> Vec<Resource> {
+        Vec::new()
+    }
+
     /// Whether or not concurrency should be enabled for this transform.
     ///
     /// When enabled, this transform may be run in parallel in order to attempt to maximize
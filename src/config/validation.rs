@@ -122,12 +122,20 @@ pub fn check_resources(config: &ConfigBuilder) -> Result<(), Vec<String>> {
         .sources
         .iter()
         .map(|(id, config)| (id, config.inner.resources()));
+    let transform_resources = config
+        .transforms
+        .iter()
+        .map(|(id, config)| (id, config.inner.resources()));
     let sink_resources = config
         .sinks
         .iter()
         .map(|(id, config)| (id, config.resources(id)));
 
-    let conflicting_components = Resource::conflicts(source_resources.chain(sink_resources));
+    let conflicting_components = Resource::conflicts(
+        source_resources
+            .chain(transform_resources)
+            .chain(sink_resources),
+    );
 
     if conflicting_components.is_empty() {
         Ok(())
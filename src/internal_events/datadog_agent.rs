@@ -0,0 +1,226 @@
+use std::path::Path;
+
+use metrics::{counter, gauge, histogram, register_histogram};
+use vector_common::internal_event::{error_stage, error_type};
+use vector_core::internal_event::{
+    ComponentEventsDropped, InternalEvent, INTENTIONAL, UNINTENTIONAL,
+};
+
+use crate::{
+    emit,
+    sources::datadog_agent::{OversizedMessageAction, RejectionReason},
+};
+
+#[derive(Debug)]
+pub struct DatadogAgentPayloadDecoded<'a> {
+    pub endpoint: &'static str,
+    pub compressed: bool,
+    pub messages: usize,
+    /// The size of each message in the payload, where the endpoint has a notion of raw messages.
+    pub message_sizes: &'a [usize],
+}
+
+impl InternalEvent for DatadogAgentPayloadDecoded<'_> {
+    fn emit(self) {
+        let compressed = if self.compressed { "true" } else { "false" };
+        histogram!(
+            "datadog_agent_messages_per_request", self.messages as f64,
+            "endpoint" => self.endpoint,
+            "compressed" => compressed,
+        );
+        if !self.message_sizes.is_empty() {
+            let message_size = register_histogram!(
+                "datadog_agent_message_size_bytes",
+                "endpoint" => self.endpoint,
+                "compressed" => compressed,
+            );
+            for size in self.message_sizes {
+                message_size.record(*size as f64);
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct DatadogAgentPayloadDecompressed {
+    pub endpoint: &'static str,
+    pub compressed_size: usize,
+    pub decompressed_size: usize,
+}
+
+impl InternalEvent for DatadogAgentPayloadDecompressed {
+    fn emit(self) {
+        if self.compressed_size > 0 {
+            gauge!(
+                "datadog_agent_decompression_ratio",
+                self.decompressed_size as f64 / self.compressed_size as f64,
+                "endpoint" => self.endpoint,
+            );
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct DatadogAgentFailedRequestDumped<'a> {
+    pub path: &'a Path,
+    pub byte_size: usize,
+}
+
+impl InternalEvent for DatadogAgentFailedRequestDumped<'_> {
+    fn emit(self) {
+        info!(
+            message = "Dumped the body of a request that failed to decode.",
+            path = %self.path.display(),
+            byte_size = %self.byte_size,
+            internal_log_rate_limit = true
+        );
+        counter!("datadog_agent_failed_request_dumps_total", 1);
+    }
+}
+
+#[derive(Debug)]
+pub struct DatadogAgentFailedRequestDumpError<'a> {
+    pub path: &'a Path,
+    pub error: std::io::Error,
+}
+
+impl InternalEvent for DatadogAgentFailedRequestDumpError<'_> {
+    fn emit(self) {
+        warn!(
+            message = "Failed to dump the body of a request that failed to decode.",
+            path = %self.path.display(),
+            error = %self.error,
+            internal_log_rate_limit = true
+        );
+        counter!("datadog_agent_failed_request_dump_errors_total", 1);
+    }
+}
+
+#[derive(Debug)]
+pub struct DatadogAgentOversizedMessage {
+    pub byte_size: usize,
+    pub max_bytes: usize,
+    pub action: OversizedMessageAction,
+}
+
+impl InternalEvent for DatadogAgentOversizedMessage {
+    fn emit(self) {
+        let action = match self.action {
+            OversizedMessageAction::Truncate => "truncate",
+            OversizedMessageAction::Drop => "drop",
+            OversizedMessageAction::Pass => "pass",
+        };
+        debug!(
+            message = "Received a log message larger than `max_message_bytes`.",
+            byte_size = %self.byte_size,
+            max_bytes = %self.max_bytes,
+            action,
+            internal_log_rate_limit = true
+        );
+        counter!("datadog_agent_oversized_messages_total", 1, "action" => action);
+        if self.action == OversizedMessageAction::Drop {
+            emit!(ComponentEventsDropped::<INTENTIONAL> {
+                count: 1,
+                reason: "Log message larger than `max_message_bytes`.",
+            });
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct DatadogAgentMessageDecodeError<'a> {
+    pub error: &'a codecs::decoding::Error,
+    /// How many frames were dropped with the rest of the message, estimated from the size of the
+    /// frames decoded before the error.
+    pub dropped_frames_estimate: usize,
+}
+
+impl InternalEvent for DatadogAgentMessageDecodeError<'_> {
+    fn emit(self) {
+        // ## skip check-validity-events ##
+        // The error itself is counted by `crate::codecs::Decoder`.
+        error!(
+            message = "Failed framing a log message, dropping the rest of it.",
+            error = %self.error,
+            dropped_frames_estimate = %self.dropped_frames_estimate,
+            error_code = "message_decode",
+            error_type = error_type::PARSER_FAILED,
+            stage = error_stage::PROCESSING,
+            internal_log_rate_limit = true
+        );
+        emit!(ComponentEventsDropped::<UNINTENTIONAL> {
+            count: self.dropped_frames_estimate,
+            reason: "Log message failed to be framed.",
+        });
+    }
+}
+
+#[derive(Debug)]
+pub struct DatadogAgentMessageDecodeDropped {
+    /// Whether some frames of the message were decoded before or after the error.
+    pub partial: bool,
+}
+
+impl InternalEvent for DatadogAgentMessageDecodeDropped {
+    fn emit(self) {
+        let dropped = if self.partial { "partial" } else { "full" };
+        counter!("datadog_agent_decode_dropped_messages_total", 1, "dropped" => dropped);
+    }
+}
+
+#[derive(Debug)]
+pub struct DatadogAgentServiceApiKeysError<E> {
+    pub error: E,
+}
+
+impl<E: std::fmt::Display> InternalEvent for DatadogAgentServiceApiKeysError<E> {
+    fn emit(self) {
+        error!(
+            message = "Failed to load service API key map file, keeping previous keys.",
+            error = %self.error,
+            error_code = "service_api_key_map_load",
+            error_type = error_type::CONFIGURATION_FAILED,
+            stage = error_stage::RECEIVING,
+            internal_log_rate_limit = true
+        );
+        counter!(
+            "component_errors_total", 1,
+            "error_code" => "service_api_key_map_load",
+            "error_type" => error_type::CONFIGURATION_FAILED,
+            "stage" => error_stage::RECEIVING,
+        );
+    }
+}
+
+#[derive(Debug)]
+pub struct DatadogAgentRequestRejected<'a> {
+    pub endpoint: &'static str,
+    pub reason: RejectionReason,
+    pub status_code: u16,
+    pub message: &'a str,
+}
+
+impl InternalEvent for DatadogAgentRequestRejected<'_> {
+    fn emit(self) {
+        let reason = self.reason.as_str();
+        error!(
+            message = "Rejected request.",
+            error = %self.message,
+            endpoint = %self.endpoint,
+            reason,
+            status_code = %self.status_code,
+            error_code = reason,
+            error_type = error_type::REQUEST_FAILED,
+            stage = error_stage::RECEIVING,
+            internal_log_rate_limit = true
+        );
+        counter!(
+            "component_errors_total", 1,
+            "endpoint" => self.endpoint,
+            "reason" => reason,
+            "error_code" => reason,
+            "error_type" => error_type::REQUEST_FAILED,
+            "stage" => error_stage::RECEIVING,
+        );
+    }
+}
@@ -1,23 +1,226 @@
 use crate::emit;
-use metrics::counter;
+use metrics::{counter, gauge};
+use vector_common::internal_event::{error_stage, error_type};
 use vector_core::internal_event::{ComponentEventsDropped, InternalEvent, INTENTIONAL};
 
 #[derive(Debug)]
 pub(crate) struct ThrottleEventDiscarded {
     pub key: String,
+    /// The `key_field` of the entry of `limits` the event exceeded, if not the `threshold`.
+    pub limit: Option<String>,
+    /// Whether the event is dropped, rather than rerouted to the `dropped` output.
+    pub drop_event: bool,
+    /// Whether the event is only reported as dropped, in dry run.
+    pub simulated: bool,
 }
 
 impl InternalEvent for ThrottleEventDiscarded {
     fn emit(self) {
-        debug!(message = "Rate limit exceeded.", key = ?self.key); // Deprecated.
+        if self.simulated {
+            debug!(message = "Rate limit would be exceeded.", key = ?self.key, limit = ?self.limit);
+            match self.limit {
+                None => counter!(
+                    "events_discarded_total", 1,
+                    "key" => self.key,
+                    "simulated" => "true",
+                ),
+                Some(limit) => counter!(
+                    "events_discarded_total", 1,
+                    "key" => self.key,
+                    "limit" => limit,
+                    "simulated" => "true",
+                ),
+            }
+            return;
+        }
+        debug!(message = "Rate limit exceeded.", key = ?self.key, limit = ?self.limit); // Deprecated.
+        match self.limit {
+            None => counter!(
+                "events_discarded_total", 1,
+                "key" => self.key,
+            ), // Deprecated.
+            Some(limit) => counter!(
+                "events_discarded_total", 1,
+                "key" => self.key,
+                "limit" => limit,
+            ), // Deprecated.
+        }
+
+        if self.drop_event {
+            emit!(ComponentEventsDropped::<INTENTIONAL> {
+                count: 1,
+                reason: "Rate limit exceeded."
+            })
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct ThrottleQuotaFileError<E> {
+    pub error: E,
+}
+
+impl<E: std::fmt::Display> InternalEvent for ThrottleQuotaFileError<E> {
+    fn emit(self) {
+        error!(
+            message = "Failed to load quota file, keeping previous quotas.",
+            error = %self.error,
+            error_code = "quota_file_load",
+            error_type = error_type::CONFIGURATION_FAILED,
+            stage = error_stage::PROCESSING,
+            internal_log_rate_limit = true,
+        );
         counter!(
-            "events_discarded_total", 1,
-            "key" => self.key,
-        ); // Deprecated.
+            "component_errors_total", 1,
+            "error_code" => "quota_file_load",
+            "error_type" => error_type::CONFIGURATION_FAILED,
+            "stage" => error_stage::PROCESSING,
+        );
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct ThrottleStateLoadError<E> {
+    pub error: E,
+}
+
+impl<E: std::fmt::Display> InternalEvent for ThrottleStateLoadError<E> {
+    fn emit(self) {
+        warn!(
+            message = "Failed to restore throttle state, starting with full budgets.",
+            error = %self.error,
+        );
+    }
+}
 
+#[derive(Debug)]
+pub(crate) struct ThrottleStateSaveError<E> {
+    pub error: E,
+}
+
+impl<E: std::fmt::Display> InternalEvent for ThrottleStateSaveError<E> {
+    fn emit(self) {
+        error!(
+            message = "Failed to save throttle state.",
+            error = %self.error,
+            error_code = "state_file_save",
+            error_type = error_type::WRITER_FAILED,
+            stage = error_stage::PROCESSING,
+            internal_log_rate_limit = true,
+        );
+        counter!(
+            "component_errors_total", 1,
+            "error_code" => "state_file_save",
+            "error_type" => error_type::WRITER_FAILED,
+            "stage" => error_stage::PROCESSING,
+        );
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct ThrottleExcludeConditionError<'a> {
+    pub error: &'a str,
+    pub drop_event: bool,
+}
+
+impl InternalEvent for ThrottleExcludeConditionError<'_> {
+    fn emit(self) {
+        let reason = "Failed to evaluate the exclude condition.";
+        error!(
+            message = reason,
+            error = %self.error,
+            error_code = "exclude_condition",
+            error_type = error_type::SCRIPT_FAILED,
+            stage = error_stage::PROCESSING,
+            drop_event = self.drop_event,
+            internal_log_rate_limit = true,
+        );
+        counter!(
+            "component_errors_total", 1,
+            "error_code" => "exclude_condition",
+            "error_type" => error_type::SCRIPT_FAILED,
+            "stage" => error_stage::PROCESSING,
+        );
+
+        if self.drop_event {
+            emit!(ComponentEventsDropped::<INTENTIONAL> { count: 1, reason });
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct ThrottleKeyCardinalityLimitReached {
+    pub max_unique_keys: usize,
+}
+
+impl InternalEvent for ThrottleKeyCardinalityLimitReached {
+    fn emit(self) {
+        warn!(
+            message = "Maximum number of unique keys reached, new keys share the overflow bucket.",
+            max_unique_keys = self.max_unique_keys,
+            internal_log_rate_limit = true,
+        );
+        counter!("throttle_key_cardinality_limit_reached_total", 1);
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct ThrottleDuplicateEventDropped;
+
+impl InternalEvent for ThrottleDuplicateEventDropped {
+    fn emit(self) {
         emit!(ComponentEventsDropped::<INTENTIONAL> {
             count: 1,
-            reason: "Rate limit exceeded."
+            reason: "Duplicate event."
         })
     }
 }
+
+#[derive(Debug)]
+pub(crate) struct ThrottleSustainedDropAlert {
+    pub key_count: usize,
+    pub ratio: f64,
+}
+
+impl InternalEvent for ThrottleSustainedDropAlert {
+    fn emit(self) {
+        warn!(
+            message = "Sustained fraction of events dropped by throttling.",
+            key_count = self.key_count,
+            ratio = self.ratio,
+        );
+        gauge!("throttle_alert_active", 1.0);
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct ThrottleSustainedDropAlertCleared {
+    pub ratio: f64,
+}
+
+impl InternalEvent for ThrottleSustainedDropAlertCleared {
+    fn emit(self) {
+        info!(
+            message = "Fraction of events dropped by throttling recovered.",
+            ratio = self.ratio,
+        );
+        gauge!("throttle_alert_active", 0.0);
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct ThrottleUpstreamShedding {
+    pub active: bool,
+}
+
+impl InternalEvent for ThrottleUpstreamShedding {
+    fn emit(self) {
+        if self.active {
+            info!(message = "Slowing down intake to shed events upstream.");
+            gauge!("throttle_shedding_active", 1.0);
+        } else {
+            info!(message = "Stopped shedding events upstream.");
+            gauge!("throttle_shedding_active", 0.0);
+        }
+    }
+}
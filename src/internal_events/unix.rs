@@ -1,6 +1,6 @@
-use std::{io::Error, path::Path};
+use std::{io::Error, path::Path, time::Duration};
 
-use metrics::counter;
+use metrics::{counter, gauge, histogram};
 use vector_common::internal_event::{
     error_stage, error_type, ComponentEventsDropped, UNINTENTIONAL,
 };
@@ -8,21 +8,73 @@ use vector_core::internal_event::InternalEvent;
 
 use crate::{emit, internal_events::SocketOutgoingConnectionError};
 
+/// The state of an outgoing Unix socket connection, as reported by the
+/// `unix_socket_connection_state` gauge.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum UnixSocketConnectionState {
+    Disconnected = 0,
+    Connecting = 1,
+    Connected = 2,
+    Sending = 3,
+}
+
+#[derive(Debug)]
+pub struct UnixSocketConnectionStateChanged {
+    pub state: UnixSocketConnectionState,
+}
+
+impl InternalEvent for UnixSocketConnectionStateChanged {
+    fn emit(self) {
+        trace!(message = "Connection state changed.", state = ?self.state);
+        gauge!("unix_socket_connection_state", self.state as u8 as f64);
+    }
+}
+
 #[derive(Debug)]
 pub struct UnixSocketConnectionEstablished<'a> {
     pub path: &'a std::path::Path,
+    /// The time spent connecting, including the backoff between failed attempts.
+    pub connect_duration: Duration,
+    /// Whether a previous connection was established by the same sink.
+    pub reconnect: bool,
 }
 
 impl InternalEvent for UnixSocketConnectionEstablished<'_> {
     fn emit(self) {
         debug!(message = "Connected.", path = ?self.path);
         counter!("connection_established_total", 1, "mode" => "unix");
+        histogram!("connect_duration_seconds", self.connect_duration, "mode" => "unix");
+        if self.reconnect {
+            counter!("reconnects_total", 1, "mode" => "unix");
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct UnixSocketHealthcheckPassed<'a> {
+    /// The absolute path of the socket.
+    pub path: &'a Path,
+    /// The size of the send buffer of the connection, if it could be read.
+    pub send_buffer_bytes: Option<usize>,
+    pub connect_duration: Duration,
+}
+
+impl InternalEvent for UnixSocketHealthcheckPassed<'_> {
+    fn emit(self) {
+        debug!(
+            message = "Healthcheck passed.",
+            path = ?self.path,
+            mode = "stream",
+            send_buffer_bytes = ?self.send_buffer_bytes,
+            connect_duration_ms = self.connect_duration.as_millis() as u64,
+        );
     }
 }
 
 #[derive(Debug)]
 pub struct UnixSocketOutgoingConnectionError<E> {
     pub error: E,
+    pub error_code: &'static str,
 }
 
 impl<E: std::error::Error> InternalEvent for UnixSocketOutgoingConnectionError<E> {
@@ -31,7 +83,90 @@ impl<E: std::error::Error> InternalEvent for UnixSocketOutgoingConnectionError<E
         // ## skip check-validity-events ##
         emit!(SocketOutgoingConnectionError { error: self.error });
         // deprecated
-        counter!("connection_failed_total", 1, "mode" => "unix");
+        counter!(
+            "connection_failed_total", 1,
+            "mode" => "unix",
+            "error_code" => self.error_code,
+        );
+    }
+}
+
+/// A failed connection attempt that isn't logged, as the same error was logged recently.
+#[derive(Debug)]
+pub struct UnixSocketOutgoingConnectionErrorSuppressed {
+    pub error_code: &'static str,
+}
+
+impl InternalEvent for UnixSocketOutgoingConnectionErrorSuppressed {
+    fn emit(self) {
+        // ## skip check-validity-events ##
+        counter!(
+            "component_errors_total", 1,
+            "error_code" => "failed_connecting",
+            "error_type" => error_type::CONNECTION_FAILED,
+            "stage" => error_stage::SENDING,
+        );
+        // deprecated
+        counter!(
+            "connection_failed_total", 1,
+            "mode" => "unix",
+            "error_code" => self.error_code,
+        );
+        counter!(
+            "connection_errors_suppressed_total", 1,
+            "mode" => "unix",
+            "error_code" => self.error_code,
+        );
+    }
+}
+
+#[derive(Debug)]
+pub struct UnixSocketConnectionRecovered<'a> {
+    pub path: &'a Path,
+    /// The time since the first failed attempt.
+    pub outage: Duration,
+    pub failed_attempts: u32,
+}
+
+impl InternalEvent for UnixSocketConnectionRecovered<'_> {
+    fn emit(self) {
+        info!(
+            message = "Connection recovered.",
+            path = ?self.path,
+            outage_secs = self.outage.as_secs_f64(),
+            failed_attempts = self.failed_attempts,
+        );
+        histogram!("connection_outage_seconds", self.outage, "mode" => "unix");
+    }
+}
+
+#[derive(Debug)]
+pub struct UnixSocketSendQueueSampled {
+    /// The bytes sent on the socket but not read by the peer yet.
+    pub queued_bytes: usize,
+}
+
+impl InternalEvent for UnixSocketSendQueueSampled {
+    fn emit(self) {
+        histogram!("unix_socket_sendq_bytes", self.queued_bytes as f64);
+    }
+}
+
+#[derive(Debug)]
+pub struct UnixSocketWaiting<'a> {
+    pub path: &'a Path,
+    /// The time spent waiting for the socket so far.
+    pub waited: Duration,
+}
+
+impl InternalEvent for UnixSocketWaiting<'_> {
+    fn emit(self) {
+        info!(
+            message = "Waiting for socket to be created.",
+            path = ?self.path,
+            waited_secs = self.waited.as_secs(),
+            internal_log_rate_limit = true,
+        );
     }
 }
 
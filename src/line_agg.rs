@@ -277,13 +277,41 @@ pub enum Emit<T> {
     Two(T, T),
 }
 
-/// A helper enum
-enum Decision {
+/// What a line means for the group it follows.
+pub(crate) enum Decision {
+    /// The line is part of the group, which may go on.
     Continue,
+    /// The line ends the group, and is part of it.
     EndInclude,
+    /// The group ended before the line.
     EndExclude,
 }
 
+impl Mode {
+    /// Decides what a line means for the group it follows, from whether it matched the condition
+    /// pattern.
+    pub(crate) const fn decide(self, condition_matched: bool) -> Decision {
+        match (self, condition_matched) {
+            // All consecutive lines matching this pattern are included in
+            // the group.
+            (Mode::ContinueThrough, true) => Decision::Continue,
+            (Mode::ContinueThrough, false) => Decision::EndExclude,
+            // All consecutive lines matching this pattern, plus one
+            // additional line, are included in the group.
+            (Mode::ContinuePast, true) => Decision::Continue,
+            (Mode::ContinuePast, false) => Decision::EndInclude,
+            // All consecutive lines not matching this pattern are included
+            // in the group.
+            (Mode::HaltBefore, true) => Decision::EndExclude,
+            (Mode::HaltBefore, false) => Decision::Continue,
+            // All consecutive lines, up to and including the first line
+            // matching this pattern, are included in the group.
+            (Mode::HaltWith, true) => Decision::EndInclude,
+            (Mode::HaltWith, false) => Decision::Continue,
+        }
+    }
+}
+
 impl<K, C> Logic<K, C>
 where
     K: Hash + Eq + Clone,
@@ -299,26 +327,7 @@ where
         match self.buffers.entry(src) {
             Entry::Occupied(mut entry) => {
                 let condition_matched = self.config.condition_pattern.is_match(line.as_ref());
-                let decision = match (self.config.mode, condition_matched) {
-                    // All consecutive lines matching this pattern are included in
-                    // the group.
-                    (Mode::ContinueThrough, true) => Decision::Continue,
-                    (Mode::ContinueThrough, false) => Decision::EndExclude,
-                    // All consecutive lines matching this pattern, plus one
-                    // additional line, are included in the group.
-                    (Mode::ContinuePast, true) => Decision::Continue,
-                    (Mode::ContinuePast, false) => Decision::EndInclude,
-                    // All consecutive lines not matching this pattern are included
-                    // in the group.
-                    (Mode::HaltBefore, true) => Decision::EndExclude,
-                    (Mode::HaltBefore, false) => Decision::Continue,
-                    // All consecutive lines, up to and including the first line
-                    // matching this pattern, are included in the group.
-                    (Mode::HaltWith, true) => Decision::EndInclude,
-                    (Mode::HaltWith, false) => Decision::Continue,
-                };
-
-                match decision {
+                match self.config.mode.decide(condition_matched) {
                     Decision::Continue => {
                         let buffered = entry.get_mut();
                         self.timeouts.reset(&buffered.0, self.config.timeout);
@@ -1,10 +1,25 @@
-use std::{path::PathBuf, pin::Pin, time::Duration};
+use std::{
+    io,
+    num::NonZeroU32,
+    path::{Path, PathBuf},
+    pin::Pin,
+    time::Duration,
+};
 
 use async_trait::async_trait;
 use bytes::{Bytes, BytesMut};
-use futures::{stream::BoxStream, SinkExt, StreamExt};
-use snafu::{ResultExt, Snafu};
-use tokio::{net::UnixStream, time::sleep};
+use futures::{
+    stream::{self, BoxStream},
+    SinkExt, StreamExt,
+};
+use notify::{RecursiveMode, Watcher};
+use snafu::Snafu;
+use socket2::{SockRef, Socket};
+use tokio::{
+    net::UnixStream,
+    sync::{mpsc, oneshot},
+    time::{interval_at, sleep, timeout, Instant},
+};
 use tokio_util::codec::Encoder;
 use vector_config::configurable_component;
 use vector_core::ByteSizeOf;
@@ -14,7 +29,10 @@ use crate::{
     event::{Event, Finalizable},
     internal_events::{
         ConnectionOpen, OpenGauge, SocketMode, UnixSocketConnectionEstablished,
-        UnixSocketOutgoingConnectionError, UnixSocketSendError,
+        UnixSocketConnectionRecovered, UnixSocketConnectionState, UnixSocketConnectionStateChanged,
+        UnixSocketHealthcheckPassed, UnixSocketOutgoingConnectionError,
+        UnixSocketOutgoingConnectionErrorSuppressed, UnixSocketSendError,
+        UnixSocketSendQueueSampled, UnixSocketWaiting,
     },
     sink::VecSinkExt,
     sinks::{
@@ -29,11 +47,59 @@ use crate::{
 
 #[derive(Debug, Snafu)]
 pub enum UnixError {
+    #[snafu(display("No socket at path {}", path.display()))]
+    SocketNotFound { path: PathBuf },
+    #[snafu(display("Permission denied connecting to socket at path {}", path.display()))]
+    PermissionDenied { path: PathBuf },
+    #[snafu(display("Connection refused by socket at path {}", path.display()))]
+    ConnectionRefused { path: PathBuf },
     #[snafu(display("Failed connecting to socket at path {}: {}", path.display(), source))]
     ConnectionError {
         source: tokio::io::Error,
         path: PathBuf,
     },
+    #[snafu(display(
+        "Connecting to socket at path {} timed out after {}s",
+        path.display(),
+        timeout.as_secs_f64()
+    ))]
+    ConnectTimeout { path: PathBuf, timeout: Duration },
+    #[snafu(display(
+        "Peer of socket at path {} runs as uid {} and gid {}, which don't match `peer_uid` and `peer_gid`",
+        path.display(),
+        uid,
+        gid
+    ))]
+    PeerCredentialMismatch { path: PathBuf, uid: u32, gid: u32 },
+}
+
+impl UnixError {
+    /// Categorizes the error of a failed connection to the socket at `path`.
+    fn connection(source: io::Error, path: &Path) -> Self {
+        let path = path.to_path_buf();
+        match source.kind() {
+            io::ErrorKind::NotFound => Self::SocketNotFound { path },
+            io::ErrorKind::PermissionDenied => Self::PermissionDenied { path },
+            io::ErrorKind::ConnectionRefused => Self::ConnectionRefused { path },
+            _ => Self::ConnectionError { source, path },
+        }
+    }
+
+    /// Whether the connection failed because there is no socket at the path.
+    const fn is_missing_socket(&self) -> bool {
+        matches!(self, Self::SocketNotFound { .. })
+    }
+
+    const fn error_code(&self) -> &'static str {
+        match self {
+            Self::SocketNotFound { .. } => "socket_not_found",
+            Self::PermissionDenied { .. } => "permission_denied",
+            Self::ConnectionRefused { .. } => "connection_refused",
+            Self::ConnectionError { .. } => "connection_failed",
+            Self::ConnectTimeout { .. } => "connect_timeout",
+            Self::PeerCredentialMismatch { .. } => "peer_credential_mismatch",
+        }
+    }
 }
 
 /// A Unix Domain Socket sink.
@@ -45,11 +111,78 @@ pub struct UnixSinkConfig {
     /// This should be an absolute path.
     #[configurable(metadata(docs::examples = "/path/to/socket"))]
     pub path: PathBuf,
+
+    /// The maximum time to wait for a connection to the socket to be established.
+    ///
+    /// If unset, connecting waits indefinitely.
+    #[configurable(metadata(docs::type_unit = "seconds"))]
+    pub connect_timeout_secs: Option<u64>,
+
+    /// Wait for the socket to be created, if it doesn't exist yet.
+    ///
+    /// Instead of retrying the connection with a backoff, the directory of the socket is watched
+    /// until the socket appears, and the healthcheck passes with a warning in the meantime. Once
+    /// the sink has connected, a socket that disappears is retried with the usual backoff.
+    #[serde(default)]
+    pub wait_for_socket: bool,
+
+    /// The user ID the process listening on the socket must run as.
+    ///
+    /// After connecting, the credentials of the peer are checked, and the connection is retried
+    /// with the usual backoff if they don't match. This prevents sending data to another process
+    /// that took over the path of the socket.
+    #[configurable(metadata(docs::examples = 1000))]
+    pub peer_uid: Option<u32>,
+
+    /// The group ID the process listening on the socket must run as.
+    ///
+    /// Checked along with `peer_uid`.
+    #[configurable(metadata(docs::examples = 1000))]
+    pub peer_gid: Option<u32>,
+
+    /// How often a failed connection attempt is logged while the socket stays unreachable.
+    ///
+    /// Failed attempts are always logged when the kind of error changes, and otherwise only every
+    /// this many attempts, the others being only counted. Once connected again, the duration of
+    /// the outage and the number of failed attempts are logged.
+    #[serde(default = "default_error_log_every_n_attempts")]
+    pub error_log_every_n_attempts: NonZeroU32,
+
+    /// The number of bytes queued in the socket above which sending pauses.
+    ///
+    /// Before each event is sent, the bytes sent on the socket but not read by the peer yet are
+    /// checked, and sending waits while there are more than this, so that a slow reader slows down
+    /// the sink before writes start blocking. The queued bytes are only known on Linux, and this
+    /// is ignored on other platforms.
+    #[configurable(metadata(docs::type_unit = "bytes"))]
+    #[configurable(metadata(docs::examples = 65536))]
+    pub block_above_sendq_bytes: Option<usize>,
+
+    /// The maximum time to wait, once there are no more events to send, for the pending writes to
+    /// be flushed and the connection to be shut down.
+    ///
+    /// If the peer doesn't read the remaining data in time, the connection is dropped and the
+    /// events not flushed yet are lost. If unset, shutting down waits indefinitely.
+    #[configurable(metadata(docs::type_unit = "seconds"))]
+    pub shutdown_timeout_secs: Option<u64>,
+}
+
+fn default_error_log_every_n_attempts() -> NonZeroU32 {
+    NonZeroU32::new(10).expect("static non-zero number")
 }
 
 impl UnixSinkConfig {
-    pub const fn new(path: PathBuf) -> Self {
-        Self { path }
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            connect_timeout_secs: None,
+            wait_for_socket: false,
+            peer_uid: None,
+            peer_gid: None,
+            error_log_every_n_attempts: default_error_log_every_n_attempts(),
+            block_above_sendq_bytes: None,
+            shutdown_timeout_secs: None,
+        }
     }
 
     pub fn build(
@@ -57,8 +190,16 @@ impl UnixSinkConfig {
         transformer: Transformer,
         encoder: impl Encoder<Event, Error = codecs::encoding::Error> + Clone + Send + Sync + 'static,
     ) -> crate::Result<(VectorSink, Healthcheck)> {
-        let connector = UnixConnector::new(self.path.clone());
-        let sink = UnixSink::new(connector.clone(), transformer, encoder);
+        let connector = UnixConnector::new(
+            self.path.clone(),
+            self.connect_timeout_secs.map(Duration::from_secs),
+        )
+        .wait_for_socket(self.wait_for_socket)
+        .peer_credentials(self.peer_uid, self.peer_gid)
+        .error_log_every_n_attempts(self.error_log_every_n_attempts);
+        let sink = UnixSink::new(connector.clone(), transformer, encoder)
+            .block_above_sendq_bytes(self.block_above_sendq_bytes)
+            .shutdown_timeout(self.shutdown_timeout_secs.map(Duration::from_secs));
         Ok((
             VectorSink::from_event_streamsink(sink),
             Box::pin(async move { connector.healthcheck().await }),
@@ -69,11 +210,39 @@ impl UnixSinkConfig {
 #[derive(Debug, Clone)]
 struct UnixConnector {
     pub path: PathBuf,
+    connect_timeout: Option<Duration>,
+    wait_for_socket: bool,
+    peer_uid: Option<u32>,
+    peer_gid: Option<u32>,
+    error_log_every_n_attempts: NonZeroU32,
 }
 
 impl UnixConnector {
-    const fn new(path: PathBuf) -> Self {
-        Self { path }
+    fn new(path: PathBuf, connect_timeout: Option<Duration>) -> Self {
+        Self {
+            path,
+            connect_timeout,
+            wait_for_socket: false,
+            peer_uid: None,
+            peer_gid: None,
+            error_log_every_n_attempts: default_error_log_every_n_attempts(),
+        }
+    }
+
+    const fn wait_for_socket(mut self, wait_for_socket: bool) -> Self {
+        self.wait_for_socket = wait_for_socket;
+        self
+    }
+
+    const fn peer_credentials(mut self, uid: Option<u32>, gid: Option<u32>) -> Self {
+        self.peer_uid = uid;
+        self.peer_gid = gid;
+        self
+    }
+
+    const fn error_log_every_n_attempts(mut self, every_n_attempts: NonZeroU32) -> Self {
+        self.error_log_every_n_attempts = every_n_attempts;
+        self
     }
 
     const fn fresh_backoff() -> ExponentialBackoff {
@@ -84,31 +253,218 @@ impl UnixConnector {
     }
 
     async fn connect(&self) -> Result<UnixStream, UnixError> {
-        UnixStream::connect(&self.path)
-            .await
-            .context(ConnectionSnafu {
+        let connect = UnixStream::connect(&self.path);
+        let result = match self.connect_timeout {
+            None => connect.await,
+            Some(duration) => match timeout(duration, connect).await {
+                Ok(result) => result,
+                Err(_) => {
+                    return ConnectTimeoutSnafu {
+                        path: self.path.clone(),
+                        timeout: duration,
+                    }
+                    .fail()
+                }
+            },
+        };
+        let stream = result.map_err(|source| UnixError::connection(source, &self.path))?;
+        self.verify_peer(&stream)?;
+        Ok(stream)
+    }
+
+    /// Checks that the process listening on the socket runs as `peer_uid` and `peer_gid`, if set.
+    fn verify_peer(&self, stream: &UnixStream) -> Result<(), UnixError> {
+        if self.peer_uid.is_none() && self.peer_gid.is_none() {
+            return Ok(());
+        }
+        let cred = stream
+            .peer_cred()
+            .map_err(|source| UnixError::connection(source, &self.path))?;
+        let (uid, gid) = (cred.uid(), cred.gid());
+        if self.peer_uid.map_or(true, |expected| expected == uid)
+            && self.peer_gid.map_or(true, |expected| expected == gid)
+        {
+            Ok(())
+        } else {
+            Err(UnixError::PeerCredentialMismatch {
                 path: self.path.clone(),
+                uid,
+                gid,
             })
+        }
     }
 
-    async fn connect_backoff(&self) -> UnixStream {
+    async fn connect_backoff(&self, reconnect: bool) -> UnixStream {
         let mut backoff = Self::fresh_backoff();
+        let mut failures = ConnectionFailures::new(self.error_log_every_n_attempts);
+        let start = Instant::now();
         loop {
             match self.connect().await {
                 Ok(stream) => {
-                    emit!(UnixSocketConnectionEstablished { path: &self.path });
+                    if let Some(outage) = failures.recover() {
+                        emit!(UnixSocketConnectionRecovered {
+                            path: &self.path,
+                            outage: outage.duration,
+                            failed_attempts: outage.attempts,
+                        });
+                    }
+                    emit!(UnixSocketConnectionEstablished {
+                        path: &self.path,
+                        connect_duration: start.elapsed(),
+                        reconnect,
+                    });
                     return stream;
                 }
+                // A socket that never existed is waited for, rather than retried.
+                Err(error) if self.wait_for_socket && !reconnect && error.is_missing_socket() => {
+                    wait_for_path(&self.path).await;
+                }
                 Err(error) => {
-                    emit!(UnixSocketOutgoingConnectionError { error });
+                    if failures.record(&error) {
+                        emit!(UnixSocketOutgoingConnectionError {
+                            error_code: error.error_code(),
+                            error,
+                        });
+                    } else {
+                        emit!(UnixSocketOutgoingConnectionErrorSuppressed {
+                            error_code: error.error_code(),
+                        });
+                    }
                     sleep(backoff.next().unwrap()).await;
                 }
             }
         }
     }
 
+    /// Connects to the socket, and reports on the connection.
+    async fn health_report(&self) -> Result<UnixHealthReport, UnixError> {
+        let start = Instant::now();
+        let stream = self.connect().await?;
+        let connect_duration = start.elapsed();
+        Ok(UnixHealthReport {
+            path: std::fs::canonicalize(&self.path).unwrap_or_else(|_| self.path.clone()),
+            send_buffer_bytes: SockRef::from(&stream).send_buffer_size().ok(),
+            connect_duration,
+        })
+    }
+
     async fn healthcheck(&self) -> crate::Result<()> {
-        self.connect().await.map(|_| ()).map_err(Into::into)
+        match self.health_report().await {
+            Ok(report) => {
+                emit!(UnixSocketHealthcheckPassed {
+                    path: &report.path,
+                    send_buffer_bytes: report.send_buffer_bytes,
+                    connect_duration: report.connect_duration,
+                });
+                Ok(())
+            }
+            Err(error) if self.wait_for_socket && error.is_missing_socket() => {
+                warn!(
+                    message = "Socket doesn't exist yet, waiting for it to be created.",
+                    path = ?self.path,
+                );
+                Ok(())
+            }
+            Err(error) => Err(error.into()),
+        }
+    }
+}
+
+/// The failed connection attempts since the socket became unreachable, deciding which of them are
+/// logged.
+#[derive(Debug)]
+struct ConnectionFailures {
+    log_every_n_attempts: NonZeroU32,
+    /// When the first attempt failed, if any did.
+    since: Option<Instant>,
+    attempts: u32,
+    last_error_code: Option<&'static str>,
+}
+
+/// A connection outage that ended.
+#[derive(Debug, PartialEq)]
+struct Outage {
+    /// The time since the first failed attempt.
+    duration: Duration,
+    attempts: u32,
+}
+
+impl ConnectionFailures {
+    const fn new(log_every_n_attempts: NonZeroU32) -> Self {
+        Self {
+            log_every_n_attempts,
+            since: None,
+            attempts: 0,
+            last_error_code: None,
+        }
+    }
+
+    /// Records a failed attempt, returning whether its error should be logged.
+    fn record(&mut self, error: &UnixError) -> bool {
+        let error_code = error.error_code();
+        self.since.get_or_insert_with(Instant::now);
+        self.attempts += 1;
+        let changed = self.last_error_code.replace(error_code) != Some(error_code);
+        changed || self.attempts % self.log_every_n_attempts.get() == 0
+    }
+
+    /// Ends the outage, if any attempt failed since the last one.
+    fn recover(&mut self) -> Option<Outage> {
+        let since = self.since.take()?;
+        self.last_error_code = None;
+        Some(Outage {
+            duration: since.elapsed(),
+            attempts: std::mem::take(&mut self.attempts),
+        })
+    }
+}
+
+/// What a successful healthcheck found out about the socket.
+#[derive(Debug)]
+struct UnixHealthReport {
+    /// The absolute path of the socket, with symbolic links resolved.
+    path: PathBuf,
+    /// The size of the send buffer of the connection, as reported by `SO_SNDBUF`.
+    send_buffer_bytes: Option<usize>,
+    connect_duration: Duration,
+}
+
+/// How often the socket path is checked while waiting for it, in case the watcher misses its
+/// creation or couldn't be set up.
+const WAIT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often a reminder is logged while waiting for the socket.
+const WAIT_REMINDER_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Waits for `path` to exist, watching its parent directory for its creation.
+async fn wait_for_path(path: &Path) {
+    let start = Instant::now();
+    emit!(UnixSocketWaiting {
+        path,
+        waited: Duration::ZERO,
+    });
+
+    // The watcher is set up before checking the path, so that its creation isn't missed in between.
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+    let _watcher = path.parent().and_then(|parent| {
+        let mut watcher = notify::recommended_watcher(move |_| {
+            let _ = sender.send(());
+        })
+        .ok()?;
+        watcher.watch(parent, RecursiveMode::NonRecursive).ok()?;
+        Some(watcher)
+    });
+
+    let mut reminder = interval_at(start + WAIT_REMINDER_INTERVAL, WAIT_REMINDER_INTERVAL);
+    while !path.exists() {
+        tokio::select! {
+            _ = receiver.recv() => (),
+            _ = sleep(WAIT_POLL_INTERVAL) => (),
+            _ = reminder.tick() => emit!(UnixSocketWaiting {
+                path,
+                waited: start.elapsed(),
+            }),
+        }
     }
 }
 
@@ -119,6 +475,9 @@ where
     connector: UnixConnector,
     transformer: Transformer,
     encoder: E,
+    connected_before: bool,
+    block_above_sendq_bytes: Option<usize>,
+    shutdown_timeout: Option<Duration>,
 }
 
 impl<E> UnixSink<E>
@@ -130,12 +489,36 @@ where
             connector,
             transformer,
             encoder,
+            connected_before: false,
+            block_above_sendq_bytes: None,
+            shutdown_timeout: None,
         }
     }
 
-    async fn connect(&mut self) -> BytesSink<UnixStream> {
-        let stream = self.connector.connect_backoff().await;
-        BytesSink::new(stream, |_| ShutdownCheck::Alive, SocketMode::Unix)
+    const fn block_above_sendq_bytes(mut self, threshold: Option<usize>) -> Self {
+        self.block_above_sendq_bytes = threshold;
+        self
+    }
+
+    const fn shutdown_timeout(mut self, shutdown_timeout: Option<Duration>) -> Self {
+        self.shutdown_timeout = shutdown_timeout;
+        self
+    }
+
+    async fn connect(&mut self) -> (BytesSink<UnixStream>, SendQueue) {
+        emit!(UnixSocketConnectionStateChanged {
+            state: UnixSocketConnectionState::Connecting
+        });
+        let stream = self.connector.connect_backoff(self.connected_before).await;
+        self.connected_before = true;
+        emit!(UnixSocketConnectionStateChanged {
+            state: UnixSocketConnectionState::Connected
+        });
+        let send_queue = SendQueue::new(&stream, self.block_above_sendq_bytes);
+        (
+            BytesSink::new(stream, |_| ShutdownCheck::Alive, SocketMode::Unix),
+            send_queue,
+        )
     }
 }
 
@@ -145,40 +528,63 @@ where
     E: Encoder<Event, Error = codecs::encoding::Error> + Clone + Send + Sync,
 {
     // Same as TcpSink, more details there.
+    //
+    // Events are only encoded once connected, so that events waiting for the connection aren't
+    // encoded ahead of time, and don't hold their encoded bytes through the backoff.
     async fn run(mut self: Box<Self>, input: BoxStream<'_, Event>) -> Result<(), ()> {
         let mut encoder = self.encoder.clone();
         let transformer = self.transformer.clone();
-        let mut input = input
-            .map(|mut event| {
-                let byte_size = event.size_of();
-
-                transformer.transform(&mut event);
-
-                let finalizers = event.take_finalizers();
-                let mut bytes = BytesMut::new();
-
-                // Errors are handled by `Encoder`.
-                if encoder.encode(event, &mut bytes).is_ok() {
-                    let item = bytes.freeze();
-                    EncodedEvent {
-                        item,
-                        finalizers,
-                        byte_size,
-                    }
-                } else {
-                    EncodedEvent::new(Bytes::new(), 0)
-                }
-            })
-            .peekable();
+        let mut input = input.peekable();
 
         while Pin::new(&mut input).peek().await.is_some() {
-            let mut sink = self.connect().await;
+            let (mut sink, send_queue) = self.connect().await;
             let _open_token = OpenGauge::new().open(|count| emit!(ConnectionOpen { count }));
 
-            let result = match sink.send_all_peekable(&mut (&mut input).peekable()).await {
-                Ok(()) => sink.close().await,
-                Err(error) => Err(error),
+            emit!(UnixSocketConnectionStateChanged {
+                state: UnixSocketConnectionState::Sending
+            });
+            let send_queue = &send_queue;
+            // Signals the end of the input, after which the remaining writes must be flushed and the
+            // connection shut down within `shutdown_timeout`.
+            let (ended_tx, ended_rx) = oneshot::channel();
+            let mut encoded = (&mut input)
+                .then(|event| async move {
+                    send_queue.wait().await;
+                    event
+                })
+                .map(|event| encode_event(&mut encoder, &transformer, event))
+                .chain(
+                    stream::once(async move {
+                        let _ = ended_tx.send(());
+                    })
+                    .filter_map(|()| async { None }),
+                )
+                .boxed()
+                .peekable();
+            let send = async {
+                match sink.send_all_peekable(&mut encoded).await {
+                    // Flushes what's left, then shuts the connection down so the peer gets an EOF.
+                    Ok(()) => sink.close().await,
+                    Err(error) => Err(error),
+                }
+            };
+            tokio::pin!(send);
+            let result = match self.shutdown_timeout {
+                Some(shutdown_timeout) => tokio::select! {
+                    result = &mut send => result,
+                    Ok(()) = ended_rx => match timeout(shutdown_timeout, &mut send).await {
+                        Ok(result) => result,
+                        Err(_) => Err(io::Error::new(
+                            io::ErrorKind::TimedOut,
+                            "timed out flushing the connection on shutdown",
+                        )),
+                    },
+                },
+                None => send.await,
             };
+            emit!(UnixSocketConnectionStateChanged {
+                state: UnixSocketConnectionState::Disconnected
+            });
 
             if let Err(error) = result {
                 emit!(UnixSocketSendError {
@@ -192,14 +598,113 @@ where
     }
 }
 
+/// How often the send queue of a connection is checked again while it's above
+/// `block_above_sendq_bytes`.
+const SEND_QUEUE_RECHECK_INTERVAL: Duration = Duration::from_millis(10);
+
+/// The bytes sent on a connection but not read by the peer yet, sampled before each event is sent.
+struct SendQueue {
+    /// A duplicate of the socket of the connection, only used to query its queue. If the socket
+    /// couldn't be duplicated, the queue is never sampled, which doesn't affect sending.
+    socket: Option<Socket>,
+    block_above: Option<usize>,
+}
+
+impl SendQueue {
+    fn new(stream: &UnixStream, block_above: Option<usize>) -> Self {
+        Self {
+            socket: SockRef::from(stream).try_clone().ok(),
+            block_above,
+        }
+    }
+
+    /// Samples the queue, and waits for it to drain below `block_above` if it's above.
+    async fn wait(&self) {
+        let queued = match self.socket.as_ref().and_then(queued_bytes) {
+            Some(queued) => queued,
+            None => return,
+        };
+        emit!(UnixSocketSendQueueSampled {
+            queued_bytes: queued
+        });
+
+        if let Some(block_above) = self.block_above {
+            let mut queued = Some(queued);
+            while queued.map_or(false, |queued| queued > block_above) {
+                sleep(SEND_QUEUE_RECHECK_INTERVAL).await;
+                queued = self.socket.as_ref().and_then(queued_bytes);
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+nix::ioctl_read_bad!(siocoutq, nix::libc::TIOCOUTQ, nix::libc::c_int);
+
+/// The bytes sent on `socket` but not read by the peer yet, if the platform can tell.
+#[cfg(target_os = "linux")]
+fn queued_bytes(socket: &Socket) -> Option<usize> {
+    use std::os::unix::io::AsRawFd;
+
+    let mut queued = 0;
+    // `SIOCOUTQ` is the same request as `TIOCOUTQ`, which `libc` defines for Linux.
+    // SAFETY: the descriptor is kept open by `socket`, and `queued` outlives the call.
+    unsafe { siocoutq(socket.as_raw_fd(), &mut queued) }.ok()?;
+    usize::try_from(queued).ok()
+}
+
+#[cfg(not(target_os = "linux"))]
+const fn queued_bytes(_socket: &Socket) -> Option<usize> {
+    None
+}
+
+/// Encodes an event to be sent, once it's about to be written.
+fn encode_event<E>(
+    encoder: &mut E,
+    transformer: &Transformer,
+    mut event: Event,
+) -> EncodedEvent<Bytes>
+where
+    E: Encoder<Event, Error = codecs::encoding::Error>,
+{
+    let byte_size = event.size_of();
+
+    transformer.transform(&mut event);
+
+    let finalizers = event.take_finalizers();
+    let mut bytes = BytesMut::new();
+
+    // Errors are handled by `Encoder`.
+    if encoder.encode(event, &mut bytes).is_ok() {
+        let item = bytes.freeze();
+        EncodedEvent {
+            item,
+            finalizers,
+            byte_size,
+        }
+    } else {
+        EncodedEvent::new(Bytes::new(), 0)
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
     use codecs::{encoding::Framer, NewlineDelimitedEncoder, TextSerializerConfig};
-    use tokio::net::UnixListener;
+    use tokio::{
+        io::{AsyncBufReadExt, BufReader},
+        net::UnixListener,
+    };
+    use vector_core::{event::MetricValue, metrics::Controller};
 
     use super::*;
     use crate::{
         codecs::Encoder,
+        event::LogEvent,
         test_util::{
             components::{assert_sink_compliance, SINK_TAGS},
             random_lines_with_stream, CountReceiver,
@@ -236,6 +741,478 @@ mod tests {
             .is_err());
     }
 
+    #[tokio::test]
+    async fn unix_sink_healthcheck_does_not_hang() {
+        let path = temp_uds_path("never_accepts");
+
+        // A listener with a backlog of zero that never accepts; once the first connection is
+        // queued, its accept queue is full.
+        let socket =
+            socket2::Socket::new(socket2::Domain::UNIX, socket2::Type::STREAM, None).unwrap();
+        socket
+            .bind(&socket2::SockAddr::unix(&path).unwrap())
+            .unwrap();
+        socket.listen(0).unwrap();
+        let _queued = std::os::unix::net::UnixStream::connect(&path).unwrap();
+
+        let mut config = UnixSinkConfig::new(path);
+        config.connect_timeout_secs = Some(1);
+        let (_, healthcheck) = config
+            .build(
+                Default::default(),
+                Encoder::<()>::new(TextSerializerConfig::default().build().into()),
+            )
+            .unwrap();
+
+        tokio::time::timeout(Duration::from_secs(5), healthcheck)
+            .await
+            .expect("healthcheck did not honor the connect timeout")
+            .unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn unix_sink_health_report() {
+        let path = temp_uds_path("health_report");
+        let _listener = UnixListener::bind(&path).unwrap();
+        let report = UnixConnector::new(path.clone(), None)
+            .health_report()
+            .await
+            .unwrap();
+        assert!(report.path.is_absolute());
+        assert_eq!(report.path, std::fs::canonicalize(&path).unwrap());
+        assert!(report.send_buffer_bytes.unwrap() > 0);
+    }
+
+    #[tokio::test]
+    async fn missing_socket_error_is_categorized() {
+        let path = temp_uds_path("missing");
+        let error = UnixConnector::new(path, None)
+            .health_report()
+            .await
+            .unwrap_err();
+        assert!(matches!(error, UnixError::SocketNotFound { .. }));
+        assert_eq!(error.error_code(), "socket_not_found");
+    }
+
+    #[tokio::test]
+    async fn unix_sink_verifies_peer_credentials() {
+        let path = temp_uds_path("peer_credentials");
+        let listener = UnixListener::bind(&path).unwrap();
+        let (uid, gid) = {
+            let stream = UnixStream::connect(&path).await.unwrap();
+            let (_peer, _) = listener.accept().await.unwrap();
+            let cred = stream.peer_cred().unwrap();
+            (cred.uid(), cred.gid())
+        };
+
+        let connector = |peer_uid, peer_gid| {
+            UnixConnector::new(path.clone(), None).peer_credentials(peer_uid, peer_gid)
+        };
+        assert!(connector(Some(uid), Some(gid)).connect().await.is_ok());
+        assert!(connector(Some(uid), None).connect().await.is_ok());
+
+        let error = connector(Some(uid.wrapping_add(1)), Some(gid))
+            .connect()
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            UnixError::PeerCredentialMismatch { uid: actual, .. } if actual == uid
+        ));
+        assert_eq!(error.error_code(), "peer_credential_mismatch");
+        assert!(connector(None, Some(gid.wrapping_add(1)))
+            .healthcheck()
+            .await
+            .is_err());
+    }
+
+    #[test]
+    fn connect_timeout_error_names_the_timeout() {
+        let error = UnixError::ConnectTimeout {
+            path: PathBuf::from("/path/to/socket"),
+            timeout: Duration::from_secs(3),
+        };
+        assert_eq!(
+            error.to_string(),
+            "Connecting to socket at path /path/to/socket timed out after 3s"
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn connection_failures_logged_on_change_or_every_n_attempts() {
+        let mut failures = ConnectionFailures::new(NonZeroU32::new(3).unwrap());
+        assert_eq!(failures.recover(), None);
+
+        let not_found = UnixError::SocketNotFound {
+            path: PathBuf::from("/path/to/socket"),
+        };
+        let refused = UnixError::ConnectionRefused {
+            path: PathBuf::from("/path/to/socket"),
+        };
+        let mut logged = Vec::new();
+        for error in [
+            &not_found, &not_found, &not_found, &not_found, &refused, &refused, &not_found,
+        ] {
+            logged.push(failures.record(error));
+            tokio::time::advance(Duration::from_secs(1)).await;
+        }
+        assert_eq!(logged, [true, false, true, false, true, true, true]);
+
+        assert_eq!(
+            failures.recover(),
+            Some(Outage {
+                duration: Duration::from_secs(7),
+                attempts: 7,
+            })
+        );
+        assert_eq!(failures.recover(), None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn connect_backoff_summarizes_outage() {
+        crate::metrics::init_test();
+        let path = temp_uds_path("down_for_a_while");
+        let connector = UnixConnector::new(path.clone(), None)
+            .error_log_every_n_attempts(NonZeroU32::new(3).unwrap());
+        let connect = tokio::spawn(async move { connector.connect_backoff(false).await });
+
+        // The backoff sleeps are skipped over, as time is paused.
+        sleep(Duration::from_secs(600)).await;
+        let listener = UnixListener::bind(&path).unwrap();
+        connect.await.unwrap();
+        let _ = listener.accept().await.unwrap();
+
+        let metrics = Controller::get().unwrap().capture_metrics();
+        let metric = |name: &str| {
+            metrics
+                .iter()
+                .find(|metric| metric.name() == name)
+                .unwrap_or_else(|| panic!("metric {} not emitted", name))
+                .value()
+                .clone()
+        };
+        let counter = |name: &str| match metric(name) {
+            MetricValue::Counter { value } => value as u32,
+            value => panic!("unexpected metric value {:?}", value),
+        };
+        let failed = counter("connection_failed_total");
+        assert!(failed > 3);
+        // The first attempt, then every third one, are logged.
+        assert_eq!(
+            counter("connection_errors_suppressed_total"),
+            failed - (1 + failed / 3)
+        );
+        match metric("connection_outage_seconds") {
+            MetricValue::AggregatedHistogram { count, sum, .. } => {
+                assert_eq!(count, 1);
+                assert!(sum >= 600.0);
+            }
+            value => panic!("unexpected metric value {:?}", value),
+        }
+    }
+
+    #[tokio::test]
+    async fn unix_sink_connection_telemetry() {
+        crate::metrics::init_test();
+        let path = temp_uds_path("reconnect");
+        let listener = UnixListener::bind(&path).unwrap();
+        let (sink, _healthcheck) = UnixSinkConfig::new(path)
+            .build(
+                Default::default(),
+                Encoder::<Framer>::new(
+                    NewlineDelimitedEncoder::new().into(),
+                    TextSerializerConfig::default().build().into(),
+                ),
+            )
+            .unwrap();
+        let (tx, rx) = futures::channel::mpsc::unbounded::<Event>();
+        let sink = tokio::spawn(sink.run(rx.map(Into::into)));
+
+        let read_line = |stream| async move {
+            let mut lines = BufReader::new(stream).lines();
+            lines.next_line().await.unwrap().unwrap()
+        };
+
+        // Connect, send, then have the peer close the connection.
+        tx.unbounded_send(Event::Log(LogEvent::from("first")))
+            .unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+        assert_eq!(read_line(stream).await, "first");
+
+        // The write on the closed connection fails, and the next event reconnects.
+        tx.unbounded_send(Event::Log(LogEvent::from("lost")))
+            .unwrap();
+        tx.unbounded_send(Event::Log(LogEvent::from("second")))
+            .unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+        assert_eq!(read_line(stream).await, "second");
+
+        drop(tx);
+        sink.await.unwrap().unwrap();
+
+        let metric = |name: &str| {
+            Controller::get()
+                .unwrap()
+                .capture_metrics()
+                .into_iter()
+                .find(|metric| metric.name() == name)
+                .unwrap_or_else(|| panic!("metric {} not emitted", name))
+                .value()
+                .clone()
+        };
+        assert_eq!(
+            metric("connection_established_total"),
+            MetricValue::Counter { value: 2.0 }
+        );
+        assert_eq!(
+            metric("reconnects_total"),
+            MetricValue::Counter { value: 1.0 }
+        );
+        assert_eq!(
+            metric("unix_socket_connection_state"),
+            MetricValue::Gauge {
+                value: UnixSocketConnectionState::Disconnected as u8 as f64
+            }
+        );
+        match metric("connect_duration_seconds") {
+            MetricValue::AggregatedHistogram { count, .. } => assert_eq!(count, 2),
+            value => panic!("unexpected metric value {:?}", value),
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn unix_sink_pauses_while_send_queue_is_full() {
+        crate::metrics::init_test();
+        let path = temp_uds_path("slow_reader");
+        let listener = UnixListener::bind(&path).unwrap();
+        let mut config = UnixSinkConfig::new(path);
+        config.block_above_sendq_bytes = Some(4096);
+        let (sink, _healthcheck) = config
+            .build(
+                Default::default(),
+                Encoder::<Framer>::new(
+                    NewlineDelimitedEncoder::new().into(),
+                    TextSerializerConfig::default().build().into(),
+                ),
+            )
+            .unwrap();
+
+        let (lines, events) = random_lines_with_stream(1000, 100, None);
+        let sink = tokio::spawn(sink.run(events));
+        let (stream, _) = listener.accept().await.unwrap();
+
+        // Nothing is read for a while, so the queue fills up to the threshold and sending pauses,
+        // well before all the lines are sent.
+        sleep(Duration::from_millis(500)).await;
+        stream.readable().await.unwrap();
+        let mut queued = 0;
+        let mut buffer = vec![0; 1 << 20];
+        loop {
+            match stream.try_read(&mut buffer[queued..]) {
+                Ok(0) => break,
+                Ok(read) => queued += read,
+                Err(error) if error.kind() == io::ErrorKind::WouldBlock => break,
+                Err(error) => panic!("unexpected read error {}", error),
+            }
+        }
+        assert!(queued > 0);
+        assert!(queued < 100 * 1000 / 2, "{} bytes were sent", queued);
+
+        // Once the reader drains the queue, the rest is sent.
+        let mut reader = tokio::io::AsyncReadExt::chain(
+            BufReader::new(&buffer[..queued]),
+            BufReader::new(stream),
+        );
+        let mut received = Vec::new();
+        while received.len() < lines.len() {
+            let mut line = String::new();
+            reader.read_line(&mut line).await.unwrap();
+            received.push(line.trim_end_matches('\n').to_owned());
+        }
+        assert_eq!(received, lines);
+        sink.await.unwrap().unwrap();
+
+        let sampled = Controller::get()
+            .unwrap()
+            .capture_metrics()
+            .into_iter()
+            .find(|metric| metric.name() == "unix_socket_sendq_bytes")
+            .expect("send queue not sampled");
+        match sampled.value() {
+            MetricValue::AggregatedHistogram { count, sum, .. } => {
+                assert_eq!(*count, 100);
+                assert!(*sum > 0.0);
+            }
+            value => panic!("unexpected metric value {:?}", value),
+        }
+    }
+
+    #[tokio::test]
+    async fn unix_sink_waits_for_socket() {
+        crate::metrics::init_test();
+        let path = temp_uds_path("created_later");
+        let mut config = UnixSinkConfig::new(path.clone());
+        config.wait_for_socket = true;
+        let (sink, healthcheck) = config
+            .build(
+                Default::default(),
+                Encoder::<Framer>::new(
+                    NewlineDelimitedEncoder::new().into(),
+                    TextSerializerConfig::default().build().into(),
+                ),
+            )
+            .unwrap();
+        healthcheck
+            .await
+            .expect("healthcheck should pass while waiting");
+
+        let (tx, rx) = futures::channel::mpsc::unbounded::<Event>();
+        let sink = tokio::spawn(sink.run(rx.map(Into::into)));
+        tx.unbounded_send(Event::Log(LogEvent::from("hello")))
+            .unwrap();
+
+        sleep(Duration::from_millis(500)).await;
+        let listener = UnixListener::bind(&path).unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut lines = BufReader::new(stream).lines();
+        assert_eq!(lines.next_line().await.unwrap().unwrap(), "hello");
+
+        drop(tx);
+        sink.await.unwrap().unwrap();
+
+        // Waiting isn't reported as failing to connect.
+        let failures = Controller::get()
+            .unwrap()
+            .capture_metrics()
+            .into_iter()
+            .filter(|metric| metric.name() == "connection_failed_total")
+            .count();
+        assert_eq!(failures, 0);
+    }
+
+    #[tokio::test]
+    async fn wait_for_path_polls_without_parent_directory() {
+        // The parent directory doesn't exist yet, so it can't be watched.
+        let dir = tempfile::tempdir().unwrap().into_path().join("later");
+        let path = dir.join("socket");
+
+        let wait = tokio::spawn({
+            let path = path.clone();
+            async move { wait_for_path(&path).await }
+        });
+        sleep(Duration::from_millis(200)).await;
+        assert!(!wait.is_finished());
+
+        std::fs::create_dir(&dir).unwrap();
+        let _listener = UnixListener::bind(&path).unwrap();
+        tokio::time::timeout(Duration::from_secs(5), wait)
+            .await
+            .expect("socket creation was not noticed")
+            .unwrap();
+    }
+
+    /// An encoder counting the events it encoded.
+    #[derive(Clone)]
+    struct CountingEncoder(Arc<AtomicUsize>);
+
+    impl tokio_util::codec::Encoder<Event> for CountingEncoder {
+        type Error = codecs::encoding::Error;
+
+        fn encode(&mut self, _event: Event, _buffer: &mut BytesMut) -> Result<(), Self::Error> {
+            self.0.fetch_add(1, Ordering::Relaxed);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn unix_sink_encodes_once_connected() {
+        let path = temp_uds_path("not_listening_yet");
+        let encoded = Arc::new(AtomicUsize::new(0));
+        let sink = UnixSink::new(
+            UnixConnector::new(path.clone(), None),
+            Default::default(),
+            CountingEncoder(Arc::clone(&encoded)),
+        );
+
+        let (tx, rx) = futures::channel::mpsc::unbounded();
+        let sink = tokio::spawn(Box::new(sink).run(rx.boxed()));
+        tx.unbounded_send(Event::Log(LogEvent::from("waiting")))
+            .unwrap();
+
+        // The event waits for the connection without being encoded.
+        sleep(Duration::from_millis(500)).await;
+        assert_eq!(encoded.load(Ordering::Relaxed), 0);
+
+        let listener = UnixListener::bind(&path).unwrap();
+        let (_stream, _) = listener.accept().await.unwrap();
+        drop(tx);
+        sink.await.unwrap().unwrap();
+        assert_eq!(encoded.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn unix_sink_flushes_pending_writes_on_shutdown() {
+        let path = temp_uds_path("slow_reader");
+        let listener = UnixListener::bind(&path).unwrap();
+
+        let mut config = UnixSinkConfig::new(path);
+        config.shutdown_timeout_secs = Some(30);
+        let (sink, _healthcheck) = config
+            .build(
+                Default::default(),
+                Encoder::<Framer>::new(
+                    NewlineDelimitedEncoder::new().into(),
+                    TextSerializerConfig::default().build().into(),
+                ),
+            )
+            .unwrap();
+
+        // More than fits in the socket buffers, so writes are still pending when the input ends.
+        let (lines, events) = random_lines_with_stream(1000, 1000, None);
+        let sink = tokio::spawn(sink.run(events));
+        let (mut stream, _) = listener.accept().await.unwrap();
+
+        sleep(Duration::from_millis(500)).await;
+        let mut received = String::new();
+        tokio::io::AsyncReadExt::read_to_string(&mut stream, &mut received)
+            .await
+            .unwrap();
+        sink.await.unwrap().unwrap();
+
+        assert_eq!(received.lines().collect::<Vec<_>>(), lines);
+    }
+
+    #[tokio::test]
+    async fn unix_sink_drops_connection_after_shutdown_timeout() {
+        let path = temp_uds_path("never_reads");
+        let listener = UnixListener::bind(&path).unwrap();
+
+        let mut config = UnixSinkConfig::new(path);
+        config.shutdown_timeout_secs = Some(1);
+        let (sink, _healthcheck) = config
+            .build(
+                Default::default(),
+                Encoder::<Framer>::new(
+                    NewlineDelimitedEncoder::new().into(),
+                    TextSerializerConfig::default().build().into(),
+                ),
+            )
+            .unwrap();
+
+        // A single event that doesn't fit in the socket buffers, so the input ends right away and
+        // only flushing it is left.
+        let (_, events) = random_lines_with_stream(4 << 20, 1, None);
+        let sink = tokio::spawn(sink.run(events));
+        let (_stream, _) = listener.accept().await.unwrap();
+
+        timeout(Duration::from_secs(10), sink)
+            .await
+            .expect("sink didn't give up flushing")
+            .unwrap()
+            .unwrap();
+    }
+
     #[tokio::test]
     async fn basic_unix_sink() {
         let num_lines = 1000;
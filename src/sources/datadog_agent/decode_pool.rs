@@ -0,0 +1,129 @@
+use std::{
+    num::NonZeroUsize,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use http::StatusCode;
+use tokio::sync::Semaphore;
+use tracing::Span;
+
+use super::{reject, RejectionReason};
+use crate::sources::util::ErrorMessage;
+
+/// Runs the CPU-heavy decoding of request bodies off the runtime's core threads.
+///
+/// Decompressing and parsing a large payload can take long enough to delay accepting other
+/// connections, so the work is run on blocking threads, at most `workers` at a time. Requests
+/// waiting for a worker are queued, up to `max_queued`, beyond which they're rejected with a 429
+/// status instead of being buffered.
+pub(crate) struct DecodePool {
+    workers: Arc<Semaphore>,
+    /// The maximum number of requests decoding or waiting for a worker.
+    capacity: usize,
+    /// The number of requests decoding or waiting for a worker.
+    pending: AtomicUsize,
+}
+
+/// Releases the slot of a request in the pool once it's done.
+struct PendingSlot<'a>(&'a AtomicUsize);
+
+impl Drop for PendingSlot<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+impl DecodePool {
+    pub(crate) fn new(workers: NonZeroUsize, max_queued: usize) -> Self {
+        Self {
+            workers: Arc::new(Semaphore::new(workers.get())),
+            capacity: workers.get() + max_queued,
+            pending: AtomicUsize::new(0),
+        }
+    }
+
+    /// Runs `work` on a worker once one is free, rejecting the request to `endpoint` if the queue
+    /// is full.
+    pub(crate) async fn run<T, F>(&self, endpoint: &'static str, work: F) -> Result<T, ErrorMessage>
+    where
+        F: FnOnce() -> Result<T, ErrorMessage> + Send + 'static,
+        T: Send + 'static,
+    {
+        if self.pending.fetch_add(1, Ordering::AcqRel) >= self.capacity {
+            self.pending.fetch_sub(1, Ordering::AcqRel);
+            return Err(reject(
+                endpoint,
+                RejectionReason::Overloaded,
+                StatusCode::TOO_MANY_REQUESTS,
+                "Too many requests are waiting to be decoded".to_string(),
+            ));
+        }
+        let _slot = PendingSlot(&self.pending);
+
+        let permit = Arc::clone(&self.workers)
+            .acquire_owned()
+            .await
+            .expect("the semaphore is never closed");
+        // The work emits the component's internal events, which are labeled from its span.
+        let span = Span::current();
+        tokio::task::spawn_blocking(move || {
+            let _enter = span.enter();
+            // The worker stays busy until the work is done, even if the request is dropped.
+            let _permit = permit;
+            work()
+        })
+        .await
+        .map_err(|error| {
+            ErrorMessage::new(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed decoding request: {}", error),
+            )
+        })?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::mpsc, time::Duration};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn rejects_requests_beyond_the_queue() {
+        let pool = Arc::new(DecodePool::new(NonZeroUsize::new(1).unwrap(), 1));
+        let (release, blocked) = mpsc::channel::<()>();
+        let blocked = Arc::new(std::sync::Mutex::new(blocked));
+
+        // The first request occupies the only worker, and the second one waits for it.
+        let mut running = Vec::new();
+        for _ in 0..2 {
+            let pool = Arc::clone(&pool);
+            let blocked = Arc::clone(&blocked);
+            running.push(tokio::spawn(async move {
+                pool.run("logs", move || {
+                    blocked.lock().unwrap().recv().unwrap();
+                    Ok(())
+                })
+                .await
+            }));
+        }
+        while pool.pending.load(Ordering::Acquire) < 2 {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+
+        let error = pool.run("logs", || Ok(())).await.unwrap_err();
+        assert_eq!(error.status_code(), StatusCode::TOO_MANY_REQUESTS);
+
+        release.send(()).unwrap();
+        release.send(()).unwrap();
+        for request in running {
+            request.await.unwrap().unwrap();
+        }
+        // Once drained, requests are accepted again.
+        pool.run("logs", || Ok(())).await.unwrap();
+        assert_eq!(pool.pending.load(Ordering::Acquire), 0);
+    }
+}
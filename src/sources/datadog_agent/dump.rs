@@ -0,0 +1,141 @@
+use std::{
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use bytes::Bytes;
+use chrono::Utc;
+use http::HeaderMap;
+use serde_json::json;
+
+use super::RejectionReason;
+use crate::{
+    internal_events::{DatadogAgentFailedRequestDumpError, DatadogAgentFailedRequestDumped},
+    sources::util::ErrorMessage,
+};
+
+/// The window over which `max_dumps_per_minute` applies.
+const DUMP_WINDOW: Duration = Duration::from_secs(60);
+
+/// Writes the bodies of requests that failed to decode to a directory, to help debug encoding
+/// issues on the agent side.
+///
+/// Each dump is a pair of files sharing a unique name: the body, possibly truncated, with a `.body`
+/// extension, and a JSON sidecar with a `.json` extension describing the request.
+pub(crate) struct FailedRequestDumper {
+    directory: PathBuf,
+    max_bytes: usize,
+    max_per_minute: u32,
+    /// The start of the current window, and the number of dumps written in it.
+    window: Mutex<(Instant, u32)>,
+    sequence: AtomicU64,
+}
+
+/// The reasons of the rejections caused by the content of the body.
+const DECODE_FAILURES: [RejectionReason; 3] = [
+    RejectionReason::Decompression,
+    RejectionReason::JsonParse,
+    RejectionReason::MessageDecode,
+];
+
+fn is_decode_failure(error: &ErrorMessage) -> bool {
+    error.reason().map_or(false, |reason| {
+        DECODE_FAILURES
+            .iter()
+            .any(|failure| failure.as_str() == reason)
+    })
+}
+
+impl FailedRequestDumper {
+    pub(crate) fn new(directory: PathBuf, max_bytes: usize, max_per_minute: u32) -> Self {
+        Self {
+            directory,
+            max_bytes,
+            max_per_minute,
+            window: Mutex::new((Instant::now(), 0)),
+            sequence: AtomicU64::new(0),
+        }
+    }
+
+    /// Writes `body` and the details of the request in the background, if `error` is a failure to
+    /// decode the body.
+    ///
+    /// Other failures, such as the request being rejected as the decode pool is overloaded, say
+    /// nothing about the body, so it isn't dumped. Failing to write the dump is only reported, so
+    /// that it doesn't change the response to the request.
+    pub(crate) fn dump(&self, body: &Bytes, headers: &HeaderMap, path: &str, error: &ErrorMessage) {
+        if !is_decode_failure(error) || !self.acquire() {
+            debug!(
+                message = "Skipped dumping failed request, too many dumps in the last minute.",
+                internal_log_rate_limit = true
+            );
+            return;
+        }
+
+        let name = format!(
+            "{}-{}",
+            Utc::now().format("%Y%m%dT%H%M%S%.6fZ"),
+            self.sequence.fetch_add(1, Ordering::Relaxed)
+        );
+        let body_path = self.directory.join(format!("{}.body", name));
+        let sidecar_path = self.directory.join(format!("{}.json", name));
+
+        let truncated = body.len() > self.max_bytes;
+        let sidecar = json!({
+            "path": path,
+            "headers": headers
+                .iter()
+                .map(|(name, value)| {
+                    let value = if name == "dd-api-key" {
+                        "[REDACTED]".into()
+                    } else {
+                        String::from_utf8_lossy(value.as_bytes()).into_owned()
+                    };
+                    (name.as_str().to_owned(), value)
+                })
+                .collect::<serde_json::Map<_, _>>(),
+            "error": error.to_string(),
+            "body_bytes": body.len(),
+            "truncated": truncated,
+        });
+        let body = body.slice(..body.len().min(self.max_bytes));
+
+        tokio::spawn(async move {
+            // The sidecar is written last, so that its presence means the dump is complete.
+            let result = async {
+                tokio::fs::write(&body_path, &body).await?;
+                let sidecar = serde_json::to_vec_pretty(&sidecar)?;
+                tokio::fs::write(&sidecar_path, sidecar).await
+            }
+            .await;
+            match result {
+                Ok(()) => emit!(DatadogAgentFailedRequestDumped {
+                    path: &body_path,
+                    byte_size: body.len(),
+                }),
+                Err(error) => emit!(DatadogAgentFailedRequestDumpError {
+                    path: &body_path,
+                    error,
+                }),
+            }
+        });
+    }
+
+    /// Counts a dump against `max_dumps_per_minute`, returning whether it's allowed.
+    fn acquire(&self) -> bool {
+        let mut window = self.window.lock().expect("mutex poisoned");
+        let now = Instant::now();
+        if now.duration_since(window.0) >= DUMP_WINDOW {
+            *window = (now, 0);
+        }
+        if window.1 >= self.max_per_minute {
+            return false;
+        }
+        window.1 += 1;
+        true
+    }
+}
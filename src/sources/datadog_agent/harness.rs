@@ -0,0 +1,374 @@
+//! Builds requests as sent by the Datadog agent, and runs them through the `datadog_agent` source.
+//!
+//! [`AgentLogPayload`] builds the body and headers of a logs request, and [`AgentHarness`] serves a
+//! source built from a given config, posts payloads to it, and captures both the response and the
+//! events sent to each output. Requests to the other endpoints are posted as raw bodies.
+
+use std::{collections::HashMap, io::Write, net::SocketAddr};
+
+use bytes::Bytes;
+use chrono::{TimeZone, Utc};
+use flate2::{
+    write::{GzEncoder, ZlibEncoder},
+    Compression,
+};
+use futures::{stream::BoxStream, FutureExt, Stream, StreamExt};
+use http::{HeaderMap, HeaderName, HeaderValue, StatusCode};
+use vector_core::config::LogNamespace;
+
+use super::{DatadogAgentConfig, LogMsg, LOGS};
+use crate::{
+    config::{SourceConfig, SourceContext},
+    event::{into_event_stream, Event, EventStatus},
+    schema,
+    test_util::{next_addr, trace_init, wait_for_tcp},
+    SourceSender,
+};
+
+/// A log with the attributes set by default on the logs of an [`AgentLogPayload`].
+pub(super) fn test_log_msg(message: &str) -> LogMsg {
+    LogMsg {
+        message: Bytes::from(message.to_owned()),
+        timestamp: Utc
+            .timestamp_opt(123, 0)
+            .single()
+            .expect("invalid timestamp"),
+        hostname: Bytes::from("festeburg"),
+        status: Bytes::from("notice"),
+        service: Bytes::from("vector"),
+        ddsource: Bytes::from("curl"),
+        ddtags: Bytes::from("one,two,three"),
+    }
+}
+
+/// How the body of a payload is compressed.
+#[derive(Clone, Copy, Debug)]
+enum PayloadCompression {
+    Gzip,
+    Deflate,
+}
+
+/// A logs request, as sent by the agent.
+///
+/// Each call to [`AgentLogPayload::message`] adds a log with the attributes of [`test_log_msg`],
+/// which the other attribute setters then change.
+#[derive(Clone, Debug)]
+pub(super) struct AgentLogPayload {
+    logs: Vec<LogMsg>,
+    raw_body: Option<Bytes>,
+    compression: Option<PayloadCompression>,
+    method: reqwest::Method,
+    path: String,
+    query: Option<String>,
+    headers: HeaderMap,
+}
+
+impl AgentLogPayload {
+    pub(super) fn new() -> Self {
+        Self {
+            logs: Vec::new(),
+            raw_body: None,
+            compression: None,
+            method: reqwest::Method::POST,
+            path: "/api/v2/logs".to_owned(),
+            query: None,
+            headers: HeaderMap::new(),
+        }
+    }
+
+    /// Adds a log with the given message.
+    pub(super) fn message(mut self, message: &str) -> Self {
+        self.logs.push(test_log_msg(message));
+        self
+    }
+
+    pub(super) fn ddtags(self, ddtags: &str) -> Self {
+        self.with_last(|log| log.ddtags = Bytes::from(ddtags.to_owned()))
+    }
+
+    pub(super) fn service(self, service: &str) -> Self {
+        self.with_last(|log| log.service = Bytes::from(service.to_owned()))
+    }
+
+    pub(super) fn ddsource(self, ddsource: &str) -> Self {
+        self.with_last(|log| log.ddsource = Bytes::from(ddsource.to_owned()))
+    }
+
+    /// Sends `body` as is instead of the logs, such as to send an invalid payload.
+    pub(super) fn raw_body(mut self, body: impl Into<Bytes>) -> Self {
+        self.raw_body = Some(body.into());
+        self
+    }
+
+    pub(super) fn compress_gzip(self) -> Self {
+        self.compress(Some(PayloadCompression::Gzip))
+    }
+
+    pub(super) fn compress_deflate(self) -> Self {
+        self.compress(Some(PayloadCompression::Deflate))
+    }
+
+    fn compress(mut self, compression: Option<PayloadCompression>) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    pub(super) fn api_key_in_header(self, api_key: &str) -> Self {
+        self.header("dd-api-key", api_key)
+    }
+
+    pub(super) fn api_key_in_query(mut self, api_key: &str) -> Self {
+        self.query = Some(format!("dd-api-key={}", api_key));
+        self
+    }
+
+    /// Sends the request to the path used by legacy agents, without an API key.
+    pub(super) fn legacy_path(mut self) -> Self {
+        self.path = "/v1/input/".to_owned();
+        self
+    }
+
+    /// Sends the request to the path used by legacy agents, which embeds the API key.
+    pub(super) fn api_key_in_path(mut self, api_key: &str) -> Self {
+        self.path = format!("/v1/input/{}", api_key);
+        self
+    }
+
+    /// Sends the request with `method` rather than `POST`.
+    pub(super) fn method(mut self, method: reqwest::Method) -> Self {
+        self.method = method;
+        self
+    }
+
+    pub(super) fn header(mut self, name: &'static str, value: &str) -> Self {
+        self.headers.insert(
+            HeaderName::from_static(name),
+            HeaderValue::from_str(value).expect("invalid header value"),
+        );
+        self
+    }
+
+    /// The body of the request, compressed if configured to.
+    fn body(&self) -> Bytes {
+        let body = match &self.raw_body {
+            Some(body) => body.clone(),
+            None => serde_json::to_vec(&self.logs)
+                .expect("logs always serialize")
+                .into(),
+        };
+        match self.compression {
+            None => body,
+            Some(PayloadCompression::Gzip) => {
+                let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(&body).unwrap();
+                encoder.finish().unwrap().into()
+            }
+            Some(PayloadCompression::Deflate) => {
+                let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(&body).unwrap();
+                encoder.finish().unwrap().into()
+            }
+        }
+    }
+
+    /// The headers of the request, including its `Content-Encoding`.
+    fn headers(&self) -> HeaderMap {
+        let mut headers = self.headers.clone();
+        let encoding = match self.compression {
+            None => return headers,
+            Some(PayloadCompression::Gzip) => "gzip",
+            Some(PayloadCompression::Deflate) => "deflate",
+        };
+        headers.insert(
+            http::header::CONTENT_ENCODING,
+            HeaderValue::from_static(encoding),
+        );
+        headers
+    }
+
+    fn with_last(mut self, update: impl FnOnce(&mut LogMsg)) -> Self {
+        if self.logs.is_empty() {
+            self.logs.push(test_log_msg("foo"));
+        }
+        update(self.logs.last_mut().expect("a log was just added"));
+        self
+    }
+}
+
+/// The outcome of a request sent through an [`AgentHarness`].
+pub(super) struct AgentResponse {
+    pub(super) status: StatusCode,
+    pub(super) headers: HeaderMap,
+    pub(super) body: Bytes,
+    /// The events sent by the source, along with the name of their output.
+    pub(super) events: Vec<(Option<String>, Event)>,
+}
+
+impl AgentResponse {
+    /// Returns the only event of a successful request.
+    pub(super) fn single_event(mut self) -> Event {
+        assert_eq!(self.status, StatusCode::OK, "{:?}", self.body);
+        assert_eq!(self.events.len(), 1, "{:?}", self.events);
+        self.events.remove(0).1
+    }
+
+    /// Returns the events of a successful request, whatever their output.
+    pub(super) fn into_events(self) -> Vec<Event> {
+        assert_eq!(self.status, StatusCode::OK, "{:?}", self.body);
+        self.events.into_iter().map(|(_, event)| event).collect()
+    }
+
+    /// Parses the body of the response as JSON.
+    pub(super) fn json(&self) -> serde_json::Value {
+        serde_json::from_slice(&self.body).expect("response body is not JSON")
+    }
+}
+
+/// A running `datadog_agent` source, along with the events it sent.
+pub(super) struct AgentHarness {
+    address: SocketAddr,
+    events: BoxStream<'static, (Option<String>, Event)>,
+    client: reqwest::Client,
+    logs_schema_definition: Option<schema::Definition>,
+}
+
+impl AgentHarness {
+    /// Builds the source from `config`, which doesn't need to include the `address`, and serves it.
+    ///
+    /// Events sent to each output are captured, and marked as delivered.
+    pub(super) async fn start(config: &str) -> Self {
+        Self::start_with_status(config, EventStatus::Delivered).await
+    }
+
+    /// Like [`AgentHarness::start`], but marks the captured events with `status`.
+    pub(super) async fn start_with_status(config: &str, status: EventStatus) -> Self {
+        let (sender, default_events) = SourceSender::new_test_finalize(status);
+        Self::serve(config, sender, default_events, status).await
+    }
+
+    /// Like [`AgentHarness::start`], but sends the events of the default output through `sender`,
+    /// capturing them from `default_events`, such as to leave them unacknowledged.
+    pub(super) async fn start_with_sender(
+        config: &str,
+        sender: SourceSender,
+        default_events: impl Stream<Item = Event> + Send + 'static,
+    ) -> Self {
+        Self::serve(config, sender, default_events, EventStatus::Delivered).await
+    }
+
+    /// Serves the source, marking the events sent to its named outputs with `status`.
+    async fn serve(
+        config: &str,
+        mut sender: SourceSender,
+        default_events: impl Stream<Item = Event> + Send + 'static,
+        status: EventStatus,
+    ) -> Self {
+        trace_init();
+        let address = next_addr();
+        let config =
+            toml::from_str::<DatadogAgentConfig>(&format!("address = \"{}\"\n{}", address, config))
+                .unwrap();
+
+        let mut events = default_events.map(|event| (None, event)).boxed();
+        let mut schema_definitions = HashMap::new();
+        for output in config.outputs(LogNamespace::Legacy) {
+            if let Some(definition) = output.schema_definition(true) {
+                schema_definitions.insert(output.port.clone(), definition);
+            }
+            if let Some(port) = output.port {
+                let named = sender
+                    .add_outputs(status, port.clone())
+                    .flat_map(into_event_stream)
+                    .map(move |event| (Some(port.clone()), event));
+                events = futures::stream::select(events, named).boxed();
+            }
+        }
+
+        let logs_schema_definition = schema_definitions
+            .get(&Some(LOGS.to_owned()))
+            .or_else(|| schema_definitions.get(&None))
+            .cloned();
+
+        let context = SourceContext::new_test(sender, Some(schema_definitions));
+        tokio::spawn(async move {
+            config.build(context).await.unwrap().await.unwrap();
+        });
+        wait_for_tcp(address).await;
+
+        Self {
+            address,
+            events,
+            client: reqwest::Client::new(),
+            logs_schema_definition,
+        }
+    }
+
+    /// The address the source listens on, for requests that a payload can't describe.
+    pub(super) const fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// The schema definition the source attaches to the logs of its default logs output.
+    pub(super) fn logs_schema_definition(&self) -> &schema::Definition {
+        self.logs_schema_definition
+            .as_ref()
+            .expect("the logs output has a schema definition")
+    }
+
+    /// Sends `payload`, returning the response along with the events sent while handling it.
+    pub(super) async fn send(&mut self, payload: AgentLogPayload) -> AgentResponse {
+        let mut url = format!("http://{}{}", self.address, payload.path);
+        if let Some(query) = &payload.query {
+            url = format!("{}?{}", url, query);
+        }
+        let request = self
+            .client
+            .request(payload.method.clone(), &url)
+            .headers(payload.headers())
+            .body(payload.body());
+        self.request(request).await
+    }
+
+    /// Posts `body` as is to `path`, such as to send metrics or traces.
+    pub(super) async fn post(
+        &mut self,
+        path: &str,
+        headers: HeaderMap,
+        body: impl Into<Bytes>,
+    ) -> AgentResponse {
+        let request = self
+            .client
+            .post(&format!("http://{}{}", self.address, path))
+            .headers(headers)
+            .body(body.into());
+        self.request(request).await
+    }
+
+    async fn request(&mut self, request: reqwest::RequestBuilder) -> AgentResponse {
+        // Events are only acknowledged once taken from the source, which the response may wait for.
+        let mut events = Vec::new();
+        let response = request.send();
+        tokio::pin!(response);
+        let response = loop {
+            tokio::select! {
+                response = &mut response => break response.unwrap(),
+                Some(event) = self.events.next() => events.push(event),
+            }
+        };
+        let status = response.status();
+        let headers = response.headers().clone();
+        let body = response.bytes().await.unwrap();
+
+        // Events are sent before the request is answered, so they're all ready by now.
+        while let Some(Some(event)) = self.events.next().now_or_never() {
+            events.push(event);
+        }
+
+        AgentResponse {
+            status,
+            headers,
+            body,
+            events,
+        }
+    }
+}
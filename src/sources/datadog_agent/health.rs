@@ -0,0 +1,92 @@
+use std::{
+    num::NonZeroU32,
+    sync::{Arc, Mutex},
+};
+
+use http::StatusCode;
+use serde::Serialize;
+use warp::{filters::BoxedFilter, reply::Response, Filter, Reply};
+
+/// The path of the health route.
+pub(crate) const PATH: &str = "health";
+
+/// Whether the source can currently deliver events, as reported by the health route.
+///
+/// This tracks the outcome of the most recent attempts to deliver the events of a request: the
+/// source is unhealthy once `failure_threshold` attempts in a row failed, until one succeeds, or
+/// for good once shutdown has begun.
+pub(crate) struct DeliveryHealth {
+    failure_threshold: NonZeroU32,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    consecutive_failures: u32,
+    last_error: Option<String>,
+    shutting_down: bool,
+}
+
+/// The body of the responses of the health route.
+#[derive(Serialize)]
+struct HealthResponse<'a> {
+    state: &'static str,
+    consecutive_failures: u32,
+    last_error: Option<&'a str>,
+}
+
+impl DeliveryHealth {
+    pub(crate) fn new(failure_threshold: NonZeroU32) -> Self {
+        Self {
+            failure_threshold,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    pub(crate) fn record_success(&self) {
+        self.state
+            .lock()
+            .expect("mutex poisoned")
+            .consecutive_failures = 0;
+    }
+
+    pub(crate) fn record_failure(&self, error: &str) {
+        let mut state = self.state.lock().expect("mutex poisoned");
+        state.consecutive_failures = state.consecutive_failures.saturating_add(1);
+        state.last_error = Some(error.to_owned());
+    }
+
+    pub(crate) fn begin_shutdown(&self) {
+        self.state.lock().expect("mutex poisoned").shutting_down = true;
+    }
+
+    fn response(&self) -> Response {
+        let state = self.state.lock().expect("mutex poisoned");
+        let (status, name) = if state.shutting_down {
+            (StatusCode::SERVICE_UNAVAILABLE, "shutting_down")
+        } else if state.consecutive_failures >= self.failure_threshold.get() {
+            (StatusCode::SERVICE_UNAVAILABLE, "unhealthy")
+        } else {
+            (StatusCode::OK, "healthy")
+        };
+        let body = HealthResponse {
+            state: name,
+            consecutive_failures: state.consecutive_failures,
+            last_error: state.last_error.as_deref(),
+        };
+        warp::reply::with_status(warp::reply::json(&body), status).into_response()
+    }
+}
+
+/// Builds the `GET /health` route, meant for liveness and readiness probes.
+///
+/// The route doesn't require an API key, and requests to it are neither decoded nor counted as
+/// received data.
+pub(crate) fn build_warp_filter(health: Arc<DeliveryHealth>) -> BoxedFilter<(Response,)> {
+    // The path is matched first, so that other routes aren't rejected with a method error.
+    warp::path(PATH)
+        .and(warp::path::end())
+        .and(warp::get())
+        .map(move || health.response())
+        .boxed()
+}
@@ -1,182 +1,764 @@
-use std::sync::Arc;
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
 
-use bytes::{BufMut, Bytes, BytesMut};
+use bytes::{Bytes, BytesMut};
 use chrono::Utc;
+use cidr_utils::cidr::IpCidr;
 use codecs::StreamDecodingError;
-use http::StatusCode;
-use lookup::path;
+use http::{uri::Authority, HeaderMap, Method, StatusCode};
+use lookup::{event_path, metadata_path, path};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
 use tokio_util::codec::Decoder;
 use vector_common::internal_event::{CountByteSize, InternalEventHandle as _};
-use vector_core::{config::LegacyKey, EstimatedJsonEncodedSizeOf};
-use warp::{filters::BoxedFilter, path as warp_path, path::FullPath, reply::Response, Filter};
+use vector_core::{
+    config::{LegacyKey, LogNamespace},
+    EstimatedJsonEncodedSizeOf,
+};
+use warp::{
+    cors::Builder, filters::BoxedFilter, path as warp_path, reject::Rejection, reply::Response,
+    Filter, Reply,
+};
 
 use crate::{
-    event::Event,
+    event::{Event, LogEvent, Value},
+    internal_events::{
+        DatadogAgentMessageDecodeDropped, DatadogAgentMessageDecodeError,
+        DatadogAgentOversizedMessage, DatadogAgentPayloadDecoded,
+    },
+    schema,
     sources::{
         datadog_agent::{
-            handle_request, ApiKeyQueryParams, DatadogAgentConfig, DatadogAgentSource, LogMsg,
+            handle_routed_request, reject,
+            request::{decoded_request, DecodedRequest},
+            DatadogAgentConfig, DatadogAgentSource, DecodeErrorAction, LogMsg,
+            OversizedMessageAction, PromoteConflict, RejectionReason, ReservedField,
+            ServiceApiKeyPrecedence, LOGS,
         },
         util::ErrorMessage,
     },
     SourceSender,
 };
 
+/// The path prefixes of the logs routes.
+pub(crate) const PATHS: &[&str] = &["/v1/input", "/api/v2/logs"];
+
+/// The methods accepted on the logs routes.
+const ALLOWED_METHODS: [Method; 2] = [Method::POST, Method::PUT];
+
 pub(crate) fn build_warp_filter(
     acknowledgements: bool,
     multiple_outputs: bool,
     out: SourceSender,
     source: DatadogAgentSource,
 ) -> BoxedFilter<(Response,)> {
-    warp::post()
-        .and(warp_path!("v1" / "input" / ..).or(warp_path!("api" / "v2" / "logs" / ..)))
-        .and(warp::path::full())
-        .and(warp::header::optional::<String>("content-encoding"))
-        .and(warp::header::optional::<String>("dd-api-key"))
-        .and(warp::query::<ApiKeyQueryParams>())
-        .and(warp::body::bytes())
+    let routes = warp_path!("v1" / "input" / ..)
+        .or(warp_path!("api" / "v2" / "logs" / ..))
+        .unify();
+    let cors = source.logs_cors.clone();
+
+    let source = Arc::new(source);
+    let ingest = warp::post()
+        .or(warp::put())
+        .unify()
+        .and(decoded_request(
+            source.api_key_extractor.clone(),
+            LOGS,
+            source.max_body_bytes,
+        ))
+        .and(request_metadata())
+        .and(warp::header::optional::<String>("x-datadog-origin"))
+        .and(warp::header::headers_cloned())
         .and_then(
-            move |_,
-                  path: FullPath,
-                  encoding_header: Option<String>,
-                  api_token: Option<String>,
-                  query_params: ApiKeyQueryParams,
-                  body: Bytes| {
-                let events = source
-                    .decode(&encoding_header, body, path.as_str())
-                    .and_then(|body| {
-                        decode_log_body(
-                            body,
-                            source.api_key_extractor.extract(
-                                path.as_str(),
-                                api_token,
-                                query_params.dd_api_key,
-                            ),
-                            &source,
-                        )
-                    });
-
-                let output = multiple_outputs.then_some(super::LOGS);
-                handle_request(events, acknowledgements, out.clone(), output)
+            move |request: Result<DecodedRequest, ErrorMessage>,
+                  request_metadata: RequestMetadataHeaders,
+                  origin: Option<String>,
+                  headers: HeaderMap| {
+                let source = Arc::clone(&source);
+                let out = out.clone();
+                async move {
+                    // The logs are routed by their API key even if it isn't stored with them.
+                    let routing_key = request
+                        .as_ref()
+                        .ok()
+                        .filter(|_| !source.api_key_outputs.is_empty())
+                        .and_then(|request| request.api_key.clone());
+                    let decoded = match request {
+                        Ok(request) => {
+                            let api_key = request.stored_api_key(&source.api_key_extractor);
+                            let request_metadata =
+                                request_metadata.into_metadata(request.remote_addr, &source);
+                            let (body, path) = (request.body.clone(), request.path.clone());
+                            // Decompressing and parsing the body is offloaded to the decode pool,
+                            // keeping the handler itself cheap.
+                            let decode = {
+                                let source = Arc::clone(&source);
+                                move || {
+                                    decode_log_body(
+                                        request.decompress(&source, LOGS)?,
+                                        api_key,
+                                        request.is_compressed(),
+                                        request_metadata.as_ref(),
+                                        origin.as_deref(),
+                                        &source,
+                                    )
+                                }
+                            };
+                            let decoded = source.decode_pool.run(LOGS, decode).await;
+                            if let (Err(error), Some(dumper)) =
+                                (&decoded, &source.failed_request_dumper)
+                            {
+                                let path = source.api_key_extractor.redact_path(path.as_str());
+                                dumper.dump(&body, &headers, &path, error);
+                            }
+                            decoded
+                        }
+                        Err(error) => Err(error),
+                    };
+                    let counts = decoded
+                        .as_ref()
+                        .ok()
+                        .filter(|_| source.verbose_responses)
+                        .map(|decoded| (decoded.accepted, decoded.rejected));
+                    let events = decoded.map(|decoded| decoded.events);
+
+                    let output = multiple_outputs.then_some(LOGS);
+                    let ddsource_outputs = Arc::clone(&source.ddsource_outputs);
+                    let api_key_output = routing_key
+                        .and_then(|api_key| source.api_key_outputs.get(&api_key).cloned());
+                    let log_namespace = source.log_namespace;
+                    let response = handle_routed_request(
+                        events,
+                        acknowledgements,
+                        source.acknowledgement_timeout,
+                        Arc::clone(&source.health),
+                        out,
+                        output,
+                        move |event| {
+                            route_by_ddsource(event, log_namespace, &ddsource_outputs)
+                                .or_else(|| Some(api_key_output.as_ref()?.route(event)))
+                        },
+                    )
+                    .await?;
+                    Ok::<_, Rejection>(match counts {
+                        Some((accepted, rejected)) if response.status() == StatusCode::OK => {
+                            verbose_response(accepted, rejected)
+                        }
+                        _ => response,
+                    })
+                }
+            },
+        );
+    let filter = ingest.or(method_not_allowed()).unify();
+
+    match cors {
+        Some(cors) => routes.and(filter.with(cors)).map(into_response).boxed(),
+        None => routes.and(filter).boxed(),
+    }
+}
+
+/// The body of successful responses when `verbose_responses` is enabled.
+#[derive(Serialize)]
+struct VerboseResponse {
+    accepted: usize,
+    rejected: usize,
+}
+
+/// Builds a successful response counting the `accepted` and `rejected` log messages.
+fn verbose_response(accepted: usize, rejected: usize) -> Response {
+    warp::reply::json(&VerboseResponse { accepted, rejected }).into_response()
+}
+
+/// Builds the CORS policy for the logs routes, if any origins are allowed.
+///
+/// An origin of `*` allows any origin.
+pub(crate) fn build_cors(allowed_origins: &[String]) -> crate::Result<Option<Builder>> {
+    if allowed_origins.is_empty() {
+        return Ok(None);
+    }
+
+    let cors = warp::cors().allow_methods(ALLOWED_METHODS).allow_headers([
+        "content-type",
+        "content-encoding",
+        "dd-api-key",
+    ]);
+    if allowed_origins.iter().any(|origin| origin == "*") {
+        return Ok(Some(cors.allow_any_origin()));
+    }
+
+    for origin in allowed_origins {
+        let valid = origin
+            .split_once("://")
+            .map_or(false, |(scheme, authority)| {
+                !scheme.is_empty() && authority.parse::<Authority>().is_ok()
+            });
+        if !valid {
+            return Err(format!(
+                "Invalid CORS origin {:?}, expected `scheme://host[:port]`",
+                origin
+            )
+            .into());
+        }
+    }
+    Ok(Some(
+        cors.allow_origins(allowed_origins.iter().map(String::as_str)),
+    ))
+}
+
+/// Rejects requests using a method other than the ones in [`ALLOWED_METHODS`] with the same JSON
+/// error body as any other failed request.
+fn method_not_allowed() -> impl Filter<Extract = (Response,), Error = Rejection> + Clone {
+    warp::method().and_then(|method: Method| async move {
+        let error = if ALLOWED_METHODS.contains(&method) {
+            warp::reject::not_found()
+        } else {
+            warn!(
+                message = "Rejected logs request with an unsupported method.",
+                %method,
+                internal_log_rate_limit = true
+            );
+            warp::reject::custom(ErrorMessage::new(
+                StatusCode::METHOD_NOT_ALLOWED,
+                format!(
+                    "Method {} not allowed, expected one of: {}",
+                    method,
+                    ALLOWED_METHODS.map(|method| method.to_string()).join(", ")
+                ),
+            ))
+        };
+        Err::<Response, _>(error)
+    })
+}
+
+/// An output dedicated to some of the logs, such as the ones of a given `ddsource`.
+#[derive(Clone, Debug)]
+pub(crate) struct RoutedOutput {
+    pub(crate) port: String,
+    pub(crate) schema_definition: Arc<schema::Definition>,
+}
+
+impl RoutedOutput {
+    /// Returns the name of the output, replacing the schema definition of `event` with its own.
+    fn route(&self, event: &mut Event) -> String {
+        event
+            .metadata_mut()
+            .set_schema_definition(&self.schema_definition);
+        self.port.clone()
+    }
+}
+
+/// The prefix of the SHA-256 digests of API keys in `api_key_routing`.
+const SHA256_PREFIX: &str = "sha256:";
+
+/// The outputs dedicated to the logs sent with given API keys.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ApiKeyOutputs {
+    keys: HashMap<String, RoutedOutput>,
+    /// The outputs of the API keys given by their hex-encoded SHA-256 digest.
+    digests: HashMap<String, RoutedOutput>,
+}
+
+impl ApiKeyOutputs {
+    pub(crate) fn new<'a>(
+        routing: impl Iterator<Item = (&'a str, RoutedOutput)>,
+    ) -> Result<Self, String> {
+        let mut outputs = Self::default();
+        for (api_key, output) in routing {
+            match api_key.strip_prefix(SHA256_PREFIX) {
+                Some(digest) => {
+                    if digest.len() != 64 || !digest.chars().all(|c| c.is_ascii_hexdigit()) {
+                        return Err(format!(
+                            "Invalid SHA-256 digest {:?} in `api_key_routing`, expected 64 hexadecimal characters",
+                            digest
+                        ));
+                    }
+                    outputs.digests.insert(digest.to_ascii_lowercase(), output);
+                }
+                None => {
+                    outputs.keys.insert(api_key.to_owned(), output);
+                }
+            }
+        }
+        Ok(outputs)
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.keys.is_empty() && self.digests.is_empty()
+    }
+
+    /// Returns the output dedicated to the logs sent with `api_key`, if any.
+    pub(crate) fn get(&self, api_key: &str) -> Option<&RoutedOutput> {
+        self.keys.get(api_key).or_else(|| {
+            if self.digests.is_empty() {
+                return None;
+            }
+            self.digests
+                .get(&hex::encode(Sha256::digest(api_key.as_bytes())))
+        })
+    }
+}
+
+/// Returns the dedicated output for the `ddsource` of `event`, if any.
+///
+/// The schema definition of a routed event is replaced with the one of its output.
+fn route_by_ddsource(
+    event: &mut Event,
+    log_namespace: LogNamespace,
+    ddsource_outputs: &HashMap<String, RoutedOutput>,
+) -> Option<String> {
+    if ddsource_outputs.is_empty() {
+        return None;
+    }
+
+    let log = match event {
+        Event::Log(log) => log,
+        _ => return None,
+    };
+    let ddsource = match log_namespace {
+        LogNamespace::Vector => log.get(metadata_path!(DatadogAgentConfig::NAME, "ddsource")),
+        LogNamespace::Legacy => log.get(event_path!("ddsource")),
+    }?;
+    let output = ddsource_outputs.get(String::from_utf8_lossy(ddsource.as_bytes()?).as_ref())?;
+
+    log.metadata_mut()
+        .set_schema_definition(&output.schema_definition);
+    Some(output.port.clone())
+}
+
+fn into_response<R: Reply>(reply: R) -> Response {
+    reply.into_response()
+}
+
+/// Details about the request that sent a batch of logs.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct RequestMetadata {
+    /// The address of the client, as reported by the trusted proxies in front of Vector.
+    pub(crate) remote_addr: Option<IpAddr>,
+    pub(crate) agent_version: Option<String>,
+    pub(crate) user_agent: Option<String>,
+}
+
+/// The headers [`RequestMetadata`] is read from.
+struct RequestMetadataHeaders {
+    forwarded_for: Option<String>,
+    agent_version: Option<String>,
+    user_agent: Option<String>,
+}
+
+impl RequestMetadataHeaders {
+    /// Builds the [`RequestMetadata`] of a request from `remote`, if `include_request_metadata` is
+    /// enabled.
+    fn into_metadata(
+        self,
+        remote: Option<SocketAddr>,
+        source: &DatadogAgentSource,
+    ) -> Option<RequestMetadata> {
+        source.include_request_metadata.then(|| RequestMetadata {
+            remote_addr: remote.map(|remote| {
+                client_addr(
+                    remote.ip(),
+                    self.forwarded_for.as_deref(),
+                    &source.trusted_proxies,
+                )
+            }),
+            agent_version: self.agent_version,
+            user_agent: self.user_agent,
+        })
+    }
+}
+
+fn request_metadata() -> impl Filter<Extract = (RequestMetadataHeaders,), Error = Rejection> + Clone
+{
+    warp::header::optional::<String>("x-forwarded-for")
+        .and(warp::header::optional::<String>("dd-agent-version"))
+        .and(warp::header::optional::<String>("user-agent"))
+        .map(
+            |forwarded_for: Option<String>,
+             agent_version: Option<String>,
+             user_agent: Option<String>| RequestMetadataHeaders {
+                forwarded_for,
+                agent_version,
+                user_agent,
             },
         )
-        .boxed()
+}
+
+/// Returns the address of the client that sent the request through `peer`.
+///
+/// Addresses in `X-Forwarded-For` are only considered when `peer` is a trusted proxy. The list
+/// is then walked from the most recent hop, and the first address that isn't a trusted proxy is
+/// the client.
+pub(crate) fn client_addr(
+    peer: IpAddr,
+    forwarded_for: Option<&str>,
+    trusted_proxies: &[IpCidr],
+) -> IpAddr {
+    let is_trusted = |addr: IpAddr| trusted_proxies.iter().any(|cidr| cidr.contains(addr));
+    if !is_trusted(peer) {
+        return peer;
+    }
+
+    let mut client = peer;
+    let hops = forwarded_for
+        .into_iter()
+        .flat_map(|header| header.rsplit(','))
+        .map(|hop| hop.trim().parse::<IpAddr>());
+    for hop in hops {
+        match hop {
+            Ok(addr) => {
+                client = addr;
+                if !is_trusted(addr) {
+                    break;
+                }
+            }
+            // Anything further down the list can't be trusted.
+            Err(_) => break,
+        }
+    }
+    client
 }
 
 pub(crate) fn decode_log_body(
     body: Bytes,
     api_key: Option<Arc<str>>,
+    compressed: bool,
+    request_metadata: Option<&RequestMetadata>,
+    origin: Option<&str>,
     source: &DatadogAgentSource,
-) -> Result<Vec<Event>, ErrorMessage> {
+) -> Result<DecodedLogs, ErrorMessage> {
     if body.is_empty() {
         // The datadog agent may send an empty payload as a keep alive
         debug!(
             message = "Empty payload ignored.",
             internal_log_rate_limit = true
         );
-        return Ok(Vec::new());
+        return Ok(DecodedLogs::default());
     }
 
     let messages: Vec<LogMsg> = serde_json::from_slice(&body).map_err(|error| {
-        ErrorMessage::new(
+        reject(
+            LOGS,
+            RejectionReason::JsonParse,
             StatusCode::BAD_REQUEST,
             format!("Error parsing JSON: {:?}", error),
         )
     })?;
 
+    let message_sizes = messages
+        .iter()
+        .map(|msg| msg.message.len())
+        .collect::<Vec<_>>();
+    emit!(DatadogAgentPayloadDecoded {
+        endpoint: LOGS,
+        compressed,
+        messages: messages.len(),
+        message_sizes: &message_sizes,
+    });
+
+    let messages = match &source.multiline {
+        Some(multiline) => multiline.apply(messages),
+        None => messages,
+    };
+
     let now = Utc::now();
     let mut decoded = Vec::new();
+    let (mut accepted, mut rejected) = (0, 0);
+    // When the framing passes messages through, they're deserialized as they are, otherwise
+    // they're framed from a buffer shared by all messages.
+    let mut decoder = source.decoder.clone();
+    let whole_messages = decoder.frames_whole_messages();
+    let mut buffer = BytesMut::new();
+    // The keys are read once for the whole request, which isn't affected by a reload in between.
+    let service_api_keys = source
+        .service_api_keys
+        .as_ref()
+        .map(|service_api_keys| service_api_keys.current());
 
     for LogMsg {
-        message,
+        mut message,
         status,
         timestamp,
         hostname,
-        service,
-        ddsource,
-        ddtags,
+        mut service,
+        mut ddsource,
+        mut ddtags,
     } in messages
     {
-        let mut decoder = source.decoder.clone();
-        let mut buffer = BytesMut::new();
-        buffer.put(message);
-        loop {
-            match decoder.decode_eof(&mut buffer) {
-                Ok(Some((events, _byte_size))) => {
-                    for mut event in events {
-                        if let Event::Log(ref mut log) = event {
-                            let namespace = &source.log_namespace;
-                            let source_name = "datadog_agent";
-
-                            namespace.insert_source_metadata(
-                                source_name,
-                                log,
-                                Some(LegacyKey::InsertIfEmpty(path!("status"))),
-                                path!("status"),
-                                status.clone(),
-                            );
-                            namespace.insert_source_metadata(
-                                source_name,
-                                log,
-                                Some(LegacyKey::InsertIfEmpty(path!("timestamp"))),
-                                path!("timestamp"),
-                                timestamp,
-                            );
-                            namespace.insert_source_metadata(
-                                source_name,
-                                log,
-                                Some(LegacyKey::InsertIfEmpty(path!("hostname"))),
-                                path!("hostname"),
-                                hostname.clone(),
-                            );
-                            namespace.insert_source_metadata(
-                                source_name,
-                                log,
-                                Some(LegacyKey::InsertIfEmpty(path!("service"))),
-                                path!("service"),
-                                service.clone(),
-                            );
-                            namespace.insert_source_metadata(
-                                source_name,
-                                log,
-                                Some(LegacyKey::InsertIfEmpty(path!("ddsource"))),
-                                path!("ddsource"),
-                                ddsource.clone(),
-                            );
-                            namespace.insert_source_metadata(
-                                source_name,
-                                log,
-                                Some(LegacyKey::InsertIfEmpty(path!("ddtags"))),
-                                path!("ddtags"),
-                                ddtags.clone(),
-                            );
-
-                            namespace.insert_standard_vector_source_metadata(
-                                log,
-                                DatadogAgentConfig::NAME,
-                                now,
-                            );
-
-                            if let Some(k) = &api_key {
-                                log.metadata_mut().set_datadog_api_key(Arc::clone(k));
-                            }
+        if let Some(origin) = origin.filter(|_| source.origin_as_tag) {
+            ddtags = tag_origin(&ddtags, origin);
+        }
+        let promoted_tags = source
+            .tag_promotion
+            .as_ref()
+            .map(|promotion| promotion.promote(&mut ddtags))
+            .unwrap_or_default();
 
-                            log.metadata_mut()
-                                .set_schema_definition(&source.logs_schema_definition);
-                        }
+        let (mut service_original, mut ddsource_original) = (None, None);
+        if source.normalize_service_names {
+            if let Some(lowercase) = to_lowercase(&service) {
+                service_original = Some(std::mem::replace(&mut service, lowercase));
+            }
+            if let Some(lowercase) = to_lowercase(&ddsource) {
+                ddsource_original = Some(std::mem::replace(&mut ddsource, lowercase));
+            }
+        }
+
+        let mut truncated = false;
+        let oversized = source
+            .oversized_messages
+            .as_ref()
+            .filter(|oversized| message.len() > oversized.max_bytes);
+        if let Some(oversized) = oversized {
+            emit!(DatadogAgentOversizedMessage {
+                byte_size: message.len(),
+                max_bytes: oversized.max_bytes,
+                action: oversized.action,
+            });
+            match oversized.action {
+                OversizedMessageAction::Truncate => {
+                    message = truncate_message(&message, oversized.max_bytes, &oversized.marker);
+                    truncated = true;
+                }
+                OversizedMessageAction::Drop => {
+                    rejected += 1;
+                    continue;
+                }
+                OversizedMessageAction::Pass => (),
+            }
+        }
+
+        let service_api_key = service_api_keys.as_ref().and_then(|keys| {
+            let service = std::str::from_utf8(&service).ok()?;
+            keys.get(service).cloned()
+        });
+        let api_key = match source.service_api_key_precedence {
+            ServiceApiKeyPrecedence::Service => service_api_key.or_else(|| api_key.clone()),
+            ServiceApiKeyPrecedence::Request => api_key.clone().or(service_api_key),
+        };
+
+        let mut push = |mut event: Event| {
+            if let Event::Log(ref mut log) = event {
+                let namespace = &source.log_namespace;
+                let source_name = "datadog_agent";
+                let reserved = |field| source.reserved_fields.contains(&field);
 
-                        decoded.push(event);
+                if reserved(ReservedField::Status) {
+                    namespace.insert_source_metadata(
+                        source_name,
+                        log,
+                        Some(LegacyKey::InsertIfEmpty(path!("status"))),
+                        path!("status"),
+                        status.clone(),
+                    );
+                }
+                if reserved(ReservedField::Timestamp) {
+                    namespace.insert_source_metadata(
+                        source_name,
+                        log,
+                        Some(LegacyKey::InsertIfEmpty(path!("timestamp"))),
+                        path!("timestamp"),
+                        timestamp,
+                    );
+                }
+                if reserved(ReservedField::Hostname) {
+                    namespace.insert_source_metadata(
+                        source_name,
+                        log,
+                        Some(LegacyKey::InsertIfEmpty(path!("hostname"))),
+                        path!("hostname"),
+                        hostname.clone(),
+                    );
+                }
+                if reserved(ReservedField::Service) {
+                    namespace.insert_source_metadata(
+                        source_name,
+                        log,
+                        Some(LegacyKey::InsertIfEmpty(path!("service"))),
+                        path!("service"),
+                        service.clone(),
+                    );
+                }
+                if reserved(ReservedField::Ddsource) {
+                    namespace.insert_source_metadata(
+                        source_name,
+                        log,
+                        Some(LegacyKey::InsertIfEmpty(path!("ddsource"))),
+                        path!("ddsource"),
+                        ddsource.clone(),
+                    );
+                }
+                if reserved(ReservedField::Ddtags) {
+                    namespace.insert_source_metadata(
+                        source_name,
+                        log,
+                        Some(LegacyKey::InsertIfEmpty(path!("ddtags"))),
+                        path!("ddtags"),
+                        ddtags.clone(),
+                    );
+                }
+
+                if let Some(original) = &service_original {
+                    namespace.insert_source_metadata(
+                        source_name,
+                        log,
+                        Some(LegacyKey::InsertIfEmpty(path!("service_original"))),
+                        path!("service_original"),
+                        original.clone(),
+                    );
+                }
+                if let Some(original) = &ddsource_original {
+                    namespace.insert_source_metadata(
+                        source_name,
+                        log,
+                        Some(LegacyKey::InsertIfEmpty(path!("ddsource_original"))),
+                        path!("ddsource_original"),
+                        original.clone(),
+                    );
+                }
+                if truncated {
+                    namespace.insert_source_metadata(
+                        source_name,
+                        log,
+                        Some(LegacyKey::InsertIfEmpty(path!("truncated"))),
+                        path!("truncated"),
+                        true,
+                    );
+                }
+                if let Some(origin) = origin {
+                    namespace.insert_source_metadata(
+                        source_name,
+                        log,
+                        Some(LegacyKey::InsertIfEmpty(path!("origin"))),
+                        path!("origin"),
+                        origin,
+                    );
+                }
+                if let Some(request) = request_metadata {
+                    insert_request_metadata(namespace, source_name, log, request);
+                }
+                if let Some(promotion) = &source.tag_promotion {
+                    for (key, value) in &promoted_tags {
+                        promotion.insert(log, key, value.clone());
                     }
                 }
-                Ok(None) => break,
-                Err(error) => {
-                    // Error is logged by `crate::codecs::Decoder`, no further
-                    // handling is needed here.
-                    if !error.can_continue() {
-                        break;
+                if source.extract_trace_context {
+                    let context = TraceContext::from_log(log);
+                    if let Some(trace_id) = context.trace_id {
+                        namespace.insert_source_metadata(
+                            source_name,
+                            log,
+                            Some(LegacyKey::InsertIfEmpty(path!("trace_id"))),
+                            path!("trace_id"),
+                            trace_id,
+                        );
+                    }
+                    if let Some(span_id) = context.span_id {
+                        namespace.insert_source_metadata(
+                            source_name,
+                            log,
+                            Some(LegacyKey::InsertIfEmpty(path!("span_id"))),
+                            path!("span_id"),
+                            span_id,
+                        );
+                    }
+                }
+
+                if source.annotate_order {
+                    // Events are only ever appended, so this is the position of the event within
+                    // the request.
+                    namespace.insert_source_metadata(
+                        source_name,
+                        log,
+                        Some(LegacyKey::InsertIfEmpty(path!("request_seq"))),
+                        path!("request_seq"),
+                        decoded.len() as i64,
+                    );
+                }
+
+                namespace.insert_standard_vector_source_metadata(
+                    log,
+                    DatadogAgentConfig::NAME,
+                    now,
+                );
+
+                if let Some(k) = &api_key {
+                    log.metadata_mut().set_datadog_api_key(Arc::clone(k));
+                }
+
+                log.metadata_mut()
+                    .set_schema_definition(&source.logs_schema_definition);
+            }
+
+            decoded.push(event);
+        };
+
+        // Errors are logged by `crate::codecs::Decoder`. The last one is kept to report the
+        // message as dropped, and to reject the request if configured to.
+        let mut decode_error = None;
+        let (mut frames, mut frame_bytes) = (0, 0);
+        if whole_messages {
+            // There's no further frame to decode from this message.
+            match decoder.decode_frame(message) {
+                Ok(Some((events, _byte_size))) => {
+                    frames += 1;
+                    events.into_iter().for_each(&mut push);
+                }
+                Ok(None) => (),
+                Err(error) => decode_error = Some(error),
+            }
+        } else {
+            let mut decoder = source.decoder.clone();
+            buffer.clear();
+            buffer.extend_from_slice(&message);
+            loop {
+                match decoder.decode_eof(&mut buffer) {
+                    Ok(Some((events, byte_size))) => {
+                        frames += 1;
+                        frame_bytes += byte_size;
+                        events.into_iter().for_each(&mut push);
+                    }
+                    Ok(None) => break,
+                    Err(error) => {
+                        let can_continue = error.can_continue();
+                        if !can_continue {
+                            emit!(DatadogAgentMessageDecodeError {
+                                error: &error,
+                                dropped_frames_estimate: estimate_dropped_frames(
+                                    buffer.len(),
+                                    frames,
+                                    frame_bytes
+                                ),
+                            });
+                        }
+                        decode_error = Some(error);
+                        if !can_continue {
+                            break;
+                        }
                     }
                 }
             }
         }
+
+        match decode_error {
+            Some(error) => {
+                emit!(DatadogAgentMessageDecodeDropped {
+                    partial: frames > 0
+                });
+                if source.on_decode_error == DecodeErrorAction::FailRequest {
+                    return Err(reject(
+                        LOGS,
+                        RejectionReason::MessageDecode,
+                        StatusCode::BAD_REQUEST,
+                        format!("Error decoding log message: {}", error),
+                    ));
+                }
+                rejected += 1;
+            }
+            None => accepted += 1,
+        }
     }
 
     source.events_received.emit(CountByteSize(
@@ -184,5 +766,285 @@ pub(crate) fn decode_log_body(
         decoded.estimated_json_encoded_size_of(),
     ));
 
-    Ok(decoded)
+    Ok(DecodedLogs {
+        events: decoded,
+        accepted,
+        rejected,
+    })
+}
+
+/// The events decoded from the log messages of a request, and how many of the messages were
+/// dropped along the way.
+///
+/// Messages are counted after `multiline` split or joined them.
+#[derive(Debug, Default)]
+pub(crate) struct DecodedLogs {
+    pub(crate) events: Vec<Event>,
+    /// The number of messages decoded into `events`.
+    pub(crate) accepted: usize,
+    /// The number of messages dropped, as they were oversized or failed to decode, even if some of
+    /// their frames were decoded into `events`.
+    pub(crate) rejected: usize,
+}
+
+/// Decodes the bodies of log requests as the source does, without serving them.
+#[cfg(feature = "datadog-agent-benches")]
+pub struct LogsBodyDecoder(DatadogAgentSource);
+
+#[cfg(feature = "datadog-agent-benches")]
+impl LogsBodyDecoder {
+    pub fn new(
+        framing: codecs::decoding::FramingConfig,
+        decoding: codecs::decoding::DeserializerConfig,
+    ) -> Self {
+        let decoder =
+            crate::codecs::DecodingConfig::new(framing, decoding, LogNamespace::Legacy).build();
+        Self(DatadogAgentSource::new(
+            false,
+            decoder,
+            "http",
+            schema::Definition::any(),
+            LogNamespace::Legacy,
+        ))
+    }
+
+    /// Decodes the log messages of `body`, a JSON array as sent by the agent.
+    pub fn decode(&self, body: Bytes) -> Vec<Event> {
+        decode_log_body(body, None, false, None, None, &self.0)
+            .map(|decoded| decoded.events)
+            .unwrap_or_default()
+    }
+}
+
+/// Estimates how many frames were left in the `remaining_bytes` of a message that can't be framed
+/// any further, from the average size of the frames decoded before. At least the frame that failed
+/// is dropped.
+fn estimate_dropped_frames(remaining_bytes: usize, frames: usize, frame_bytes: usize) -> usize {
+    if frame_bytes == 0 {
+        return 1;
+    }
+    ((remaining_bytes * frames + frame_bytes - 1) / frame_bytes).max(1)
+}
+
+/// How log messages larger than `max_message_bytes` are handled.
+#[derive(Clone, Debug)]
+pub(crate) struct OversizedMessages {
+    pub(crate) max_bytes: usize,
+    pub(crate) action: OversizedMessageAction,
+    pub(crate) marker: String,
+}
+
+/// How the tags of `ddtags` are promoted to top-level fields of logs.
+#[derive(Clone, Debug)]
+pub(crate) struct TagPromotion {
+    pub(crate) keys: Vec<String>,
+    pub(crate) conflict: PromoteConflict,
+    pub(crate) remove: bool,
+}
+
+impl TagPromotion {
+    /// Returns the tags of `ddtags` to promote, by key, removing them from `ddtags` if configured
+    /// to.
+    ///
+    /// Tags that aren't valid UTF-8 are left untouched.
+    fn promote(&self, ddtags: &mut Bytes) -> Vec<(String, Bytes)> {
+        let tags = match std::str::from_utf8(ddtags) {
+            Ok(tags) => tags,
+            Err(_) => return Vec::new(),
+        };
+
+        let mut promoted: Vec<(String, Bytes)> = Vec::new();
+        let mut kept = Vec::new();
+        for tag in tags.split(',') {
+            let promotable = tag
+                .split_once(':')
+                .filter(|(key, _)| self.keys.iter().any(|promoted| promoted == key));
+            match promotable {
+                Some((key, value)) => {
+                    if !promoted.iter().any(|(promoted, _)| promoted == key) {
+                        promoted.push((key.to_owned(), Bytes::from(value.to_owned())));
+                    }
+                }
+                None => kept.push(tag),
+            }
+        }
+        if self.remove && !promoted.is_empty() {
+            *ddtags = Bytes::from(kept.join(","));
+        }
+        promoted
+    }
+
+    /// Writes a promoted tag to `log`, resolving conflicts with its existing fields.
+    ///
+    /// Logs that aren't objects, which only happens with the `vector` namespace, are left untouched.
+    fn insert(&self, log: &mut LogEvent, key: &str, value: Bytes) {
+        if !log.value().is_object() {
+            return;
+        }
+        let renamed;
+        let key = if log.contains(event_path!(key)) {
+            match self.conflict {
+                PromoteConflict::KeepExisting => return,
+                PromoteConflict::Overwrite => key,
+                PromoteConflict::Rename => {
+                    renamed = format!("{}_tag", key);
+                    renamed.as_str()
+                }
+            }
+        } else {
+            key
+        };
+        log.insert(event_path!(key), value);
+    }
+}
+
+/// The trace and span a log is correlated with, from its `dd.trace_id` and `dd.span_id` fields.
+///
+/// The fields are looked up both as a `dd` object and as keys containing a dot. IDs are decimal
+/// integers, which may be sent as numbers or strings, and are normalized to strings. Malformed IDs
+/// are ignored.
+#[derive(Debug, Default, PartialEq)]
+struct TraceContext {
+    trace_id: Option<String>,
+    span_id: Option<String>,
+}
+
+impl TraceContext {
+    /// Extracts the trace context of a decoded log.
+    ///
+    /// If the log doesn't have the fields, which is the case when its message is left as bytes by
+    /// the codec, the message is parsed as JSON to look for them.
+    fn from_log(log: &LogEvent) -> Self {
+        let context = Self {
+            trace_id: structured_id(log.value(), "trace_id"),
+            span_id: structured_id(log.value(), "span_id"),
+        };
+        if context != Self::default() {
+            return context;
+        }
+        match log.get_message().unwrap_or_else(|| log.value()) {
+            Value::Bytes(message) => Self::scan(message),
+            _ => context,
+        }
+    }
+
+    /// Extracts the trace context of a raw message, skipping the parsing of messages that can't
+    /// contain it.
+    fn scan(message: &[u8]) -> Self {
+        let mentions = |key: &[u8]| message.windows(key.len()).any(|window| window == key);
+        if !mentions(b"trace_id") && !mentions(b"span_id") {
+            return Self::default();
+        }
+        match serde_json::from_slice::<serde_json::Value>(message) {
+            Ok(message) => Self {
+                trace_id: raw_id(&message, "trace_id"),
+                span_id: raw_id(&message, "span_id"),
+            },
+            Err(_) => Self::default(),
+        }
+    }
+}
+
+fn structured_id(log: &Value, name: &str) -> Option<String> {
+    let dotted = format!("dd.{}", name);
+    match log
+        .get(path!("dd", name))
+        .or_else(|| log.get(path!(dotted.as_str())))
+    {
+        Some(Value::Integer(id)) => u64::try_from(*id).ok().map(|id| id.to_string()),
+        Some(Value::Bytes(id)) => std::str::from_utf8(id).ok().and_then(normalize_id),
+        _ => None,
+    }
+}
+
+fn raw_id(message: &serde_json::Value, name: &str) -> Option<String> {
+    let id = message
+        .get("dd")
+        .and_then(|dd| dd.get(name))
+        .or_else(|| message.get(format!("dd.{}", name)))?;
+    match id {
+        serde_json::Value::Number(id) => id.as_u64().map(|id| id.to_string()),
+        serde_json::Value::String(id) => normalize_id(id),
+        _ => None,
+    }
+}
+
+/// Returns the ID in `id`, if it's a decimal integer.
+fn normalize_id(id: &str) -> Option<String> {
+    id.trim().parse::<u64>().ok().map(|id| id.to_string())
+}
+
+/// Cuts `message` so that it fits within `max_bytes` once `marker` is appended to it.
+///
+/// The cut is moved back to the start of the UTF-8 character it would split, if any, so that a
+/// valid message stays valid.
+fn truncate_message(message: &Bytes, max_bytes: usize, marker: &str) -> Bytes {
+    let mut end = max_bytes.saturating_sub(marker.len()).min(message.len());
+    // Continuation bytes are the only ones of the form `0b10xx_xxxx`.
+    while end > 0 && end < message.len() && message[end] & 0xC0 == 0x80 {
+        end -= 1;
+    }
+
+    let mut truncated = BytesMut::with_capacity(end + marker.len());
+    truncated.extend_from_slice(&message[..end]);
+    truncated.extend_from_slice(marker.as_bytes());
+    truncated.freeze()
+}
+
+/// Returns `value` converted to lowercase, if that changes it.
+///
+/// Values that aren't valid UTF-8 are left untouched.
+fn to_lowercase(value: &Bytes) -> Option<Bytes> {
+    let value = std::str::from_utf8(value).ok()?;
+    let lowercase = value.to_lowercase();
+    (lowercase != value).then(|| Bytes::from(lowercase))
+}
+
+/// Appends an `origin:<origin>` tag to the comma separated `ddtags` of a log.
+fn tag_origin(ddtags: &Bytes, origin: &str) -> Bytes {
+    let tag = format!("origin:{}", origin);
+    if ddtags.is_empty() {
+        return tag.into();
+    }
+
+    let mut tagged = BytesMut::with_capacity(ddtags.len() + 1 + tag.len());
+    tagged.extend_from_slice(ddtags);
+    tagged.extend_from_slice(b",");
+    tagged.extend_from_slice(tag.as_bytes());
+    tagged.freeze()
+}
+
+fn insert_request_metadata(
+    namespace: &LogNamespace,
+    source_name: &'static str,
+    log: &mut LogEvent,
+    request: &RequestMetadata,
+) {
+    if let Some(remote_addr) = request.remote_addr {
+        namespace.insert_source_metadata(
+            source_name,
+            log,
+            Some(LegacyKey::InsertIfEmpty(path!("remote_addr"))),
+            path!("remote_addr"),
+            remote_addr.to_string(),
+        );
+    }
+    if let Some(agent_version) = &request.agent_version {
+        namespace.insert_source_metadata(
+            source_name,
+            log,
+            Some(LegacyKey::InsertIfEmpty(path!("agent_version"))),
+            path!("agent_version"),
+            agent_version.clone(),
+        );
+    }
+    if let Some(user_agent) = &request.user_agent {
+        namespace.insert_source_metadata(
+            source_name,
+            log,
+            Some(LegacyKey::InsertIfEmpty(path!("user_agent"))),
+            path!("user_agent"),
+            user_agent.clone(),
+        );
+    }
 }
@@ -7,7 +7,7 @@ use prost::Message;
 use serde::{Deserialize, Serialize};
 use vector_common::internal_event::{CountByteSize, InternalEventHandle as _, Registered};
 use vector_core::{metrics::AgentDDSketch, EstimatedJsonEncodedSizeOf};
-use warp::{filters::BoxedFilter, path, path::FullPath, reply::Response, Filter};
+use warp::{filters::BoxedFilter, path, reply::Response, Filter};
 
 use crate::{
     common::datadog::{DatadogMetricType, DatadogSeriesMetric},
@@ -16,12 +16,14 @@ use crate::{
         metric::{Metric, MetricValue},
         Event, MetricKind, MetricTags,
     },
-    internal_events::EventsReceived,
+    internal_events::{DatadogAgentPayloadDecoded, EventsReceived},
     schema,
     sources::{
         datadog_agent::{
             ddmetric_proto::{metric_payload, MetricPayload, SketchPayload},
-            handle_request, ApiKeyQueryParams, DatadogAgentSource,
+            handle_request, reject,
+            request::{decoded_request, DecodedRequest},
+            DatadogAgentSource, RejectionReason, METRICS,
         },
         util::{extract_tag_key_and_value, ErrorMessage},
     },
@@ -33,6 +35,9 @@ pub(crate) struct DatadogSeriesRequest {
     pub(crate) series: Vec<DatadogSeriesMetric>,
 }
 
+/// The path prefixes of the metrics routes.
+pub(crate) const PATHS: &[&str] = &["/api/beta/sketches", "/api/v1/series", "/api/v2/series"];
+
 pub(crate) fn build_warp_filter(
     acknowledgements: bool,
     multiple_outputs: bool,
@@ -60,33 +65,31 @@ fn sketches_service(
 ) -> BoxedFilter<(Response,)> {
     warp::post()
         .and(path!("api" / "beta" / "sketches" / ..))
-        .and(warp::path::full())
-        .and(warp::header::optional::<String>("content-encoding"))
-        .and(warp::header::optional::<String>("dd-api-key"))
-        .and(warp::query::<ApiKeyQueryParams>())
-        .and(warp::body::bytes())
-        .and_then(
-            move |path: FullPath,
-                  encoding_header: Option<String>,
-                  api_token: Option<String>,
-                  query_params: ApiKeyQueryParams,
-                  body: Bytes| {
-                let events = source
-                    .decode(&encoding_header, body, path.as_str())
-                    .and_then(|body| {
-                        decode_datadog_sketches(
-                            body,
-                            source.api_key_extractor.extract(
-                                path.as_str(),
-                                api_token,
-                                query_params.dd_api_key,
-                            ),
-                            &source.events_received,
-                        )
-                    });
-                handle_request(events, acknowledgements, out.clone(), output)
-            },
-        )
+        .and(decoded_request(
+            source.api_key_extractor.clone(),
+            METRICS,
+            None,
+        ))
+        .and_then(move |request: Result<DecodedRequest, ErrorMessage>| {
+            let events = request.and_then(|request| {
+                let api_key = request.stored_api_key(&source.api_key_extractor);
+                let body = request.decompress(&source, METRICS)?;
+                decode_datadog_sketches(
+                    body,
+                    api_key,
+                    request.is_compressed(),
+                    &source.events_received,
+                )
+            });
+            handle_request(
+                events,
+                acknowledgements,
+                source.acknowledgement_timeout,
+                Arc::clone(&source.health),
+                out.clone(),
+                output,
+            )
+        })
         .boxed()
 }
 
@@ -98,36 +101,34 @@ fn series_v1_service(
 ) -> BoxedFilter<(Response,)> {
     warp::post()
         .and(path!("api" / "v1" / "series" / ..))
-        .and(warp::path::full())
-        .and(warp::header::optional::<String>("content-encoding"))
-        .and(warp::header::optional::<String>("dd-api-key"))
-        .and(warp::query::<ApiKeyQueryParams>())
-        .and(warp::body::bytes())
-        .and_then(
-            move |path: FullPath,
-                  encoding_header: Option<String>,
-                  api_token: Option<String>,
-                  query_params: ApiKeyQueryParams,
-                  body: Bytes| {
-                let events = source
-                    .decode(&encoding_header, body, path.as_str())
-                    .and_then(|body| {
-                        decode_datadog_series_v1(
-                            body,
-                            source.api_key_extractor.extract(
-                                path.as_str(),
-                                api_token,
-                                query_params.dd_api_key,
-                            ),
-                            // Currently metrics do not have schemas defined, so for now we just pass a
-                            // default one.
-                            &Arc::new(schema::Definition::default_legacy_namespace()),
-                            &source.events_received,
-                        )
-                    });
-                handle_request(events, acknowledgements, out.clone(), output)
-            },
-        )
+        .and(decoded_request(
+            source.api_key_extractor.clone(),
+            METRICS,
+            None,
+        ))
+        .and_then(move |request: Result<DecodedRequest, ErrorMessage>| {
+            let events = request.and_then(|request| {
+                let api_key = request.stored_api_key(&source.api_key_extractor);
+                let body = request.decompress(&source, METRICS)?;
+                decode_datadog_series_v1(
+                    body,
+                    api_key,
+                    // Currently metrics do not have schemas defined, so for now we just pass a
+                    // default one.
+                    &Arc::new(schema::Definition::default_legacy_namespace()),
+                    request.is_compressed(),
+                    &source.events_received,
+                )
+            });
+            handle_request(
+                events,
+                acknowledgements,
+                source.acknowledgement_timeout,
+                Arc::clone(&source.health),
+                out.clone(),
+                output,
+            )
+        })
         .boxed()
 }
 
@@ -139,39 +140,38 @@ fn series_v2_service(
 ) -> BoxedFilter<(Response,)> {
     warp::post()
         .and(path!("api" / "v2" / "series" / ..))
-        .and(warp::path::full())
-        .and(warp::header::optional::<String>("content-encoding"))
-        .and(warp::header::optional::<String>("dd-api-key"))
-        .and(warp::query::<ApiKeyQueryParams>())
-        .and(warp::body::bytes())
-        .and_then(
-            move |path: FullPath,
-                  encoding_header: Option<String>,
-                  api_token: Option<String>,
-                  query_params: ApiKeyQueryParams,
-                  body: Bytes| {
-                let events = source
-                    .decode(&encoding_header, body, path.as_str())
-                    .and_then(|body| {
-                        decode_datadog_series_v2(
-                            body,
-                            source.api_key_extractor.extract(
-                                path.as_str(),
-                                api_token,
-                                query_params.dd_api_key,
-                            ),
-                            &source.events_received,
-                        )
-                    });
-                handle_request(events, acknowledgements, out.clone(), output)
-            },
-        )
+        .and(decoded_request(
+            source.api_key_extractor.clone(),
+            METRICS,
+            None,
+        ))
+        .and_then(move |request: Result<DecodedRequest, ErrorMessage>| {
+            let events = request.and_then(|request| {
+                let api_key = request.stored_api_key(&source.api_key_extractor);
+                let body = request.decompress(&source, METRICS)?;
+                decode_datadog_series_v2(
+                    body,
+                    api_key,
+                    request.is_compressed(),
+                    &source.events_received,
+                )
+            });
+            handle_request(
+                events,
+                acknowledgements,
+                source.acknowledgement_timeout,
+                Arc::clone(&source.health),
+                out.clone(),
+                output,
+            )
+        })
         .boxed()
 }
 
 fn decode_datadog_sketches(
     body: Bytes,
     api_key: Option<Arc<str>>,
+    compressed: bool,
     events_received: &Registered<EventsReceived>,
 ) -> Result<Vec<Event>, ErrorMessage> {
     if body.is_empty() {
@@ -184,12 +184,20 @@ fn decode_datadog_sketches(
     }
 
     let metrics = decode_ddsketch(body, &api_key).map_err(|error| {
-        ErrorMessage::new(
+        reject(
+            METRICS,
+            RejectionReason::PayloadDecode,
             StatusCode::UNPROCESSABLE_ENTITY,
             format!("Error decoding Datadog sketch: {:?}", error),
         )
     })?;
 
+    emit!(DatadogAgentPayloadDecoded {
+        endpoint: METRICS,
+        compressed,
+        messages: metrics.len(),
+        message_sizes: &[],
+    });
     events_received.emit(CountByteSize(
         metrics.len(),
         metrics.estimated_json_encoded_size_of(),
@@ -201,6 +209,7 @@ fn decode_datadog_sketches(
 fn decode_datadog_series_v2(
     body: Bytes,
     api_key: Option<Arc<str>>,
+    compressed: bool,
     events_received: &Registered<EventsReceived>,
 ) -> Result<Vec<Event>, ErrorMessage> {
     if body.is_empty() {
@@ -213,12 +222,20 @@ fn decode_datadog_series_v2(
     }
 
     let metrics = decode_ddseries_v2(body, &api_key, events_received).map_err(|error| {
-        ErrorMessage::new(
+        reject(
+            METRICS,
+            RejectionReason::PayloadDecode,
             StatusCode::UNPROCESSABLE_ENTITY,
             format!("Error decoding Datadog sketch: {:?}", error),
         )
     })?;
 
+    emit!(DatadogAgentPayloadDecoded {
+        endpoint: METRICS,
+        compressed,
+        messages: metrics.len(),
+        message_sizes: &[],
+    });
     events_received.emit(CountByteSize(
         metrics.len(),
         metrics.estimated_json_encoded_size_of(),
@@ -348,6 +365,7 @@ fn decode_datadog_series_v1(
     body: Bytes,
     api_key: Option<Arc<str>>,
     schema_definition: &Arc<schema::Definition>,
+    compressed: bool,
     events_received: &Registered<EventsReceived>,
 ) -> Result<Vec<Event>, ErrorMessage> {
     if body.is_empty() {
@@ -360,7 +378,9 @@ fn decode_datadog_series_v1(
     }
 
     let metrics: DatadogSeriesRequest = serde_json::from_slice(&body).map_err(|error| {
-        ErrorMessage::new(
+        reject(
+            METRICS,
+            RejectionReason::JsonParse,
             StatusCode::BAD_REQUEST,
             format!("Error parsing JSON: {:?}", error),
         )
@@ -372,6 +392,12 @@ fn decode_datadog_series_v1(
         .flat_map(|m| into_vector_metric(m, api_key.clone(), schema_definition))
         .collect();
 
+    emit!(DatadogAgentPayloadDecoded {
+        endpoint: METRICS,
+        compressed,
+        messages: decoded_metrics.len(),
+        message_sizes: &[],
+    });
     events_received.emit(CountByteSize(
         decoded_metrics.len(),
         decoded_metrics.estimated_json_encoded_size_of(),
@@ -1,10 +1,18 @@
+#[cfg(test)]
+mod harness;
 #[cfg(all(test, feature = "datadog-agent-integration-tests"))]
 mod integration_tests;
 #[cfg(test)]
 mod tests;
 
+mod decode_pool;
+mod dump;
+mod health;
 pub mod logs;
 pub mod metrics;
+mod multiline;
+mod request;
+mod service_api_keys;
 pub mod traces;
 
 #[allow(warnings, clippy::pedantic, clippy::nursery)]
@@ -17,25 +25,55 @@ pub(crate) mod ddtrace_proto {
     include!(concat!(env!("OUT_DIR"), "/dd_trace.rs"));
 }
 
-use std::{fmt::Debug, io::Read, net::SocketAddr, sync::Arc};
+use std::{
+    borrow::Cow,
+    collections::{BTreeSet, HashMap, HashSet},
+    convert::Infallible,
+    fmt::Debug,
+    io::{Read, Write},
+    net::SocketAddr,
+    num::{NonZeroU32, NonZeroUsize},
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use bytes::{Buf, Bytes};
 use chrono::{serde::ts_milliseconds, DateTime, Utc};
+use cidr_utils::cidr::IpCidr;
 use codecs::decoding::{DeserializerConfig, FramingConfig};
-use flate2::read::{MultiGzDecoder, ZlibDecoder};
-use futures::FutureExt;
-use http::StatusCode;
+use flate2::{
+    read::{MultiGzDecoder, ZlibDecoder},
+    write::GzEncoder,
+    Compression,
+};
+use futures::{future::join_all, FutureExt};
+use http::{
+    header::{ACCEPT_ENCODING, CONNECTION, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE},
+    HeaderValue, Request, StatusCode,
+};
+use hyper::{
+    server::accept,
+    service::{make_service_fn, service_fn, Service},
+    Body, Server,
+};
 use lookup::owned_value_path;
+use once_cell::sync::Lazy;
 use regex::Regex;
 use serde::{Deserialize, Serialize};
-use snafu::Snafu;
+use tokio::net::TcpStream;
 use tracing::Span;
 use value::Kind;
-use vector_common::internal_event::{EventsReceived, Registered};
+use vector_common::{
+    internal_event::{EventsReceived, Registered},
+    sensitive_string::SensitiveString,
+};
 use vector_config::configurable_component;
 use vector_core::config::{LegacyKey, LogNamespace};
 use vector_core::event::{BatchNotifier, BatchStatus};
-use warp::{filters::BoxedFilter, reject::Rejection, reply::Response, Filter, Reply};
+use warp::{
+    filters::BoxedFilter, path::FullPath, reject::Rejection, reply::Response, Filter, Reply,
+};
 
 use crate::{
     codecs::{Decoder, DecodingConfig},
@@ -44,11 +82,21 @@ use crate::{
         SourceContext, SourceOutput,
     },
     event::Event,
-    internal_events::{HttpBytesReceived, HttpDecompressError, StreamClosedError},
+    internal_events::{
+        DatadogAgentPayloadDecompressed, DatadogAgentRequestRejected, HttpBytesReceived,
+        StreamClosedError,
+    },
     schema,
     serde::{bool_or_struct, default_decoding, default_framing_message_based},
-    sources::{self, util::ErrorMessage},
-    tls::{MaybeTlsSettings, TlsEnableableConfig},
+    shutdown::ShutdownSignal,
+    sources::{
+        self,
+        datadog_agent::{
+            decode_pool::DecodePool, dump::FailedRequestDumper, health::DeliveryHealth,
+        },
+        util::ErrorMessage,
+    },
+    tls::{MaybeTlsIncomingStream, MaybeTlsListener, MaybeTlsSettings, TlsEnableableConfig},
     SourceSender,
 };
 
@@ -76,17 +124,35 @@ pub struct DatadogAgentConfig {
     #[serde(default = "crate::serde::default_true")]
     store_api_key: bool,
 
+    /// The API keys allowed to send data to the component.
+    ///
+    /// If set, requests without an API key, or with an API key not in this list, are rejected. The
+    /// API key is taken from the `/v1/input/<API_KEY>` path, the `dd-api-key` query parameter, or
+    /// the `DD-API-KEY` header, in that order.
+    #[configurable(metadata(docs::advanced))]
+    #[serde(default)]
+    allowed_api_keys: Vec<SensitiveString>,
+
     /// If this is set to `true`, logs are not accepted by the component.
+    ///
+    /// The `logs` output is then omitted when `multiple_outputs` is enabled, and requests sent to
+    /// the logs routes are answered with a `404 Not Found` explaining that they're disabled.
     #[configurable(metadata(docs::advanced))]
     #[serde(default = "crate::serde::default_false")]
     disable_logs: bool,
 
     /// If this is set to `true`, metrics are not accepted by the component.
+    ///
+    /// The `metrics` output is then omitted when `multiple_outputs` is enabled, and requests sent to
+    /// the metrics routes are answered with a `404 Not Found` explaining that they're disabled.
     #[configurable(metadata(docs::advanced))]
     #[serde(default = "crate::serde::default_false")]
     disable_metrics: bool,
 
     /// If this is set to `true`, traces are not accepted by the component.
+    ///
+    /// The `traces` output is then omitted when `multiple_outputs` is enabled, and requests sent to
+    /// the traces routes are answered with a `404 Not Found` explaining that they're disabled.
     #[configurable(metadata(docs::advanced))]
     #[serde(default = "crate::serde::default_false")]
     disable_traces: bool,
@@ -101,6 +167,235 @@ pub struct DatadogAgentConfig {
     #[serde(default = "crate::serde::default_false")]
     multiple_outputs: bool,
 
+    /// If this is set to `true`, and `multiple_outputs` is enabled, logs are sent to a dedicated
+    /// output for their `ddsource`, if it's listed in `ddsource_outputs`.
+    ///
+    /// For a source component named `agent`, logs with a `ddsource` of `nginx` can then be
+    /// configured as input to other components by specifying `agent.logs.nginx`. Other logs are
+    /// still sent to `agent.logs`.
+    #[configurable(metadata(docs::advanced))]
+    #[serde(default = "crate::serde::default_false")]
+    map_ddsource_to_output: bool,
+
+    /// The `ddsource` values given a dedicated output when `map_ddsource_to_output` is enabled.
+    #[configurable(metadata(docs::advanced))]
+    #[configurable(metadata(docs::examples = "nginx"))]
+    #[configurable(metadata(docs::examples = "postgres"))]
+    #[serde(default)]
+    ddsource_outputs: Vec<String>,
+
+    /// The outputs dedicated to the logs sent with given API keys, when `multiple_outputs` is
+    /// enabled.
+    ///
+    /// Each entry maps an API key, or the hex-encoded SHA-256 digest of one prefixed with
+    /// `sha256:`, to the name of an output. For a source component named `agent`, logs sent with a
+    /// key mapped to `prod` can then be configured as input to other components by specifying
+    /// `agent.logs.prod`. Logs sent with other keys, or without one, are still sent to
+    /// `agent.logs`, and outputs dedicated to a `ddsource` take precedence.
+    #[configurable(metadata(docs::advanced))]
+    #[configurable(metadata(
+        docs::additional_props_description = "An API key, or the SHA-256 digest of one, and the name of its output."
+    ))]
+    #[serde(default)]
+    api_key_routing: HashMap<String, String>,
+
+    /// If this is set to `true`, logs are enriched with details about the request that sent them.
+    ///
+    /// The address of the client, and the `DD-Agent-Version` and `User-Agent` headers, are added
+    /// to each log as `remote_addr`, `agent_version`, and `user_agent`, respectively.
+    #[configurable(metadata(docs::advanced))]
+    #[serde(default = "crate::serde::default_false")]
+    include_request_metadata: bool,
+
+    /// The proxies trusted to report the address of the client.
+    ///
+    /// When a request comes from one of these addresses, the client address recorded with
+    /// `include_request_metadata` is taken from the `X-Forwarded-For` header instead. Each entry is
+    /// an IP address or a CIDR block.
+    #[configurable(metadata(docs::advanced))]
+    #[configurable(metadata(docs::examples = "10.0.0.0/8"))]
+    #[configurable(metadata(docs::examples = "192.168.1.1"))]
+    #[serde(default)]
+    trusted_proxies: Vec<String>,
+
+    /// If this is set to `true`, successful responses to log requests have a JSON body counting
+    /// the log messages accepted and rejected, such as `{"accepted": 124, "rejected": 0}`.
+    ///
+    /// Messages dropped as they fail to decode, or as they're larger than `max_message_bytes` with
+    /// `on_oversized` set to `drop`, are counted as rejected. A request that isn't valid JSON is
+    /// still rejected entirely. By default, the body is empty, like the responses of the Datadog
+    /// intake.
+    #[configurable(metadata(docs::advanced))]
+    #[serde(default = "crate::serde::default_false")]
+    verbose_responses: bool,
+
+    /// If this is set to `true`, the origin of logs sent with an `X-Datadog-Origin` header is also
+    /// appended to their `ddtags`, as an `origin:<value>` tag.
+    ///
+    /// The origin is always added to the metadata of the logs as `origin`, whether this is set or
+    /// not. Logs sent without the header are left untouched.
+    #[configurable(metadata(docs::advanced))]
+    #[serde(default = "crate::serde::default_false")]
+    origin_as_tag: bool,
+
+    /// The maximum size of the message of a single log, in bytes.
+    ///
+    /// Messages larger than this are handled according to `on_oversized` before being decoded. By
+    /// default, messages of any size are accepted.
+    #[configurable(metadata(docs::advanced))]
+    #[configurable(metadata(docs::type_unit = "bytes"))]
+    max_message_bytes: Option<usize>,
+
+    /// The maximum size of the body of a log request, in bytes, as received.
+    ///
+    /// Larger requests are rejected with a `413 Payload Too Large` response as soon as the body
+    /// crosses the limit, without reading the rest of it. This also applies to bodies sent with
+    /// chunked transfer encoding. By default, bodies of any size are accepted.
+    #[configurable(metadata(docs::advanced))]
+    #[configurable(metadata(docs::type_unit = "bytes"))]
+    max_body_bytes: Option<usize>,
+
+    /// What to do with messages larger than `max_message_bytes`.
+    #[configurable(metadata(docs::advanced))]
+    #[serde(default)]
+    on_oversized: OversizedMessageAction,
+
+    /// The marker appended to messages truncated by `on_oversized`.
+    ///
+    /// The marker counts towards `max_message_bytes`, so it must be shorter than it for truncated
+    /// messages to keep any of their content.
+    #[configurable(metadata(docs::advanced))]
+    #[serde(default = "default_truncation_marker")]
+    truncation_marker: String,
+
+    /// What to do with a request when one of its log messages fails to be decoded by `framing` and
+    /// `decoding`.
+    #[configurable(metadata(docs::advanced))]
+    #[serde(default)]
+    on_decode_error: DecodeErrorAction,
+
+    /// The reserved attributes of logs inserted into each event.
+    ///
+    /// With the `vector` log namespace, they're inserted into the metadata of the event instead.
+    /// Attributes left out are dropped, which keeps events smaller when they aren't needed. The
+    /// `source_type` and ingest timestamp are always inserted. Defaults to all of them.
+    #[configurable(metadata(docs::advanced))]
+    #[serde(default = "default_reserved_fields")]
+    reserved_fields: Vec<ReservedField>,
+
+    /// If this is set to `true`, the `service` and `ddsource` of logs are converted to lowercase.
+    ///
+    /// When this changes a value, the value as received is kept in the metadata of the log, as
+    /// `service_original` or `ddsource_original`. Logs are routed by `ddsource` after it is
+    /// converted.
+    #[configurable(metadata(docs::advanced))]
+    #[serde(default = "crate::serde::default_false")]
+    normalize_service_names: bool,
+
+    /// The keys of the tags to promote from `ddtags` to top-level fields of logs.
+    ///
+    /// A tag such as `env:prod` is written to an `env` field set to `prod`. Tags without a value
+    /// aren't promoted, and only the first value of a key is promoted. With the `vector` log
+    /// namespace, tags are only promoted to logs decoded as objects.
+    #[configurable(metadata(docs::advanced))]
+    #[configurable(metadata(docs::examples = "env", docs::examples = "version"))]
+    #[serde(default)]
+    promote_tags: Vec<String>,
+
+    /// What to do with a promoted tag whose key is already a field of the log.
+    #[configurable(metadata(docs::advanced))]
+    #[serde(default)]
+    promote_conflict: PromoteConflict,
+
+    /// If this is set to `true`, the tags whose key is in `promote_tags` are removed from `ddtags`.
+    ///
+    /// This includes the tags that weren't written to the log because of `promote_conflict`.
+    #[configurable(metadata(docs::advanced))]
+    #[serde(default = "crate::serde::default_false")]
+    remove_promoted: bool,
+
+    /// If this is set to `true`, the `dd.trace_id` and `dd.span_id` fields of logs are copied to
+    /// their metadata, as `trace_id` and `span_id`.
+    ///
+    /// The fields are read from logs decoded as objects, or parsed from the message of logs left as
+    /// bytes by the codec. IDs may be integers or strings of digits, and are written as strings.
+    /// Missing or malformed IDs are ignored, and the fields themselves are left untouched.
+    #[configurable(metadata(docs::advanced))]
+    #[serde(default = "crate::serde::default_false")]
+    extract_trace_context: bool,
+
+    /// If this is set to `true`, logs are numbered in the order they were sent in within their
+    /// request, with a `request_seq` metadata field starting at `0`.
+    ///
+    /// The logs of a request are always sent on in their original order, including the events
+    /// decoded from the frames of a single message, but later components may reorder them. The
+    /// number lets consumers detect and repair that.
+    #[configurable(metadata(docs::advanced))]
+    #[serde(default = "crate::serde::default_false")]
+    annotate_order: bool,
+
+    /// Splits log messages into their lines, or joins consecutive log messages, before they are
+    /// decoded.
+    ///
+    /// The logs decoded from a split or joined message get the reserved attributes of the message
+    /// they were split from, or of the first message they were joined from.
+    #[configurable(derived)]
+    #[configurable(metadata(docs::advanced))]
+    multiline: Option<multiline::MultilineMessagesConfig>,
+
+    /// The path to a file mapping the `service` of logs to the Datadog API key to send them with.
+    ///
+    /// The file is a YAML (or JSON) mapping of services to API keys, such as `checkout: <api key>`.
+    /// The key of the service of each log, once normalized by `normalize_service_names`, is stored
+    /// with the log as if it had been sent with the request, so that the `datadog_logs` sink sends
+    /// it with that key. Which key wins when the request has one too is set by
+    /// `service_api_key_precedence`.
+    ///
+    /// The file is watched for changes, and the new keys apply to subsequent requests. If the file
+    /// can't be read or parsed, the previously loaded keys are kept.
+    #[configurable(metadata(docs::advanced))]
+    #[configurable(metadata(docs::examples = "/etc/vector/service_api_keys.yaml"))]
+    service_api_key_map_file: Option<PathBuf>,
+
+    /// Which API key a log is stored with, when both its service is in `service_api_key_map_file`
+    /// and its request has a key stored with `store_api_key`.
+    #[configurable(metadata(docs::advanced))]
+    #[serde(default)]
+    service_api_key_precedence: ServiceApiKeyPrecedence,
+
+    /// The directory to dump the bodies of log requests that fail to decode to.
+    ///
+    /// Each dump is a pair of files sharing a unique name: the body as received, with a `.body`
+    /// extension, and a JSON file with a `.json` extension containing the path, headers, and error
+    /// of the request. This is meant to help debug encoding issues on the agent side, and doesn't
+    /// change the response to the request.
+    #[configurable(metadata(docs::advanced))]
+    #[configurable(metadata(docs::examples = "/var/lib/vector/datadog_agent/failed_requests"))]
+    failed_request_dump_path: Option<PathBuf>,
+
+    /// The maximum number of bytes of a body to dump.
+    ///
+    /// Longer bodies are truncated.
+    #[configurable(metadata(docs::advanced))]
+    #[configurable(metadata(docs::type_unit = "bytes"))]
+    #[serde(default = "default_max_dump_bytes")]
+    max_dump_bytes: usize,
+
+    /// The maximum number of requests dumped per minute.
+    #[configurable(metadata(docs::advanced))]
+    #[serde(default = "default_max_dumps_per_minute")]
+    max_dumps_per_minute: u32,
+
+    /// The number of consecutive requests whose events fail to be delivered after which the
+    /// `GET /health` route reports the source as unhealthy.
+    ///
+    /// The route answers with a 200 status while the source is healthy, and a 503 status once this
+    /// many requests in a row failed, until one succeeds, or once the source is shutting down. It
+    /// doesn't require an API key, and is meant for liveness and readiness probes.
+    #[configurable(metadata(docs::advanced))]
+    #[serde(default = "default_health_failure_threshold")]
+    health_failure_threshold: NonZeroU32,
+
     /// The namespace to use for logs. This overrides the global setting.
     #[serde(default)]
     #[configurable(metadata(docs::hidden))]
@@ -109,6 +404,36 @@ pub struct DatadogAgentConfig {
     #[configurable(derived)]
     tls: Option<TlsEnableableConfig>,
 
+    #[configurable(derived)]
+    #[serde(default)]
+    keepalive: KeepaliveConfig,
+
+    #[configurable(derived)]
+    #[serde(default)]
+    decode_pool: DecodePoolConfig,
+
+    /// The origins allowed to send logs from a browser.
+    ///
+    /// When set, CORS preflight requests to the logs routes are answered, and responses to
+    /// allowed origins include the appropriate CORS headers. Each origin is of the form
+    /// `scheme://host[:port]`, or `*` to allow any origin.
+    #[configurable(metadata(docs::advanced))]
+    #[configurable(metadata(docs::examples = "https://example.com"))]
+    #[serde(default)]
+    cors_allowed_origins: Vec<String>,
+
+    /// Serves logs on a dedicated listener instead of the one configured with `address`.
+    #[configurable(metadata(docs::advanced))]
+    logs_listener: Option<ListenerConfig>,
+
+    /// Serves metrics on a dedicated listener instead of the one configured with `address`.
+    #[configurable(metadata(docs::advanced))]
+    metrics_listener: Option<ListenerConfig>,
+
+    /// Serves traces on a dedicated listener instead of the one configured with `address`.
+    #[configurable(metadata(docs::advanced))]
+    traces_listener: Option<ListenerConfig>,
+
     #[configurable(derived)]
     #[serde(default = "default_framing_message_based")]
     framing: FramingConfig,
@@ -120,6 +445,226 @@ pub struct DatadogAgentConfig {
     #[configurable(derived)]
     #[serde(default, deserialize_with = "bool_or_struct")]
     acknowledgements: SourceAcknowledgementsConfig,
+
+    /// The maximum number of seconds to wait for the events of a request to be acknowledged.
+    ///
+    /// When the events aren't acknowledged in time, the request is answered with a 504 status so
+    /// that the agent retries it. By default, the source waits until the events are acknowledged.
+    /// Only applies when acknowledgements are enabled.
+    #[configurable(metadata(docs::advanced))]
+    #[configurable(metadata(docs::type_unit = "seconds"))]
+    acknowledgement_timeout_secs: Option<u64>,
+}
+
+const fn default_max_dump_bytes() -> usize {
+    1024 * 1024
+}
+
+const fn default_max_dumps_per_minute() -> u32 {
+    10
+}
+
+fn default_truncation_marker() -> String {
+    "...[truncated]".to_owned()
+}
+
+/// What to do with log messages larger than `max_message_bytes`.
+#[configurable_component]
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum OversizedMessageAction {
+    /// Truncate the message to `max_message_bytes`, ending it with `truncation_marker`.
+    ///
+    /// The cut never splits a UTF-8 character, and the log is flagged with a `truncated` metadata
+    /// field set to `true`.
+    #[default]
+    Truncate,
+
+    /// Drop the log.
+    Drop,
+
+    /// Pass the message through unchanged.
+    Pass,
+}
+
+/// What to do with a request when one of its log messages fails to be decoded.
+#[configurable_component]
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DecodeErrorAction {
+    /// Skip what couldn't be decoded, and accept the rest of the request.
+    ///
+    /// A frame that fails to be parsed is skipped. When the framing itself fails, the rest of the
+    /// message is skipped, since where its next frame starts is unknown.
+    #[default]
+    SkipMessage,
+
+    /// Reject the whole request with a `400 Bad Request` response, which the Agent retries.
+    FailRequest,
+}
+
+/// Which API key a log is stored with, when both its service and its request have one.
+#[configurable_component]
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ServiceApiKeyPrecedence {
+    /// The key of the service, from `service_api_key_map_file`.
+    #[default]
+    Service,
+
+    /// The key of the request. The key of the service only applies to requests without one.
+    Request,
+}
+
+/// A reserved attribute of logs.
+#[configurable_component]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ReservedField {
+    /// The `status` of the log.
+    Status,
+
+    /// The `timestamp` of the log.
+    Timestamp,
+
+    /// The `hostname` of the log.
+    Hostname,
+
+    /// The `service` of the log.
+    Service,
+
+    /// The `ddsource` of the log.
+    Ddsource,
+
+    /// The `ddtags` of the log.
+    Ddtags,
+}
+
+impl ReservedField {
+    const ALL: [Self; 6] = [
+        Self::Status,
+        Self::Timestamp,
+        Self::Hostname,
+        Self::Service,
+        Self::Ddsource,
+        Self::Ddtags,
+    ];
+
+    /// The name of the field, or of the metadata with the `vector` log namespace.
+    const fn name(self) -> &'static str {
+        match self {
+            Self::Status => "status",
+            Self::Timestamp => "timestamp",
+            Self::Hostname => "hostname",
+            Self::Service => "service",
+            Self::Ddsource => "ddsource",
+            Self::Ddtags => "ddtags",
+        }
+    }
+
+    /// The kind of the field, and its semantic meaning.
+    fn definition(self) -> (Kind, &'static str) {
+        match self {
+            Self::Status => (Kind::bytes(), "severity"),
+            Self::Timestamp => (Kind::timestamp(), "timestamp"),
+            Self::Hostname => (Kind::bytes(), "host"),
+            Self::Service => (Kind::bytes(), "service"),
+            Self::Ddsource => (Kind::bytes(), "source"),
+            Self::Ddtags => (Kind::bytes(), "tags"),
+        }
+    }
+}
+
+fn default_reserved_fields() -> Vec<ReservedField> {
+    ReservedField::ALL.to_vec()
+}
+
+/// What to do with a promoted tag whose key is already a field of the log.
+#[configurable_component]
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum PromoteConflict {
+    /// Keep the existing field, and don't promote the tag.
+    #[default]
+    KeepExisting,
+
+    /// Overwrite the existing field with the tag.
+    Overwrite,
+
+    /// Promote the tag to a field named after its key with a `_tag` suffix instead, such as
+    /// `env_tag`.
+    Rename,
+}
+
+fn default_decode_workers() -> NonZeroUsize {
+    NonZeroUsize::new(crate::num_threads()).expect("at least one thread")
+}
+
+fn default_health_failure_threshold() -> NonZeroU32 {
+    NonZeroU32::new(3).expect("static non-zero number")
+}
+
+/// HTTP keep-alive settings of the `datadog_agent` source.
+#[configurable_component]
+#[derive(Clone, Copy, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct KeepaliveConfig {
+    /// The maximum time a connection is kept open for, in seconds.
+    ///
+    /// The first response sent on a connection older than this has a `Connection: close` header,
+    /// and the connection is closed once it's sent. Clients then reconnect, which spreads the load
+    /// across the instances behind a load balancer. By default, connections are kept open for as
+    /// long as clients use them.
+    #[configurable(metadata(docs::type_unit = "seconds"))]
+    #[configurable(metadata(docs::examples = 300))]
+    max_connection_age_secs: Option<u64>,
+}
+
+/// Settings of the workers decompressing and parsing the bodies of log requests.
+#[configurable_component]
+#[derive(Clone, Copy, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct DecodePoolConfig {
+    /// The maximum number of log requests decoded at once.
+    ///
+    /// Decoding runs on dedicated threads, so that large payloads don't delay accepting other
+    /// requests. Defaults to the number of available CPUs.
+    #[configurable(metadata(docs::examples = 4))]
+    workers: Option<NonZeroUsize>,
+
+    /// The maximum number of log requests waiting for a worker.
+    ///
+    /// Requests received while the queue is full are rejected with a `429 Too Many Requests`
+    /// response, which the agent retries, instead of being buffered.
+    #[serde(default = "default_max_queued_requests")]
+    max_queued_requests: usize,
+}
+
+impl Default for DecodePoolConfig {
+    fn default() -> Self {
+        Self {
+            workers: None,
+            max_queued_requests: default_max_queued_requests(),
+        }
+    }
+}
+
+const fn default_max_queued_requests() -> usize {
+    256
+}
+
+/// A dedicated listener for one kind of data accepted by the `datadog_agent` source.
+#[configurable_component]
+#[derive(Clone, Debug)]
+pub struct ListenerConfig {
+    /// The socket address to accept connections on.
+    ///
+    /// It _must_ include a port.
+    #[configurable(metadata(docs::examples = "0.0.0.0:8081"))]
+    address: SocketAddr,
+
+    #[configurable(derived)]
+    tls: Option<TlsEnableableConfig>,
 }
 
 impl GenerateConfig for DatadogAgentConfig {
@@ -127,137 +672,485 @@ impl GenerateConfig for DatadogAgentConfig {
         toml::Value::try_from(Self {
             address: "0.0.0.0:8080".parse().unwrap(),
             tls: None,
+            cors_allowed_origins: Vec::new(),
+            logs_listener: None,
+            metrics_listener: None,
+            traces_listener: None,
             store_api_key: true,
+            allowed_api_keys: Vec::new(),
             framing: default_framing_message_based(),
             decoding: default_decoding(),
             acknowledgements: SourceAcknowledgementsConfig::default(),
+            acknowledgement_timeout_secs: None,
             disable_logs: false,
             disable_metrics: false,
             disable_traces: false,
             multiple_outputs: false,
+            map_ddsource_to_output: false,
+            ddsource_outputs: Vec::new(),
+            api_key_routing: HashMap::new(),
+            include_request_metadata: false,
+            trusted_proxies: Vec::new(),
+            verbose_responses: false,
+            origin_as_tag: false,
+            max_message_bytes: None,
+            max_body_bytes: None,
+            on_oversized: OversizedMessageAction::Truncate,
+            on_decode_error: DecodeErrorAction::SkipMessage,
+            truncation_marker: default_truncation_marker(),
+            reserved_fields: default_reserved_fields(),
+            normalize_service_names: false,
+            promote_tags: Vec::new(),
+            promote_conflict: PromoteConflict::KeepExisting,
+            remove_promoted: false,
+            extract_trace_context: false,
+            annotate_order: false,
+            multiline: None,
+            service_api_key_map_file: None,
+            service_api_key_precedence: ServiceApiKeyPrecedence::Service,
+            failed_request_dump_path: None,
+            max_dump_bytes: default_max_dump_bytes(),
+            max_dumps_per_minute: default_max_dumps_per_minute(),
+            health_failure_threshold: default_health_failure_threshold(),
+            keepalive: KeepaliveConfig::default(),
+            decode_pool: DecodePoolConfig::default(),
             log_namespace: Some(false),
         })
         .unwrap()
     }
 }
 
+impl DatadogAgentConfig {
+    /// The `ddsource` values given a dedicated output, along with the name of that output.
+    fn ddsource_outputs(&self) -> impl Iterator<Item = (&str, String)> {
+        let enabled = self.multiple_outputs && self.map_ddsource_to_output && !self.disable_logs;
+        self.ddsource_outputs
+            .iter()
+            .filter(move |_| enabled)
+            .map(|ddsource| (ddsource.as_str(), format!("{}.{}", LOGS, ddsource)))
+    }
+
+    /// The names of the outputs dedicated to the logs sent with given API keys.
+    fn api_key_outputs(&self) -> impl Iterator<Item = String> + '_ {
+        let enabled = self.multiple_outputs && !self.disable_logs;
+        self.api_key_routing
+            .values()
+            .filter(move |_| enabled)
+            .map(String::as_str)
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .map(|name| format!("{}.{}", LOGS, name))
+    }
+}
+
 #[async_trait::async_trait]
 #[typetag::serde(name = "datadog_agent")]
 impl SourceConfig for DatadogAgentConfig {
     async fn build(&self, cx: SourceContext) -> crate::Result<sources::Source> {
         let log_namespace = cx.log_namespace(self.log_namespace);
 
+        // There is no logs output to get a schema from when logs are disabled, but then no log is
+        // ever built either.
         let logs_schema_definition = cx
             .schema_definitions
             .get(&Some(LOGS.to_owned()))
             .or_else(|| cx.schema_definitions.get(&None))
-            .expect("registered log schema required")
-            .clone();
+            .cloned()
+            .or_else(|| self.disable_logs.then(schema::Definition::any))
+            .expect("registered log schema required");
 
         let decoder =
             DecodingConfig::new(self.framing.clone(), self.decoding.clone(), log_namespace).build();
 
         let tls = MaybeTlsSettings::from_config(&self.tls, true)?;
-        let source = DatadogAgentSource::new(
+        let mut source = DatadogAgentSource::new(
             self.store_api_key,
             decoder,
             tls.http_protocol_name(),
             logs_schema_definition,
             log_namespace,
         );
-        let listener = tls.bind(&self.address).await?;
+        if !self.allowed_api_keys.is_empty() {
+            source.api_key_extractor.allowed_keys = Some(Arc::new(
+                self.allowed_api_keys
+                    .iter()
+                    .map(|key| key.inner().to_owned())
+                    .collect(),
+            ));
+        }
+        source.logs_cors = logs::build_cors(&self.cors_allowed_origins)?;
+        source.include_request_metadata = self.include_request_metadata;
+        source.verbose_responses = self.verbose_responses;
+        source.origin_as_tag = self.origin_as_tag;
+        source.max_body_bytes = self.max_body_bytes;
+        source.decode_pool = Arc::new(DecodePool::new(
+            self.decode_pool
+                .workers
+                .unwrap_or_else(default_decode_workers),
+            self.decode_pool.max_queued_requests,
+        ));
+        if let Some(max_bytes) = self.max_message_bytes.filter(|max_bytes| {
+            self.on_oversized == OversizedMessageAction::Truncate
+                && self.truncation_marker.len() >= *max_bytes
+        }) {
+            return Err(format!(
+                "`truncation_marker` must be shorter than `max_message_bytes` ({} bytes)",
+                max_bytes
+            )
+            .into());
+        }
+        source.oversized_messages =
+            self.max_message_bytes
+                .map(|max_bytes| logs::OversizedMessages {
+                    max_bytes,
+                    action: self.on_oversized,
+                    marker: self.truncation_marker.clone(),
+                });
+        source.on_decode_error = self.on_decode_error;
+        source.reserved_fields = self.reserved_fields.clone();
+        source.normalize_service_names = self.normalize_service_names;
+        source.tag_promotion = (!self.promote_tags.is_empty()).then(|| logs::TagPromotion {
+            keys: self.promote_tags.clone(),
+            conflict: self.promote_conflict,
+            remove: self.remove_promoted,
+        });
+        source.extract_trace_context = self.extract_trace_context;
+        source.annotate_order = self.annotate_order;
+        if let Some(config) = &self.multiline {
+            source.multiline = multiline::MultilineMessages::new(config)?;
+        }
+        if let Some(path) = &self.service_api_key_map_file {
+            source.service_api_keys = Some(Arc::new(service_api_keys::ServiceApiKeys::load(
+                path.clone(),
+            )?));
+        }
+        source.service_api_key_precedence = self.service_api_key_precedence;
+        source.acknowledgement_timeout = self.acknowledgement_timeout_secs.map(Duration::from_secs);
+        source.health = Arc::new(DeliveryHealth::new(self.health_failure_threshold));
+        source.trusted_proxies = self
+            .trusted_proxies
+            .iter()
+            .map(|proxy| {
+                IpCidr::from_str(proxy)
+                    .map_err(|error| format!("Invalid trusted proxy {:?}: {}", proxy, error))
+            })
+            .collect::<Result<_, _>>()?;
+        if let Some(path) = &self.failed_request_dump_path {
+            std::fs::create_dir_all(path).map_err(|error| {
+                format!(
+                    "Could not create failed request dump directory {:?}: {}",
+                    path, error
+                )
+            })?;
+            source.failed_request_dumper = Some(Arc::new(FailedRequestDumper::new(
+                path.clone(),
+                self.max_dump_bytes,
+                self.max_dumps_per_minute,
+            )));
+        }
+        if self.map_ddsource_to_output && !self.multiple_outputs {
+            return Err(
+                "`map_ddsource_to_output` requires `multiple_outputs` to be enabled".into(),
+            );
+        }
+        if self.map_ddsource_to_output && !self.reserved_fields.contains(&ReservedField::Ddsource) {
+            return Err(
+                "`map_ddsource_to_output` requires `reserved_fields` to include `ddsource`".into(),
+            );
+        }
+        if !self.api_key_routing.is_empty() && !self.multiple_outputs {
+            return Err("`api_key_routing` requires `multiple_outputs` to be enabled".into());
+        }
+        if let Some(port) = self.api_key_outputs().find(|port| {
+            self.ddsource_outputs()
+                .any(|(_, ddsource_port)| *port == ddsource_port)
+        }) {
+            return Err(format!(
+                "Output `{}` is used by both `ddsource_outputs` and `api_key_routing`",
+                port
+            )
+            .into());
+        }
+        let routed_output = |port: String| {
+            let schema_definition = cx
+                .schema_definitions
+                .get(&Some(port.clone()))
+                .expect("registered log schema required")
+                .clone();
+            logs::RoutedOutput {
+                port,
+                schema_definition: Arc::new(schema_definition),
+            }
+        };
+        source.ddsource_outputs = Arc::new(
+            self.ddsource_outputs()
+                .map(|(ddsource, port)| (ddsource.to_owned(), routed_output(port)))
+                .collect(),
+        );
+        if !self.disable_logs {
+            source.api_key_outputs = Arc::new(logs::ApiKeyOutputs::new(
+                self.api_key_routing.iter().map(|(api_key, name)| {
+                    (
+                        api_key.as_str(),
+                        routed_output(format!("{}.{}", LOGS, name)),
+                    )
+                }),
+            )?);
+        }
         let acknowledgements = cx.do_acknowledgements(self.acknowledgements);
-        let filters = source.build_warp_filters(cx.out, acknowledgements, self)?;
         let shutdown = cx.shutdown;
 
-        info!(message = "Building HTTP server.", address = %self.address);
+        if let Some(service_api_keys) = &source.service_api_keys {
+            tokio::spawn(Arc::clone(service_api_keys).watch(shutdown.clone()));
+        }
+
+        let health = Arc::clone(&source.health);
+        let shutdown_begun = shutdown.clone();
+        tokio::spawn(async move {
+            shutdown_begun.await;
+            health.begin_shutdown();
+        });
+        let health_route = health::build_warp_filter(Arc::clone(&source.health));
 
-        Ok(Box::pin(async move {
-            let span = Span::current();
-            let routes = filters
-                .with(warp::trace(move |_info| span.clone()))
-                .recover(|r: Rejection| async move {
-                    if let Some(e_msg) = r.find::<ErrorMessage>() {
-                        let json = warp::reply::json(e_msg);
-                        Ok(warp::reply::with_status(json, e_msg.status_code()))
-                    } else {
-                        // other internal error - will return 500 internal server error
-                        Err(r)
-                    }
-                });
+        let endpoints: [(bool, &Option<ListenerConfig>, BuildWarpFilter); 3] = [
+            (
+                !self.disable_logs,
+                &self.logs_listener,
+                logs::build_warp_filter,
+            ),
+            (
+                !self.disable_traces,
+                &self.traces_listener,
+                traces::build_warp_filter,
+            ),
+            (
+                !self.disable_metrics,
+                &self.metrics_listener,
+                metrics::build_warp_filter,
+            ),
+        ];
 
-            warp::serve(routes)
-                .serve_incoming_with_graceful_shutdown(
-                    listener.accept_stream(),
-                    shutdown.map(|_| ()),
-                )
-                .await;
+        let max_connection_age = self
+            .keepalive
+            .max_connection_age_secs
+            .map(Duration::from_secs);
+        let mut servers = Vec::new();
+        let mut primary_filters: Option<BoxedFilter<(Response,)>> = None;
+        for (enabled, listener, build_warp_filter) in endpoints {
+            if !enabled {
+                continue;
+            }
+            match listener {
+                Some(listener) => {
+                    let tls = MaybeTlsSettings::from_config(&listener.tls, true)?;
+                    let mut source = source.clone();
+                    source.protocol = tls.http_protocol_name();
+                    let filters = build_warp_filter(
+                        acknowledgements,
+                        self.multiple_outputs,
+                        cx.out.clone(),
+                        source,
+                    );
+                    servers.push(serve(
+                        health_route.clone().or(filters).unify().boxed(),
+                        tls.bind(&listener.address).await?,
+                        listener.address,
+                        max_connection_age,
+                        shutdown.clone(),
+                    ));
+                }
+                None => {
+                    let filter = build_warp_filter(
+                        acknowledgements,
+                        self.multiple_outputs,
+                        cx.out.clone(),
+                        source.clone(),
+                    );
+                    primary_filters = Some(match primary_filters {
+                        Some(filters) => filters.or(filter).unify().boxed(),
+                        None => filter,
+                    });
+                }
+            }
+        }
+
+        if let Some(mut filters) = primary_filters {
+            let endpoints = [
+                (self.disable_logs, LOGS, logs::PATHS),
+                (self.disable_traces, TRACES, traces::PATHS),
+                (self.disable_metrics, METRICS, metrics::PATHS),
+            ];
+            for (disabled, endpoint, paths) in endpoints {
+                if disabled {
+                    filters = filters
+                        .or(disabled_endpoint(endpoint, paths))
+                        .unify()
+                        .boxed();
+                }
+            }
+            servers.push(serve(
+                health_route.or(filters).unify().boxed(),
+                tls.bind(&self.address).await?,
+                self.address,
+                max_connection_age,
+                shutdown,
+            ));
+        }
 
+        if servers.is_empty() {
+            return Err("At least one of the supported data type shall be enabled".into());
+        }
+
+        Ok(Box::pin(async move {
+            join_all(servers).await;
             Ok(())
         }))
     }
 
     fn outputs(&self, global_log_namespace: LogNamespace) -> Vec<SourceOutput> {
-        let definition = self
+        let mut definition = self
             .decoding
-            .schema_definition(global_log_namespace.merge(self.log_namespace))
-            .with_source_metadata(
-                Self::NAME,
-                Some(LegacyKey::InsertIfEmpty(owned_value_path!("status"))),
-                &owned_value_path!("status"),
-                Kind::bytes(),
-                Some("severity"),
-            )
-            .with_source_metadata(
-                Self::NAME,
-                Some(LegacyKey::InsertIfEmpty(owned_value_path!("timestamp"))),
-                &owned_value_path!("timestamp"),
-                Kind::timestamp(),
-                Some("timestamp"),
-            )
-            .with_source_metadata(
+            .schema_definition(global_log_namespace.merge(self.log_namespace));
+        for field in ReservedField::ALL
+            .into_iter()
+            .filter(|field| self.reserved_fields.contains(field))
+        {
+            let (kind, meaning) = field.definition();
+            definition = definition.with_source_metadata(
                 Self::NAME,
-                Some(LegacyKey::InsertIfEmpty(owned_value_path!("hostname"))),
-                &owned_value_path!("hostname"),
-                Kind::bytes(),
-                Some("host"),
-            )
+                Some(LegacyKey::InsertIfEmpty(owned_value_path!(field.name()))),
+                &owned_value_path!(field.name()),
+                kind,
+                Some(meaning),
+            );
+        }
+        definition = definition
             .with_source_metadata(
                 Self::NAME,
-                Some(LegacyKey::InsertIfEmpty(owned_value_path!("service"))),
-                &owned_value_path!("service"),
-                Kind::bytes(),
-                Some("service"),
+                Some(LegacyKey::InsertIfEmpty(owned_value_path!("origin"))),
+                &owned_value_path!("origin"),
+                Kind::bytes().or_undefined(),
+                None,
             )
-            .with_source_metadata(
+            .with_standard_vector_source_metadata();
+
+        if self.max_message_bytes.is_some() && self.on_oversized == OversizedMessageAction::Truncate
+        {
+            definition = definition.with_source_metadata(
                 Self::NAME,
-                Some(LegacyKey::InsertIfEmpty(owned_value_path!("ddsource"))),
-                &owned_value_path!("ddsource"),
-                Kind::bytes(),
-                Some("source"),
-            )
-            .with_source_metadata(
+                Some(LegacyKey::InsertIfEmpty(owned_value_path!("truncated"))),
+                &owned_value_path!("truncated"),
+                Kind::boolean().or_undefined(),
+                None,
+            );
+        }
+
+        if self.normalize_service_names {
+            for field in ["service_original", "ddsource_original"] {
+                definition = definition.with_source_metadata(
+                    Self::NAME,
+                    Some(LegacyKey::InsertIfEmpty(owned_value_path!(field))),
+                    &owned_value_path!(field),
+                    Kind::bytes().or_undefined(),
+                    None,
+                );
+            }
+        }
+
+        if self.extract_trace_context {
+            for field in ["trace_id", "span_id"] {
+                definition = definition.with_source_metadata(
+                    Self::NAME,
+                    Some(LegacyKey::InsertIfEmpty(owned_value_path!(field))),
+                    &owned_value_path!(field),
+                    Kind::bytes().or_undefined(),
+                    None,
+                );
+            }
+        }
+
+        if self.annotate_order {
+            definition = definition.with_source_metadata(
                 Self::NAME,
-                Some(LegacyKey::InsertIfEmpty(owned_value_path!("ddtags"))),
-                &owned_value_path!("ddtags"),
-                Kind::bytes(),
-                Some("tags"),
-            )
-            .with_standard_vector_source_metadata();
+                Some(LegacyKey::InsertIfEmpty(owned_value_path!("request_seq"))),
+                &owned_value_path!("request_seq"),
+                Kind::integer(),
+                None,
+            );
+        }
+
+        // With the `vector` namespace, the root of the log is whatever the codec decoded, which
+        // already allows any field when it's an object.
+        if global_log_namespace.merge(self.log_namespace) == LogNamespace::Legacy {
+            let conflict = self.promote_conflict;
+            for key in &self.promote_tags {
+                definition = definition.with_event_field(
+                    &owned_value_path!(key.as_str()),
+                    Kind::bytes().or_undefined(),
+                    None,
+                );
+                if conflict == PromoteConflict::Rename {
+                    definition = definition.with_event_field(
+                        &owned_value_path!(format!("{}_tag", key).as_str()),
+                        Kind::bytes().or_undefined(),
+                        None,
+                    );
+                }
+            }
+        }
+
+        if self.include_request_metadata {
+            for field in ["remote_addr", "agent_version", "user_agent"] {
+                definition = definition.with_source_metadata(
+                    Self::NAME,
+                    Some(LegacyKey::InsertIfEmpty(owned_value_path!(field))),
+                    &owned_value_path!(field),
+                    Kind::bytes().or_undefined(),
+                    None,
+                );
+            }
+        }
 
         if self.multiple_outputs {
-            vec![
-                SourceOutput::new_logs(DataType::Log, definition).with_port(LOGS),
-                SourceOutput::new_metrics().with_port(METRICS),
-                SourceOutput::new_traces().with_port(TRACES),
-            ]
+            let mut outputs = Vec::new();
+            if !self.disable_logs {
+                outputs.extend(
+                    self.ddsource_outputs()
+                        .map(|(_, port)| port)
+                        .chain(self.api_key_outputs())
+                        .map(|port| {
+                            SourceOutput::new_logs(DataType::Log, definition.clone())
+                                .with_port(port)
+                        }),
+                );
+                outputs.push(SourceOutput::new_logs(DataType::Log, definition).with_port(LOGS));
+            }
+            if !self.disable_metrics {
+                outputs.push(SourceOutput::new_metrics().with_port(METRICS));
+            }
+            if !self.disable_traces {
+                outputs.push(SourceOutput::new_traces().with_port(TRACES));
+            }
+            outputs
         } else {
             vec![SourceOutput::new_logs(DataType::all(), definition)]
         }
     }
 
     fn resources(&self) -> Vec<Resource> {
-        vec![Resource::tcp(self.address)]
+        std::iter::once(self.address)
+            .chain(
+                [
+                    &self.logs_listener,
+                    &self.metrics_listener,
+                    &self.traces_listener,
+                ]
+                .into_iter()
+                .flatten()
+                .map(|listener| listener.address),
+            )
+            .map(Resource::tcp)
+            .collect()
     }
 
     fn can_acknowledge(&self) -> bool {
@@ -265,15 +1158,6 @@ impl SourceConfig for DatadogAgentConfig {
     }
 }
 
-#[derive(Clone, Copy, Debug, Snafu)]
-pub(crate) enum ApiError {
-    BadRequest,
-    InvalidDataFormat,
-    ServerShutdown,
-}
-
-impl warp::reject::Reject for ApiError {}
-
 #[derive(Deserialize)]
 pub struct ApiKeyQueryParams {
     #[serde(rename = "dd-api-key")]
@@ -289,33 +1173,99 @@ pub(crate) struct DatadogAgentSource {
     pub(crate) decoder: Decoder,
     protocol: &'static str,
     logs_schema_definition: Arc<schema::Definition>,
+    logs_cors: Option<warp::cors::Builder>,
+    include_request_metadata: bool,
+    verbose_responses: bool,
+    origin_as_tag: bool,
+    max_body_bytes: Option<usize>,
+    pub(crate) decode_pool: Arc<DecodePool>,
+    oversized_messages: Option<logs::OversizedMessages>,
+    on_decode_error: DecodeErrorAction,
+    reserved_fields: Vec<ReservedField>,
+    normalize_service_names: bool,
+    tag_promotion: Option<logs::TagPromotion>,
+    extract_trace_context: bool,
+    annotate_order: bool,
+    multiline: Option<multiline::MultilineMessages>,
+    service_api_keys: Option<Arc<service_api_keys::ServiceApiKeys>>,
+    service_api_key_precedence: ServiceApiKeyPrecedence,
+    pub(crate) acknowledgement_timeout: Option<Duration>,
+    pub(crate) health: Arc<DeliveryHealth>,
+    trusted_proxies: Arc<[IpCidr]>,
+    failed_request_dumper: Option<Arc<FailedRequestDumper>>,
+    ddsource_outputs: Arc<HashMap<String, logs::RoutedOutput>>,
+    api_key_outputs: Arc<logs::ApiKeyOutputs>,
     events_received: Registered<EventsReceived>,
 }
 
+/// Matches the API key embedded in the path used by legacy agents, whatever its shape.
+static PATH_API_KEY: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^/v1/input/[^/]+").expect("static regex always compiles"));
+
 #[derive(Clone)]
 pub struct ApiKeyExtractor {
     matcher: Regex,
     store_api_key: bool,
+    allowed_keys: Option<Arc<HashSet<String>>>,
 }
 
 impl ApiKeyExtractor {
+    /// Extracts the API key of a request, rejecting the request if the key isn't allowed.
+    ///
+    /// The key is only returned if it's meant to be stored with the events.
     pub fn extract(
         &self,
         path: &str,
         header: Option<String>,
         query_params: Option<String>,
-    ) -> Option<Arc<str>> {
-        if !self.store_api_key {
-            return None;
+        endpoint: &'static str,
+    ) -> Result<Option<Arc<str>>, ErrorMessage> {
+        if !self.store_api_key && self.allowed_keys.is_none() {
+            return Ok(None);
         }
+        let api_key = self.extract_key(path, header, query_params, endpoint)?;
+        Ok(api_key.filter(|_| self.store_api_key))
+    }
+
+    /// Extracts the API key of a request like [`ApiKeyExtractor::extract`], but returns it even
+    /// if it isn't meant to be stored, such as to route the events of the request by their key.
+    pub fn extract_key(
+        &self,
+        path: &str,
+        header: Option<String>,
+        query_params: Option<String>,
+        endpoint: &'static str,
+    ) -> Result<Option<Arc<str>>, ErrorMessage> {
         // Grab from URL first
-        self.matcher
+        let api_key = self
+            .matcher
             .captures(path)
             .and_then(|cap| cap.name("api_key").map(|key| key.as_str()).map(Arc::from))
             // Try from query params
             .or_else(|| query_params.map(Arc::from))
             // Try from header next
-            .or_else(|| header.map(Arc::from))
+            .or_else(|| header.map(Arc::from));
+
+        if let Some(allowed_keys) = &self.allowed_keys {
+            let allowed = api_key
+                .as_deref()
+                .map_or(false, |key| allowed_keys.contains(key));
+            if !allowed {
+                return Err(reject(
+                    endpoint,
+                    RejectionReason::InvalidApiKey,
+                    StatusCode::FORBIDDEN,
+                    "Missing or invalid API key".to_string(),
+                ));
+            }
+        }
+
+        Ok(api_key)
+    }
+
+    /// Replaces the API key embedded in `path` by legacy agents, so that it can be logged.
+    pub fn redact_path<'a>(&self, path: &'a str) -> Cow<'a, str> {
+        PATH_API_KEY.replace(path, "/v1/input/[REDACTED]")
     }
 }
 
@@ -332,65 +1282,51 @@ impl DatadogAgentSource {
                 store_api_key,
                 matcher: Regex::new(r"^/v1/input/(?P<api_key>[[:alnum:]]{32})/??")
                     .expect("static regex always compiles"),
+                allowed_keys: None,
             },
             log_schema_host_key: log_schema().host_key(),
             log_schema_source_type_key: log_schema().source_type_key(),
             decoder,
             protocol,
             logs_schema_definition: Arc::new(logs_schema_definition),
+            logs_cors: None,
+            include_request_metadata: false,
+            verbose_responses: false,
+            origin_as_tag: false,
+            max_body_bytes: None,
+            decode_pool: Arc::new(DecodePool::new(
+                default_decode_workers(),
+                default_max_queued_requests(),
+            )),
+            oversized_messages: None,
+            on_decode_error: DecodeErrorAction::SkipMessage,
+            reserved_fields: default_reserved_fields(),
+            normalize_service_names: false,
+            tag_promotion: None,
+            extract_trace_context: false,
+            annotate_order: false,
+            multiline: None,
+            service_api_keys: None,
+            service_api_key_precedence: ServiceApiKeyPrecedence::Service,
+            acknowledgement_timeout: None,
+            health: Arc::new(DeliveryHealth::new(default_health_failure_threshold())),
+            trusted_proxies: Arc::from([]),
+            failed_request_dumper: None,
+            ddsource_outputs: Arc::new(HashMap::new()),
+            api_key_outputs: Arc::new(logs::ApiKeyOutputs::default()),
             log_namespace,
             events_received: register!(EventsReceived),
         }
     }
 
-    fn build_warp_filters(
-        &self,
-        out: SourceSender,
-        acknowledgements: bool,
-        config: &DatadogAgentConfig,
-    ) -> crate::Result<BoxedFilter<(Response,)>> {
-        let mut filters = (!config.disable_logs).then(|| {
-            logs::build_warp_filter(
-                acknowledgements,
-                config.multiple_outputs,
-                out.clone(),
-                self.clone(),
-            )
-        });
-
-        if !config.disable_traces {
-            let trace_filter = traces::build_warp_filter(
-                acknowledgements,
-                config.multiple_outputs,
-                out.clone(),
-                self.clone(),
-            );
-            filters = filters
-                .map(|f| f.or(trace_filter.clone()).unify().boxed())
-                .or(Some(trace_filter));
-        }
-
-        if !config.disable_metrics {
-            let metrics_filter = metrics::build_warp_filter(
-                acknowledgements,
-                config.multiple_outputs,
-                out,
-                self.clone(),
-            );
-            filters = filters
-                .map(|f| f.or(metrics_filter.clone()).unify().boxed())
-                .or(Some(metrics_filter));
-        }
-
-        filters.ok_or_else(|| "At least one of the supported data type shall be enabled".into())
-    }
-
     pub(crate) fn decode(
         &self,
         header: &Option<String>,
         mut body: Bytes,
         path: &str,
+        endpoint: &'static str,
     ) -> Result<Bytes, ErrorMessage> {
+        let compressed_size = body.len();
         if let Some(encodings) = header {
             for encoding in encodings.rsplit(',').map(str::trim) {
                 body = match encoding {
@@ -399,18 +1335,20 @@ impl DatadogAgentSource {
                         let mut decoded = Vec::new();
                         MultiGzDecoder::new(body.reader())
                             .read_to_end(&mut decoded)
-                            .map_err(|error| handle_decode_error(encoding, error))?;
+                            .map_err(|error| handle_decode_error(endpoint, encoding, error))?;
                         decoded.into()
                     }
                     "deflate" | "x-deflate" => {
                         let mut decoded = Vec::new();
                         ZlibDecoder::new(body.reader())
                             .read_to_end(&mut decoded)
-                            .map_err(|error| handle_decode_error(encoding, error))?;
+                            .map_err(|error| handle_decode_error(endpoint, encoding, error))?;
                         decoded.into()
                     }
                     encoding => {
-                        return Err(ErrorMessage::new(
+                        return Err(reject(
+                            endpoint,
+                            RejectionReason::UnsupportedContentType,
                             StatusCode::UNSUPPORTED_MEDIA_TYPE,
                             format!("Unsupported encoding {}", encoding),
                         ))
@@ -418,65 +1356,384 @@ impl DatadogAgentSource {
                 }
             }
         }
+        if is_compressed(header) {
+            emit!(DatadogAgentPayloadDecompressed {
+                endpoint,
+                compressed_size,
+                decompressed_size: body.len(),
+            });
+        }
         emit!(HttpBytesReceived {
             byte_size: body.len(),
-            http_path: path,
+            http_path: &self.api_key_extractor.redact_path(path),
             protocol: self.protocol,
         });
         Ok(body)
     }
 }
 
+/// Rejects requests to the routes of a disabled endpoint, with a body explaining why.
+///
+/// Requests to other routes are left to the other filters.
+fn disabled_endpoint(
+    endpoint: &'static str,
+    paths: &'static [&'static str],
+) -> BoxedFilter<(Response,)> {
+    warp::path::full()
+        .and_then(move |path: FullPath| async move {
+            let disabled = paths.iter().any(|prefix| {
+                path.as_str()
+                    .strip_prefix(prefix)
+                    .map_or(false, |rest| rest.is_empty() || rest.starts_with('/'))
+            });
+            let error = if disabled {
+                warp::reject::custom(ErrorMessage::new(
+                    StatusCode::NOT_FOUND,
+                    format!("The {} endpoint is disabled", endpoint),
+                ))
+            } else {
+                warp::reject::not_found()
+            };
+            Err::<Response, _>(error)
+        })
+        .boxed()
+}
+
+type BuildWarpFilter = fn(bool, bool, SourceSender, DatadogAgentSource) -> BoxedFilter<(Response,)>;
+
+async fn serve(
+    filters: BoxedFilter<(Response,)>,
+    listener: MaybeTlsListener,
+    address: SocketAddr,
+    max_connection_age: Option<Duration>,
+    shutdown: ShutdownSignal,
+) {
+    info!(message = "Building HTTP server.", address = %address);
+
+    let span = Span::current();
+    let routes = filters
+        .with(warp::trace(move |_info| span.clone()))
+        .recover(|r: Rejection| async move {
+            if let Some(e_msg) = r.find::<ErrorMessage>() {
+                let json = warp::reply::json(e_msg);
+                Ok(warp::reply::with_status(json, e_msg.status_code()))
+            } else {
+                // other internal error - will return 500 internal server error
+                Err(r)
+            }
+        });
+
+    let service = warp::service(routes);
+    let make_service = make_service_fn(move |conn: &MaybeTlsIncomingStream<TcpStream>| {
+        let service = service.clone();
+        let established = Instant::now();
+        // `warp::service` doesn't know the peer of the connection, so it's passed along with each
+        // request for `warp::ext` to extract.
+        let peer_addr = conn.peer_addr();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |mut request: Request<Body>| {
+                request.extensions_mut().insert(peer_addr);
+                let gzip = request
+                    .headers()
+                    .get_all(ACCEPT_ENCODING)
+                    .iter()
+                    .filter_map(|value| value.to_str().ok())
+                    .any(accepts_gzip);
+                let close = max_connection_age.map_or(false, |age| established.elapsed() >= age);
+                let mut service = service.clone();
+                async move {
+                    let response = service.call(request).await?;
+                    Ok::<_, Infallible>(finish_response(response, gzip, close).await)
+                }
+            }))
+        }
+    });
+
+    if let Err(error) = Server::builder(accept::from_stream(listener.accept_stream()))
+        .serve(make_service)
+        .with_graceful_shutdown(shutdown.map(|_| ()))
+        .await
+    {
+        error!(message = "Server error.", %error);
+    }
+}
+
+/// Adjusts a response to the request it answers, and to the connection it's sent on.
+///
+/// JSON bodies, such as those of errors and verbose responses, are compressed with gzip if the
+/// request accepts it. Once `close` is set, the connection is closed after the response.
+async fn finish_response(mut response: Response, gzip: bool, close: bool) -> Response {
+    let is_json = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .map_or(false, |content_type| content_type == "application/json");
+    if gzip && is_json && !response.headers().contains_key(CONTENT_ENCODING) {
+        if let Ok(body) = hyper::body::to_bytes(std::mem::take(response.body_mut())).await {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            let compressed = encoder.write_all(&body).and_then(|()| encoder.finish());
+            *response.body_mut() = match compressed {
+                Ok(compressed) => {
+                    let headers = response.headers_mut();
+                    headers.insert(CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+                    headers.remove(CONTENT_LENGTH);
+                    Body::from(compressed)
+                }
+                Err(_) => Body::from(body),
+            };
+        }
+    }
+    if close {
+        response
+            .headers_mut()
+            .insert(CONNECTION, HeaderValue::from_static("close"));
+    }
+    response
+}
+
+/// Returns whether an `Accept-Encoding` header value allows gzip.
+fn accepts_gzip(accept_encoding: &str) -> bool {
+    accept_encoding.split(',').any(|coding| {
+        let mut params = coding.split(';').map(str::trim);
+        let name = params.next().unwrap_or_default();
+        let disabled = params.any(|param| {
+            param
+                .strip_prefix("q=")
+                .and_then(|q| q.parse::<f32>().ok())
+                .map_or(false, |q| q == 0.0)
+        });
+        !disabled && ["gzip", "x-gzip", "*"].contains(&name.to_ascii_lowercase().as_str())
+    })
+}
+
+/// The number of seconds after which clients are asked to retry requests whose events couldn't be
+/// delivered.
+const RETRY_AFTER_SECS: u64 = 5;
+
 pub(crate) async fn handle_request(
     events: Result<Vec<Event>, ErrorMessage>,
     acknowledgements: bool,
+    acknowledgement_timeout: Option<Duration>,
+    health: Arc<DeliveryHealth>,
+    out: SourceSender,
+    output: Option<&str>,
+) -> Result<Response, Rejection> {
+    handle_routed_request(
+        events,
+        acknowledgements,
+        acknowledgement_timeout,
+        health,
+        out,
+        output,
+        |_| None,
+    )
+    .await
+}
+
+/// Handles a request like [`handle_request`], except that events for which `route` returns the
+/// name of an output are sent to that output instead of `output`.
+///
+/// Requests that fail to decode are answered with the status of their error, while requests whose
+/// events couldn't be delivered are answered with a 503 status, or a 504 status if their
+/// acknowledgement timed out, so that the agent retries them. The outcome of the delivery is
+/// recorded in `health`.
+pub(crate) async fn handle_routed_request(
+    events: Result<Vec<Event>, ErrorMessage>,
+    acknowledgements: bool,
+    acknowledgement_timeout: Option<Duration>,
+    health: Arc<DeliveryHealth>,
     mut out: SourceSender,
     output: Option<&str>,
+    route: impl Fn(&mut Event) -> Option<String>,
 ) -> Result<Response, Rejection> {
     match events {
         Ok(mut events) => {
             let receiver = BatchNotifier::maybe_apply_to(acknowledgements, &mut events);
-            let count = events.len();
 
-            if let Some(name) = output {
-                out.send_batch_named(name, events).await
-            } else {
-                out.send_batch(events).await
+            // The events of each output are sent as a single batch, in the order they were decoded
+            // in, which keeps the order of the logs of the request.
+            let mut routed: HashMap<String, Vec<Event>> = HashMap::new();
+            let mut unrouted = Vec::with_capacity(events.len());
+            for mut event in events {
+                match route(&mut event) {
+                    Some(name) => routed.entry(name).or_default().push(event),
+                    None => unrouted.push(event),
+                }
             }
-            .map_err(move |error: crate::source_sender::ClosedError| {
-                emit!(StreamClosedError { error, count });
-                warp::reject::custom(ApiError::ServerShutdown)
-            })?;
-            match receiver {
-                None => Ok(warp::reply().into_response()),
-                Some(receiver) => match receiver.await {
-                    BatchStatus::Delivered => Ok(warp::reply().into_response()),
-                    BatchStatus::Errored => Err(warp::reject::custom(ErrorMessage::new(
-                        StatusCode::INTERNAL_SERVER_ERROR,
-                        "Error delivering contents to sink".into(),
-                    ))),
-                    BatchStatus::Rejected => Err(warp::reject::custom(ErrorMessage::new(
+
+            let failed = |status, message| {
+                health.record_failure(message);
+                retry_later(status, message)
+            };
+            let closed = |count| {
+                let failed = &failed;
+                move |error: crate::source_sender::ClosedError| {
+                    emit!(StreamClosedError { error, count });
+                    failed(
+                        StatusCode::SERVICE_UNAVAILABLE,
+                        "Source is shutting down, contents were not delivered",
+                    )
+                }
+            };
+            if !unrouted.is_empty() || routed.is_empty() {
+                let count = unrouted.len();
+                let sent = if let Some(name) = output {
+                    out.send_batch_named(name, unrouted).await
+                } else {
+                    out.send_batch(unrouted).await
+                };
+                if let Err(error) = sent {
+                    return Ok(closed(count)(error));
+                }
+            }
+            for (name, events) in routed {
+                let count = events.len();
+                if let Err(error) = out.send_batch_named(&name, events).await {
+                    return Ok(closed(count)(error));
+                }
+            }
+            let receiver = match receiver {
+                None => {
+                    health.record_success();
+                    return Ok(warp::reply().into_response());
+                }
+                Some(receiver) => receiver,
+            };
+            let status = match acknowledgement_timeout {
+                None => receiver.await,
+                Some(timeout) => match tokio::time::timeout(timeout, receiver).await {
+                    Ok(status) => status,
+                    Err(_) => {
+                        return Ok(failed(
+                            StatusCode::GATEWAY_TIMEOUT,
+                            "Timed out waiting for contents to be delivered to sink",
+                        ))
+                    }
+                },
+            };
+            match status {
+                BatchStatus::Delivered => {
+                    health.record_success();
+                    Ok(warp::reply().into_response())
+                }
+                BatchStatus::Errored => Ok(failed(
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    "Error delivering contents to sink",
+                )),
+                // The sink won't ever accept these contents, so retrying them is pointless. The
+                // contents did reach it though, so this doesn't make the source unhealthy.
+                BatchStatus::Rejected => {
+                    health.record_success();
+                    Err(warp::reject::custom(ErrorMessage::new(
                         StatusCode::BAD_REQUEST,
                         "Contents failed to deliver to sink".into(),
-                    ))),
-                },
+                    )))
+                }
             }
         }
         Err(err) => Err(warp::reject::custom(err)),
     }
 }
 
-fn handle_decode_error(encoding: &str, error: impl std::error::Error) -> ErrorMessage {
-    emit!(HttpDecompressError {
-        encoding,
-        error: &error
-    });
-    ErrorMessage::new(
+/// Builds the response to a request whose events couldn't be delivered, asking the client to retry
+/// it later.
+fn retry_later(status: StatusCode, message: &str) -> Response {
+    let error = ErrorMessage::new(status, message.to_owned());
+    let reply = warp::reply::with_status(warp::reply::json(&error), status);
+    warp::reply::with_header(
+        reply,
+        http::header::RETRY_AFTER,
+        RETRY_AFTER_SECS.to_string(),
+    )
+    .into_response()
+}
+
+/// Returns `true` if the `Content-Encoding` header names any encoding other than `identity`.
+pub(crate) fn is_compressed(header: &Option<String>) -> bool {
+    header.as_deref().map_or(false, |encodings| {
+        encodings
+            .split(',')
+            .map(str::trim)
+            .any(|encoding| !encoding.is_empty() && encoding != "identity")
+    })
+}
+
+fn handle_decode_error(
+    endpoint: &'static str,
+    encoding: &str,
+    error: impl std::error::Error,
+) -> ErrorMessage {
+    reject(
+        endpoint,
+        RejectionReason::Decompression,
         StatusCode::UNPROCESSABLE_ENTITY,
-        format!("Failed decompressing payload with {} decoder.", encoding),
+        format!(
+            "Failed decompressing payload with {} decoder: {}",
+            encoding, error
+        ),
     )
 }
 
+/// Why a request was rejected.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum RejectionReason {
+    /// The body isn't valid JSON, or doesn't match the expected schema.
+    JsonParse,
+
+    /// The body isn't a valid protobuf or MessagePack payload.
+    PayloadDecode,
+
+    /// The body couldn't be decompressed.
+    Decompression,
+
+    /// The API key is missing, or isn't one of `allowed_api_keys`.
+    InvalidApiKey,
+
+    /// The `Content-Encoding` of the body isn't supported.
+    UnsupportedContentType,
+
+    /// The body is larger than `max_body_bytes`.
+    BodyTooLarge,
+
+    /// Too many requests are waiting to be decoded.
+    Overloaded,
+
+    /// A log message failed to be decoded, with `on_decode_error` set to `fail_request`.
+    MessageDecode,
+}
+
+impl RejectionReason {
+    /// The value of the `reason` field of the error body, and of the `reason` tag of the internal
+    /// metrics.
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::JsonParse => "json_parse",
+            Self::PayloadDecode => "payload_decode",
+            Self::Decompression => "decompression",
+            Self::InvalidApiKey => "invalid_api_key",
+            Self::UnsupportedContentType => "unsupported_content_type",
+            Self::BodyTooLarge => "body_too_large",
+            Self::Overloaded => "overloaded",
+            Self::MessageDecode => "message_decode",
+        }
+    }
+}
+
+/// Builds the error rejecting a request to `endpoint`, reporting why it was rejected.
+pub(crate) fn reject(
+    endpoint: &'static str,
+    reason: RejectionReason,
+    status: StatusCode,
+    message: String,
+) -> ErrorMessage {
+    emit!(DatadogAgentRequestRejected {
+        endpoint,
+        reason,
+        status_code: status.as_u16(),
+        message: &message,
+    });
+    ErrorMessage::new(status, message).with_reason(reason.as_str())
+}
+
 // https://github.com/DataDog/datadog-agent/blob/a33248c2bc125920a9577af1e16f12298875a4ad/pkg/logs/processor/json.go#L23-L49
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(deny_unknown_fields)]
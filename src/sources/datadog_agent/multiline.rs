@@ -0,0 +1,233 @@
+use std::ops::Range;
+
+use bytes::{Bytes, BytesMut};
+use regex::bytes::Regex;
+use vector_config::configurable_component;
+
+use crate::{
+    line_agg::{Decision, Mode},
+    sources::{datadog_agent::LogMsg, util::multiline_config::Error},
+};
+
+/// Multi-line handling of log messages.
+///
+/// Lines are grouped as by the `multiline` option of the `file` source, except that there is no
+/// timeout, as all the lines of a request are at hand.
+#[configurable_component]
+#[derive(Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct MultilineMessagesConfig {
+    #[configurable(derived)]
+    #[serde(default)]
+    mode: MultilineMessagesMode,
+
+    /// Regular expression pattern that is used to match the start of a new message.
+    #[configurable(metadata(docs::examples = "^[^\\s]"))]
+    #[configurable(metadata(docs::examples = "^(INFO|ERROR) "))]
+    start_pattern: String,
+
+    /// Regular expression pattern that is used to determine whether or not more lines should be read.
+    ///
+    /// This setting must be configured in conjunction with `condition_mode`.
+    #[configurable(metadata(docs::examples = "^[\\s]+"))]
+    #[configurable(metadata(docs::examples = "^(INFO|ERROR) "))]
+    condition_pattern: String,
+
+    /// Aggregation mode.
+    ///
+    /// This setting must be configured in conjunction with `condition_pattern`.
+    #[configurable(derived)]
+    condition_mode: Mode,
+}
+
+/// What to do with the lines of log messages.
+#[configurable_component]
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum MultilineMessagesMode {
+    /// Leave messages as they are.
+    #[default]
+    Off,
+
+    /// Split each message into a message for each group of its lines.
+    ///
+    /// This is useful when the agent sends several logical events, such as the lines of a stack
+    /// trace meant to be read separately, as a single message.
+    Split,
+
+    /// Join consecutive messages of a request into a single message, each of them as a line of it.
+    ///
+    /// Only messages from the same `hostname`, `service`, and `ddsource` are joined. This is useful
+    /// when the agent splits a logical event, such as a stack trace, into several messages.
+    Join,
+}
+
+/// Splits or joins the messages of a request, according to [`MultilineMessagesConfig`].
+#[derive(Clone, Debug)]
+pub(crate) struct MultilineMessages {
+    join: bool,
+    start_pattern: Regex,
+    condition_pattern: Regex,
+    mode: Mode,
+}
+
+impl MultilineMessages {
+    /// Builds the handling of `config`, which is `None` if it leaves messages as they are.
+    pub(crate) fn new(config: &MultilineMessagesConfig) -> Result<Option<Self>, Error> {
+        let join = match config.mode {
+            MultilineMessagesMode::Off => return Ok(None),
+            MultilineMessagesMode::Split => false,
+            MultilineMessagesMode::Join => true,
+        };
+        let start_pattern = Regex::new(&config.start_pattern).map_err(|source| {
+            Error::InvalidMultilineStartPattern {
+                start_pattern: config.start_pattern.clone(),
+                source,
+            }
+        })?;
+        let condition_pattern = Regex::new(&config.condition_pattern).map_err(|source| {
+            Error::InvalidMultilineConditionPattern {
+                condition_pattern: config.condition_pattern.clone(),
+                source,
+            }
+        })?;
+        Ok(Some(Self {
+            join,
+            start_pattern,
+            condition_pattern,
+            mode: config.condition_mode,
+        }))
+    }
+
+    /// Splits or joins `messages`, keeping their order.
+    ///
+    /// Each resulting message has the attributes of the message it was split from, or of the first
+    /// of the messages it was joined from.
+    pub(crate) fn apply(&self, messages: Vec<LogMsg>) -> Vec<LogMsg> {
+        if self.join {
+            self.group(messages, |msg| &msg.message[..], same_origin)
+                .into_iter()
+                .map(join)
+                .collect()
+        } else {
+            messages
+                .into_iter()
+                .flat_map(|msg| self.split(msg))
+                .collect()
+        }
+    }
+
+    /// Splits `msg` into a message for each group of its lines.
+    fn split(&self, msg: LogMsg) -> Vec<LogMsg> {
+        let lines = line_ranges(&msg.message);
+        if lines.len() < 2 {
+            return vec![msg];
+        }
+        let lines = lines
+            .into_iter()
+            .map(|range| (range.clone(), msg.message.slice(range)));
+        // The lines of a group are contiguous, so each group is a slice of the message.
+        self.group(lines, |(_, line)| &line[..], |_, _| true)
+            .into_iter()
+            .map(|group| {
+                let start = group.first().expect("groups are never empty").0.start;
+                let end = group.last().expect("groups are never empty").0.end;
+                LogMsg {
+                    message: msg.message.slice(start..end),
+                    ..msg.clone()
+                }
+            })
+            .collect()
+    }
+
+    /// Groups `items` as [`crate::line_agg::Logic`] groups lines, reading the line of each item
+    /// with `line`.
+    ///
+    /// An item only continues a group if `continues` holds between the first item of the group and
+    /// itself, otherwise the group ends before it.
+    fn group<T>(
+        &self,
+        items: impl IntoIterator<Item = T>,
+        line: impl Fn(&T) -> &[u8],
+        continues: impl Fn(&T, &T) -> bool,
+    ) -> Vec<Vec<T>> {
+        let mut groups = Vec::new();
+        let mut current: Option<Vec<T>> = None;
+        for item in items {
+            let item = match current.take() {
+                Some(mut group) if continues(&group[0], &item) => {
+                    let condition_matched = self.condition_pattern.is_match(line(&item));
+                    match self.mode.decide(condition_matched) {
+                        Decision::Continue => {
+                            group.push(item);
+                            current = Some(group);
+                            continue;
+                        }
+                        Decision::EndInclude => {
+                            group.push(item);
+                            groups.push(group);
+                            continue;
+                        }
+                        // The group ended before the item, which may start the next one.
+                        Decision::EndExclude => {
+                            groups.push(group);
+                            item
+                        }
+                    }
+                }
+                previous => {
+                    groups.extend(previous);
+                    item
+                }
+            };
+            if self.start_pattern.is_match(line(&item)) {
+                current = Some(vec![item]);
+            } else {
+                groups.push(vec![item]);
+            }
+        }
+        groups.extend(current);
+        groups
+    }
+}
+
+/// Returns the ranges of the lines of `message`, without their line feed.
+///
+/// A line feed ending the message doesn't start another line.
+fn line_ranges(message: &[u8]) -> Vec<Range<usize>> {
+    let mut lines = Vec::new();
+    let mut start = 0;
+    for (index, byte) in message.iter().enumerate() {
+        if *byte == b'\n' {
+            lines.push(start..index);
+            start = index + 1;
+        }
+    }
+    if start < message.len() {
+        lines.push(start..message.len());
+    }
+    lines
+}
+
+fn same_origin(first: &LogMsg, msg: &LogMsg) -> bool {
+    first.hostname == msg.hostname && first.service == msg.service && first.ddsource == msg.ddsource
+}
+
+/// Joins `group` into its first message, each message being a line of it.
+fn join(mut group: Vec<LogMsg>) -> LogMsg {
+    if group.len() == 1 {
+        return group.pop().expect("groups are never empty");
+    }
+    let mut message = BytesMut::new();
+    for (index, msg) in group.iter().enumerate() {
+        if index > 0 {
+            message.extend_from_slice(b"\n");
+        }
+        message.extend_from_slice(&msg.message);
+    }
+    let first = group.swap_remove(0);
+    LogMsg {
+        message: message.freeze(),
+        ..first
+    }
+}
@@ -0,0 +1,144 @@
+use std::{net::SocketAddr, sync::Arc};
+
+use bytes::{Buf, Bytes, BytesMut};
+use futures::{stream::BoxStream, Stream, StreamExt, TryStreamExt};
+use http::StatusCode;
+use warp::{path::FullPath, reject::Rejection, Filter};
+
+use crate::sources::{
+    datadog_agent::{
+        is_compressed, reject, ApiKeyExtractor, ApiKeyQueryParams, DatadogAgentSource,
+        RejectionReason,
+    },
+    util::ErrorMessage,
+};
+
+/// A request to one of the endpoints, with the parts all of them handle alike extracted.
+pub(crate) struct DecodedRequest {
+    /// The body, as received.
+    pub(crate) body: Bytes,
+    /// The API key of the request, even if it isn't meant to be stored with its events.
+    pub(crate) api_key: Option<Arc<str>>,
+    /// The `Content-Encoding` header of the request.
+    pub(crate) encoding: Option<String>,
+    pub(crate) path: FullPath,
+    /// The address of the peer that sent the request, which may be a proxy.
+    pub(crate) remote_addr: Option<SocketAddr>,
+}
+
+impl DecodedRequest {
+    /// The API key to store with the events of the request, if configured to.
+    pub(crate) fn stored_api_key(&self, extractor: &ApiKeyExtractor) -> Option<Arc<str>> {
+        self.api_key.clone().filter(|_| extractor.store_api_key)
+    }
+
+    pub(crate) fn is_compressed(&self) -> bool {
+        is_compressed(&self.encoding)
+    }
+
+    /// Decompresses the body according to its `Content-Encoding`.
+    pub(crate) fn decompress(
+        &self,
+        source: &DatadogAgentSource,
+        endpoint: &'static str,
+    ) -> Result<Bytes, ErrorMessage> {
+        source.decode(
+            &self.encoding,
+            self.body.clone(),
+            self.path.as_str(),
+            endpoint,
+        )
+    }
+}
+
+/// Extracts the [`DecodedRequest`] of a request to `endpoint`.
+///
+/// The API key is validated before the body is read, so that unauthorized requests are rejected
+/// without receiving their body. Bodies larger than `max_body_bytes` are rejected as well. The
+/// requests rejected by either are extracted as the error to answer them with.
+pub(crate) fn decoded_request(
+    extractor: ApiKeyExtractor,
+    endpoint: &'static str,
+    max_body_bytes: Option<usize>,
+) -> impl Filter<Extract = (Result<DecodedRequest, ErrorMessage>,), Error = Rejection> + Clone {
+    warp::path::full()
+        .and(warp::header::optional::<String>("content-encoding"))
+        .and(warp::header::optional::<String>("dd-api-key"))
+        .and(warp::query::<ApiKeyQueryParams>())
+        .and(warp::ext::optional::<SocketAddr>())
+        .and(warp::body::stream().map(boxed_body))
+        .and_then(
+            move |path: FullPath,
+                  encoding: Option<String>,
+                  api_token: Option<String>,
+                  query_params: ApiKeyQueryParams,
+                  remote_addr: Option<SocketAddr>,
+                  body: BodyStream| {
+                let extracted = extractor.extract_key(
+                    path.as_str(),
+                    api_token,
+                    query_params.dd_api_key,
+                    endpoint,
+                );
+                async move {
+                    let request = match extracted {
+                        Ok(api_key) => {
+                            collect_body(body, max_body_bytes, endpoint)
+                                .await
+                                .map(|body| DecodedRequest {
+                                    body,
+                                    api_key,
+                                    encoding,
+                                    path,
+                                    remote_addr,
+                                })
+                        }
+                        Err(error) => Err(error),
+                    };
+                    Ok::<_, Rejection>(request)
+                }
+            },
+        )
+}
+
+/// The body of a request, as received.
+type BodyStream = BoxStream<'static, Result<Bytes, warp::Error>>;
+
+fn boxed_body<S, B>(body: S) -> BodyStream
+where
+    S: Stream<Item = Result<B, warp::Error>> + Send + 'static,
+    B: Buf,
+{
+    body.map_ok(|mut chunk| chunk.copy_to_bytes(chunk.remaining()))
+        .boxed()
+}
+
+/// Reads the whole body of a request, as it's received.
+///
+/// Bodies larger than `max_bytes` are rejected as soon as they cross it, without reading the rest
+/// of them. This also applies to chunked bodies, whose size isn't known upfront.
+async fn collect_body(
+    mut body: BodyStream,
+    max_bytes: Option<usize>,
+    endpoint: &'static str,
+) -> Result<Bytes, ErrorMessage> {
+    let mut collected = BytesMut::new();
+    while let Some(chunk) = body.next().await {
+        let chunk = chunk.map_err(|error| {
+            ErrorMessage::new(
+                StatusCode::BAD_REQUEST,
+                format!("Error reading body: {}", error),
+            )
+        })?;
+        if let Some(max_bytes) = max_bytes.filter(|max| collected.len() + chunk.len() > *max) {
+            return Err(reject(
+                endpoint,
+                RejectionReason::BodyTooLarge,
+                StatusCode::PAYLOAD_TOO_LARGE,
+                format!("Request body is larger than {} bytes", max_bytes),
+            ));
+        }
+        collected.extend_from_slice(&chunk);
+    }
+    Ok(collected.freeze())
+}
@@ -0,0 +1,86 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+};
+
+use snafu::{ResultExt, Snafu};
+
+use crate::{
+    common::reloadable_file::{self, ReloadableFile},
+    internal_events::DatadogAgentServiceApiKeysError,
+    shutdown::ShutdownSignal,
+};
+
+#[derive(Debug, Snafu)]
+pub enum ServiceApiKeysError {
+    #[snafu(display("Unable to read service API key map file {}: {}", path.display(), source))]
+    Read {
+        source: std::io::Error,
+        path: PathBuf,
+    },
+    #[snafu(display("Unable to parse service API key map file {}: {}", path.display(), source))]
+    Parse {
+        source: serde_yaml::Error,
+        path: PathBuf,
+    },
+}
+
+/// The API keys of logs by their `service`, read from `service_api_key_map_file`.
+///
+/// The file is a YAML (or JSON) mapping of services to API keys. It's reloaded when it changes, and
+/// the previous keys are kept if it can't be read or parsed.
+pub(crate) struct ServiceApiKeys {
+    keys: RwLock<ReloadableFile<Arc<HashMap<String, Arc<str>>>>>,
+}
+
+impl ServiceApiKeys {
+    pub(crate) fn load(path: PathBuf) -> Result<Self, ServiceApiKeysError> {
+        let keys = ReloadableFile::load(path, |path| read(path).map(Arc::new))?;
+        Ok(Self {
+            keys: RwLock::new(keys),
+        })
+    }
+
+    /// The keys currently loaded, which stay the same for the caller while the file is reloaded.
+    pub(crate) fn current(&self) -> Arc<HashMap<String, Arc<str>>> {
+        Arc::clone(self.keys.read().expect("poisoned lock").contents())
+    }
+
+    /// Reloads the keys if the file changed since it was last read, keeping the previous keys if
+    /// it can't be read or parsed.
+    pub(crate) fn reload(&self) -> Result<bool, ServiceApiKeysError> {
+        self.keys
+            .write()
+            .expect("poisoned lock")
+            .reload(|path| read(path).map(Arc::new))
+    }
+
+    /// Reloads the keys whenever the file changes, until `shutdown` resolves.
+    pub(crate) async fn watch(self: Arc<Self>, shutdown: ShutdownSignal) {
+        let mut check = tokio::time::interval(reloadable_file::CHECK_INTERVAL);
+        tokio::pin!(shutdown);
+        loop {
+            tokio::select! {
+                _ = &mut shutdown => break,
+                _ = check.tick() => match self.reload() {
+                    Ok(true) => debug!(message = "Reloaded service API key map file."),
+                    Ok(false) => (),
+                    Err(error) => emit!(DatadogAgentServiceApiKeysError { error }),
+                },
+            }
+        }
+    }
+}
+
+fn read(path: &Path) -> Result<HashMap<String, Arc<str>>, ServiceApiKeysError> {
+    let contents = fs::read_to_string(path).context(ReadSnafu { path })?;
+    let keys = serde_yaml::from_str::<Option<HashMap<String, String>>>(&contents)
+        .context(ParseSnafu { path })?
+        .unwrap_or_default();
+    Ok(keys
+        .into_iter()
+        .map(|(service, api_key)| (service, Arc::from(api_key)))
+        .collect())
+}
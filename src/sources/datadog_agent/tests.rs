@@ -1,24 +1,32 @@
 use std::{
-    collections::{BTreeMap, HashMap},
+    collections::{BTreeMap, HashMap, HashSet},
+    io::{Read, Write},
     iter::FromIterator,
-    net::SocketAddr,
-    str,
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+    sync::Arc,
+    time::Duration,
 };
 
 use bytes::Bytes;
 use chrono::{TimeZone, Utc};
+use cidr_utils::cidr::IpCidr;
 use codecs::{
     decoding::{Deserializer, DeserializerConfig, Framer},
-    BytesDecoder, BytesDeserializer,
+    BytesDecoder, BytesDeserializer, JsonDeserializer, LengthDelimitedDecoder,
+    NewlineDelimitedDecoder,
 };
-use futures::{Stream, StreamExt};
+use flate2::{write::GzEncoder, Compression};
+use futures::{stream, StreamExt};
 use http::HeaderMap;
 use indoc::indoc;
-use lookup::{owned_value_path, OwnedTargetPath};
+use lookup::{metadata_path, owned_value_path, OwnedTargetPath};
 use ordered_float::NotNan;
 use prost::Message;
 use quickcheck::{Arbitrary, Gen, QuickCheck, TestResult};
+use sha2::{Digest, Sha256};
 use similar_asserts::assert_eq;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use value::Kind;
 use vector_core::{
     config::LogNamespace,
@@ -36,11 +44,23 @@ use crate::{
         metric::{MetricKind, MetricSketch, MetricValue},
         Event, EventStatus, Metric, Value,
     },
+    metrics::{self, Controller},
     schema,
-    serde::{default_decoding, default_framing_message_based},
-    sources::datadog_agent::{
-        ddmetric_proto, ddtrace_proto, logs::decode_log_body, metrics::DatadogSeriesRequest,
-        DatadogAgentConfig, DatadogAgentSource, LogMsg, LOGS, METRICS, TRACES,
+    serde::default_decoding,
+    sources::{
+        datadog_agent::{
+            ddmetric_proto, ddtrace_proto,
+            dump::FailedRequestDumper,
+            harness::{test_log_msg, AgentHarness, AgentLogPayload},
+            logs::{client_addr, decode_log_body, truncate_message},
+            metrics::DatadogSeriesRequest,
+            reject,
+            request::{decoded_request, DecodedRequest},
+            service_api_keys::ServiceApiKeys,
+            DatadogAgentConfig, DatadogAgentSource, DecodeErrorAction, LogMsg, RejectionReason,
+            ServiceApiKeyPrecedence, LOGS, METRICS, TRACES,
+        },
+        util::ErrorMessage,
     },
     test_util::{
         components::{assert_source_compliance, HTTP_PUSH_SOURCE_TAGS},
@@ -96,7 +116,9 @@ fn test_decode_log_body() {
             LogNamespace::Legacy,
         );
 
-        let events = decode_log_body(body, api_key, &source).unwrap();
+        let events = decode_log_body(body, api_key, false, None, None, &source)
+            .unwrap()
+            .events;
         assert_eq!(events.len(), msgs.len());
         for (msg, event) in msgs.into_iter().zip(events.into_iter()) {
             let log = event.as_log();
@@ -121,47 +143,510 @@ fn test_decode_log_body() {
 }
 
 #[test]
-fn generate_config() {
-    crate::test_util::test_generate_config::<DatadogAgentConfig>();
+fn decode_log_body_frames_each_message() {
+    let decoder = crate::codecs::Decoder::new(
+        Framer::NewlineDelimited(NewlineDelimitedDecoder::new()),
+        Deserializer::Json(JsonDeserializer::new()),
+    );
+    let source = DatadogAgentSource::new(
+        true,
+        decoder,
+        "http",
+        test_logs_schema_definition(),
+        LogNamespace::Legacy,
+    );
+
+    let mut first = test_log_msg("{\"n\": 1}\n{\"n\": 2}");
+    first.service = Bytes::from("first");
+    // A shorter message, so that leftovers of the previous one in a reused buffer would show.
+    let mut second = test_log_msg("{\"n\": 3}");
+    second.service = Bytes::from("second");
+    let body = Bytes::from(serde_json::to_string(&[first, second]).unwrap());
+
+    let events = decode_log_body(body, None, false, None, None, &source)
+        .unwrap()
+        .events;
+    let decoded = events
+        .iter()
+        .map(|event| {
+            let log = event.as_log();
+            (log["n"].clone(), log["service"].clone())
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        decoded,
+        vec![
+            (Value::from(1), Value::from("first")),
+            (Value::from(2), Value::from("first")),
+            (Value::from(3), Value::from("second")),
+        ]
+    );
 }
 
-async fn source(
-    status: EventStatus,
-    acknowledgements: bool,
-    store_api_key: bool,
-    multiple_outputs: bool,
-) -> (
-    impl Stream<Item = Event> + Unpin,
-    Option<impl Stream<Item = Event>>,
-    Option<impl Stream<Item = Event>>,
-    SocketAddr,
-) {
-    let (mut sender, recv) = SourceSender::new_test_finalize(status);
-    let mut logs_output = None;
-    let mut metrics_output = None;
-    if multiple_outputs {
-        logs_output = Some(
-            sender
-                .add_outputs(status, "logs".to_string())
-                .flat_map(into_event_stream),
-        );
-        metrics_output = Some(
-            sender
-                .add_outputs(status, "metrics".to_string())
-                .flat_map(into_event_stream),
+/// A source framing messages by length, whose framing fails on truncated frames.
+fn length_delimited_source(on_decode_error: DecodeErrorAction) -> DatadogAgentSource {
+    let decoder = crate::codecs::Decoder::new(
+        Framer::LengthDelimited(LengthDelimitedDecoder::new()),
+        Deserializer::Bytes(BytesDeserializer::new()),
+    );
+    let mut source = DatadogAgentSource::new(
+        true,
+        decoder,
+        "http",
+        test_logs_schema_definition(),
+        LogNamespace::Legacy,
+    );
+    source.on_decode_error = on_decode_error;
+    source
+}
+
+/// A body whose first message ends with a truncated frame, and whose last one is only that.
+fn truncated_frames_body() -> Bytes {
+    let messages = [
+        test_log_msg("\0\0\0\x03one\0\0\0\x03two\0\0\0\x09abc"),
+        test_log_msg("\0\0\0\x04next"),
+        test_log_msg("\0\0\0\x09abc"),
+    ];
+    Bytes::from(serde_json::to_string(&messages).unwrap())
+}
+
+#[test]
+fn decode_log_body_skips_the_rest_of_unframeable_messages() {
+    metrics::init_test();
+    let source = length_delimited_source(DecodeErrorAction::SkipMessage);
+
+    let decoded =
+        decode_log_body(truncated_frames_body(), None, false, None, None, &source).unwrap();
+    let messages = decoded
+        .events
+        .iter()
+        .map(|event| event.as_log()["message"].clone())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        messages,
+        vec![Value::from("one"), Value::from("two"), Value::from("next")]
+    );
+    // The partially decoded message counts as rejected too.
+    assert_eq!((decoded.accepted, decoded.rejected), (1, 2));
+
+    for dropped in ["partial", "full"] {
+        let metric = captured_metric(
+            "datadog_agent_decode_dropped_messages_total",
+            &[("dropped", dropped)],
         );
+        assert_eq!(metric.value(), &MetricValue::Counter { value: 1.0 });
     }
-    let address = next_addr();
-    let config = toml::from_str::<DatadogAgentConfig>(&format!(
+    // Each truncated frame is reported as dropped.
+    let discarded = captured_metric(
+        "component_discarded_events_total",
+        &[("intentional", "false")],
+    );
+    assert_eq!(discarded.value(), &MetricValue::Counter { value: 2.0 });
+}
+
+#[test]
+fn decode_log_body_fails_request_on_decode_error() {
+    metrics::init_test();
+    let source = length_delimited_source(DecodeErrorAction::FailRequest);
+
+    let error =
+        decode_log_body(truncated_frames_body(), None, false, None, None, &source).unwrap_err();
+    assert_rejected(&error, LOGS, "message_decode", 400);
+}
+
+fn service_api_key_source(
+    path: PathBuf,
+    precedence: ServiceApiKeyPrecedence,
+) -> DatadogAgentSource {
+    let mut source = test_logs_source();
+    source.service_api_keys = Some(Arc::new(ServiceApiKeys::load(path).unwrap()));
+    source.service_api_key_precedence = precedence;
+    source
+}
+
+fn service_log_msg(service: &str) -> LogMsg {
+    LogMsg {
+        service: Bytes::from(service.to_owned()),
+        ..test_log_msg(service)
+    }
+}
+
+fn decoded_api_keys(
+    source: &DatadogAgentSource,
+    request_api_key: Option<&str>,
+) -> Vec<Option<String>> {
+    let msgs = [service_log_msg("checkout"), service_log_msg("search")];
+    let body = Bytes::from(serde_json::to_string(&msgs).unwrap());
+    decode_log_body(
+        body,
+        request_api_key.map(Arc::from),
+        false,
+        None,
+        None,
+        source,
+    )
+    .unwrap()
+    .events
+    .into_iter()
+    .map(|event| {
+        event
+            .metadata()
+            .datadog_api_key()
+            .map(|key| key.to_string())
+    })
+    .collect()
+}
+
+#[test]
+fn decode_log_body_selects_api_key_by_service() {
+    let directory = tempfile::tempdir().unwrap();
+    let path = directory.path().join("api_keys.yaml");
+    std::fs::write(&path, "checkout: checkout-key\n").unwrap();
+
+    let source = service_api_key_source(path.clone(), ServiceApiKeyPrecedence::Service);
+    // Unmapped services fall back to the key of the request, if any.
+    assert_eq!(
+        decoded_api_keys(&source, Some("request-key")),
+        vec![
+            Some("checkout-key".to_owned()),
+            Some("request-key".to_owned())
+        ]
+    );
+    assert_eq!(
+        decoded_api_keys(&source, None),
+        vec![Some("checkout-key".to_owned()), None]
+    );
+
+    let source = service_api_key_source(path, ServiceApiKeyPrecedence::Request);
+    assert_eq!(
+        decoded_api_keys(&source, Some("request-key")),
+        vec![
+            Some("request-key".to_owned()),
+            Some("request-key".to_owned())
+        ]
+    );
+    assert_eq!(
+        decoded_api_keys(&source, None),
+        vec![Some("checkout-key".to_owned()), None]
+    );
+}
+
+#[test]
+fn service_api_keys_reload_keeps_previous_keys_on_error() {
+    let directory = tempfile::tempdir().unwrap();
+    let path = directory.path().join("api_keys.yaml");
+    std::fs::write(&path, "checkout: checkout-key\n").unwrap();
+    let source = service_api_key_source(path.clone(), ServiceApiKeyPrecedence::Service);
+    let service_api_keys = source.service_api_keys.as_ref().unwrap();
+
+    // Nothing changed since the file was loaded.
+    assert!(!service_api_keys.reload().unwrap());
+
+    // Replace the file rather than rewrite it so the modification time is sure to change.
+    let replace = |contents: &str| {
+        let next = directory.path().join("api_keys.yaml.next");
+        std::fs::write(&next, contents).unwrap();
+        std::thread::sleep(Duration::from_millis(10));
+        std::fs::rename(&next, &path).unwrap();
+    };
+
+    replace("checkout: rotated-key\nsearch: search-key\n");
+    assert!(service_api_keys.reload().unwrap());
+    assert_eq!(
+        decoded_api_keys(&source, None),
+        vec![
+            Some("rotated-key".to_owned()),
+            Some("search-key".to_owned())
+        ]
+    );
+
+    replace("checkout: [not, a, key]\n");
+    service_api_keys.reload().unwrap_err();
+    assert_eq!(
+        decoded_api_keys(&source, None),
+        vec![
+            Some("rotated-key".to_owned()),
+            Some("search-key".to_owned())
+        ]
+    );
+}
+
+fn test_logs_source() -> DatadogAgentSource {
+    let decoder = crate::codecs::Decoder::new(
+        Framer::Bytes(BytesDecoder::new()),
+        Deserializer::Bytes(BytesDeserializer::new()),
+    );
+
+    DatadogAgentSource::new(
+        true,
+        decoder,
+        "http",
+        test_logs_schema_definition(),
+        LogNamespace::Legacy,
+    )
+}
+
+fn captured_metric(name: &str, tags: &[(&str, &str)]) -> Metric {
+    Controller::get()
+        .expect("There must be a controller")
+        .capture_metrics()
+        .into_iter()
+        .find(|metric| {
+            metric.name() == name
+                && tags
+                    .iter()
+                    .all(|(key, value)| metric.tag_value(key).as_deref() == Some(*value))
+        })
+        .unwrap_or_else(|| panic!("metric {} not emitted", name))
+}
+
+#[test]
+fn decode_log_body_emits_payload_shape_metrics() {
+    metrics::init_test();
+    let source = test_logs_source();
+    let msgs = [test_log_msg("foo"), test_log_msg("barbaz")];
+    let body = Bytes::from(serde_json::to_string(&msgs).unwrap());
+
+    decode_log_body(body, None, true, None, None, &source).unwrap();
+
+    let tags = [("endpoint", LOGS), ("compressed", "true")];
+    match captured_metric("datadog_agent_messages_per_request", &tags).value() {
+        MetricValue::AggregatedHistogram { count, sum, .. } => {
+            assert_eq!(*count, 1);
+            assert_eq!(*sum, 2.0);
+        }
+        value => panic!("unexpected metric value {:?}", value),
+    }
+    match captured_metric("datadog_agent_message_size_bytes", &tags).value() {
+        MetricValue::AggregatedHistogram { count, sum, .. } => {
+            assert_eq!(*count, 2);
+            assert_eq!(*sum, 9.0);
+        }
+        value => panic!("unexpected metric value {:?}", value),
+    }
+}
+
+#[test]
+fn decode_emits_decompression_ratio() {
+    metrics::init_test();
+    let source = test_logs_source();
+    let payload = "a".repeat(1000);
+
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(payload.as_bytes()).unwrap();
+    let compressed = Bytes::from(encoder.finish().unwrap());
+    let compressed_size = compressed.len();
+
+    let body = source
+        .decode(&Some("gzip".to_owned()), compressed, "/api/v2/logs", LOGS)
+        .unwrap();
+    assert_eq!(&body[..], payload.as_bytes());
+
+    let ratio = captured_metric("datadog_agent_decompression_ratio", &[("endpoint", LOGS)]);
+    assert_eq!(
+        ratio.value(),
+        &MetricValue::Gauge {
+            value: payload.len() as f64 / compressed_size as f64
+        }
+    );
+}
+
+#[test]
+fn decode_redacts_path_api_key() {
+    metrics::init_test();
+    let source = test_logs_source();
+    let path = "/v1/input/12345678abcdefgh12345678abcdefgh";
+
+    source.decode(&None, Bytes::from("[]"), path, LOGS).unwrap();
+
+    captured_metric(
+        "component_received_bytes_total",
+        &[("http_path", "/v1/input/[REDACTED]")],
+    );
+    assert!(!Controller::get()
+        .unwrap()
+        .capture_metrics()
+        .into_iter()
+        .any(|metric| metric.tag_value("http_path").as_deref() == Some(path)));
+}
+
+/// Asserts that `error` was reported as a rejection of a request to `endpoint` for `reason`.
+fn assert_rejected(error: &ErrorMessage, endpoint: &str, reason: &str, status_code: u16) {
+    assert_eq!(error.reason(), Some(reason));
+    assert_eq!(error.status_code().as_u16(), status_code);
+    let tags = [
+        ("endpoint", endpoint),
+        ("reason", reason),
+        ("error_code", reason),
+        ("stage", "receiving"),
+    ];
+    let errors = captured_metric("component_errors_total", &tags);
+    assert_eq!(errors.value(), &MetricValue::Counter { value: 1.0 });
+}
+
+#[test]
+fn decode_rejects_unsupported_encoding() {
+    metrics::init_test();
+    let source = test_logs_source();
+
+    let error = source
+        .decode(
+            &Some("br".to_owned()),
+            Bytes::from("[]"),
+            "/api/v2/logs",
+            LOGS,
+        )
+        .unwrap_err();
+    assert_rejected(&error, LOGS, "unsupported_content_type", 415);
+}
+
+#[test]
+fn decode_rejects_corrupt_payload() {
+    metrics::init_test();
+    let source = test_logs_source();
+
+    let error = source
+        .decode(
+            &Some("gzip".to_owned()),
+            Bytes::from("not gzip"),
+            "/api/v1/series",
+            METRICS,
+        )
+        .unwrap_err();
+    assert_rejected(&error, METRICS, "decompression", 422);
+}
+
+#[test]
+fn decode_log_body_rejects_invalid_json() {
+    metrics::init_test();
+    let source = test_logs_source();
+
+    let error = decode_log_body(Bytes::from("{"), None, false, None, None, &source).unwrap_err();
+    assert_rejected(&error, LOGS, "json_parse", 400);
+}
+
+#[test]
+fn extract_rejects_unknown_api_key() {
+    metrics::init_test();
+    let mut extractor = test_logs_source().api_key_extractor;
+    extractor.allowed_keys = Some(Arc::new(HashSet::from(["allowed".to_owned()])));
+
+    let error = extractor
+        .extract("/api/v2/logs", Some("denied".to_owned()), None, TRACES)
+        .unwrap_err();
+    assert_rejected(&error, TRACES, "invalid_api_key", 403);
+}
+
+#[tokio::test]
+async fn decoded_request_checks_api_key_for_every_endpoint() {
+    metrics::init_test();
+    let mut extractor = test_logs_source().api_key_extractor;
+    extractor.allowed_keys = Some(Arc::new(HashSet::from(["allowed".to_owned()])));
+
+    for endpoint in [LOGS, METRICS, TRACES] {
+        let filter = decoded_request(extractor.clone(), endpoint, None);
+        let request = warp::test::request()
+            .method("POST")
+            .path("/api/v2/series")
+            .header("dd-api-key", "denied")
+            .body("payload")
+            .filter(&filter)
+            .await
+            .unwrap();
+        match request {
+            Ok(_) => panic!("request to {} with a denied API key was accepted", endpoint),
+            Err(error) => assert_rejected(&error, endpoint, "invalid_api_key", 403),
+        }
+    }
+}
+
+#[tokio::test]
+async fn decoded_request_extracts_shared_parts_and_limits_body() {
+    metrics::init_test();
+    let filter = decoded_request(test_logs_source().api_key_extractor, METRICS, Some(8));
+
+    let request = warp::test::request()
+        .method("POST")
+        .path("/api/v1/series?dd-api-key=12345678abcdefgh12345678abcdefgh")
+        .header("content-encoding", "gzip")
+        .body("payload")
+        .filter(&filter)
+        .await
+        .unwrap();
+    let request: DecodedRequest = match request {
+        Ok(request) => request,
+        Err(error) => panic!("request was rejected: {}", error.message()),
+    };
+    assert_eq!(request.body, Bytes::from("payload"));
+    assert_eq!(
+        request.api_key.as_deref(),
+        Some("12345678abcdefgh12345678abcdefgh")
+    );
+    assert!(request.is_compressed());
+    assert_eq!(request.path.as_str(), "/api/v1/series");
+
+    let request = warp::test::request()
+        .method("POST")
+        .path("/api/v1/series")
+        .body("too large payload")
+        .filter(&filter)
+        .await
+        .unwrap();
+    match request {
+        Ok(_) => panic!("request with a body above the limit was accepted"),
+        Err(error) => assert_rejected(&error, METRICS, "body_too_large", 413),
+    }
+}
+
+#[test]
+fn redact_path_only_touches_path_api_keys() {
+    let extractor = test_logs_source().api_key_extractor;
+
+    assert_eq!(
+        extractor.redact_path("/v1/input/12345678abcdefgh12345678abcdefgh"),
+        "/v1/input/[REDACTED]"
+    );
+    assert_eq!(
+        extractor.redact_path("/v1/input/not-a-valid-key/extra"),
+        "/v1/input/[REDACTED]/extra"
+    );
+    assert_eq!(extractor.redact_path("/api/v2/logs"), "/api/v2/logs");
+}
+
+#[test]
+fn generate_config() {
+    crate::test_util::test_generate_config::<DatadogAgentConfig>();
+}
+
+/// Serves a source accepting the payloads of every endpoint, with their API key stored.
+async fn agent_harness(multiple_outputs: bool) -> AgentHarness {
+    AgentHarness::start(&format!(
         indoc! { r#"
-            address = "{}"
             compression = "none"
-            store_api_key = {}
-            acknowledgements = {}
+            store_api_key = true
+            acknowledgements = true
             multiple_outputs = {}
             trace_proto = "v1v2"
         "#},
-        address, store_api_key, acknowledgements, multiple_outputs
+        multiple_outputs
+    ))
+    .await
+}
+
+#[tokio::test]
+async fn logs_on_dedicated_listener() {
+    trace_init();
+    let (sender, rx) = SourceSender::new_test_finalize(EventStatus::Delivered);
+    let address = next_addr();
+    let logs_address = next_addr();
+    let config = toml::from_str::<DatadogAgentConfig>(&format!(
+        indoc! { r#"
+            address = "{}"
+
+            [logs_listener]
+            address = "{}"
+        "#},
+        address, logs_address
     ))
     .unwrap();
     let schema_definitions =
@@ -171,480 +656,1787 @@ async fn source(
         config.build(context).await.unwrap().await.unwrap();
     });
     wait_for_tcp(address).await;
-    (recv, logs_output, metrics_output, address)
+    wait_for_tcp(logs_address).await;
+
+    let body = serde_json::to_string(&[test_log_msg("foo")]).unwrap();
+    let post = move |address: SocketAddr| {
+        reqwest::Client::new()
+            .post(&format!("http://{}/api/v2/logs", address))
+            .body(body.clone())
+            .send()
+    };
+
+    // The primary listener no longer serves the logs routes.
+    assert_eq!(404, post(address).await.unwrap().status().as_u16());
+
+    let mut events = spawn_collect_n(
+        async move {
+            assert_eq!(200, post(logs_address).await.unwrap().status().as_u16());
+        },
+        rx,
+        1,
+    )
+    .await;
+
+    assert_eq!(events.remove(0).as_log()["message"], "foo".into());
+}
+
+#[tokio::test]
+async fn logs_decoding_is_offloaded_and_bounded() {
+    let harness = AgentHarness::start(indoc! { r#"
+        decode_pool.workers = 1
+        decode_pool.max_queued_requests = 1
+    "#})
+    .await;
+    let address = harness.address();
+
+    // Bodies decompressing to a lot of whitespace keep a worker busy, then fail to parse.
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::fast());
+    encoder.write_all(&vec![b' '; 64 * 1024 * 1024]).unwrap();
+    let body = Bytes::from(encoder.finish().unwrap());
+    let large = (0..8)
+        .map(|_| {
+            let body = body.clone();
+            tokio::spawn(async move {
+                reqwest::Client::new()
+                    .post(&format!("http://{}/api/v2/logs", address))
+                    .header("content-encoding", "gzip")
+                    .body(body)
+                    .send()
+                    .await
+                    .unwrap()
+                    .status()
+                    .as_u16()
+            })
+        })
+        .collect::<Vec<_>>();
+
+    // Other requests are still answered while the workers are busy.
+    let start = std::time::Instant::now();
+    let (status, _) = get_health(address).await;
+    assert_eq!(status, 200);
+    assert!(start.elapsed() < Duration::from_secs(1));
+
+    let mut statuses = Vec::new();
+    for request in large {
+        statuses.push(request.await.unwrap());
+    }
+    assert!(statuses.contains(&429), "{:?}", statuses);
+    assert!(
+        statuses.iter().all(|status| [400, 429].contains(status)),
+        "{:?}",
+        statuses
+    );
 }
 
-async fn send_with_path(address: SocketAddr, body: &str, headers: HeaderMap, path: &str) -> u16 {
-    reqwest::Client::new()
-        .post(&format!("http://{}{}", address, path))
-        .headers(headers)
-        .body(body.to_owned())
-        .send()
+#[tokio::test]
+async fn logs_accepted_on_put() {
+    let payload = AgentLogPayload::new()
+        .message("foo")
+        .method(reqwest::Method::PUT);
+    let event = AgentHarness::start("")
         .await
-        .unwrap()
-        .status()
-        .as_u16()
+        .send(payload)
+        .await
+        .single_event();
+
+    assert_eq!(event.as_log()["message"], "foo".into());
 }
 
 #[tokio::test]
-async fn full_payload_v1() {
-    assert_source_compliance(&HTTP_PUSH_SOURCE_TAGS, async {
-        let (rx, _, _, addr) = source(EventStatus::Delivered, true, true, false).await;
-
-        let mut events = spawn_collect_n(
-            async move {
-                assert_eq!(
-                    200,
-                    send_with_path(
-                        addr,
-                        &serde_json::to_string(&[LogMsg {
-                            message: Bytes::from("foo"),
-                            timestamp: Utc
-                                .timestamp_opt(123, 0)
-                                .single()
-                                .expect("invalid timestamp"),
-                            hostname: Bytes::from("festeburg"),
-                            status: Bytes::from("notice"),
-                            service: Bytes::from("vector"),
-                            ddsource: Bytes::from("curl"),
-                            ddtags: Bytes::from("one,two,three"),
-                        }])
-                        .unwrap(),
-                        HeaderMap::new(),
-                        "/v1/input/"
-                    )
-                    .await
-                );
-            },
-            rx,
-            1,
-        )
-        .await;
+async fn logs_cors_preflight() {
+    let mut harness =
+        AgentHarness::start(r#"cors_allowed_origins = ["https://example.com"]"#).await;
+    let preflight = |origin: &str| {
+        AgentLogPayload::new()
+            .method(reqwest::Method::OPTIONS)
+            .header("origin", origin)
+            .header("access-control-request-method", "PUT")
+            .header("access-control-request-headers", "content-type")
+    };
+
+    let response = harness.send(preflight("https://example.com")).await;
+    assert_eq!(response.status, 200);
+    let headers = &response.headers;
+    assert_eq!(
+        headers["access-control-allow-origin"],
+        "https://example.com"
+    );
+    let methods = headers["access-control-allow-methods"].to_str().unwrap();
+    assert!(methods.contains("POST"));
+    assert!(methods.contains("PUT"));
+
+    let response = harness.send(preflight("https://other.example.com")).await;
+    assert_eq!(response.status, 403);
+    assert!(!response.headers.contains_key("access-control-allow-origin"));
+}
 
-        {
-            let event = events.remove(0);
-            let log = event.as_log();
-            assert_eq!(log["message"], "foo".into());
-            assert_eq!(
-                log["timestamp"],
-                Utc.timestamp_opt(123, 0)
-                    .single()
-                    .expect("invalid timestamp")
-                    .into()
-            );
-            assert_eq!(log["hostname"], "festeburg".into());
-            assert_eq!(log["status"], "notice".into());
-            assert_eq!(log["service"], "vector".into());
-            assert_eq!(log["ddsource"], "curl".into());
-            assert_eq!(log["ddtags"], "one,two,three".into());
-            assert!(event.metadata().datadog_api_key().is_none());
-            assert_eq!(log[log_schema().source_type_key()], "datadog_agent".into());
-            assert_eq!(
-                event.metadata().schema_definition(),
-                &test_logs_schema_definition()
-            );
-        }
-    })
-    .await;
+#[tokio::test]
+async fn logs_unsupported_method_is_json_error() {
+    let mut harness = AgentHarness::start("").await;
+
+    for method in [
+        reqwest::Method::GET,
+        reqwest::Method::DELETE,
+        reqwest::Method::OPTIONS,
+    ] {
+        let payload = AgentLogPayload::new().method(method.clone());
+        let response = harness.send(payload).await;
+        assert_eq!(response.status, 405);
+        assert_eq!(
+            response.json(),
+            serde_json::json!({
+                "code": 405,
+                "message": format!("Method {} not allowed, expected one of: POST, PUT", method),
+            })
+        );
+    }
+}
+
+async fn post_log_with_request_headers(extra_config: &str) -> Event {
+    let payload = AgentLogPayload::new()
+        .message("foo")
+        .header("dd-agent-version", "7.43.1")
+        .header("user-agent", "datadog-agent/7.43.1")
+        .header("x-forwarded-for", "203.0.113.7, 10.1.2.3");
+    AgentHarness::start(extra_config)
+        .await
+        .send(payload)
+        .await
+        .single_event()
 }
 
 #[tokio::test]
-async fn full_payload_v2() {
-    assert_source_compliance(&HTTP_PUSH_SOURCE_TAGS, async {
-        let (rx, _, _, addr) = source(EventStatus::Delivered, true, true, false).await;
-
-        let mut events = spawn_collect_n(
-            async move {
-                assert_eq!(
-                    200,
-                    send_with_path(
-                        addr,
-                        &serde_json::to_string(&[LogMsg {
-                            message: Bytes::from("foo"),
-                            timestamp: Utc
-                                .timestamp_opt(123, 0)
-                                .single()
-                                .expect("invalid timestamp"),
-                            hostname: Bytes::from("festeburg"),
-                            status: Bytes::from("notice"),
-                            service: Bytes::from("vector"),
-                            ddsource: Bytes::from("curl"),
-                            ddtags: Bytes::from("one,two,three"),
-                        }])
-                        .unwrap(),
-                        HeaderMap::new(),
-                        "/api/v2/logs"
-                    )
-                    .await
-                );
-            },
-            rx,
-            1,
-        )
-        .await;
+async fn logs_request_metadata() {
+    let event = post_log_with_request_headers("include_request_metadata = true").await;
+    let log = event.as_log();
+
+    // The peer isn't a trusted proxy, so `X-Forwarded-For` is ignored.
+    assert_eq!(log["remote_addr"], "127.0.0.1".into());
+    assert_eq!(log["agent_version"], "7.43.1".into());
+    assert_eq!(log["user_agent"], "datadog-agent/7.43.1".into());
+}
 
-        {
-            let event = events.remove(0);
-            let log = event.as_log();
-            assert_eq!(log["message"], "foo".into());
-            assert_eq!(
-                log["timestamp"],
-                Utc.timestamp_opt(123, 0)
-                    .single()
-                    .expect("invalid timestamp")
-                    .into()
-            );
-            assert_eq!(log["hostname"], "festeburg".into());
-            assert_eq!(log["status"], "notice".into());
-            assert_eq!(log["service"], "vector".into());
-            assert_eq!(log["ddsource"], "curl".into());
-            assert_eq!(log["ddtags"], "one,two,three".into());
-            assert!(event.metadata().datadog_api_key().is_none());
-            assert_eq!(log[log_schema().source_type_key()], "datadog_agent".into());
-            assert_eq!(
-                event.metadata().schema_definition(),
-                &test_logs_schema_definition()
-            );
-        }
-    })
+#[tokio::test]
+async fn logs_request_metadata_with_max_connection_age() {
+    let event = post_log_with_request_headers(indoc! { r#"
+        include_request_metadata = true
+        keepalive.max_connection_age_secs = 60
+    "#})
     .await;
+
+    assert_eq!(event.as_log()["remote_addr"], "127.0.0.1".into());
 }
 
 #[tokio::test]
-async fn no_api_key() {
-    assert_source_compliance(&HTTP_PUSH_SOURCE_TAGS, async {
-        let (rx, _, _, addr) = source(EventStatus::Delivered, true, true, false).await;
-
-        let mut events = spawn_collect_n(
-            async move {
-                assert_eq!(
-                    200,
-                    send_with_path(
-                        addr,
-                        &serde_json::to_string(&[LogMsg {
-                            message: Bytes::from("foo"),
-                            timestamp: Utc
-                                .timestamp_opt(123, 0)
-                                .single()
-                                .expect("invalid timestamp"),
-                            hostname: Bytes::from("festeburg"),
-                            status: Bytes::from("notice"),
-                            service: Bytes::from("vector"),
-                            ddsource: Bytes::from("curl"),
-                            ddtags: Bytes::from("one,two,three"),
-                        }])
-                        .unwrap(),
-                        HeaderMap::new(),
-                        "/v1/input/"
-                    )
-                    .await
-                );
-            },
-            rx,
-            1,
-        )
-        .await;
-
-        {
-            let event = events.remove(0);
-            let log = event.as_log();
-            assert_eq!(log["message"], "foo".into());
-            assert_eq!(
-                log["timestamp"],
-                Utc.timestamp_opt(123, 0)
-                    .single()
-                    .expect("invalid timestamp")
-                    .into()
-            );
-            assert_eq!(log["hostname"], "festeburg".into());
-            assert_eq!(log["status"], "notice".into());
-            assert_eq!(log["service"], "vector".into());
-            assert_eq!(log["ddsource"], "curl".into());
-            assert_eq!(log["ddtags"], "one,two,three".into());
-            assert!(event.metadata().datadog_api_key().is_none());
-            assert_eq!(log[log_schema().source_type_key()], "datadog_agent".into());
-            assert_eq!(
-                event.metadata().schema_definition(),
-                &test_logs_schema_definition()
-            );
-        }
-    })
+async fn logs_request_metadata_from_trusted_proxy() {
+    let event = post_log_with_request_headers(indoc! { r#"
+        include_request_metadata = true
+        trusted_proxies = ["127.0.0.1", "10.0.0.0/8"]
+    "#})
     .await;
+
+    assert_eq!(event.as_log()["remote_addr"], "203.0.113.7".into());
 }
 
 #[tokio::test]
-async fn api_key_in_url() {
-    assert_source_compliance(&HTTP_PUSH_SOURCE_TAGS, async {
-        let (rx, _, _, addr) = source(EventStatus::Delivered, true, true, false).await;
-
-        let mut events = spawn_collect_n(
-            async move {
-                assert_eq!(
-                    200,
-                    send_with_path(
-                        addr,
-                        &serde_json::to_string(&[LogMsg {
-                            message: Bytes::from("bar"),
-                            timestamp: Utc
-                                .timestamp_opt(456, 0)
-                                .single()
-                                .expect("invalid timestamp"),
-                            hostname: Bytes::from("festeburg"),
-                            status: Bytes::from("notice"),
-                            service: Bytes::from("vector"),
-                            ddsource: Bytes::from("curl"),
-                            ddtags: Bytes::from("one,two,three"),
-                        }])
-                        .unwrap(),
-                        HeaderMap::new(),
-                        "/v1/input/12345678abcdefgh12345678abcdefgh"
-                    )
-                    .await
-                );
-            },
-            rx,
-            1,
-        )
-        .await;
+async fn logs_request_metadata_disabled() {
+    let event = post_log_with_request_headers("").await;
+    let log = event.as_log();
 
-        {
-            let event = events.remove(0);
-            let log = event.as_log();
-            assert_eq!(log["message"], "bar".into());
-            assert_eq!(
-                log["timestamp"],
-                Utc.timestamp_opt(456, 0)
-                    .single()
-                    .expect("invalid timestamp")
-                    .into()
-            );
-            assert_eq!(log["hostname"], "festeburg".into());
-            assert_eq!(log["status"], "notice".into());
-            assert_eq!(log["service"], "vector".into());
-            assert_eq!(log["ddsource"], "curl".into());
-            assert_eq!(log["ddtags"], "one,two,three".into());
-            assert_eq!(log[log_schema().source_type_key()], "datadog_agent".into());
-            assert_eq!(
-                &event.metadata().datadog_api_key().as_ref().unwrap()[..],
-                "12345678abcdefgh12345678abcdefgh"
-            );
-            assert_eq!(
-                event.metadata().schema_definition(),
-                &test_logs_schema_definition()
-            );
+    assert!(!log.contains("remote_addr"));
+    assert!(!log.contains("agent_version"));
+    assert!(!log.contains("user_agent"));
+}
+
+async fn post_log_with_origin(extra_config: &str, origin: Option<&str>) -> Event {
+    let mut payload = AgentLogPayload::new().message("foo");
+    if let Some(origin) = origin {
+        payload = payload.header("x-datadog-origin", origin);
+    }
+    AgentHarness::start(extra_config)
+        .await
+        .send(payload)
+        .await
+        .single_event()
+}
+
+#[tokio::test]
+async fn logs_origin_legacy_namespace() {
+    let event = post_log_with_origin("", Some("agent-pipeline")).await;
+    let log = event.as_log();
+    assert_eq!(log["origin"], "agent-pipeline".into());
+    assert_eq!(log["ddtags"], "one,two,three".into());
+
+    let event = post_log_with_origin("origin_as_tag = true", Some("agent-pipeline")).await;
+    let log = event.as_log();
+    assert_eq!(log["origin"], "agent-pipeline".into());
+    assert_eq!(log["ddtags"], "one,two,three,origin:agent-pipeline".into());
+
+    let event = post_log_with_origin("origin_as_tag = true", None).await;
+    let log = event.as_log();
+    assert!(!log.contains("origin"));
+    assert_eq!(log["ddtags"], "one,two,three".into());
+}
+
+#[tokio::test]
+async fn logs_origin_vector_namespace() {
+    let event = post_log_with_origin("log_namespace = true", Some("agent-pipeline")).await;
+    let log = event.as_log();
+    assert_eq!(
+        log.get(metadata_path!("datadog_agent", "origin")),
+        Some(&"agent-pipeline".into())
+    );
+    assert_eq!(
+        log.get(metadata_path!("datadog_agent", "ddtags")),
+        Some(&"one,two,three".into())
+    );
+    assert!(!log.contains("origin"));
+
+    let event = post_log_with_origin(
+        indoc! { r#"
+            log_namespace = true
+            origin_as_tag = true
+        "#},
+        Some("agent-pipeline"),
+    )
+    .await;
+    let log = event.as_log();
+    assert_eq!(
+        log.get(metadata_path!("datadog_agent", "origin")),
+        Some(&"agent-pipeline".into())
+    );
+    assert_eq!(
+        log.get(metadata_path!("datadog_agent", "ddtags")),
+        Some(&"one,two,three,origin:agent-pipeline".into())
+    );
+
+    let event = post_log_with_origin(
+        indoc! { r#"
+            log_namespace = true
+            origin_as_tag = true
+        "#},
+        None,
+    )
+    .await;
+    let log = event.as_log();
+    assert!(log.get(metadata_path!("datadog_agent", "origin")).is_none());
+    assert_eq!(
+        log.get(metadata_path!("datadog_agent", "ddtags")),
+        Some(&"one,two,three".into())
+    );
+}
+
+async fn post_log_with_service(extra_config: &str, service: &str, ddsource: &str) -> Event {
+    let payload = AgentLogPayload::new()
+        .message("foo")
+        .service(service)
+        .ddsource(ddsource);
+    AgentHarness::start(extra_config)
+        .await
+        .send(payload)
+        .await
+        .single_event()
+}
+
+#[tokio::test]
+async fn logs_normalize_service_names_legacy_namespace() {
+    let config = "normalize_service_names = true";
+
+    let event = post_log_with_service(config, "MyApp", "Java").await;
+    let log = event.as_log();
+    assert_eq!(log["service"], "myapp".into());
+    assert_eq!(log["service_original"], "MyApp".into());
+    assert_eq!(log["ddsource"], "java".into());
+    assert_eq!(log["ddsource_original"], "Java".into());
+
+    let event = post_log_with_service(config, "myapp", "java").await;
+    let log = event.as_log();
+    assert_eq!(log["service"], "myapp".into());
+    assert!(!log.contains("service_original"));
+    assert!(!log.contains("ddsource_original"));
+
+    let event = post_log_with_service(config, "ÉCOLE-Ünïcode", "java").await;
+    let log = event.as_log();
+    assert_eq!(log["service"], "école-ünïcode".into());
+    assert_eq!(log["service_original"], "ÉCOLE-Ünïcode".into());
+    assert!(!log.contains("ddsource_original"));
+
+    let event = post_log_with_service("", "MyApp", "Java").await;
+    let log = event.as_log();
+    assert_eq!(log["service"], "MyApp".into());
+    assert_eq!(log["ddsource"], "Java".into());
+    assert!(!log.contains("service_original"));
+}
+
+#[tokio::test]
+async fn logs_normalize_service_names_vector_namespace() {
+    let event = post_log_with_service(
+        indoc! { r#"
+            log_namespace = true
+            normalize_service_names = true
+        "#},
+        "MyApp",
+        "java",
+    )
+    .await;
+    let log = event.as_log();
+    assert_eq!(
+        log.get(metadata_path!("datadog_agent", "service")),
+        Some(&"myapp".into())
+    );
+    assert_eq!(
+        log.get(metadata_path!("datadog_agent", "service_original")),
+        Some(&"MyApp".into())
+    );
+    assert!(log
+        .get(metadata_path!("datadog_agent", "ddsource_original"))
+        .is_none());
+}
+
+async fn post_log_with_tags(extra_config: &str, message: &str) -> Event {
+    let payload = AgentLogPayload::new()
+        .message(message)
+        .ddtags("env:prod,version:1.2,service:api,team:core,env:dev");
+    AgentHarness::start(extra_config)
+        .await
+        .send(payload)
+        .await
+        .single_event()
+}
+
+const PROMOTE_TAGS: &str = r#"promote_tags = ["env", "version", "service", "missing"]"#;
+
+#[tokio::test]
+async fn logs_promote_tags_keep_existing() {
+    let event = post_log_with_tags(PROMOTE_TAGS, "foo").await;
+    let log = event.as_log();
+    // Only the first value of a key is promoted.
+    assert_eq!(log["env"], "prod".into());
+    assert_eq!(log["version"], "1.2".into());
+    assert_eq!(log["service"], "vector".into());
+    assert!(!log.contains("missing"));
+    assert!(!log.contains("team"));
+    assert_eq!(
+        log["ddtags"],
+        "env:prod,version:1.2,service:api,team:core,env:dev".into()
+    );
+}
+
+#[tokio::test]
+async fn logs_promote_tags_overwrite() {
+    let config = format!("{}\npromote_conflict = \"overwrite\"", PROMOTE_TAGS);
+    let event = post_log_with_tags(&config, "foo").await;
+    let log = event.as_log();
+    assert_eq!(log["env"], "prod".into());
+    assert_eq!(log["service"], "api".into());
+    assert!(!log.contains("service_tag"));
+}
+
+#[tokio::test]
+async fn logs_promote_tags_rename() {
+    let config = format!("{}\npromote_conflict = \"rename\"", PROMOTE_TAGS);
+    let event = post_log_with_tags(&config, "foo").await;
+    let log = event.as_log();
+    assert_eq!(log["env"], "prod".into());
+    assert!(!log.contains("env_tag"));
+    assert_eq!(log["service"], "vector".into());
+    assert_eq!(log["service_tag"], "api".into());
+}
+
+#[tokio::test]
+async fn logs_promote_tags_removed() {
+    let config = format!("{}\nremove_promoted = true", PROMOTE_TAGS);
+    let event = post_log_with_tags(&config, "foo").await;
+    let log = event.as_log();
+    assert_eq!(log["env"], "prod".into());
+    assert_eq!(log["service"], "vector".into());
+    assert_eq!(log["ddtags"], "team:core".into());
+}
+
+#[tokio::test]
+async fn logs_promote_tags_vector_namespace() {
+    let config = indoc! { r#"
+        log_namespace = true
+        decoding.codec = "json"
+        promote_tags = ["env", "foo"]
+        remove_promoted = true
+    "#};
+    let event = post_log_with_tags(config, r#"{"foo": "bar"}"#).await;
+    let log = event.as_log();
+    assert_eq!(log["env"], "prod".into());
+    assert_eq!(log["foo"], "bar".into());
+    assert_eq!(
+        log.get(metadata_path!("datadog_agent", "ddtags")),
+        Some(&"version:1.2,service:api,team:core".into())
+    );
+
+    // Logs that aren't objects have no fields to promote tags to.
+    let config = indoc! { r#"
+        log_namespace = true
+        promote_tags = ["env"]
+    "#};
+    let event = post_log_with_tags(config, "foo").await;
+    assert_eq!(event.as_log().value(), &"foo".into());
+}
+
+#[tokio::test]
+async fn logs_reserved_fields_subset_legacy_namespace() {
+    let event = post_log_with_tags(r#"reserved_fields = ["service"]"#, "foo").await;
+    let log = event.as_log();
+    assert_eq!(log["message"], "foo".into());
+    assert_eq!(log["service"], "vector".into());
+    assert_eq!(log["source_type"], "datadog_agent".into());
+    // The ingest timestamp is still inserted.
+    assert!(log["timestamp"].as_timestamp().unwrap() > &Utc.timestamp_opt(123, 0).unwrap());
+    for field in ["status", "hostname", "ddsource", "ddtags"] {
+        assert!(!log.contains(field), "{}", field);
+    }
+}
+
+#[tokio::test]
+async fn logs_reserved_fields_subset_vector_namespace() {
+    let config = indoc! { r#"
+        log_namespace = true
+        reserved_fields = ["service", "ddtags"]
+    "#};
+    let event = post_log_with_tags(config, "foo").await;
+    let log = event.as_log();
+    assert_eq!(log.value(), &"foo".into());
+    assert_eq!(
+        log.get(metadata_path!("datadog_agent", "service")),
+        Some(&"vector".into())
+    );
+    assert_eq!(
+        log.get(metadata_path!("datadog_agent", "ddtags")),
+        Some(&"env:prod,version:1.2,service:api,team:core,env:dev".into())
+    );
+    for field in ["status", "timestamp", "hostname", "ddsource"] {
+        assert!(
+            log.get(metadata_path!("datadog_agent", field)).is_none(),
+            "{}",
+            field
+        );
+    }
+    assert!(log.get(metadata_path!("vector", "source_type")).is_some());
+    assert!(log
+        .get(metadata_path!("vector", "ingest_timestamp"))
+        .is_some());
+}
+
+#[tokio::test]
+async fn map_ddsource_to_output_requires_reserved_ddsource() {
+    let config = toml::from_str::<DatadogAgentConfig>(indoc! { r#"
+        address = "0.0.0.0:8012"
+        multiple_outputs = true
+        map_ddsource_to_output = true
+        reserved_fields = ["service"]
+    "#})
+    .unwrap();
+    let (sender, _rx) = SourceSender::new_test();
+    let schema_definitions =
+        HashMap::from([(Some(LOGS.to_owned()), test_logs_schema_definition())]);
+    let context = SourceContext::new_test(sender, Some(schema_definitions));
+    let error = config.build(context).await.err().unwrap();
+    assert!(error.to_string().contains("reserved_fields"), "{}", error);
+}
+
+#[tokio::test]
+async fn logs_rewritten_tags_are_kept_once() {
+    let config = format!("{}\nremove_promoted = true", PROMOTE_TAGS);
+    let event = post_log_with_tags(&config, "foo").await;
+    let log = event.as_log();
+    assert_eq!(log["ddtags"], "team:core".into());
+    assert!(log.get(metadata_path!("datadog_agent", "ddtags")).is_none());
+
+    let config = indoc! { r#"
+        log_namespace = true
+        decoding.codec = "json"
+        promote_tags = ["env", "version", "service"]
+        remove_promoted = true
+    "#};
+    let event = post_log_with_tags(config, r#"{"message": "foo"}"#).await;
+    let log = event.as_log();
+    assert_eq!(
+        log.get(metadata_path!("datadog_agent", "ddtags")),
+        Some(&"team:core".into())
+    );
+    assert!(!log.contains("ddtags"));
+}
+
+#[tokio::test]
+async fn logs_extract_trace_context_structured() {
+    let config = indoc! { r#"
+        decoding.codec = "json"
+        extract_trace_context = true
+    "#};
+    let message = r#"{"dd": {"trace_id": 1234567890, "span_id": "987654321"}}"#;
+    let event = post_log_with_tags(config, message).await;
+    let log = event.as_log();
+    assert_eq!(log["trace_id"], "1234567890".into());
+    assert_eq!(log["span_id"], "987654321".into());
+    assert_eq!(log["dd.trace_id"], 1234567890.into());
+    assert_eq!(log["dd.span_id"], "987654321".into());
+
+    let config = indoc! { r#"
+        log_namespace = true
+        decoding.codec = "json"
+        extract_trace_context = true
+    "#};
+    let event = post_log_with_tags(config, message).await;
+    let log = event.as_log();
+    assert_eq!(
+        log.get(metadata_path!("datadog_agent", "trace_id")),
+        Some(&"1234567890".into())
+    );
+    assert_eq!(
+        log.get(metadata_path!("datadog_agent", "span_id")),
+        Some(&"987654321".into())
+    );
+}
+
+#[tokio::test]
+async fn logs_annotate_order_numbers_frames_in_request_order() {
+    let config = indoc! { r#"
+        framing.method = "newline_delimited"
+        decoding.codec = "json"
+        acknowledgements = true
+        annotate_order = true
+    "#};
+    let mut harness = AgentHarness::start(config).await;
+    let payload = AgentLogPayload::new()
+        .message("{\"n\": 0}\n{\"n\": 1}\n{\"n\": 2}")
+        .message("{\"n\": 3}")
+        .message("{\"n\": 4}\n{\"n\": 5}");
+    let response = harness.send(payload).await;
+    assert_eq!(response.status, 200);
+
+    let numbers = response
+        .events
+        .iter()
+        .map(|(_, event)| {
+            let log = event.as_log();
+            (log["n"].clone(), log["request_seq"].clone())
+        })
+        .collect::<Vec<_>>();
+    let expected = (0..6)
+        .map(|n| (Value::from(n), Value::from(n)))
+        .collect::<Vec<_>>();
+    assert_eq!(numbers, expected);
+
+    // Each request is numbered on its own.
+    let response = harness
+        .send(AgentLogPayload::new().message("{\"n\": 6}"))
+        .await;
+    assert_eq!(response.single_event().as_log()["request_seq"], 0.into());
+
+    let mut harness = AgentHarness::start(indoc! { r#"
+        log_namespace = true
+        annotate_order = true
+    "#})
+    .await;
+    let event = harness
+        .send(AgentLogPayload::new().message("foo"))
+        .await
+        .single_event();
+    assert_eq!(
+        event
+            .as_log()
+            .get(metadata_path!("datadog_agent", "request_seq")),
+        Some(&0.into())
+    );
+}
+
+#[tokio::test]
+async fn logs_not_numbered_by_default() {
+    let event = post_log_with_tags("", "foo").await;
+    assert!(!event.as_log().contains("request_seq"));
+}
+
+const STACK_TRACE: &str = "Exception in thread \"main\" java.lang.IllegalStateException: boom\n\
+                           \tat com.example.App.run(App.java:10)\n\
+                           \tat com.example.App.main(App.java:5)";
+
+/// Sends `payload` to a source splitting or joining messages as `mode`, on Java stack traces.
+async fn multiline_messages(mode: &str, payload: AgentLogPayload) -> Vec<(Value, Value)> {
+    let config = format!(
+        r#"multiline = {{ mode = "{}", start_pattern = '^\S', condition_pattern = '^\s', condition_mode = "continue_through" }}"#,
+        mode
+    );
+    let response = AgentHarness::start(&config).await.send(payload).await;
+    assert_eq!(response.status, 200);
+    response
+        .events
+        .iter()
+        .map(|(_, event)| {
+            let log = event.as_log();
+            (log["message"].clone(), log["service"].clone())
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn logs_multiline_split_messages_into_their_lines() {
+    let message = format!("Starting up\n{}\nShutting down\n", STACK_TRACE);
+    let events = multiline_messages(
+        "split",
+        AgentLogPayload::new().message(&message).service("app"),
+    )
+    .await;
+    assert_eq!(
+        events,
+        vec![
+            ("Starting up".into(), "app".into()),
+            (STACK_TRACE.into(), "app".into()),
+            ("Shutting down".into(), "app".into()),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn logs_multiline_join_consecutive_messages() {
+    let mut lines = STACK_TRACE.lines();
+    let payload = AgentLogPayload::new()
+        .message(lines.next().unwrap())
+        .service("app")
+        .message(lines.next().unwrap())
+        .service("app")
+        .message(lines.next().unwrap())
+        .service("app")
+        // Messages of another service are never joined with those of the trace.
+        .message("\tat com.example.Other.run(Other.java:1)")
+        .service("other")
+        .message("Shutting down")
+        .service("app");
+    let events = multiline_messages("join", payload).await;
+    assert_eq!(
+        events,
+        vec![
+            (STACK_TRACE.into(), "app".into()),
+            (
+                "\tat com.example.Other.run(Other.java:1)".into(),
+                "other".into()
+            ),
+            ("Shutting down".into(), "app".into()),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn logs_multiline_off_leaves_messages_as_they_are() {
+    let events = multiline_messages("off", AgentLogPayload::new().message(STACK_TRACE)).await;
+    assert_eq!(events, vec![(STACK_TRACE.into(), "vector".into())]);
+}
+
+#[tokio::test]
+async fn logs_extract_trace_context_raw() {
+    let message = r#"{"dd.trace_id": "1234567890", "dd.span_id": 987654321, "msg": "hi"}"#;
+    let event = post_log_with_tags("extract_trace_context = true", message).await;
+    let log = event.as_log();
+    assert_eq!(log["trace_id"], "1234567890".into());
+    assert_eq!(log["span_id"], "987654321".into());
+    assert_eq!(log["message"], message.into());
+
+    let event = post_log_with_tags("", message).await;
+    assert!(!event.as_log().contains("trace_id"));
+}
+
+#[tokio::test]
+async fn logs_extract_trace_context_ignores_malformed_ids() {
+    let config = indoc! { r#"
+        decoding.codec = "json"
+        extract_trace_context = true
+    "#};
+    let message = r#"{"dd": {"trace_id": "not-an-id", "span_id": -1}}"#;
+    let event = post_log_with_tags(config, message).await;
+    let log = event.as_log();
+    assert!(!log.contains("trace_id"));
+    assert!(!log.contains("span_id"));
+    assert_eq!(log["dd.trace_id"], "not-an-id".into());
+
+    let event = post_log_with_tags("extract_trace_context = true", "{\"dd\": {\"trace_id\"").await;
+    assert!(!event.as_log().contains("trace_id"));
+}
+
+async fn post_oversized_logs(extra_config: &str, expected: usize) -> Vec<Event> {
+    // "é" is two bytes long, so a cut after an odd number of bytes splits it.
+    let payload = AgentLogPayload::new()
+        .message("short")
+        .message(&"é".repeat(20))
+        .message("also short");
+    let response = AgentHarness::start(extra_config).await.send(payload).await;
+    assert_eq!(response.status, 200);
+    assert_eq!(response.events.len(), expected, "{:?}", response.events);
+    response
+        .events
+        .into_iter()
+        .map(|(_, event)| event)
+        .collect()
+}
+
+#[tokio::test]
+async fn logs_oversized_messages_truncated() {
+    let events = post_oversized_logs("max_message_bytes = 21", 3).await;
+
+    let log = events[1].as_log();
+    assert_eq!(log["message"], "ééé...[truncated]".into());
+    assert_eq!(log["truncated"], true.into());
+    assert!(log["message"].as_bytes().unwrap().len() <= 21);
+
+    for event in [&events[0], &events[2]] {
+        assert!(!event.as_log().contains("truncated"));
+    }
+    assert_eq!(events[0].as_log()["message"], "short".into());
+    assert_eq!(events[2].as_log()["message"], "also short".into());
+}
+
+#[tokio::test]
+async fn logs_oversized_messages_truncated_vector_namespace() {
+    let events = post_oversized_logs(
+        indoc! { r#"
+            log_namespace = true
+            max_message_bytes = 30
+            truncation_marker = "…"
+        "#},
+        3,
+    )
+    .await;
+
+    let log = events[1].as_log();
+    assert_eq!(*log.value(), format!("{}…", "é".repeat(13)).into());
+    assert_eq!(
+        log.get(metadata_path!("datadog_agent", "truncated")),
+        Some(&true.into())
+    );
+    assert!(events[0]
+        .as_log()
+        .get(metadata_path!("datadog_agent", "truncated"))
+        .is_none());
+}
+
+#[tokio::test]
+async fn logs_oversized_messages_dropped() {
+    let events = post_oversized_logs(
+        indoc! { r#"
+            max_message_bytes = 21
+            on_oversized = "drop"
+        "#},
+        2,
+    )
+    .await;
+
+    assert_eq!(events[0].as_log()["message"], "short".into());
+    assert_eq!(events[1].as_log()["message"], "also short".into());
+}
+
+#[tokio::test]
+async fn logs_oversized_messages_passed() {
+    let events = post_oversized_logs(
+        indoc! { r#"
+            max_message_bytes = 21
+            on_oversized = "pass"
+        "#},
+        3,
+    )
+    .await;
+
+    let log = events[1].as_log();
+    assert_eq!(log["message"], "é".repeat(20).into());
+    assert!(!log.contains("truncated"));
+}
+
+#[test]
+fn truncate_message_keeps_utf8_characters_whole() {
+    let message = Bytes::from("a€€");
+    for max_bytes in 0..=message.len() {
+        let truncated = truncate_message(&message, max_bytes, "");
+        assert!(std::str::from_utf8(&truncated).is_ok());
+        assert!(truncated.len() <= max_bytes);
+    }
+    assert_eq!(truncate_message(&message, 6, ""), Bytes::from("a€"));
+    assert_eq!(truncate_message(&message, 3, "!!"), Bytes::from("a!!"));
+    // A marker longer than the limit is kept whole.
+    assert_eq!(truncate_message(&message, 1, "!!"), Bytes::from("!!"));
+}
+
+#[tokio::test]
+async fn truncation_marker_must_be_shorter_than_max_message_bytes() {
+    let build = |config: &str| {
+        let config = toml::from_str::<DatadogAgentConfig>(&format!(
+            "address = \"{}\"\n{}",
+            next_addr(),
+            config
+        ))
+        .unwrap();
+        let schema_definitions = config
+            .outputs(LogNamespace::Legacy)
+            .into_iter()
+            .filter_map(|output| Some((output.port.clone(), output.schema_definition(true)?)))
+            .collect();
+        let (sender, _rx) = SourceSender::new_test();
+        let context = SourceContext::new_test(sender, Some(schema_definitions));
+        async move { config.build(context).await }
+    };
+
+    let error = build("max_message_bytes = 14").await.err().unwrap();
+    assert_eq!(
+        error.to_string(),
+        "`truncation_marker` must be shorter than `max_message_bytes` (14 bytes)"
+    );
+    build("max_message_bytes = 4\ntruncation_marker = \"...\"")
+        .await
+        .unwrap();
+    // The marker is only used to truncate messages.
+    build("max_message_bytes = 4\non_oversized = \"drop\"")
+        .await
+        .unwrap();
+}
+
+#[test]
+fn client_addr_skips_trusted_proxies() {
+    let trusted = [
+        IpCidr::from_str("127.0.0.1").unwrap(),
+        IpCidr::from_str("10.0.0.0/8").unwrap(),
+    ];
+    let peer: IpAddr = "127.0.0.1".parse().unwrap();
+    let client = |forwarded_for| client_addr(peer, forwarded_for, &trusted).to_string();
+
+    assert_eq!(client(None), "127.0.0.1");
+    assert_eq!(client(Some("203.0.113.7")), "203.0.113.7");
+    assert_eq!(
+        client(Some("198.51.100.1, 203.0.113.7, 10.0.0.1")),
+        "203.0.113.7"
+    );
+    assert_eq!(client(Some("10.0.0.2, 10.0.0.1")), "10.0.0.2");
+    assert_eq!(client(Some("203.0.113.7, garbage")), "127.0.0.1");
+
+    let untrusted_peer: IpAddr = "192.0.2.1".parse().unwrap();
+    assert_eq!(
+        client_addr(untrusted_peer, Some("203.0.113.7"), &trusted),
+        untrusted_peer
+    );
+}
+
+/// Sends a chunked request to `path`, one chunk of `chunk_size` bytes at a time, until the
+/// response arrives.
+///
+/// Returns the status of the response, along with the number of chunks sent before it arrived.
+/// The body is never terminated, so a response only arrives if the request is rejected early.
+async fn send_chunked(
+    address: SocketAddr,
+    path: &str,
+    extra_headers: &str,
+    chunks: usize,
+    chunk_size: usize,
+) -> (u16, usize) {
+    let stream = tokio::net::TcpStream::connect(address).await.unwrap();
+    let (mut reader, mut writer) = stream.into_split();
+    let response = tokio::spawn(async move {
+        let mut response = Vec::new();
+        let mut buffer = [0; 1024];
+        while !response.windows(2).any(|window| window == b"\r\n") {
+            match reader.read(&mut buffer).await.unwrap() {
+                0 => break,
+                read => response.extend_from_slice(&buffer[..read]),
+            }
+        }
+        let status_line = String::from_utf8_lossy(&response).into_owned();
+        status_line
+            .split(' ')
+            .nth(1)
+            .unwrap()
+            .parse::<u16>()
+            .unwrap()
+    });
+
+    let head = format!(
+        "POST {} HTTP/1.1\r\nhost: {}\r\ntransfer-encoding: chunked\r\n{}\r\n",
+        path, address, extra_headers
+    );
+    writer.write_all(head.as_bytes()).await.unwrap();
+
+    let chunk = format!("{:x}\r\n{}\r\n", chunk_size, "a".repeat(chunk_size));
+    let mut sent = 0;
+    while sent < chunks {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        if response.is_finished() || writer.write_all(chunk.as_bytes()).await.is_err() {
+            break;
         }
+        sent += 1;
+    }
+
+    let status = tokio::time::timeout(Duration::from_secs(5), response)
+        .await
+        .expect("the request wasn't rejected before its body ended")
+        .unwrap();
+    (status, sent)
+}
+
+#[tokio::test]
+async fn logs_rejects_large_chunked_bodies_early() {
+    let mut harness = AgentHarness::start("max_body_bytes = 100").await;
+
+    let (status, sent) = send_chunked(harness.address(), "/api/v2/logs", "", 10, 40).await;
+    assert_eq!(status, 413);
+    // The limit is crossed by the third chunk.
+    assert!(sent < 10, "sent {} chunks", sent);
+
+    let payload = AgentLogPayload::new().message(&"a".repeat(100));
+    assert_eq!(harness.send(payload).await.status, 413);
+}
+
+#[tokio::test]
+async fn logs_rejects_unauthorized_chunked_requests_before_their_body() {
+    let allowed = "12345678abcdefgh12345678abcdefgh";
+    let harness = AgentHarness::start(&format!("allowed_api_keys = [\"{}\"]", allowed)).await;
+
+    let (status, sent) = send_chunked(
+        harness.address(),
+        "/api/v2/logs",
+        "dd-api-key: abcdefgh12345678abcdefgh12345678\r\n",
+        10,
+        40,
+    )
+    .await;
+    assert_eq!(status, 403);
+    assert!(sent < 10, "sent {} chunks", sent);
+}
+
+#[tokio::test]
+async fn closes_connections_past_their_max_age() {
+    // The harness keeps its connection open across requests.
+    let mut harness = AgentHarness::start("keepalive.max_connection_age_secs = 1").await;
+    let payload = || AgentLogPayload::new().message("foo");
+
+    assert!(!harness
+        .send(payload())
+        .await
+        .headers
+        .contains_key("connection"));
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    assert_eq!(harness.send(payload()).await.headers["connection"], "close");
+    // The client reconnects.
+    assert!(!harness
+        .send(payload())
+        .await
+        .headers
+        .contains_key("connection"));
+}
+
+#[tokio::test]
+async fn compresses_error_bodies_when_accepted() {
+    let mut harness = AgentHarness::start("").await;
+    let payload = |accept_encoding: &str| {
+        AgentLogPayload::new()
+            .raw_body("not json")
+            .header("accept-encoding", accept_encoding)
+    };
+
+    let response = harness.send(payload("gzip, deflate")).await;
+    assert_eq!(response.status, 400);
+    assert_eq!(response.headers["content-encoding"], "gzip");
+    let mut body = String::new();
+    flate2::read::GzDecoder::new(&response.body[..])
+        .read_to_string(&mut body)
+        .unwrap();
+    let body = serde_json::from_str::<serde_json::Value>(&body).unwrap();
+    assert_eq!(body["reason"], "json_parse");
+
+    for accept_encoding in ["identity", "gzip;q=0"] {
+        let response = harness.send(payload(accept_encoding)).await;
+        assert_eq!(response.status, 400);
+        assert!(!response.headers.contains_key("content-encoding"));
+        assert_eq!(response.json()["reason"], "json_parse");
+    }
+}
+
+#[tokio::test]
+async fn logs_rejects_disallowed_api_keys() {
+    let allowed = "12345678abcdefgh12345678abcdefgh";
+    let other = "abcdefgh12345678abcdefgh12345678";
+    let mut harness = AgentHarness::start(&format!("allowed_api_keys = [\"{}\"]", allowed)).await;
+
+    let payload = || AgentLogPayload::new().message("foo");
+    let cases = [
+        (payload().api_key_in_path(other), 403),
+        // The key in the path takes precedence over the one in the header.
+        (
+            payload().api_key_in_path(other).api_key_in_header(allowed),
+            403,
+        ),
+        (payload().api_key_in_header(other), 403),
+        (payload(), 403),
+        (payload().api_key_in_path(allowed), 200),
+        (payload().api_key_in_header(allowed), 200),
+    ];
+    let mut events = Vec::new();
+    for (payload, status) in cases {
+        let response = harness.send(payload).await;
+        assert_eq!(response.status, status);
+        events.extend(response.events.into_iter().map(|(_, event)| event));
+    }
+
+    assert_eq!(events.len(), 2);
+    for event in events {
+        assert_eq!(event.metadata().datadog_api_key().as_deref(), Some(allowed));
+    }
+}
+
+#[tokio::test]
+async fn logs_dumps_failed_requests() {
+    let directory = tempfile::tempdir().unwrap();
+    let mut harness = AgentHarness::start(&format!(
+        "failed_request_dump_path = {:?}\nmax_dump_bytes = 8\nmax_dumps_per_minute = 2",
+        directory.path()
+    ))
+    .await;
+
+    let api_key = "12345678abcdefgh12345678abcdefgh";
+    let payload = AgentLogPayload::new()
+        .raw_body("not a json payload")
+        .api_key_in_path(api_key)
+        .api_key_in_header(api_key);
+    for _ in 0..3 {
+        assert_eq!(harness.send(payload.clone()).await.status, 400);
+    }
+
+    // The dumps are written in the background, and the sidecar last.
+    let sidecars = || {
+        std::fs::read_dir(directory.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| {
+                path.extension()
+                    .map_or(false, |extension| extension == "json")
+            })
+            .collect::<Vec<_>>()
+    };
+    for _ in 0..100 {
+        if sidecars().len() >= 2 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let sidecars = sidecars();
+    assert_eq!(sidecars.len(), 2);
+    assert_eq!(std::fs::read_dir(directory.path()).unwrap().count(), 4);
+    for sidecar in sidecars {
+        let body = std::fs::read(sidecar.with_extension("body")).unwrap();
+        assert_eq!(body, b"not a js");
+
+        let sidecar: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(sidecar).unwrap()).unwrap();
+        assert_eq!(sidecar["path"], "/v1/input/[REDACTED]");
+        assert_eq!(sidecar["headers"]["dd-api-key"], "[REDACTED]");
+        assert_eq!(sidecar["body_bytes"], 18);
+        assert_eq!(sidecar["truncated"], true);
+        assert!(sidecar["error"]
+            .as_str()
+            .unwrap()
+            .starts_with("400: Error parsing JSON"));
+    }
+}
+
+#[tokio::test]
+async fn dumper_skips_failures_unrelated_to_the_body() {
+    metrics::init_test();
+    let directory = tempfile::tempdir().unwrap();
+    let dumper = FailedRequestDumper::new(directory.path().to_owned(), 1024, 10);
+
+    let overloaded = reject(
+        LOGS,
+        RejectionReason::Overloaded,
+        http::StatusCode::TOO_MANY_REQUESTS,
+        "Too many requests are waiting to be decoded".to_string(),
+    );
+    let join_error = ErrorMessage::new(
+        http::StatusCode::INTERNAL_SERVER_ERROR,
+        "Failed decoding request".to_string(),
+    );
+    for error in [overloaded, join_error] {
+        dumper.dump(
+            &Bytes::from("[]"),
+            &HeaderMap::new(),
+            "/api/v2/logs",
+            &error,
+        );
+    }
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(std::fs::read_dir(directory.path()).unwrap().count(), 0);
+}
+
+#[tokio::test]
+async fn logs_routed_by_ddsource() {
+    trace_init();
+    let (mut sender, _rx) = SourceSender::new_test_finalize(EventStatus::Delivered);
+    let mut output = |name: &str| {
+        sender
+            .add_outputs(EventStatus::Delivered, name.to_owned())
+            .flat_map(into_event_stream)
+    };
+    let logs = output("logs");
+    let mut nginx = output("logs.nginx");
+    let mut postgres = output("logs.postgres");
+
+    // Each output gets a distinct schema definition, to check the one attached to routed events.
+    let definition = |field: &str| {
+        test_logs_schema_definition().with_event_field(
+            &owned_value_path!(field),
+            Kind::bytes(),
+            None,
+        )
+    };
+    let schema_definitions = HashMap::from([
+        (Some(LOGS.to_owned()), test_logs_schema_definition()),
+        (Some("logs.nginx".to_owned()), definition("nginx")),
+        (Some("logs.postgres".to_owned()), definition("postgres")),
+    ]);
+
+    let address = next_addr();
+    let config = toml::from_str::<DatadogAgentConfig>(&format!(
+        indoc! { r#"
+            address = "{}"
+            multiple_outputs = true
+            map_ddsource_to_output = true
+            ddsource_outputs = ["nginx", "postgres"]
+        "#},
+        address
+    ))
+    .unwrap();
+    let context = SourceContext::new_test(sender, Some(schema_definitions));
+    tokio::spawn(async move {
+        config.build(context).await.unwrap().await.unwrap();
+    });
+    wait_for_tcp(address).await;
+
+    let msg = |message: &str, ddsource: &'static str| LogMsg {
+        ddsource: Bytes::from(ddsource),
+        ..test_log_msg(message)
+    };
+    let body = serde_json::to_string(&[
+        msg("from nginx", "nginx"),
+        msg("from curl", "curl"),
+        msg("from postgres", "postgres"),
+    ])
+    .unwrap();
+
+    let mut events = spawn_collect_n(
+        async move {
+            let response = reqwest::Client::new()
+                .post(&format!("http://{}/api/v2/logs", address))
+                .body(body)
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(200, response.status().as_u16());
+        },
+        logs,
+        1,
+    )
+    .await;
+
+    let default = events.remove(0);
+    assert_eq!(default.as_log()["message"], "from curl".into());
+    assert_eq!(
+        default.metadata().schema_definition(),
+        &test_logs_schema_definition()
+    );
+
+    for (stream, ddsource) in [(&mut nginx, "nginx"), (&mut postgres, "postgres")] {
+        let event = stream.next().await.unwrap();
+        assert_eq!(
+            event.as_log()["message"],
+            format!("from {}", ddsource).into()
+        );
+        assert_eq!(event.metadata().schema_definition(), &definition(ddsource));
+    }
+}
+
+#[test]
+fn ddsource_outputs_are_listed() {
+    let config = |multiple_outputs: bool| {
+        toml::from_str::<DatadogAgentConfig>(&format!(
+            indoc! { r#"
+                address = "0.0.0.0:8012"
+                multiple_outputs = {}
+                map_ddsource_to_output = true
+                ddsource_outputs = ["nginx", "postgres"]
+            "#},
+            multiple_outputs
+        ))
+        .unwrap()
+    };
+
+    let outputs = config(true).outputs(LogNamespace::Legacy);
+    let ports = outputs
+        .iter()
+        .map(|output| output.port.as_deref())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        ports,
+        [
+            Some("logs.nginx"),
+            Some("logs.postgres"),
+            Some(LOGS),
+            Some(METRICS),
+            Some(TRACES)
+        ]
+    );
+    assert_eq!(outputs[0].schema_definition, outputs[2].schema_definition);
+    assert_eq!(outputs[1].schema_definition, outputs[2].schema_definition);
+
+    assert_eq!(config(false).outputs(LogNamespace::Legacy).len(), 1);
+}
+
+#[tokio::test]
+async fn logs_routed_by_api_key() {
+    let prod = "12345678abcdefgh12345678abcdefgh";
+    let staging = "abcdefgh12345678abcdefgh12345678";
+    let other = "00000000abcdefgh12345678abcdefgh";
+    let staging_digest = hex::encode(Sha256::digest(staging.as_bytes()));
+    let mut harness = AgentHarness::start(&format!(
+        indoc! { r#"
+            multiple_outputs = true
+            store_api_key = false
+            api_key_routing."{}" = "prod"
+            api_key_routing."sha256:{}" = "staging"
+        "#},
+        prod,
+        staging_digest.to_uppercase()
+    ))
+    .await;
+
+    for (api_key, output) in [
+        (Some(prod), "logs.prod"),
+        (Some(staging), "logs.staging"),
+        (Some(other), LOGS),
+        (None, LOGS),
+    ] {
+        let mut payload = AgentLogPayload::new().message("foo");
+        if let Some(api_key) = api_key {
+            payload = payload.api_key_in_header(api_key);
+        }
+        let mut response = harness.send(payload).await;
+        assert_eq!(response.status, 200);
+        assert_eq!(response.events.len(), 1, "{:?}", api_key);
+        let (port, event) = response.events.remove(0);
+        assert_eq!(port.as_deref(), Some(output), "{:?}", api_key);
+        // The logs are routed by their API key even though it isn't stored with them.
+        assert!(event.metadata().datadog_api_key().is_none());
+    }
+}
+
+#[test]
+fn api_key_routing_outputs_are_listed() {
+    let config = toml::from_str::<DatadogAgentConfig>(indoc! { r#"
+        address = "0.0.0.0:8012"
+        multiple_outputs = true
+        map_ddsource_to_output = true
+        ddsource_outputs = ["nginx"]
+        api_key_routing.a = "staging"
+        api_key_routing.b = "prod"
+        api_key_routing.c = "prod"
+    "#})
+    .unwrap();
+
+    let ports = config
+        .outputs(LogNamespace::Legacy)
+        .into_iter()
+        .map(|output| output.port.unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        ports,
+        [
+            "logs.nginx",
+            "logs.prod",
+            "logs.staging",
+            LOGS,
+            METRICS,
+            TRACES
+        ]
+    );
+}
+
+#[tokio::test]
+async fn api_key_routing_rejects_invalid_configs() {
+    let build_error = |config: &str| {
+        let config = toml::from_str::<DatadogAgentConfig>(&format!(
+            "address = \"0.0.0.0:8012\"\n{}",
+            config
+        ))
+        .unwrap();
+        let schema_definitions = config
+            .outputs(LogNamespace::Legacy)
+            .into_iter()
+            .filter_map(|output| Some((output.port.clone(), output.schema_definition(true)?)))
+            .collect();
+        let (sender, _rx) = SourceSender::new_test();
+        let context = SourceContext::new_test(sender, Some(schema_definitions));
+        async move { config.build(context).await.err().unwrap().to_string() }
+    };
+
+    assert_eq!(
+        build_error("api_key_routing.a = \"prod\"").await,
+        "`api_key_routing` requires `multiple_outputs` to be enabled"
+    );
+    assert_eq!(
+        build_error(indoc! { r#"
+            multiple_outputs = true
+            map_ddsource_to_output = true
+            ddsource_outputs = ["prod"]
+            api_key_routing.a = "prod"
+        "#})
+        .await,
+        "Output `logs.prod` is used by both `ddsource_outputs` and `api_key_routing`"
+    );
+    assert_eq!(
+        build_error("multiple_outputs = true\napi_key_routing.\"sha256:abc\" = \"prod\"").await,
+        "Invalid SHA-256 digest \"abc\" in `api_key_routing`, expected 64 hexadecimal characters"
+    );
+}
+
+#[test]
+fn disabled_endpoints_omit_outputs() {
+    let ports = |disabled: &str| {
+        toml::from_str::<DatadogAgentConfig>(&format!(
+            "address = \"0.0.0.0:8012\"\nmultiple_outputs = true\n{}",
+            disabled
+        ))
+        .unwrap()
+        .outputs(LogNamespace::Legacy)
+        .into_iter()
+        .map(|output| output.port.unwrap())
+        .collect::<Vec<_>>()
+    };
+
+    assert_eq!(ports(""), [LOGS, METRICS, TRACES]);
+    assert_eq!(ports("disable_logs = true"), [METRICS, TRACES]);
+    assert_eq!(ports("disable_metrics = true"), [LOGS, TRACES]);
+    assert_eq!(ports("disable_traces = true"), [LOGS, METRICS]);
+    assert_eq!(
+        ports("disable_metrics = true\ndisable_traces = true"),
+        [LOGS]
+    );
+    assert_eq!(
+        ports(indoc! { r#"
+            disable_logs = true
+            map_ddsource_to_output = true
+            ddsource_outputs = ["nginx"]
+        "#}),
+        [METRICS, TRACES]
+    );
+}
+
+#[tokio::test]
+async fn disabled_endpoints_are_not_found() {
+    let mut harness = AgentHarness::start("disable_metrics = true\ndisable_traces = true").await;
+
+    assert_eq!(harness.send(AgentLogPayload::new()).await.status, 200);
+    for (path, endpoint) in [
+        ("/api/v2/series", METRICS),
+        ("/api/beta/sketches", METRICS),
+        ("/api/v0.2/traces", TRACES),
+    ] {
+        let response = harness.post(path, HeaderMap::new(), "[]").await;
+        assert_eq!(response.status, 404);
+        let body = response.json();
+        assert_eq!(
+            body["message"],
+            format!("The {} endpoint is disabled", endpoint)
+        );
+    }
+
+    let response = harness
+        .post("/api/v2/unknown", HeaderMap::new(), "[]")
+        .await;
+    assert_eq!(response.status, 404);
+    assert!(!String::from_utf8_lossy(&response.body).contains("disabled"));
+}
+
+/// Asserts that `event` is the log of an [`AgentLogPayload`] with the given `message`, as
+/// decoded by the source of `harness`.
+fn assert_agent_log(harness: &AgentHarness, event: &Event, message: &str, api_key: Option<&str>) {
+    let log = event.as_log();
+    assert_eq!(log["message"], message.into());
+    assert_eq!(
+        log["timestamp"],
+        Utc.timestamp_opt(123, 0)
+            .single()
+            .expect("invalid timestamp")
+            .into()
+    );
+    assert_eq!(log["hostname"], "festeburg".into());
+    assert_eq!(log["status"], "notice".into());
+    assert_eq!(log["service"], "vector".into());
+    assert_eq!(log["ddsource"], "curl".into());
+    assert_eq!(log["ddtags"], "one,two,three".into());
+    assert_eq!(log[log_schema().source_type_key()], "datadog_agent".into());
+    assert_eq!(event.metadata().datadog_api_key().as_deref(), api_key);
+    assert_eq!(
+        event.metadata().schema_definition(),
+        harness.logs_schema_definition()
+    );
+}
+
+#[tokio::test]
+async fn full_payload_v1() {
+    assert_source_compliance(&HTTP_PUSH_SOURCE_TAGS, async {
+        let mut harness = AgentHarness::start("acknowledgements = true").await;
+
+        let payload = AgentLogPayload::new().message("foo").legacy_path();
+        let event = harness.send(payload).await.single_event();
+        assert_agent_log(&harness, &event, "foo", None);
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn full_payload_v2() {
+    assert_source_compliance(&HTTP_PUSH_SOURCE_TAGS, async {
+        let mut harness = AgentHarness::start("acknowledgements = true").await;
+
+        let event = harness
+            .send(AgentLogPayload::new().message("foo"))
+            .await
+            .single_event();
+        assert_agent_log(&harness, &event, "foo", None);
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn no_api_key() {
+    assert_source_compliance(&HTTP_PUSH_SOURCE_TAGS, async {
+        let mut harness = AgentHarness::start("acknowledgements = true").await;
+
+        let payload = AgentLogPayload::new().message("foo").legacy_path();
+        let event = harness.send(payload).await.single_event();
+        assert_agent_log(&harness, &event, "foo", None);
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn api_key_in_url() {
+    assert_source_compliance(&HTTP_PUSH_SOURCE_TAGS, async {
+        let mut harness = AgentHarness::start("acknowledgements = true").await;
+
+        let payload = AgentLogPayload::new()
+            .message("bar")
+            .api_key_in_path("12345678abcdefgh12345678abcdefgh");
+        let event = harness.send(payload).await.single_event();
+        assert_agent_log(
+            &harness,
+            &event,
+            "bar",
+            Some("12345678abcdefgh12345678abcdefgh"),
+        );
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn api_key_in_query_params() {
+    assert_source_compliance(&HTTP_PUSH_SOURCE_TAGS, async {
+        let mut harness = AgentHarness::start("acknowledgements = true").await;
+
+        let payload = AgentLogPayload::new()
+            .message("bar")
+            .api_key_in_query("12345678abcdefgh12345678abcdefgh");
+        let event = harness.send(payload).await.single_event();
+        assert_agent_log(
+            &harness,
+            &event,
+            "bar",
+            Some("12345678abcdefgh12345678abcdefgh"),
+        );
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn api_key_in_header() {
+    assert_source_compliance(&HTTP_PUSH_SOURCE_TAGS, async {
+        let mut harness = AgentHarness::start("acknowledgements = true").await;
+
+        let payload = AgentLogPayload::new()
+            .message("baz")
+            .legacy_path()
+            .api_key_in_header("12345678abcdefgh12345678abcdefgh");
+        let event = harness.send(payload).await.single_event();
+        assert_agent_log(
+            &harness,
+            &event,
+            "baz",
+            Some("12345678abcdefgh12345678abcdefgh"),
+        );
     })
     .await;
 }
 
 #[tokio::test]
-async fn api_key_in_query_params() {
-    assert_source_compliance(&HTTP_PUSH_SOURCE_TAGS, async {
-        let (rx, _, _, addr) = source(EventStatus::Delivered, true, true, false).await;
-
-        let mut events = spawn_collect_n(
-            async move {
-                assert_eq!(
-                    200,
-                    send_with_path(
-                        addr,
-                        &serde_json::to_string(&[LogMsg {
-                            message: Bytes::from("bar"),
-                            timestamp: Utc
-                                .timestamp_opt(456, 0)
-                                .single()
-                                .expect("invalid timestamp"),
-                            hostname: Bytes::from("festeburg"),
-                            status: Bytes::from("notice"),
-                            service: Bytes::from("vector"),
-                            ddsource: Bytes::from("curl"),
-                            ddtags: Bytes::from("one,two,three"),
-                        }])
-                        .unwrap(),
-                        HeaderMap::new(),
-                        "/api/v2/logs?dd-api-key=12345678abcdefgh12345678abcdefgh"
-                    )
-                    .await
-                );
-            },
-            rx,
-            1,
-        )
+async fn delivery_failure() {
+    let mut harness =
+        AgentHarness::start_with_status("acknowledgements = true", EventStatus::Rejected).await;
+
+    let payload = AgentLogPayload::new().message("foo").legacy_path();
+    let response = harness.send(payload).await;
+    assert_eq!(response.status, 400);
+    assert_eq!(response.events.len(), 1);
+}
+
+#[tokio::test]
+async fn logs_decode_failure_is_not_retried() {
+    let mut harness = AgentHarness::start("acknowledgements = true").await;
+
+    let response = harness
+        .send(AgentLogPayload::new().raw_body("not a json payload"))
         .await;
+    assert_eq!(response.status, 400);
+    assert!(response.headers.get("retry-after").is_none());
+    assert_eq!(response.json()["reason"], "json_parse");
+    assert!(response.events.is_empty());
+}
 
-        {
-            let event = events.remove(0);
-            let log = event.as_log();
-            assert_eq!(log["message"], "bar".into());
-            assert_eq!(
-                log["timestamp"],
-                Utc.timestamp_opt(456, 0)
-                    .single()
-                    .expect("invalid timestamp")
-                    .into()
-            );
-            assert_eq!(log["hostname"], "festeburg".into());
-            assert_eq!(log["status"], "notice".into());
-            assert_eq!(log["service"], "vector".into());
-            assert_eq!(log["ddsource"], "curl".into());
-            assert_eq!(log["ddtags"], "one,two,three".into());
-            assert_eq!(log[log_schema().source_type_key()], "datadog_agent".into());
-            assert_eq!(
-                &event.metadata().datadog_api_key().as_ref().unwrap()[..],
-                "12345678abcdefgh12345678abcdefgh"
-            );
-            assert_eq!(
-                event.metadata().schema_definition(),
-                &test_logs_schema_definition()
-            );
+#[tokio::test]
+async fn logs_option_matrix() {
+    let api_key = "12345678abcdefgh12345678abcdefgh";
+    let compressions: [fn(AgentLogPayload) -> AgentLogPayload; 3] = [
+        |payload| payload,
+        AgentLogPayload::compress_gzip,
+        AgentLogPayload::compress_deflate,
+    ];
+    let api_keys: [fn(AgentLogPayload, &str) -> AgentLogPayload; 3] = [
+        AgentLogPayload::api_key_in_header,
+        AgentLogPayload::api_key_in_query,
+        AgentLogPayload::api_key_in_path,
+    ];
+
+    for log_namespace in [false, true] {
+        for multiple_outputs in [false, true] {
+            for origin_as_tag in [false, true] {
+                let mut harness = AgentHarness::start(&format!(
+                    "log_namespace = {}\nmultiple_outputs = {}\norigin_as_tag = {}",
+                    log_namespace, multiple_outputs, origin_as_tag
+                ))
+                .await;
+
+                for (compress, with_api_key) in compressions
+                    .iter()
+                    .flat_map(|compress| api_keys.iter().map(move |key| (compress, key)))
+                {
+                    let payload = AgentLogPayload::new()
+                        .message("foo")
+                        .ddtags("a:b,c")
+                        .header("x-datadog-origin", "agent-pipeline");
+                    let mut response = harness.send(compress(with_api_key(payload, api_key))).await;
+                    let case = format!(
+                        "log_namespace = {}, multiple_outputs = {}, origin_as_tag = {}",
+                        log_namespace, multiple_outputs, origin_as_tag
+                    );
+                    assert_eq!(response.status, 200, "{}", case);
+                    assert_eq!(response.events.len(), 1, "{}", case);
+                    let (output, event) = response.events.remove(0);
+
+                    let expected_output = multiple_outputs.then(|| LOGS.to_owned());
+                    assert_eq!(output, expected_output, "{}", case);
+                    assert_eq!(
+                        event.metadata().datadog_api_key().as_deref(),
+                        Some(api_key),
+                        "{}",
+                        case
+                    );
+
+                    let expected_tags = if origin_as_tag {
+                        "a:b,c,origin:agent-pipeline"
+                    } else {
+                        "a:b,c"
+                    };
+                    let log = event.as_log();
+                    if log_namespace {
+                        assert_eq!(log.value(), &"foo".into(), "{}", case);
+                        assert_eq!(
+                            log.get(metadata_path!("datadog_agent", "ddtags")),
+                            Some(&expected_tags.into()),
+                            "{}",
+                            case
+                        );
+                    } else {
+                        assert_eq!(log["message"], "foo".into(), "{}", case);
+                        assert_eq!(log["ddtags"], expected_tags.into(), "{}", case);
+                    }
+                }
+            }
         }
-    })
-    .await;
+    }
 }
 
 #[tokio::test]
-async fn api_key_in_header() {
-    assert_source_compliance(&HTTP_PUSH_SOURCE_TAGS, async {
-        let (rx, _, _, addr) = source(EventStatus::Delivered, true, true, false).await;
+async fn logs_empty_response_by_default() {
+    let payload = AgentLogPayload::new().message("foo").message("bar");
+    let response = AgentHarness::start("").await.send(payload).await;
+
+    assert_eq!(response.status, 200);
+    assert!(response.headers.get("content-type").is_none());
+    assert!(response.body.is_empty());
+    assert_eq!(response.events.len(), 2);
+}
 
-        let mut headers = HeaderMap::new();
-        headers.insert(
-            "dd-api-key",
-            "12345678abcdefgh12345678abcdefgh".parse().unwrap(),
-        );
+#[tokio::test]
+async fn logs_verbose_responses() {
+    let mut harness = AgentHarness::start("verbose_responses = true").await;
 
-        let mut events = spawn_collect_n(
-            async move {
-                assert_eq!(
-                    200,
-                    send_with_path(
-                        addr,
-                        &serde_json::to_string(&[LogMsg {
-                            message: Bytes::from("baz"),
-                            timestamp: Utc
-                                .timestamp_opt(789, 0)
-                                .single()
-                                .expect("invalid timestamp"),
-                            hostname: Bytes::from("festeburg"),
-                            status: Bytes::from("notice"),
-                            service: Bytes::from("vector"),
-                            ddsource: Bytes::from("curl"),
-                            ddtags: Bytes::from("one,two,three"),
-                        }])
-                        .unwrap(),
-                        headers,
-                        "/v1/input/"
-                    )
-                    .await
-                );
-            },
-            rx,
-            1,
-        )
-        .await;
+    let payload = AgentLogPayload::new().message("foo").message("bar");
+    let response = harness.send(payload).await;
+    assert_eq!(response.status, 200);
+    assert_eq!(response.headers["content-type"], "application/json");
+    assert_eq!(
+        response.json(),
+        serde_json::json!({"accepted": 2, "rejected": 0})
+    );
+    assert_eq!(response.events.len(), 2);
+
+    // A payload with an invalid log is rejected as a whole, with the usual error body.
+    let payload = AgentLogPayload::new()
+        .raw_body(r#"[{"message": "foo", "timestamp": 123}, {"message": 42}]"#);
+    let response = harness.send(payload).await;
+    assert_eq!(response.status, 400);
+    assert!(response.json().get("accepted").is_none());
+}
 
-        {
-            let event = events.remove(0);
-            let log = event.as_log();
-            assert_eq!(log["message"], "baz".into());
-            assert_eq!(
-                log["timestamp"],
-                Utc.timestamp_opt(789, 0)
-                    .single()
-                    .expect("invalid timestamp")
-                    .into()
-            );
-            assert_eq!(log["hostname"], "festeburg".into());
-            assert_eq!(log["status"], "notice".into());
-            assert_eq!(log["service"], "vector".into());
-            assert_eq!(log["ddsource"], "curl".into());
-            assert_eq!(log["ddtags"], "one,two,three".into());
-            assert_eq!(log[log_schema().source_type_key()], "datadog_agent".into());
-            assert_eq!(
-                &event.metadata().datadog_api_key().as_ref().unwrap()[..],
-                "12345678abcdefgh12345678abcdefgh"
-            );
-            assert_eq!(
-                event.metadata().schema_definition(),
-                &test_logs_schema_definition()
-            );
-        }
-    })
+#[tokio::test]
+async fn logs_verbose_responses_count_rejected_messages() {
+    let mut harness = AgentHarness::start(indoc! { r#"
+        verbose_responses = true
+        decoding.codec = "json"
+        max_message_bytes = 32
+        on_oversized = "drop"
+    "#})
     .await;
+    // The payload is valid JSON, but its second message isn't, and its third one is oversized.
+    let payload = AgentLogPayload::new()
+        .message(r#"{"n": 1}"#)
+        .message("not json")
+        .message(&format!(r#"{{"n": "{}"}}"#, "x".repeat(64)));
+
+    let response = harness.send(payload).await;
+    assert_eq!(
+        response.json(),
+        serde_json::json!({"accepted": 1, "rejected": 2})
+    );
+    assert_eq!(response.single_event().as_log()["n"], 1.into());
 }
 
 #[tokio::test]
-async fn delivery_failure() {
-    trace_init();
-    let (rx, _, _, addr) = source(EventStatus::Rejected, true, true, false).await;
+async fn logs_closed_sender_is_retried() {
+    let (sender, rx) = SourceSender::new_test();
+    drop(rx);
+    let mut harness =
+        AgentHarness::start_with_sender("acknowledgements = true", sender, stream::empty()).await;
+
+    let response = harness.send(AgentLogPayload::new().message("foo")).await;
+    assert_eq!(response.status, 503);
+    assert_eq!(response.headers["retry-after"], "5");
+}
 
-    spawn_collect_n(
-        async move {
-            assert_eq!(
-                400,
-                send_with_path(
-                    addr,
-                    &serde_json::to_string(&[LogMsg {
-                        message: Bytes::from("foo"),
-                        timestamp: Utc
-                            .timestamp_opt(123, 0)
-                            .single()
-                            .expect("invalid timestamp"),
-                        hostname: Bytes::from("festeburg"),
-                        status: Bytes::from("notice"),
-                        service: Bytes::from("vector"),
-                        ddsource: Bytes::from("curl"),
-                        ddtags: Bytes::from("one,two,three"),
-                    }])
-                    .unwrap(),
-                    HeaderMap::new(),
-                    "/v1/input/"
-                )
-                .await
-            );
-        },
+#[tokio::test]
+async fn logs_errored_delivery_is_retried() {
+    let mut harness =
+        AgentHarness::start_with_status("acknowledgements = true", EventStatus::Errored).await;
+
+    let response = harness.send(AgentLogPayload::new().message("foo")).await;
+    assert_eq!(response.status, 503);
+    assert_eq!(response.headers["retry-after"], "5");
+    assert_eq!(response.events.len(), 1);
+}
+
+#[tokio::test]
+async fn logs_acknowledgement_timeout() {
+    // Events are never finalized while the harness holds them.
+    let (sender, rx) = SourceSender::new_test();
+    let mut harness = AgentHarness::start_with_sender(
+        "acknowledgements = true\nacknowledgement_timeout_secs = 1",
+        sender,
+        rx,
+    )
+    .await;
+
+    let response = harness.send(AgentLogPayload::new().message("foo")).await;
+    assert_eq!(response.status, 504);
+    assert_eq!(response.headers["retry-after"], "5");
+}
+
+async fn get_health(address: SocketAddr) -> (u16, serde_json::Value) {
+    let response = reqwest::get(&format!("http://{}/health", address))
+        .await
+        .unwrap();
+    let status = response.status().as_u16();
+    (status, response.json().await.unwrap())
+}
+
+#[tokio::test]
+async fn health_reflects_delivery_failures() {
+    // The first two batches fail to be delivered, and the following ones succeed.
+    let (sender, rx) = SourceSender::new_test_errors(|batch| batch < 2);
+    let api_key = "12345678abcdefgh12345678abcdefgh";
+    let mut harness = AgentHarness::start_with_sender(
+        &format!(
+            "acknowledgements = true\nhealth_failure_threshold = 2\nallowed_api_keys = [\"{}\"]",
+            api_key
+        ),
+        sender,
         rx,
-        1,
     )
     .await;
+    let address = harness.address();
+    let payload = || {
+        AgentLogPayload::new()
+            .message("foo")
+            .api_key_in_query(api_key)
+    };
+
+    // The health route doesn't require an API key.
+    let (status, health) = get_health(address).await;
+    assert_eq!(status, 200);
+    assert_eq!(
+        health,
+        serde_json::json!({"state": "healthy", "consecutive_failures": 0, "last_error": null})
+    );
+
+    assert_eq!(harness.send(payload()).await.status, 503);
+    assert_eq!(get_health(address).await.0, 200);
+
+    assert_eq!(harness.send(payload()).await.status, 503);
+    let (status, health) = get_health(address).await;
+    assert_eq!(status, 503);
+    assert_eq!(
+        health,
+        serde_json::json!({
+            "state": "unhealthy",
+            "consecutive_failures": 2,
+            "last_error": "Error delivering contents to sink",
+        })
+    );
+
+    assert_eq!(harness.send(payload()).await.status, 200);
+    let (status, health) = get_health(address).await;
+    assert_eq!(status, 200);
+    assert_eq!(health["state"], "healthy");
+    assert_eq!(health["consecutive_failures"], 0);
 }
 
 #[tokio::test]
 async fn ignores_disabled_acknowledgements() {
     assert_source_compliance(&HTTP_PUSH_SOURCE_TAGS, async {
-        let (rx, _, _, addr) = source(EventStatus::Rejected, false, true, false).await;
-
-        let events = spawn_collect_n(
-            async move {
-                assert_eq!(
-                    200,
-                    send_with_path(
-                        addr,
-                        &serde_json::to_string(&[LogMsg {
-                            message: Bytes::from("foo"),
-                            timestamp: Utc
-                                .timestamp_opt(123, 0)
-                                .single()
-                                .expect("invalid timestamp"),
-                            hostname: Bytes::from("festeburg"),
-                            status: Bytes::from("notice"),
-                            service: Bytes::from("vector"),
-                            ddsource: Bytes::from("curl"),
-                            ddtags: Bytes::from("one,two,three"),
-                        }])
-                        .unwrap(),
-                        HeaderMap::new(),
-                        "/v1/input/"
-                    )
-                    .await
-                );
-            },
-            rx,
-            1,
-        )
-        .await;
+        let mut harness =
+            AgentHarness::start_with_status("acknowledgements = false", EventStatus::Rejected)
+                .await;
 
-        assert_eq!(events.len(), 1);
+        let payload = AgentLogPayload::new().message("foo").legacy_path();
+        harness.send(payload).await.single_event();
     })
     .await;
 }
@@ -652,67 +2444,18 @@ async fn ignores_disabled_acknowledgements() {
 #[tokio::test]
 async fn ignores_api_key() {
     assert_source_compliance(&HTTP_PUSH_SOURCE_TAGS, async {
-        let (rx, _, _, addr) = source(EventStatus::Delivered, true, false, false).await;
-
-        let mut headers = HeaderMap::new();
-        headers.insert(
-            "dd-api-key",
-            "12345678abcdefgh12345678abcdefgh".parse().unwrap(),
-        );
-
-        let mut events = spawn_collect_n(
-            async move {
-                assert_eq!(
-                    200,
-                    send_with_path(
-                        addr,
-                        &serde_json::to_string(&[LogMsg {
-                            message: Bytes::from("baz"),
-                            timestamp: Utc
-                                .timestamp_opt(789, 0)
-                                .single()
-                                .expect("invalid timestamp"),
-                            hostname: Bytes::from("festeburg"),
-                            status: Bytes::from("notice"),
-                            service: Bytes::from("vector"),
-                            ddsource: Bytes::from("curl"),
-                            ddtags: Bytes::from("one,two,three"),
-                        }])
-                        .unwrap(),
-                        headers,
-                        "/v1/input/12345678abcdefgh12345678abcdefgh"
-                    )
-                    .await
-                );
-            },
-            rx,
-            1,
-        )
+        let mut harness = AgentHarness::start(indoc! { r#"
+            acknowledgements = true
+            store_api_key = false
+        "#})
         .await;
 
-        {
-            let event = events.remove(0);
-            let log = event.as_log();
-            assert_eq!(log["message"], "baz".into());
-            assert_eq!(
-                log["timestamp"],
-                Utc.timestamp_opt(789, 0)
-                    .single()
-                    .expect("invalid timestamp")
-                    .into()
-            );
-            assert_eq!(log["hostname"], "festeburg".into());
-            assert_eq!(log["status"], "notice".into());
-            assert_eq!(log["service"], "vector".into());
-            assert_eq!(log["ddsource"], "curl".into());
-            assert_eq!(log["ddtags"], "one,two,three".into());
-            assert_eq!(log[log_schema().source_type_key()], "datadog_agent".into());
-            assert!(event.metadata().datadog_api_key().is_none());
-            assert_eq!(
-                event.metadata().schema_definition(),
-                &test_logs_schema_definition()
-            );
-        }
+        let payload = AgentLogPayload::new()
+            .message("baz")
+            .api_key_in_path("12345678abcdefgh12345678abcdefgh")
+            .api_key_in_header("12345678abcdefgh12345678abcdefgh");
+        let event = harness.send(payload).await.single_event();
+        assert_agent_log(&harness, &event, "baz", None);
     })
     .await;
 }
@@ -720,7 +2463,7 @@ async fn ignores_api_key() {
 #[tokio::test]
 async fn decode_series_endpoint_v1() {
     assert_source_compliance(&HTTP_PUSH_SOURCE_TAGS, async {
-        let (rx, _, _, addr) = source(EventStatus::Delivered, true, true, false).await;
+        let mut harness = agent_harness(false).await;
 
         let mut headers = HeaderMap::new();
         headers.insert(
@@ -785,23 +2528,15 @@ async fn decode_series_endpoint_v1() {
                 },
             ],
         };
-        let events = spawn_collect_n(
-            async move {
-                assert_eq!(
-                    200,
-                    send_with_path(
-                        addr,
-                        &serde_json::to_string(&dd_metric_request).unwrap(),
-                        headers,
-                        "/api/v1/series"
-                    )
-                    .await
-                );
-            },
-            rx,
-            6,
-        )
-        .await;
+        let events = harness
+            .post(
+                "/api/v1/series",
+                headers,
+                serde_json::to_string(&dd_metric_request).unwrap(),
+            )
+            .await
+            .into_events();
+        assert_eq!(events.len(), 6);
 
         {
             let mut metric = events[0].as_metric();
@@ -932,7 +2667,7 @@ async fn decode_series_endpoint_v1() {
 #[tokio::test]
 async fn decode_sketches() {
     assert_source_compliance(&HTTP_PUSH_SOURCE_TAGS, async {
-        let (rx, _, _, addr) = source(EventStatus::Delivered, true, true, false).await;
+        let mut harness = agent_harness(false).await;
 
         let mut headers = HeaderMap::new();
         headers.insert(
@@ -969,23 +2704,11 @@ async fn decode_sketches() {
 
         sketch_payload.encode(&mut buf).unwrap();
 
-        let events = spawn_collect_n(
-            async move {
-                assert_eq!(
-                    200,
-                    send_with_path(
-                        addr,
-                        unsafe { str::from_utf8_unchecked(&buf) },
-                        headers,
-                        "/api/beta/sketches"
-                    )
-                    .await
-                );
-            },
-            rx,
-            1,
-        )
-        .await;
+        let events = harness
+            .post("/api/beta/sketches", headers, buf)
+            .await
+            .into_events();
+        assert_eq!(events.len(), 1);
 
         {
             let metric = events[0].as_metric();
@@ -1034,7 +2757,7 @@ async fn decode_sketches() {
 #[tokio::test]
 async fn decode_traces() {
     assert_source_compliance(&HTTP_PUSH_SOURCE_TAGS, async {
-        let (rx, _, _, addr) = source(EventStatus::Delivered, true, true, false).await;
+        let mut harness = agent_harness(false).await;
 
         let mut headers = HeaderMap::new();
         headers.insert(
@@ -1120,33 +2843,17 @@ async fn decode_traces() {
 
         payload_v2.encode(&mut buf_v2).unwrap();
 
-        let events = spawn_collect_n(
-            async move {
-                assert_eq!(
-                    200,
-                    send_with_path(
-                        addr,
-                        unsafe { str::from_utf8_unchecked(&buf_v1) },
-                        headers.clone(),
-                        "/api/v0.2/traces"
-                    )
-                    .await
-                );
-                assert_eq!(
-                    200,
-                    send_with_path(
-                        addr,
-                        unsafe { str::from_utf8_unchecked(&buf_v2) },
-                        headers,
-                        "/api/v0.2/traces"
-                    )
-                    .await
-                );
-            },
-            rx,
-            3,
-        )
-        .await;
+        let mut events = harness
+            .post("/api/v0.2/traces", headers.clone(), buf_v1)
+            .await
+            .into_events();
+        events.extend(
+            harness
+                .post("/api/v0.2/traces", headers, buf_v2)
+                .await
+                .into_events(),
+        );
+        assert_eq!(events.len(), 3);
 
         {
             let trace_v1 = events[0].as_trace();
@@ -1279,7 +2986,7 @@ async fn decode_traces() {
 #[tokio::test]
 async fn split_outputs() {
     assert_source_compliance(&HTTP_PUSH_SOURCE_TAGS, async {
-        let (_, rx_logs, rx_metrics, addr) = source(EventStatus::Delivered, true, true, true).await;
+        let mut harness = agent_harness(true).await;
 
         let mut headers_for_log = HeaderMap::new();
         headers_for_log.insert(
@@ -1287,35 +2994,29 @@ async fn split_outputs() {
             "12345678abcdefgh12345678abcdefgh".parse().unwrap(),
         );
 
-        let mut log_event = spawn_collect_n(
-            async move {
-                assert_eq!(
-                    200,
-                    send_with_path(
-                        addr,
-                        &serde_json::to_string(&[LogMsg {
-                            message: Bytes::from("baz"),
-                            timestamp: Utc
-                                .timestamp_opt(789, 0)
-                                .single()
-                                .expect("invalid timestamp"),
-                            hostname: Bytes::from("festeburg"),
-                            status: Bytes::from("notice"),
-                            service: Bytes::from("vector"),
-                            ddsource: Bytes::from("curl"),
-                            ddtags: Bytes::from("one,two,three"),
-                        }])
-                        .unwrap(),
-                        headers_for_log,
-                        "/v1/input/"
-                    )
-                    .await
-                );
-            },
-            rx_logs.unwrap(),
-            1,
-        )
-        .await;
+        let response = harness
+            .post(
+                "/v1/input/",
+                headers_for_log,
+                serde_json::to_string(&[LogMsg {
+                    message: Bytes::from("baz"),
+                    timestamp: Utc
+                        .timestamp_opt(789, 0)
+                        .single()
+                        .expect("invalid timestamp"),
+                    hostname: Bytes::from("festeburg"),
+                    status: Bytes::from("notice"),
+                    service: Bytes::from("vector"),
+                    ddsource: Bytes::from("curl"),
+                    ddtags: Bytes::from("one,two,three"),
+                }])
+                .unwrap(),
+            )
+            .await;
+        assert_eq!(response.status, 200);
+        let mut log_event = response.events;
+        assert_eq!(log_event.len(), 1);
+        assert_eq!(log_event[0].0.as_deref(), Some(LOGS));
 
         let mut headers_for_metric = HeaderMap::new();
         headers_for_metric.insert(
@@ -1337,26 +3038,20 @@ async fn split_outputs() {
                 device: None,
             }],
         };
-        let mut metric_event = spawn_collect_n(
-            async move {
-                assert_eq!(
-                    200,
-                    send_with_path(
-                        addr,
-                        &serde_json::to_string(&dd_metric_request).unwrap(),
-                        headers_for_metric,
-                        "/api/v1/series"
-                    )
-                    .await
-                );
-            },
-            rx_metrics.unwrap(),
-            1,
-        )
-        .await;
+        let response = harness
+            .post(
+                "/api/v1/series",
+                headers_for_metric,
+                serde_json::to_string(&dd_metric_request).unwrap(),
+            )
+            .await;
+        assert_eq!(response.status, 200);
+        let mut metric_event = response.events;
+        assert_eq!(metric_event.len(), 1);
+        assert_eq!(metric_event[0].0.as_deref(), Some(METRICS));
 
         {
-            let event = metric_event.remove(0);
+            let (_, event) = metric_event.remove(0);
             let metric = event.as_metric();
             assert_eq!(metric.name(), "dd_gauge");
             assert_eq!(
@@ -1383,7 +3078,7 @@ async fn split_outputs() {
         }
 
         {
-            let event = log_event.remove(0);
+            let (_, event) = log_event.remove(0);
             let log = event.as_log();
             assert_eq!(log["message"], "baz".into());
             assert_eq!(
@@ -1405,7 +3100,7 @@ async fn split_outputs() {
             );
             assert_eq!(
                 event.metadata().schema_definition(),
-                &test_logs_schema_definition()
+                harness.logs_schema_definition()
             );
         }
     })
@@ -1473,6 +3168,7 @@ fn test_config_outputs() {
                                 Kind::bytes(),
                                 Some("tags"),
                             )
+                            .optional_field(&owned_value_path!("origin"), Kind::bytes(), None)
                             .with_event_field(
                                 &owned_value_path!("source_type"),
                                 Kind::bytes(),
@@ -1526,6 +3222,7 @@ fn test_config_outputs() {
                                 Kind::bytes(),
                                 Some("tags"),
                             )
+                            .optional_field(&owned_value_path!("origin"), Kind::bytes(), None)
                             .with_event_field(
                                 &owned_value_path!("source_type"),
                                 Kind::bytes(),
@@ -1580,6 +3277,7 @@ fn test_config_outputs() {
                                     Kind::bytes(),
                                     Some("tags"),
                                 )
+                                .optional_field(&owned_value_path!("origin"), Kind::bytes(), None)
                                 .with_event_field(
                                     &owned_value_path!("source_type"),
                                     Kind::bytes(),
@@ -1609,6 +3307,11 @@ fn test_config_outputs() {
                             .with_event_field(&owned_value_path!("source_type"), Kind::json(), None)
                             .with_event_field(&owned_value_path!("ddsource"), Kind::json(), None)
                             .with_event_field(&owned_value_path!("ddtags"), Kind::json(), None)
+                            .with_event_field(
+                                &owned_value_path!("origin"),
+                                Kind::json().or_undefined(),
+                                None,
+                            )
                             .with_event_field(&owned_value_path!("hostname"), Kind::json(), None)
                             .with_event_field(&owned_value_path!("service"), Kind::json(), None)
                             .with_event_field(&owned_value_path!("status"), Kind::json(), None)
@@ -1643,6 +3346,11 @@ fn test_config_outputs() {
                                     None,
                                 )
                                 .with_event_field(&owned_value_path!("ddtags"), Kind::json(), None)
+                                .with_event_field(
+                                    &owned_value_path!("origin"),
+                                    Kind::json().or_undefined(),
+                                    None,
+                                )
                                 .with_event_field(
                                     &owned_value_path!("hostname"),
                                     Kind::json(),
@@ -1712,6 +3420,13 @@ fn test_config_outputs() {
                                 Kind::bytes().or_object(Collection::from_unknown(Kind::bytes())),
                                 None,
                             )
+                            .with_event_field(
+                                &owned_value_path!("origin"),
+                                Kind::bytes()
+                                    .or_object(Collection::from_unknown(Kind::bytes()))
+                                    .or_undefined(),
+                                None,
+                            )
                             .with_event_field(
                                 &owned_value_path!("service"),
                                 Kind::bytes().or_object(Collection::from_unknown(Kind::bytes())),
@@ -1791,6 +3506,13 @@ fn test_config_outputs() {
                                         .or_object(Collection::from_unknown(Kind::bytes())),
                                     None,
                                 )
+                                .with_event_field(
+                                    &owned_value_path!("origin"),
+                                    Kind::bytes()
+                                        .or_object(Collection::from_unknown(Kind::bytes()))
+                                        .or_undefined(),
+                                    None,
+                                )
                                 .with_event_field(
                                     &owned_value_path!("service"),
                                     Kind::bytes()
@@ -1814,19 +3536,11 @@ fn test_config_outputs() {
             },
         ),
     ] {
-        let config = DatadogAgentConfig {
-            address: "0.0.0.0:8080".parse().unwrap(),
-            tls: None,
-            store_api_key: true,
-            framing: default_framing_message_based(),
-            decoding,
-            acknowledgements: Default::default(),
-            multiple_outputs,
-            disable_logs: false,
-            disable_metrics: false,
-            disable_traces: false,
-            log_namespace: Some(false),
-        };
+        let mut config =
+            toml::from_str::<DatadogAgentConfig>("address = \"0.0.0.0:8080\"").unwrap();
+        config.decoding = decoding;
+        config.multiple_outputs = multiple_outputs;
+        config.log_namespace = Some(false);
 
         let mut outputs = config
             .outputs(LogNamespace::Legacy)
@@ -1847,7 +3561,7 @@ fn test_config_outputs() {
 #[tokio::test]
 async fn decode_series_endpoint_v2() {
     assert_source_compliance(&HTTP_PUSH_SOURCE_TAGS, async {
-        let (rx, _, _, addr) = source(EventStatus::Delivered, true, true, false).await;
+        let mut harness = agent_harness(false).await;
 
         let mut headers = HeaderMap::new();
         headers.insert(
@@ -1917,23 +3631,11 @@ async fn decode_series_endpoint_v2() {
         let mut buf = Vec::new();
         series_payload.encode(&mut buf).unwrap();
 
-        let events = spawn_collect_n(
-            async move {
-                assert_eq!(
-                    200,
-                    send_with_path(
-                        addr,
-                        unsafe { str::from_utf8_unchecked(&buf) },
-                        headers,
-                        "/api/v2/series"
-                    )
-                    .await
-                );
-            },
-            rx,
-            4,
-        )
-        .await;
+        let events = harness
+            .post("/api/v2/series", headers, buf)
+            .await
+            .into_events();
+        assert_eq!(events.len(), 4);
 
         {
             let mut metric = events[0].as_metric();
@@ -2055,6 +3757,26 @@ async fn decode_series_endpoint_v2() {
     .await;
 }
 
+#[tokio::test]
+async fn logs_schema_definition_follows_decoding_codec() {
+    let mut harness = AgentHarness::start(indoc! { r#"
+        decoding.codec = "json"
+        log_namespace = true
+    "#})
+    .await;
+
+    // The definition advertised by the source is the one the topology hands back to it.
+    let advertised = harness.logs_schema_definition().clone();
+    assert!(advertised.event_kind().as_object().is_some());
+
+    let payload = AgentLogPayload::new().message(r#"{"user":"alice"}"#);
+    let event = harness.send(payload).await.single_event();
+
+    assert!(event.as_log().value().is_object());
+    assert_eq!(event.as_log()["user"], "alice".into());
+    assert_eq!(event.metadata().schema_definition(), &advertised);
+}
+
 #[test]
 fn test_output_schema_definition_json_vector_namespace() {
     let definition = toml::from_str::<DatadogAgentConfig>(indoc! { r#"
@@ -2080,6 +3802,11 @@ fn test_output_schema_definition_json_vector_namespace() {
                     Kind::bytes(),
                     Some("tags")
                 )
+                .with_metadata_field(
+                    &owned_value_path!("datadog_agent", "origin"),
+                    Kind::bytes().or_undefined(),
+                    None
+                )
                 .with_metadata_field(
                     &owned_value_path!("datadog_agent", "hostname"),
                     Kind::bytes(),
@@ -2139,6 +3866,11 @@ fn test_output_schema_definition_bytes_vector_namespace() {
                     Kind::bytes(),
                     Some("tags")
                 )
+                .with_metadata_field(
+                    &owned_value_path!("datadog_agent", "origin"),
+                    Kind::bytes().or_undefined(),
+                    None
+                )
                 .with_metadata_field(
                     &owned_value_path!("datadog_agent", "hostname"),
                     Kind::bytes(),
@@ -2196,6 +3928,11 @@ fn test_output_schema_definition_json_legacy_namespace() {
                 )
                 .with_event_field(&owned_value_path!("ddsource"), Kind::json(), None)
                 .with_event_field(&owned_value_path!("ddtags"), Kind::json(), None)
+                .with_event_field(
+                    &owned_value_path!("origin"),
+                    Kind::json().or_undefined(),
+                    None
+                )
                 .with_event_field(&owned_value_path!("hostname"), Kind::json(), None)
                 .with_event_field(&owned_value_path!("service"), Kind::json(), None)
                 .with_event_field(&owned_value_path!("source_type"), Kind::json(), None)
@@ -2228,6 +3965,7 @@ fn test_output_schema_definition_bytes_legacy_namespace() {
                 Some("source")
             )
             .with_event_field(&owned_value_path!("ddtags"), Kind::bytes(), Some("tags"))
+            .optional_field(&owned_value_path!("origin"), Kind::bytes(), None)
             .with_event_field(&owned_value_path!("hostname"), Kind::bytes(), Some("host"))
             .with_event_field(
                 &owned_value_path!("message"),
@@ -2254,6 +3992,46 @@ fn test_output_schema_definition_bytes_legacy_namespace() {
     )
 }
 
+#[test]
+fn test_output_schema_definition_reserved_fields_subset() {
+    let definition = toml::from_str::<DatadogAgentConfig>(indoc! { r#"
+            address = "0.0.0.0:8012"
+            decoding.codec = "bytes"
+            reserved_fields = ["timestamp", "service"]
+        "#})
+    .unwrap()
+    .outputs(LogNamespace::Legacy)
+    .remove(0)
+    .schema_definition(true);
+
+    assert_eq!(
+        definition,
+        Some(
+            Definition::new_with_default_metadata(
+                Kind::object(Collection::empty()),
+                [LogNamespace::Legacy]
+            )
+            .optional_field(&owned_value_path!("origin"), Kind::bytes(), None)
+            .with_event_field(
+                &owned_value_path!("message"),
+                Kind::bytes(),
+                Some("message")
+            )
+            .with_event_field(
+                &owned_value_path!("service"),
+                Kind::bytes(),
+                Some("service")
+            )
+            .with_event_field(&owned_value_path!("source_type"), Kind::bytes(), None)
+            .with_event_field(
+                &owned_value_path!("timestamp"),
+                Kind::timestamp(),
+                Some("timestamp")
+            )
+        )
+    )
+}
+
 fn assert_tags(metric: &Metric, tags: MetricTags) {
     assert_eq!(metric.tags().expect("Missing tags"), &tags);
 }
@@ -8,17 +8,25 @@ use ordered_float::NotNan;
 use prost::Message;
 use vector_common::internal_event::{CountByteSize, InternalEventHandle as _};
 use vector_core::EstimatedJsonEncodedSizeOf;
-use warp::{filters::BoxedFilter, path, path::FullPath, reply::Response, Filter, Rejection, Reply};
+use warp::{filters::BoxedFilter, path, reply::Response, Filter, Rejection, Reply};
 
 use crate::{
     event::{Event, TraceEvent, Value},
+    internal_events::DatadogAgentPayloadDecoded,
     sources::{
-        datadog_agent::{ddtrace_proto, handle_request, ApiKeyQueryParams, DatadogAgentSource},
+        datadog_agent::{
+            ddtrace_proto, handle_request, reject,
+            request::{decoded_request, DecodedRequest},
+            DatadogAgentSource, RejectionReason, TRACES,
+        },
         util::ErrorMessage,
     },
     SourceSender,
 };
 
+/// The path prefixes of the traces routes.
+pub(crate) const PATHS: &[&str] = &["/api/v0.2/traces", "/api/v0.2/stats"];
+
 pub(crate) fn build_warp_filter(
     acknowledgements: bool,
     multiple_outputs: bool,
@@ -39,43 +47,45 @@ fn build_trace_filter(
 ) -> BoxedFilter<(Response,)> {
     warp::post()
         .and(path!("api" / "v0.2" / "traces" / ..))
-        .and(warp::path::full())
-        .and(warp::header::optional::<String>("content-encoding"))
-        .and(warp::header::optional::<String>("dd-api-key"))
         .and(warp::header::optional::<String>(
             "X-Datadog-Reported-Languages",
         ))
-        .and(warp::query::<ApiKeyQueryParams>())
-        .and(warp::body::bytes())
+        .and(decoded_request(
+            source.api_key_extractor.clone(),
+            TRACES,
+            None,
+        ))
         .and_then(
-            move |path: FullPath,
-                  encoding_header: Option<String>,
-                  api_token: Option<String>,
-                  reported_language: Option<String>,
-                  query_params: ApiKeyQueryParams,
-                  body: Bytes| {
-                let events = source
-                    .decode(&encoding_header, body, path.as_str())
-                    .and_then(|body| {
-                        handle_dd_trace_payload(
-                            body,
-                            source.api_key_extractor.extract(
-                                path.as_str(),
-                                api_token,
-                                query_params.dd_api_key,
-                            ),
-                            reported_language.as_ref(),
-                            &source,
+            move |reported_language: Option<String>,
+                  request: Result<DecodedRequest, ErrorMessage>| {
+                let events = request.and_then(|request| {
+                    let api_key = request.stored_api_key(&source.api_key_extractor);
+                    let body = request.decompress(&source, TRACES)?;
+                    handle_dd_trace_payload(
+                        body,
+                        api_key,
+                        reported_language.as_ref(),
+                        request.is_compressed(),
+                        &source,
+                    )
+                    .map_err(|error| {
+                        reject(
+                            TRACES,
+                            RejectionReason::PayloadDecode,
+                            StatusCode::UNPROCESSABLE_ENTITY,
+                            format!("Error decoding Datadog traces: {:?}", error),
                         )
-                        .map_err(|error| {
-                            ErrorMessage::new(
-                                StatusCode::UNPROCESSABLE_ENTITY,
-                                format!("Error decoding Datadog traces: {:?}", error),
-                            )
-                        })
-                    });
-                let output = multiple_outputs.then_some(super::TRACES);
-                handle_request(events, acknowledgements, out.clone(), output)
+                    })
+                });
+                let output = multiple_outputs.then_some(TRACES);
+                handle_request(
+                    events,
+                    acknowledgements,
+                    source.acknowledgement_timeout,
+                    Arc::clone(&source.health),
+                    out.clone(),
+                    output,
+                )
             },
         )
         .boxed()
@@ -97,16 +107,24 @@ fn handle_dd_trace_payload(
     frame: Bytes,
     api_key: Option<Arc<str>>,
     lang: Option<&String>,
+    compressed: bool,
     source: &DatadogAgentSource,
 ) -> crate::Result<Vec<Event>> {
     let decoded_payload = ddtrace_proto::TracePayload::decode(frame)?;
-    if decoded_payload.tracer_payloads.is_empty() {
+    let events = if decoded_payload.tracer_payloads.is_empty() {
         debug!("Older trace payload decoded.");
         handle_dd_trace_payload_v0(decoded_payload, api_key, lang, source)
     } else {
         debug!("Newer trace payload decoded.");
         handle_dd_trace_payload_v1(decoded_payload, api_key, source)
-    }
+    }?;
+    emit!(DatadogAgentPayloadDecoded {
+        endpoint: TRACES,
+        compressed,
+        messages: events.len(),
+        message_sizes: &[],
+    });
+    Ok(events)
 }
 
 /// Decode Datadog newer protobuf schema
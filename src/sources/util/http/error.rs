@@ -6,6 +6,9 @@ use serde::Serialize;
 pub struct ErrorMessage {
     code: u16,
     message: String,
+    /// A short, machine-readable reason for the error, for sources that report one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<&'static str>,
 }
 
 #[cfg(any(
@@ -20,9 +23,21 @@ impl ErrorMessage {
         ErrorMessage {
             code: code.as_u16(),
             message,
+            reason: None,
         }
     }
 
+    #[allow(unused)] // triggered by check-component-features
+    pub const fn with_reason(mut self, reason: &'static str) -> Self {
+        self.reason = Some(reason);
+        self
+    }
+
+    #[allow(unused)] // triggered by check-component-features
+    pub const fn reason(&self) -> Option<&'static str> {
+        self.reason
+    }
+
     #[allow(unused)] // triggered by check-component-features
     pub fn status_code(&self) -> http::StatusCode {
         http::StatusCode::from_u16(self.code).unwrap_or(http::StatusCode::INTERNAL_SERVER_ERROR)
@@ -44,13 +44,16 @@ use crate::{
         ComponentKey, DataType, EnrichmentTableConfig, Input, Inputs, OutputId, ProxyConfig,
         SinkConfig, SinkContext, SourceContext, TransformContext, TransformOuter, TransformOutput,
     },
-    event::{EventArray, EventContainer},
+    event::{into_event_stream, EventArray, EventContainer},
     internal_events::EventsReceived,
     shutdown::SourceShutdownCoordinator,
     source_sender::CHUNK_SIZE,
     spawn_named,
     topology::task::TaskError,
-    transforms::{SyncTransform, TaskTransform, Transform, TransformOutputs, TransformOutputsBuf},
+    transforms::{
+        MultiOutputTaskTransform, SyncTransform, TaskTransform, Transform, TransformOutputs,
+        TransformOutputsBuf,
+    },
     utilization::wrap,
     SourceSender,
 };
@@ -703,6 +706,7 @@ fn build_transform(
             node.typetag,
             &node.key,
         ),
+        Transform::MultiOutputTask(t) => build_multi_output_task_transform(t, node, input_rx),
     }
 }
 
@@ -735,17 +739,24 @@ fn build_sync_transform(
         }
     };
 
-    let mut output_controls = HashMap::new();
-    for (name, control) in controls {
-        let id = name
-            .map(|name| OutputId::from((&node.key, name)))
-            .unwrap_or_else(|| OutputId::from(&node.key));
-        output_controls.insert(id, control);
-    }
-
     let task = Task::new(node.key.clone(), node.typetag, transform);
 
-    (task, output_controls)
+    (task, output_controls(&node.key, controls))
+}
+
+fn output_controls(
+    key: &ComponentKey,
+    controls: HashMap<Option<String>, fanout::ControlChannel>,
+) -> HashMap<OutputId, fanout::ControlChannel> {
+    controls
+        .into_iter()
+        .map(|(name, control)| {
+            let id = name
+                .map(|name| OutputId::from((key, name)))
+                .unwrap_or_else(|| OutputId::from(key));
+            (id, control)
+        })
+        .collect()
 }
 
 struct Runner {
@@ -939,3 +950,44 @@ fn build_task_transform(
 
     (task, outputs)
 }
+
+fn build_multi_output_task_transform(
+    t: Box<dyn MultiOutputTaskTransform>,
+    node: TransformNode,
+    input_rx: BufferReceiver<EventArray>,
+) -> (Task, HashMap<OutputId, fanout::ControlChannel>) {
+    let (mut outputs, controls) = TransformOutputs::new(node.outputs);
+
+    let input_rx = crate::utilization::wrap(input_rx.into_stream());
+
+    let input_type = node.input_details.data_type();
+    let events_received = register!(EventsReceived);
+    let filtered = input_rx
+        .filter(move |events| ready(filter_events_type(events, input_type)))
+        .inspect(move |events| {
+            events_received.emit(CountByteSize(
+                events.len(),
+                events.estimated_json_encoded_size_of(),
+            ))
+        })
+        .flat_map(into_event_stream);
+    let mut stream = t.transform(Box::pin(filtered));
+    let transform = async move {
+        debug!("Task transform starting.");
+
+        while let Some(mut outputs_buf) = stream.next().await {
+            if let Err(e) = outputs.send(&mut outputs_buf).await {
+                debug!("Task transform finished with an error.");
+                return Err(TaskError::wrapped(e));
+            }
+        }
+
+        debug!("Task transform finished normally.");
+        Ok(TaskOutput::Transform)
+    }
+    .boxed();
+
+    let task = Task::new(node.key.clone(), node.typetag, transform);
+
+    (task, output_controls(&node.key, controls))
+}
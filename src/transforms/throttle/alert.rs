@@ -0,0 +1,179 @@
+use std::{collections::HashSet, time::Duration};
+
+use governor::{
+    clock::{self, Reference as _},
+    nanos::Nanos,
+};
+
+use super::Bucket;
+use crate::internal_events::{ThrottleSustainedDropAlert, ThrottleSustainedDropAlertCleared};
+
+/// Raises an alert while the transform drops a large fraction of its events.
+///
+/// Admitted and dropped events are counted between evaluations. The alert is raised once the
+/// fraction of dropped events stayed above the raise ratio for the whole alert window, and only
+/// cleared once it falls below the lower clear ratio, so that it doesn't flap around a single
+/// threshold.
+pub struct DropAlert<C: clock::Clock> {
+    raise_ratio: f64,
+    clear_ratio: f64,
+    window: Duration,
+    clock: C,
+    admitted: u64,
+    dropped: u64,
+    /// The buckets that dropped events since the last evaluation.
+    dropping_keys: HashSet<Bucket>,
+    /// When the events counted so far started being counted.
+    period_start: C::Instant,
+    /// When the dropped fraction started being above the raise ratio.
+    above_since: Option<C::Instant>,
+    active: bool,
+}
+
+impl<C: clock::Clock> DropAlert<C> {
+    pub fn new(raise_ratio: f64, clear_ratio: f64, window: Duration, clock: C) -> Self {
+        let period_start = clock.now();
+        Self {
+            raise_ratio,
+            clear_ratio,
+            window,
+            clock,
+            admitted: 0,
+            dropped: 0,
+            dropping_keys: HashSet::new(),
+            period_start,
+            above_since: None,
+            active: false,
+        }
+    }
+
+    pub fn record_admitted(&mut self) {
+        self.admitted += 1;
+    }
+
+    pub fn record_dropped(&mut self, bucket: &Bucket) {
+        self.dropped += 1;
+        if !self.dropping_keys.contains(bucket) {
+            self.dropping_keys.insert(bucket.clone());
+        }
+    }
+
+    /// Checks the fraction of events dropped since the last evaluation, raising or clearing the
+    /// alert accordingly, and starts counting anew.
+    pub fn evaluate(&mut self) {
+        let now = self.clock.now();
+        let total = self.admitted + self.dropped;
+        let ratio = if total == 0 {
+            0.0
+        } else {
+            self.dropped as f64 / total as f64
+        };
+
+        if self.active {
+            if ratio < self.clear_ratio {
+                self.active = false;
+                emit!(ThrottleSustainedDropAlertCleared { ratio });
+            }
+        } else if ratio > self.raise_ratio {
+            let since = *self.above_since.get_or_insert(self.period_start);
+            if now.duration_since(since) >= Nanos::from(self.window) {
+                self.active = true;
+                self.above_since = None;
+                emit!(ThrottleSustainedDropAlert {
+                    key_count: self.dropping_keys.len(),
+                    ratio,
+                });
+            }
+        } else {
+            self.above_since = None;
+        }
+
+        self.admitted = 0;
+        self.dropped = 0;
+        self.dropping_keys.clear();
+        self.period_start = now;
+    }
+
+    pub const fn is_active(&self) -> bool {
+        self.active
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use governor::clock::FakeRelativeClock;
+
+    use super::*;
+
+    /// Feeds `admitted` and `dropped` events over a second, then evaluates the alert.
+    fn tick(
+        alert: &mut DropAlert<FakeRelativeClock>,
+        clock: &FakeRelativeClock,
+        admitted: usize,
+        dropped: usize,
+    ) -> bool {
+        (0..admitted).for_each(|_| alert.record_admitted());
+        (0..dropped).for_each(|key| alert.record_dropped(&Bucket::Key(Some(key.to_string()))));
+        clock.advance(Duration::from_secs(1));
+        alert.evaluate();
+        alert.is_active()
+    }
+
+    fn alert(clock: &FakeRelativeClock) -> DropAlert<FakeRelativeClock> {
+        DropAlert::new(0.5, 0.2, Duration::from_secs(3), clock.clone())
+    }
+
+    #[test]
+    fn raises_once_the_ratio_is_sustained() {
+        let clock = FakeRelativeClock::default();
+        let mut alert = alert(&clock);
+
+        assert!(!tick(&mut alert, &clock, 1, 9));
+        assert!(!tick(&mut alert, &clock, 1, 9));
+        assert!(tick(&mut alert, &clock, 1, 9));
+        // Still raised while the ratio stays high.
+        assert!(tick(&mut alert, &clock, 4, 6));
+    }
+
+    #[test]
+    fn restarts_the_window_when_the_ratio_dips() {
+        let clock = FakeRelativeClock::default();
+        let mut alert = alert(&clock);
+
+        assert!(!tick(&mut alert, &clock, 1, 9));
+        assert!(!tick(&mut alert, &clock, 1, 9));
+        assert!(!tick(&mut alert, &clock, 6, 4));
+        assert!(!tick(&mut alert, &clock, 1, 9));
+        assert!(!tick(&mut alert, &clock, 1, 9));
+        assert!(tick(&mut alert, &clock, 1, 9));
+    }
+
+    #[test]
+    fn clears_below_the_clear_ratio() {
+        let clock = FakeRelativeClock::default();
+        let mut alert = alert(&clock);
+        (0..3).for_each(|_| {
+            tick(&mut alert, &clock, 0, 10);
+        });
+        assert!(alert.is_active());
+
+        // Between the clear and raise ratios, the alert holds.
+        assert!(tick(&mut alert, &clock, 7, 3));
+        assert!(tick(&mut alert, &clock, 6, 4));
+        assert!(!tick(&mut alert, &clock, 9, 1));
+        // And it has to be sustained again to be raised once more.
+        assert!(!tick(&mut alert, &clock, 0, 10));
+    }
+
+    #[test]
+    fn idle_periods_count_as_recovered() {
+        let clock = FakeRelativeClock::default();
+        let mut alert = alert(&clock);
+        (0..3).for_each(|_| {
+            tick(&mut alert, &clock, 0, 10);
+        });
+        assert!(alert.is_active());
+
+        assert!(!tick(&mut alert, &clock, 0, 0));
+    }
+}
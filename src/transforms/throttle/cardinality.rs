@@ -0,0 +1,68 @@
+use std::{collections::HashMap, num::NonZeroUsize, time::Duration};
+
+use governor::{
+    clock::{self, Reference as _},
+    nanos::Nanos,
+};
+
+use crate::internal_events::ThrottleKeyCardinalityLimitReached;
+
+/// The keys given a bucket of their own, up to `max_unique_keys`.
+///
+/// The number of tracked keys is the length of the map, so checking it doesn't involve the state
+/// stores of the limiters.
+pub struct KeyCardinality<C: clock::Clock> {
+    max_keys: NonZeroUsize,
+    window: Duration,
+    clock: C,
+    /// The tracked keys, and when they were last seen.
+    keys: HashMap<Option<String>, C::Instant>,
+    /// When the limit was last reported, so that it's reported at most once per window.
+    reported_at: Option<C::Instant>,
+}
+
+impl<C: clock::Clock> KeyCardinality<C> {
+    pub fn new(max_keys: NonZeroUsize, window: Duration, clock: C) -> Self {
+        Self {
+            max_keys,
+            window,
+            clock,
+            keys: HashMap::new(),
+            reported_at: None,
+        }
+    }
+
+    /// Returns whether `key` has a bucket of its own, tracking it if there's room for it.
+    pub fn track(&mut self, key: &Option<String>) -> bool {
+        let now = self.clock.now();
+        if let Some(seen) = self.keys.get_mut(key) {
+            *seen = now;
+            return true;
+        }
+        if self.keys.len() < self.max_keys.get() {
+            self.keys.insert(key.clone(), now);
+            return true;
+        }
+
+        let window = Nanos::from(self.window).as_u64();
+        let report = self
+            .reported_at
+            .map_or(true, |at| now.duration_since(at).as_u64() >= window);
+        if report {
+            self.reported_at = Some(now);
+            emit!(ThrottleKeyCardinalityLimitReached {
+                max_unique_keys: self.max_keys.get(),
+            });
+        }
+        false
+    }
+
+    /// Stops tracking keys that weren't seen for a whole window, whose buckets are back to their
+    /// full threshold anyway.
+    pub fn retain_recent(&mut self) {
+        let now = self.clock.now();
+        let window = Nanos::from(self.window).as_u64();
+        self.keys
+            .retain(|_, seen| now.duration_since(*seen).as_u64() < window);
+    }
+}
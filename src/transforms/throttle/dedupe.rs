@@ -0,0 +1,80 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    num::NonZeroUsize,
+    time::Duration,
+};
+
+use governor::{
+    clock::{self, Reference as _},
+    nanos::Nanos,
+};
+
+/// The values of `dedupe_field` recently seen for each key, so that retransmitted events don't
+/// consume the budget of their bucket.
+///
+/// At most `max_values` values are remembered across all keys. Once full, the oldest value is
+/// forgotten to make room for the next one, so memory stays bounded however many keys there are.
+pub struct Dedupe<C: clock::Clock> {
+    max_values: NonZeroUsize,
+    ttl: Duration,
+    clock: C,
+    /// The remembered values, and when they were first seen.
+    seen: HashMap<(Option<String>, String), C::Instant>,
+    /// The remembered values, oldest first.
+    order: VecDeque<((Option<String>, String), C::Instant)>,
+}
+
+impl<C: clock::Clock> Dedupe<C> {
+    pub fn new(max_values: NonZeroUsize, ttl: Duration, clock: C) -> Self {
+        Self {
+            max_values,
+            ttl,
+            clock,
+            seen: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    /// Returns whether `value` was already seen for `key` within the TTL, remembering it if not.
+    pub fn check(&mut self, key: &Option<String>, value: String) -> bool {
+        let now = self.clock.now();
+        let ttl = Nanos::from(self.ttl).as_u64();
+        let entry = (key.clone(), value);
+        if let Some(seen) = self.seen.get(&entry) {
+            if now.duration_since(*seen).as_u64() < ttl {
+                return true;
+            }
+            self.seen.remove(&entry);
+        }
+
+        while self.seen.len() >= self.max_values.get() {
+            self.pop_oldest();
+        }
+        self.seen.insert(entry.clone(), now);
+        self.order.push_back((entry, now));
+        false
+    }
+
+    /// Forgets the values seen more than the TTL ago.
+    pub fn retain_recent(&mut self) {
+        let now = self.clock.now();
+        let ttl = Nanos::from(self.ttl).as_u64();
+        while self
+            .order
+            .front()
+            .map_or(false, |(_, at)| now.duration_since(*at).as_u64() >= ttl)
+        {
+            self.pop_oldest();
+        }
+    }
+
+    fn pop_oldest(&mut self) {
+        if let Some((entry, at)) = self.order.pop_front() {
+            // A value seen again after it expired is queued once more, and only its latest
+            // occurrence is still remembered.
+            if self.seen.get(&entry) == Some(&at) {
+                self.seen.remove(&entry);
+            }
+        }
+    }
+}
@@ -0,0 +1,98 @@
+use std::{collections::HashMap, num::NonZeroU32, time::Duration};
+
+use governor::{
+    clock::{self, Reference as _},
+    nanos::Nanos,
+};
+
+/// The keys recently seen, and how many of their events may still bypass their limiter.
+///
+/// A key is new when it wasn't seen for `ttl`, in which case its next `events` events bypass the
+/// limiter. Keys are forgotten once they weren't seen for `ttl`, so only the keys active within it
+/// are remembered.
+pub struct Grace<C: clock::Clock> {
+    events: NonZeroU32,
+    ttl: Duration,
+    clock: C,
+    /// The remembered keys, when they were last seen, and how many events they may still pass.
+    keys: HashMap<Option<String>, (C::Instant, u32)>,
+}
+
+impl<C: clock::Clock> Grace<C> {
+    pub fn new(events: NonZeroU32, ttl: Duration, clock: C) -> Self {
+        Self {
+            events,
+            ttl,
+            clock,
+            keys: HashMap::new(),
+        }
+    }
+
+    /// Returns whether an event of `key` bypasses its limiter, counting it against the grace of
+    /// the key if so.
+    pub fn admits(&mut self, key: &Option<String>) -> bool {
+        let now = self.clock.now();
+        let ttl = Nanos::from(self.ttl).as_u64();
+        if !self.keys.contains_key(key) {
+            self.keys.insert(key.clone(), (now, self.events.get()));
+        }
+        let (seen, remaining) = self.keys.get_mut(key).expect("inserted above");
+        if now.duration_since(*seen).as_u64() >= ttl {
+            *remaining = self.events.get();
+        }
+        *seen = now;
+        if *remaining == 0 {
+            return false;
+        }
+        *remaining -= 1;
+        true
+    }
+
+    /// Forgets the keys that weren't seen for `ttl`, which are new again.
+    pub fn retain_recent(&mut self) {
+        let now = self.clock.now();
+        let ttl = Nanos::from(self.ttl).as_u64();
+        self.keys
+            .retain(|_, (seen, _)| now.duration_since(*seen).as_u64() < ttl);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use governor::clock::FakeRelativeClock;
+
+    use super::*;
+
+    fn admitted(grace: &mut Grace<FakeRelativeClock>, key: &str, events: usize) -> usize {
+        let key = Some(key.to_owned());
+        (0..events).filter(|_| grace.admits(&key)).count()
+    }
+
+    #[test]
+    fn admits_the_first_events_of_new_keys() {
+        let clock = FakeRelativeClock::default();
+        let mut grace = Grace::new(
+            NonZeroU32::new(3).unwrap(),
+            Duration::from_secs(10),
+            clock.clone(),
+        );
+
+        assert_eq!(admitted(&mut grace, "a", 5), 3);
+        assert_eq!(admitted(&mut grace, "b", 2), 2);
+        assert_eq!(admitted(&mut grace, "b", 2), 1);
+
+        // Keys seen within the TTL aren't new again.
+        clock.advance(Duration::from_secs(9));
+        assert_eq!(admitted(&mut grace, "a", 1), 0);
+        clock.advance(Duration::from_secs(9));
+        grace.retain_recent();
+        assert_eq!(admitted(&mut grace, "a", 1), 0);
+
+        // But they are once they weren't seen for the TTL.
+        assert_eq!(grace.keys.len(), 1);
+        clock.advance(Duration::from_secs(10));
+        grace.retain_recent();
+        assert!(grace.keys.is_empty());
+        assert_eq!(admitted(&mut grace, "a", 5), 3);
+    }
+}
@@ -0,0 +1,396 @@
+//! The keyed rate limiters of the `throttle` transform.
+//!
+//! The limiters of a transform can be shared through [`SharedLimiters`], which lets the
+//! `throttle_exceeded` condition check the budget of its keys.
+
+use std::{
+    collections::HashMap,
+    hash::{Hash, Hasher},
+    num::NonZeroU32,
+    sync::{Arc, Mutex, MutexGuard},
+    time::Duration,
+};
+
+use governor::{
+    clock::{self, Clock as _, Reference as _},
+    middleware::StateInformationMiddleware,
+    nanos::Nanos,
+    state::keyed::DefaultKeyedStateStore,
+    Quota, RateLimiter,
+};
+
+use super::{state::Consumed, Bucket, ConfigError};
+use crate::conditions::ThrottleBudget;
+
+/// A number of events allowed per window.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct Limit {
+    pub(super) threshold: NonZeroU32,
+    pub(super) window: Duration,
+}
+
+/// The shortest window allowed.
+///
+/// Buckets are forgotten every other window, so shorter windows would mostly be spent doing so.
+pub(super) const MIN_WINDOW: Duration = Duration::from_millis(10);
+
+/// The longest window allowed, a year.
+///
+/// The timers of the transform are derived from the window, and can't be scheduled much further
+/// in the future.
+pub(super) const MAX_WINDOW: Duration = Duration::from_secs(365 * 24 * 60 * 60);
+
+/// Builds the quota allowing `threshold` events per `window`.
+///
+/// Governor replenishes one event per `window / threshold`, which must be at least a nanosecond.
+pub(super) fn quota(window: Duration, threshold: NonZeroU32) -> Result<Quota, ConfigError> {
+    let window_secs = window.as_secs_f64();
+    if window.is_zero() {
+        return Err(ConfigError::NonZero);
+    }
+    if window < MIN_WINDOW {
+        return Err(ConfigError::WindowTooShort { window_secs });
+    }
+    if window > MAX_WINDOW {
+        return Err(ConfigError::WindowTooLong { window_secs });
+    }
+    Quota::with_period(window / threshold.get())
+        .map(|quota| quota.allow_burst(threshold))
+        .ok_or(ConfigError::Period {
+            window_secs,
+            threshold: threshold.get(),
+        })
+}
+
+pub(super) type KeyedRateLimiter<C> =
+    RateLimiter<Bucket, DefaultKeyedStateStore<Bucket>, C, StateInformationMiddleware>;
+
+/// A rate limiter for a single limit, along with the last known state of each key.
+///
+/// Governor doesn't allow inspecting a key's state without consuming a token, so the remaining
+/// capacity reported after each check is kept around for [`Limiters::remaining`], when recorded.
+struct Limiter<C: clock::Clock> {
+    limiter: KeyedRateLimiter<C>,
+    replenish_interval: Duration,
+    snapshots: HashMap<Bucket, (C::Instant, u32)>,
+}
+
+/// Rate limiters for each distinct limit in use.
+///
+/// Keys sharing a limit share a limiter, so swapping the quota table only resets the state of keys
+/// whose limit actually changed, and keys with different windows are limited side by side.
+///
+/// With `spread`, each key starts from a budget partially spent by an offset derived from its hash,
+/// which staggers the points at which keys regain their full budget. See [`phase_offset`].
+///
+/// The state of each key is only recorded with `record_snapshots`, as nothing reads it otherwise.
+/// Without it, every key is reported with its full budget.
+pub(super) struct Limiters<C: clock::Clock> {
+    clock: C,
+    spread: bool,
+    record_snapshots: bool,
+    by_limit: HashMap<Limit, Limiter<C>>,
+}
+
+impl<C: clock::Clock> Limiters<C> {
+    pub(super) fn new(clock: C, spread: bool, record_snapshots: bool) -> Self {
+        Self {
+            clock,
+            spread,
+            // Spreading tells new keys apart by their lack of a snapshot.
+            record_snapshots: record_snapshots || spread,
+            by_limit: HashMap::new(),
+        }
+    }
+
+    /// Checks whether the event for `bucket` is within its `limit`, returning the number of events
+    /// remaining after it.
+    ///
+    /// Otherwise, returns how long to wait until it would be.
+    pub(super) fn check_key(&mut self, bucket: &Bucket, limit: Limit) -> Result<u32, Duration> {
+        let spread = self.spread;
+        let record_snapshots = self.record_snapshots;
+        let now = self.clock.now();
+        let Limiter {
+            limiter, snapshots, ..
+        } = self.limiter(limit);
+
+        // A key without a snapshot has a full budget, either because it was never seen or because
+        // it was idle for a whole window, so its phase is applied again.
+        if spread && !snapshots.contains_key(bucket) {
+            if let Some(offset) = NonZeroU32::new(phase_offset(bucket, limit.threshold)) {
+                // The offset is below the threshold, so there is always capacity for it.
+                let _ = limiter.check_key_n(bucket, offset);
+            }
+        }
+
+        let (result, remaining) = match limiter.check_key(bucket) {
+            Ok(snapshot) => {
+                let remaining = snapshot.remaining_burst_capacity();
+                (Ok(remaining), remaining)
+            }
+            Err(not_until) => (Err(not_until.wait_time_from(now)), 0),
+        };
+        if record_snapshots {
+            snapshots.insert(bucket.clone(), (now, remaining));
+        }
+        result
+    }
+
+    /// Returns the limiter of `limit`, creating it if needed.
+    fn limiter(&mut self, limit: Limit) -> &mut Limiter<C> {
+        let clock = &self.clock;
+        self.by_limit.entry(limit).or_insert_with(|| {
+            let quota = quota(limit.window, limit.threshold).expect("limits are validated on load");
+            Limiter {
+                limiter: RateLimiter::dashmap_with_clock(quota, clock)
+                    .with_middleware::<StateInformationMiddleware>(),
+                replenish_interval: quota.replenish_interval(),
+                snapshots: HashMap::new(),
+            }
+        })
+    }
+
+    /// Returns the number of events each bucket currently has consumed out of its limits, for the
+    /// buckets that consumed any.
+    pub(super) fn consumed(&self) -> Vec<Consumed> {
+        self.by_limit
+            .iter()
+            .flat_map(|(limit, limiter)| {
+                limiter.snapshots.keys().filter_map(move |bucket| {
+                    let events = limit.threshold.get() - self.remaining(bucket, *limit);
+                    (events > 0).then(|| Consumed {
+                        bucket: bucket.clone(),
+                        limit: *limit,
+                        events,
+                    })
+                })
+            })
+            .collect()
+    }
+
+    /// Consumes `events` out of the budget of `bucket` under `limit`, as restored from a snapshot.
+    pub(super) fn restore(&mut self, consumed: Consumed) {
+        let Consumed {
+            bucket,
+            limit,
+            events,
+        } = consumed;
+        let now = self.clock.now();
+        let events = events.min(limit.threshold.get());
+        let Limiter {
+            limiter, snapshots, ..
+        } = self.limiter(limit);
+        if let Some(events) = NonZeroU32::new(events) {
+            // The events are capped to the threshold, so there is always capacity for them.
+            let _ = limiter.check_key_n(&bucket, events);
+        }
+        snapshots.insert(bucket, (now, limit.threshold.get() - events));
+    }
+
+    /// Returns the number of events `bucket` may still send under `limit`, without consuming any of
+    /// them.
+    ///
+    /// This is derived from the state seen by the last check of `bucket`, so it may be one event
+    /// short of what the limiter would actually allow.
+    pub(super) fn remaining(&self, bucket: &Bucket, limit: Limit) -> u32 {
+        let threshold = limit.threshold;
+        let snapshot = self
+            .by_limit
+            .get(&limit)
+            .and_then(|limiter| Some((limiter, limiter.snapshots.get(bucket)?)));
+        match snapshot {
+            Some((limiter, (at, remaining))) => {
+                let elapsed = self.clock.now().duration_since(*at).as_u64();
+                let interval = Nanos::from(limiter.replenish_interval).as_u64().max(1);
+                let replenished = u32::try_from(elapsed / interval).unwrap_or(u32::MAX);
+                remaining.saturating_add(replenished).min(threshold.get())
+            }
+            None => threshold.get(),
+        }
+    }
+
+    /// Returns whether `bucket` is out of budget under the limits it was last checked against,
+    /// without consuming any of it.
+    ///
+    /// Like [`Limiters::remaining`], this may report a bucket one event early.
+    fn exceeded(&self, bucket: &Bucket) -> bool {
+        self.by_limit.iter().any(|(limit, limiter)| {
+            limiter.snapshots.contains_key(bucket) && self.remaining(bucket, *limit) == 0
+        })
+    }
+
+    pub(super) fn retain_recent(&mut self) {
+        let now = self.clock.now();
+        // Once a whole window has passed, a key is back to its full threshold, which is also what's
+        // reported for keys without a snapshot.
+        for (
+            limit,
+            Limiter {
+                limiter, snapshots, ..
+            },
+        ) in self.by_limit.iter_mut()
+        {
+            let window = Nanos::from(limit.window).as_u64();
+            limiter.retain_recent();
+            snapshots.retain(|_, (at, _)| now.duration_since(*at).as_u64() < window);
+        }
+    }
+}
+
+/// Returns the number of events a key starts its budget with already spent, when spreading
+/// replenishment.
+///
+/// Governor replenishes one event every `window / threshold`, so starting a key `offset` events
+/// into its budget shifts the phase of its window by `offset` replenishment intervals. Keys
+/// saturating their limit at the same time then regain their budget at different points of the
+/// window, rather than all at once, while each key is still allowed `threshold` events per window
+/// once past its first one.
+///
+/// The offset is derived from a fixed hash of the key, so that a key always gets the same phase.
+fn phase_offset(bucket: &Bucket, threshold: NonZeroU32) -> u32 {
+    let mut hasher = seahash::SeaHasher::default();
+    bucket.hash(&mut hasher);
+    (hasher.finish() % u64::from(threshold.get())) as u32
+}
+
+/// [`Limiters`] shared between a transform and the conditions checking its budget.
+pub(super) struct SharedLimiters<C: clock::Clock>(Arc<Mutex<Limiters<C>>>);
+
+impl<C: clock::Clock> Clone for SharedLimiters<C> {
+    fn clone(&self) -> Self {
+        Self(Arc::clone(&self.0))
+    }
+}
+
+impl<C: clock::Clock> SharedLimiters<C> {
+    pub(super) fn new(clock: C, spread: bool, record_snapshots: bool) -> Self {
+        Self(Arc::new(Mutex::new(Limiters::new(
+            clock,
+            spread,
+            record_snapshots,
+        ))))
+    }
+
+    pub(super) fn lock(&self) -> MutexGuard<'_, Limiters<C>> {
+        self.0.lock().expect("poisoned lock")
+    }
+}
+
+impl<C> ThrottleBudget for SharedLimiters<C>
+where
+    C: clock::Clock + Send + 'static,
+    C::Instant: Send,
+{
+    fn exceeded(&self, key: Option<&str>) -> bool {
+        self.lock().exceeded(&Bucket::Key(key.map(str::to_owned)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use quickcheck::{QuickCheck, TestResult};
+
+    use super::*;
+
+    /// Checks that the quota of `threshold` events per `window` allows exactly that, up to the
+    /// nanosecond lost by rounding the period of each event.
+    fn quota_matches(window: Duration, threshold: NonZeroU32) -> bool {
+        let quota = quota(window, threshold).unwrap();
+        let replenished = u128::from(threshold.get()) * quota.replenish_interval().as_nanos();
+        quota.burst_size() == threshold
+            && replenished <= window.as_nanos()
+            && window.as_nanos() - replenished < u128::from(threshold.get())
+    }
+
+    #[test]
+    fn quota_allows_threshold_per_window() {
+        let windows = [0.01, 0.5, 1.0, 3.7, 60.0, 86_400.0, 365.0 * 86_400.0];
+        let thresholds = [1, 2, 3, 7, 1000, 1_000_000, 10_000_000];
+        for window in windows.map(Duration::from_secs_f64) {
+            for threshold in thresholds.map(|threshold| NonZeroU32::new(threshold).unwrap()) {
+                assert!(
+                    quota_matches(window, threshold),
+                    "window: {:?}, threshold: {}",
+                    window,
+                    threshold
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn quota_allows_threshold_per_window_or_rejects_the_period() {
+        fn property(window_ms: u64, threshold: u32) -> TestResult {
+            let window = Duration::from_millis(window_ms);
+            let threshold = match NonZeroU32::new(threshold) {
+                Some(threshold) => threshold,
+                None => return TestResult::discard(),
+            };
+            if window < MIN_WINDOW || window > MAX_WINDOW {
+                return TestResult::discard();
+            }
+            match quota(window, threshold) {
+                Ok(_) => TestResult::from_bool(quota_matches(window, threshold)),
+                Err(ConfigError::Period { .. }) => {
+                    TestResult::from_bool(window.as_nanos() < u128::from(threshold.get()))
+                }
+                Err(_) => TestResult::failed(),
+            }
+        }
+
+        QuickCheck::new()
+            .tests(1000)
+            .quickcheck(property as fn(u64, u32) -> TestResult);
+    }
+
+    #[test]
+    fn quota_rejects_unusable_windows() {
+        let threshold = NonZeroU32::new(1).unwrap();
+
+        let error = quota(Duration::from_millis(1), threshold).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "`window_secs` of 0.001s is shorter than the minimum of 0.01s"
+        );
+
+        let error = quota(Duration::from_secs_f64(1e12), threshold).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "`window_secs` of 1000000000000s is longer than the maximum of 31536000s"
+        );
+
+        let threshold = NonZeroU32::new(u32::MAX).unwrap();
+        let error = quota(Duration::from_secs_f64(0.5), threshold).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "`window_secs` of 0.5s is too short for a `threshold` of 4294967295, which would allow \
+             an event less than a nanosecond after the previous one"
+        );
+    }
+
+    #[test]
+    fn snapshots_are_only_recorded_when_needed() {
+        let clock = clock::FakeRelativeClock::default();
+        let limit = Limit {
+            threshold: NonZeroU32::new(3).unwrap(),
+            window: Duration::from_secs(10),
+        };
+        let bucket = Bucket::Key(Some("a".into()));
+
+        let mut unrecorded = Limiters::new(clock.clone(), false, false);
+        assert_eq!(unrecorded.check_key(&bucket, limit), Ok(2));
+        assert!(unrecorded.consumed().is_empty());
+
+        let mut recorded = Limiters::new(clock.clone(), false, true);
+        assert_eq!(recorded.check_key(&bucket, limit), Ok(2));
+        assert_eq!(recorded.remaining(&bucket, limit), 2);
+        assert_eq!(recorded.consumed().len(), 1);
+
+        // Idle keys are forgotten along with their limiter state.
+        clock.advance(Duration::from_secs(10));
+        recorded.retain_recent();
+        assert!(recorded.consumed().is_empty());
+        assert!(recorded.by_limit[&limit].snapshots.is_empty());
+    }
+}
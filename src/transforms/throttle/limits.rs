@@ -0,0 +1,181 @@
+use std::{num::NonZeroU32, time::Duration};
+
+use governor::clock;
+use serde_with::serde_as;
+use vector_config::configurable_component;
+
+use super::{
+    limiter::{Limit, Limiters},
+    quota, Bucket, ConfigError,
+};
+use crate::{event::Event, internal_events::TemplateRenderingError, template::Template};
+
+/// An additional limit, applied to the events of each value of its own key.
+#[serde_as]
+#[configurable_component]
+#[derive(Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct LimitConfig {
+    /// The name of the log field whose value determines the bucket of the event under this limit.
+    ///
+    /// Events without it share a single bucket.
+    #[configurable(metadata(docs::examples = "{{ hostname }}"))]
+    key_field: Template,
+
+    /// The number of events allowed for a given bucket per configured `window_secs`.
+    threshold: u32,
+
+    /// The time window in which the configured `threshold` is applied, in seconds.
+    #[serde_as(as = "serde_with::DurationSeconds<f64>")]
+    window_secs: Duration,
+}
+
+/// Checks that each limit has a usable threshold and window.
+pub fn validate(limits: &[LimitConfig]) -> Result<(), ConfigError> {
+    for limit in limits {
+        let threshold = NonZeroU32::new(limit.threshold).ok_or(ConfigError::NonZero)?;
+        quota(limit.window_secs, threshold)?;
+    }
+    Ok(())
+}
+
+struct KeyedLimit<C: clock::Clock> {
+    key_field: Template,
+    limit: Limit,
+    limiters: Limiters<C>,
+}
+
+/// The rate limiters of each of the `limits`.
+///
+/// Each limit has limiters of its own, so that buckets with the same key under different limits
+/// don't share their budget.
+pub struct Limits<C: clock::Clock> {
+    limits: Vec<KeyedLimit<C>>,
+}
+
+impl<C: clock::Clock> Limits<C> {
+    /// Builds the limiters for limits checked with [`validate`].
+    pub fn new(limits: &[LimitConfig], clock: &C) -> Self {
+        let limits = limits
+            .iter()
+            .map(|limit| KeyedLimit {
+                key_field: limit.key_field.clone(),
+                limit: Limit {
+                    threshold: NonZeroU32::new(limit.threshold).expect("limits are validated"),
+                    window: limit.window_secs,
+                },
+                limiters: Limiters::new(clock.clone(), false, true),
+            })
+            .collect();
+        Self { limits }
+    }
+
+    /// Renders the bucket of `event` under each limit.
+    pub fn buckets(&self, event: &Event) -> Vec<Bucket> {
+        self.limits
+            .iter()
+            .map(|limit| {
+                let key = limit
+                    .key_field
+                    .render_string(event)
+                    .map_err(|error| {
+                        emit!(TemplateRenderingError {
+                            error,
+                            field: Some("limits.key_field"),
+                            drop_event: false,
+                        })
+                    })
+                    .ok();
+                Bucket::Key(key)
+            })
+            .collect()
+    }
+
+    /// Returns the position of the first limit under which the bucket in `buckets` is out of
+    /// budget, without consuming any of it.
+    ///
+    /// Like [`Limiters::remaining`], this may report a bucket one event early.
+    pub fn exceeded(&self, buckets: &[Bucket]) -> Option<usize> {
+        self.limits
+            .iter()
+            .zip(buckets)
+            .position(|(limit, bucket)| limit.limiters.remaining(bucket, limit.limit) == 0)
+    }
+
+    /// Consumes an event out of the budget of each bucket in `buckets`.
+    ///
+    /// Only called once [`Limits::exceeded`] found budget left under every limit, so that an event
+    /// rejected by one limit doesn't consume the budget of the others.
+    pub fn consume(&mut self, buckets: &[Bucket]) {
+        for (limit, bucket) in self.limits.iter_mut().zip(buckets) {
+            // The budget was checked by `exceeded`, which may only underestimate it.
+            let _ = limit.limiters.check_key(bucket, limit.limit);
+        }
+    }
+
+    /// The `key_field` of the limit at `index`, as configured.
+    pub fn key_field(&self, index: usize) -> &str {
+        self.limits[index].key_field.get_ref()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.limits.is_empty()
+    }
+
+    pub fn retain_recent(&mut self) {
+        for limit in &mut self.limits {
+            limit.limiters.retain_recent();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use governor::clock::FakeRelativeClock;
+
+    use super::*;
+    use crate::event::LogEvent;
+
+    fn limit(key_field: &str, threshold: u32) -> LimitConfig {
+        LimitConfig {
+            key_field: Template::try_from(key_field).unwrap(),
+            threshold,
+            window_secs: Duration::from_secs(60),
+        }
+    }
+
+    fn bucket(key: &str) -> Bucket {
+        Bucket::Key(Some(key.to_owned()))
+    }
+
+    #[test]
+    fn limits_only_consume_once_every_limit_has_budget() {
+        let clock = FakeRelativeClock::default();
+        let mut limits = Limits::new(&[limit("{{ service }}", 1), limit("{{ host }}", 2)], &clock);
+
+        let buckets = vec![bucket("api"), bucket("h1")];
+        assert_eq!(limits.exceeded(&buckets), None);
+        limits.consume(&buckets);
+        assert_eq!(limits.exceeded(&buckets), Some(0));
+
+        // The rejected event didn't consume the budget of the host, which is left for other services.
+        let buckets = vec![bucket("web"), bucket("h1")];
+        assert_eq!(limits.exceeded(&buckets), None);
+        limits.consume(&buckets);
+        assert_eq!(limits.exceeded(&[bucket("db"), bucket("h1")]), Some(1));
+        assert_eq!(limits.exceeded(&[bucket("db"), bucket("h2")]), None);
+    }
+
+    #[test]
+    fn limits_render_buckets_by_their_own_key() {
+        let clock = FakeRelativeClock::default();
+        let limits = Limits::new(&[limit("{{ service }}", 1), limit("{{ host }}", 1)], &clock);
+
+        let mut log = LogEvent::default();
+        log.insert("service", "api");
+        assert_eq!(
+            limits.buckets(&log.into()),
+            vec![bucket("api"), Bucket::Key(None)]
+        );
+    }
+}
@@ -0,0 +1,147 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    num::NonZeroUsize,
+};
+
+use vector_core::ByteSizeOf;
+
+use super::{Bucket, Limit, QueueFullAction};
+use crate::event::Event;
+
+/// Events over their limit, held until their bucket has room for them.
+///
+/// Events are queued per bucket, and released in order once their bucket's limiter allows them.
+/// At most `max_events` events, and `max_bytes` bytes as measured by [`ByteSizeOf`], are queued at
+/// once across all buckets. Once full, either the new event or the oldest queued ones are dropped,
+/// according to `full_action`. Without a `full_action`, nothing is dropped, and the intake must stop
+/// instead while the queue [blocks it](Queue::blocks_intake).
+pub struct Queue {
+    max_events: NonZeroUsize,
+    max_bytes: Option<NonZeroUsize>,
+    full_action: Option<QueueFullAction>,
+    by_bucket: HashMap<Bucket, VecDeque<Queued>>,
+    events: usize,
+    bytes: usize,
+    /// The sequence number of the next queued event, to tell which event is the oldest.
+    next_seq: u64,
+}
+
+struct Queued {
+    seq: u64,
+    event: Event,
+    byte_size: usize,
+    limit: Limit,
+}
+
+impl Queue {
+    pub fn new(
+        max_events: NonZeroUsize,
+        max_bytes: Option<NonZeroUsize>,
+        full_action: Option<QueueFullAction>,
+    ) -> Self {
+        Self {
+            max_events,
+            max_bytes,
+            full_action,
+            by_bucket: HashMap::new(),
+            events: 0,
+            bytes: 0,
+            next_seq: 0,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events == 0
+    }
+
+    /// Returns whether no further events may be taken in until queued ones are released, as the
+    /// queue is full and doesn't drop any event.
+    pub fn blocks_intake(&self) -> bool {
+        self.full_action.is_none() && self.is_full(0)
+    }
+
+    /// Returns whether events of `bucket` are waiting, in which case new events of the bucket must
+    /// wait behind them.
+    pub fn has_backlog(&self, bucket: &Bucket) -> bool {
+        self.by_bucket.contains_key(bucket)
+    }
+
+    /// Queues `event`, returning the events dropped to make room for it, if any.
+    ///
+    /// The dropped events may include `event` itself.
+    pub fn push(&mut self, bucket: Bucket, limit: Limit, event: Event) -> Vec<(Bucket, Event)> {
+        let byte_size = event.size_of();
+        let mut dropped = Vec::new();
+        if self.full_action == Some(QueueFullAction::DropOldest) {
+            while self.is_full(byte_size) {
+                match self.pop_oldest() {
+                    Some((bucket, _, event)) => dropped.push((bucket, event)),
+                    None => break,
+                }
+            }
+        }
+        if self.full_action.is_some() && self.is_full(byte_size) {
+            dropped.push((bucket, event));
+            return dropped;
+        }
+
+        self.events += 1;
+        self.bytes += byte_size;
+        self.by_bucket.entry(bucket).or_default().push_back(Queued {
+            seq: self.next_seq,
+            event,
+            byte_size,
+            limit,
+        });
+        self.next_seq += 1;
+        dropped
+    }
+
+    /// Returns the bucket of the event waiting the longest, among the buckets `skip` doesn't
+    /// exclude.
+    ///
+    /// Releasing events in this order keeps them in the order they arrived in, across buckets.
+    pub fn oldest_bucket(&self, skip: &HashSet<Bucket>) -> Option<Bucket> {
+        self.by_bucket
+            .iter()
+            .filter(|(bucket, _)| !skip.contains(bucket))
+            .filter_map(|(bucket, queued)| Some((queued.front()?.seq, bucket)))
+            .min_by_key(|(seq, _)| *seq)
+            .map(|(_, bucket)| bucket.clone())
+    }
+
+    /// Returns the limit of the next event of `bucket`, if any.
+    pub fn front_limit(&self, bucket: &Bucket) -> Option<Limit> {
+        self.by_bucket
+            .get(bucket)
+            .and_then(VecDeque::front)
+            .map(|queued| queued.limit)
+    }
+
+    /// Takes the next event of `bucket`, if any.
+    pub fn pop_front(&mut self, bucket: &Bucket) -> Option<Event> {
+        let queue = self.by_bucket.get_mut(bucket)?;
+        let queued = queue.pop_front()?;
+        if queue.is_empty() {
+            self.by_bucket.remove(bucket);
+        }
+        self.events -= 1;
+        self.bytes -= queued.byte_size;
+        Some(queued.event)
+    }
+
+    fn is_full(&self, byte_size: usize) -> bool {
+        self.events >= self.max_events.get()
+            || self
+                .max_bytes
+                .map_or(false, |max_bytes| self.bytes + byte_size > max_bytes.get())
+    }
+
+    /// Takes the event waiting the longest, along with its bucket and limit.
+    pub fn pop_oldest(&mut self) -> Option<(Bucket, Limit, Event)> {
+        let bucket = self.oldest_bucket(&HashSet::new())?;
+        let limit = self.front_limit(&bucket)?;
+        let event = self.pop_front(&bucket)?;
+        Some((bucket, limit, event))
+    }
+}
@@ -0,0 +1,277 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt, fs,
+    num::NonZeroU32,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use serde::{
+    de::{self, MapAccess},
+    Deserialize, Deserializer,
+};
+use snafu::{ResultExt, Snafu};
+
+use super::{quota, ConfigError, Limit};
+use crate::common::reloadable_file::ReloadableFile;
+
+#[derive(Debug, Snafu)]
+pub enum QuotaFileError {
+    #[snafu(display("Unable to read quota file {}: {}", path.display(), source))]
+    Read {
+        source: std::io::Error,
+        path: PathBuf,
+    },
+    #[snafu(display("Unable to parse quota file {}: {}", path.display(), source))]
+    Parse {
+        source: serde_yaml::Error,
+        path: PathBuf,
+    },
+    #[snafu(display("Invalid key pattern {:?}: {}", pattern, source))]
+    Pattern {
+        source: glob::PatternError,
+        pattern: String,
+    },
+    #[snafu(display("Invalid threshold for key pattern {:?}: {}", pattern, source))]
+    Threshold {
+        source: ConfigError,
+        pattern: String,
+    },
+}
+
+/// The limit of a key pattern in the quota file.
+///
+/// Either a threshold applied over the transform's window, or a threshold along with its own
+/// window.
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(untagged)]
+enum Entry {
+    Threshold(u32),
+    Limit(LimitEntry),
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LimitEntry {
+    threshold: u32,
+    window_secs: Option<f64>,
+}
+
+/// The entries of a quota file, in the order they appear.
+///
+/// Unlike a map, this rejects patterns appearing more than once, instead of silently keeping the
+/// last one.
+#[derive(Debug, Default)]
+struct Entries(Vec<(String, Entry)>);
+
+impl<'de> Deserialize<'de> for Entries {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct EntriesVisitor;
+
+        impl<'de> de::Visitor<'de> for EntriesVisitor {
+            type Value = Entries;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a mapping of key patterns to thresholds")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Entries, A::Error> {
+                let mut seen = HashSet::new();
+                let mut entries = Vec::new();
+                while let Some((pattern, entry)) = map.next_entry::<String, Entry>()? {
+                    if !seen.insert(pattern.clone()) {
+                        return Err(de::Error::custom(format!(
+                            "duplicate key pattern {:?}",
+                            pattern
+                        )));
+                    }
+                    entries.push((pattern, entry));
+                }
+                Ok(Entries(entries))
+            }
+        }
+
+        deserializer.deserialize_map(EntriesVisitor)
+    }
+}
+
+/// Per-key limits, matched either exactly or by glob pattern.
+///
+/// Exact keys take precedence over patterns, and patterns are tried in the order they appear in
+/// the file.
+#[derive(Clone, Debug, Default)]
+pub struct QuotaTable {
+    exact: HashMap<String, Limit>,
+    patterns: Vec<(glob::Pattern, Limit)>,
+}
+
+impl QuotaTable {
+    fn new(entries: Entries, window: Duration) -> Result<Self, QuotaFileError> {
+        let mut table = Self::default();
+        for (pattern, entry) in entries.0 {
+            let (threshold, window_secs) = match entry {
+                Entry::Threshold(threshold) => (threshold, None),
+                Entry::Limit(LimitEntry {
+                    threshold,
+                    window_secs,
+                }) => (threshold, window_secs),
+            };
+            let limit = window_secs
+                .map_or(Ok(window), |secs| {
+                    Duration::try_from_secs_f64(secs).map_err(|_| ConfigError::NonZero)
+                })
+                .and_then(|window| {
+                    let threshold = NonZeroU32::new(threshold).ok_or(ConfigError::NonZero)?;
+                    quota(window, threshold)?;
+                    Ok(Limit { threshold, window })
+                })
+                .context(ThresholdSnafu {
+                    pattern: pattern.clone(),
+                })?;
+
+            if is_glob(&pattern) {
+                let matcher = glob::Pattern::new(&pattern).context(PatternSnafu {
+                    pattern: pattern.clone(),
+                })?;
+                table.patterns.push((matcher, limit));
+            } else {
+                table.exact.insert(pattern, limit);
+            }
+        }
+        Ok(table)
+    }
+
+    /// Returns the limit configured for the given key, if any.
+    pub fn limit(&self, key: &str) -> Option<Limit> {
+        self.exact.get(key).copied().or_else(|| {
+            self.patterns
+                .iter()
+                .find(|(pattern, _)| pattern.matches(key))
+                .map(|(_, limit)| *limit)
+        })
+    }
+}
+
+/// A [`QuotaTable`] backed by a file on disk.
+///
+/// The file is a YAML (or JSON) mapping of key patterns to thresholds, or to objects holding a
+/// `threshold` and an optional `window_secs`.
+#[derive(Clone, Debug)]
+pub struct QuotaFile {
+    window: Duration,
+    file: ReloadableFile<QuotaTable>,
+}
+
+impl QuotaFile {
+    pub fn load(path: PathBuf, window: Duration) -> Result<Self, QuotaFileError> {
+        let file = ReloadableFile::load(path, |path| read(path, window))?;
+        Ok(Self { window, file })
+    }
+
+    pub const fn table(&self) -> &QuotaTable {
+        self.file.contents()
+    }
+
+    /// Reloads the quota table if the file changed since it was last read, keeping the previous
+    /// table if it can't be read or parsed.
+    pub fn reload(&mut self) -> Result<bool, QuotaFileError> {
+        let window = self.window;
+        self.file.reload(|path| read(path, window))
+    }
+}
+
+fn read(path: &Path, window: Duration) -> Result<QuotaTable, QuotaFileError> {
+    let contents = fs::read_to_string(path).context(ReadSnafu { path })?;
+    let entries = serde_yaml::from_str::<Option<Entries>>(&contents)
+        .context(ParseSnafu { path })?
+        .unwrap_or_default();
+    QuotaTable::new(entries, window)
+}
+
+fn is_glob(pattern: &str) -> bool {
+    pattern.contains(['*', '?', '['])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(entries: &[(&str, u32)]) -> QuotaTable {
+        let entries = entries
+            .iter()
+            .map(|(pattern, threshold)| (pattern.to_string(), Entry::Threshold(*threshold)))
+            .collect();
+        QuotaTable::new(Entries(entries), Duration::from_secs(1)).unwrap()
+    }
+
+    impl QuotaTable {
+        fn threshold(&self, key: &str) -> Option<NonZeroU32> {
+            self.limit(key).map(|limit| limit.threshold)
+        }
+    }
+
+    #[test]
+    fn exact_keys_take_precedence_over_patterns() {
+        let table = table(&[("team-*", 10), ("team-a", 100)]);
+
+        assert_eq!(table.threshold("team-a"), NonZeroU32::new(100));
+        assert_eq!(table.threshold("team-b"), NonZeroU32::new(10));
+        assert_eq!(table.threshold("other"), None);
+    }
+
+    #[test]
+    fn patterns_match_in_file_order() {
+        let table = table(&[("team-a*", 5), ("team-*", 10)]);
+
+        assert_eq!(table.threshold("team-ab"), NonZeroU32::new(5));
+        assert_eq!(table.threshold("team-b"), NonZeroU32::new(10));
+    }
+
+    #[test]
+    fn rejects_zero_threshold() {
+        let entries = Entries(vec![("team-a".to_string(), Entry::Threshold(0))]);
+        assert!(matches!(
+            QuotaTable::new(entries, Duration::from_secs(1)),
+            Err(QuotaFileError::Threshold { .. })
+        ));
+    }
+
+    #[test]
+    fn entries_may_have_their_own_window() {
+        let entries = serde_yaml::from_str::<Entries>(
+            "team-a: 5\nteam-*: {threshold: 10, window_secs: 3600}\n",
+        )
+        .unwrap();
+        let table = QuotaTable::new(entries, Duration::from_secs(1)).unwrap();
+
+        let team_a = table.limit("team-a").unwrap();
+        assert_eq!(team_a.threshold, NonZeroU32::new(5).unwrap());
+        assert_eq!(team_a.window, Duration::from_secs(1));
+        let team_b = table.limit("team-b").unwrap();
+        assert_eq!(team_b.threshold, NonZeroU32::new(10).unwrap());
+        assert_eq!(team_b.window, Duration::from_secs(3600));
+    }
+
+    #[test]
+    fn rejects_duplicate_patterns() {
+        let error = serde_yaml::from_str::<Entries>("team-*: 5\nteam-*: 10\n").unwrap_err();
+        assert!(error.to_string().contains("duplicate"));
+    }
+
+    #[test]
+    fn malformed_file_keeps_previous_table() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("quotas.yaml");
+        fs::write(&path, "team-a: 1\n").unwrap();
+
+        let mut file = QuotaFile::load(path.clone(), Duration::from_secs(1)).unwrap();
+        assert_eq!(file.table().threshold("team-a"), NonZeroU32::new(1));
+
+        file.file.forget_modified();
+        fs::write(&path, "team-a: [not, a, number]\n").unwrap();
+
+        assert!(file.reload().is_err());
+        assert_eq!(file.table().threshold("team-a"), NonZeroU32::new(1));
+        assert!(!file.reload().unwrap());
+    }
+}
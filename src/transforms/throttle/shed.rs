@@ -0,0 +1,112 @@
+use std::time::Duration;
+
+use governor::{
+    clock::{self, Reference as _},
+    nanos::Nanos,
+};
+
+use crate::internal_events::ThrottleUpstreamShedding;
+
+/// Decides when the transform slows down its intake, because it drops most of its events.
+///
+/// Admitted and dropped events are counted over periods of at least the window. Shedding starts
+/// once the fraction of dropped events over a period is above the ratio, and stops once a whole
+/// period passes without any event dropped. While shedding, the transform only takes in about as
+/// many events as it admits, so the fraction it drops isn't representative anymore.
+pub struct Shedder<C: clock::Clock> {
+    ratio: f64,
+    window: Duration,
+    clock: C,
+    admitted: u64,
+    dropped: u64,
+    /// When the events counted so far started being counted.
+    period_start: C::Instant,
+    active: bool,
+}
+
+impl<C: clock::Clock> Shedder<C> {
+    pub fn new(ratio: f64, window: Duration, clock: C) -> Self {
+        let period_start = clock.now();
+        Self {
+            ratio,
+            window,
+            clock,
+            admitted: 0,
+            dropped: 0,
+            period_start,
+            active: false,
+        }
+    }
+
+    pub fn record_admitted(&mut self) {
+        self.roll();
+        self.admitted += 1;
+    }
+
+    pub fn record_dropped(&mut self) {
+        self.roll();
+        self.dropped += 1;
+    }
+
+    pub fn is_active(&mut self) -> bool {
+        self.roll();
+        self.active
+    }
+
+    /// Starts or stops shedding according to the events counted, once the period is over.
+    fn roll(&mut self) {
+        let now = self.clock.now();
+        if now.duration_since(self.period_start) < Nanos::from(self.window) {
+            return;
+        }
+
+        let total = self.admitted + self.dropped;
+        let active = if self.active {
+            self.dropped > 0
+        } else {
+            total > 0 && self.dropped as f64 / total as f64 > self.ratio
+        };
+        if active != self.active {
+            self.active = active;
+            emit!(ThrottleUpstreamShedding { active });
+        }
+
+        self.admitted = 0;
+        self.dropped = 0;
+        self.period_start = now;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use governor::clock::FakeRelativeClock;
+
+    use super::*;
+
+    /// Feeds `admitted` and `dropped` events over a second, then checks whether shedding applies.
+    fn tick(
+        shedder: &mut Shedder<FakeRelativeClock>,
+        clock: &FakeRelativeClock,
+        admitted: usize,
+        dropped: usize,
+    ) -> bool {
+        (0..admitted).for_each(|_| shedder.record_admitted());
+        (0..dropped).for_each(|_| shedder.record_dropped());
+        clock.advance(Duration::from_secs(1));
+        shedder.is_active()
+    }
+
+    #[test]
+    fn shedder_starts_above_ratio_and_stops_once_nothing_is_dropped() {
+        let clock = FakeRelativeClock::default();
+        let mut shedder = Shedder::new(0.8, Duration::from_secs(1), clock.clone());
+
+        assert!(!tick(&mut shedder, &clock, 5, 5));
+        assert!(tick(&mut shedder, &clock, 1, 9));
+        // Shedding keeps the fraction low, which doesn't stop it while events are still dropped.
+        assert!(tick(&mut shedder, &clock, 5, 5));
+        assert!(tick(&mut shedder, &clock, 9, 1));
+        assert!(!tick(&mut shedder, &clock, 10, 0));
+        assert!(!tick(&mut shedder, &clock, 0, 0));
+    }
+}
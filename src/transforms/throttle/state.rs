@@ -0,0 +1,282 @@
+use std::{
+    fs, io,
+    num::NonZeroU32,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use snafu::{ResultExt, Snafu};
+
+use super::{Bucket, Limit};
+
+/// The version of the snapshot format, bumped on incompatible changes.
+const VERSION: u32 = 1;
+
+/// How far ahead of the system clock a snapshot may have been saved, in seconds, before the clock
+/// is considered to have stepped backwards since.
+const MAX_CLOCK_SKEW_SECS: f64 = 1.0;
+
+#[derive(Debug, Snafu)]
+pub enum StateFileError {
+    #[snafu(display("Unable to read state file {}: {}", path.display(), source))]
+    Read { source: io::Error, path: PathBuf },
+    #[snafu(display("Unable to parse state file {}: {}", path.display(), source))]
+    Parse {
+        source: serde_json::Error,
+        path: PathBuf,
+    },
+    #[snafu(display(
+        "Unsupported version {} of state file {}, expected {}",
+        version,
+        path.display(),
+        VERSION
+    ))]
+    Version { version: u32, path: PathBuf },
+    #[snafu(display(
+        "State file {} was saved {:.0}s ahead of the system clock, which likely stepped backwards",
+        path.display(),
+        ahead_secs
+    ))]
+    SavedAhead { ahead_secs: f64, path: PathBuf },
+    #[snafu(display("Unable to write state file {}: {}", path.display(), source))]
+    Write { source: io::Error, path: PathBuf },
+}
+
+/// The number of events a bucket consumed out of a limit.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Consumed {
+    pub bucket: Bucket,
+    pub limit: Limit,
+    pub events: u32,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct Snapshot {
+    version: u32,
+    /// When the snapshot was taken, in seconds since the Unix epoch.
+    saved_at: f64,
+    buckets: Vec<BucketSnapshot>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct BucketSnapshot {
+    bucket: Bucket,
+    threshold: NonZeroU32,
+    window_secs: f64,
+    consumed: u32,
+}
+
+/// The state of the limiters, snapshotted to a file so that it survives restarts.
+///
+/// Only the number of events each bucket consumed is kept. Once restored, the budget a bucket
+/// regained while the transform wasn't running is given back to it, and buckets whose window
+/// passed entirely are discarded.
+///
+/// The time elapsed since the snapshot is read from the system clock, the only state of the
+/// transform that is. A snapshot saved ahead of the clock is rejected, as the budget regained since
+/// can't be known once the clock stepped backwards. A forward step is indistinguishable from
+/// downtime, and regains at most what the buckets consumed. Either way, admission is off by at most
+/// one threshold.
+#[derive(Clone, Debug)]
+pub struct StateFile {
+    path: PathBuf,
+}
+
+impl StateFile {
+    pub const fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    /// Reads the last snapshot, if any.
+    pub fn load(&self) -> Result<Vec<Consumed>, StateFileError> {
+        let contents = match fs::read(&self.path) {
+            Ok(contents) => contents,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(source) => {
+                return Err(StateFileError::Read {
+                    source,
+                    path: self.path.clone(),
+                })
+            }
+        };
+        let snapshot = serde_json::from_slice::<Snapshot>(&contents)
+            .context(ParseSnafu { path: &self.path })?;
+        if snapshot.version != VERSION {
+            return Err(StateFileError::Version {
+                version: snapshot.version,
+                path: self.path.clone(),
+            });
+        }
+
+        let elapsed = unix_now() - snapshot.saved_at;
+        if elapsed < -MAX_CLOCK_SKEW_SECS {
+            return Err(StateFileError::SavedAhead {
+                ahead_secs: -elapsed,
+                path: self.path.clone(),
+            });
+        }
+        let elapsed = elapsed.max(0.0);
+        Ok(snapshot
+            .buckets
+            .into_iter()
+            .filter_map(|state| {
+                let window = Duration::try_from_secs_f64(state.window_secs).ok()?;
+                let limit = Limit {
+                    threshold: state.threshold,
+                    window,
+                };
+                let events = state.consumed.saturating_sub(replenished(limit, elapsed));
+                (events > 0).then_some(Consumed {
+                    bucket: state.bucket,
+                    limit,
+                    events,
+                })
+            })
+            .collect())
+    }
+
+    /// Replaces the snapshot with `consumed`.
+    ///
+    /// The snapshot is written to a temporary file next to the state file, then renamed over it, so
+    /// that a crash while writing never leaves a partial snapshot behind.
+    pub fn save(&self, consumed: Vec<Consumed>) -> Result<(), StateFileError> {
+        let snapshot = Snapshot {
+            version: VERSION,
+            saved_at: unix_now(),
+            buckets: consumed
+                .into_iter()
+                .map(|consumed| BucketSnapshot {
+                    bucket: consumed.bucket,
+                    threshold: consumed.limit.threshold,
+                    window_secs: consumed.limit.window.as_secs_f64(),
+                    consumed: consumed.events,
+                })
+                .collect(),
+        };
+        let contents = serde_json::to_vec(&snapshot).expect("snapshots always serialize");
+
+        let temp_path = temp_path(&self.path);
+        fs::write(&temp_path, contents)
+            .and_then(|()| fs::rename(&temp_path, &self.path))
+            .context(WriteSnafu { path: &self.path })
+    }
+}
+
+/// Returns the number of events regained under `limit` over `elapsed` seconds.
+fn replenished(limit: Limit, elapsed: f64) -> u32 {
+    let interval = limit.window.as_secs_f64() / f64::from(limit.threshold.get());
+    // The cast saturates, so a long downtime regains the whole budget.
+    (elapsed / interval) as u32
+}
+
+fn temp_path(path: &Path) -> PathBuf {
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".tmp");
+    path.with_file_name(file_name)
+}
+
+fn unix_now() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0.0, |now| now.as_secs_f64())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn consumed(key: &str, events: u32) -> Consumed {
+        Consumed {
+            bucket: Bucket::Key(Some(key.to_owned())),
+            limit: Limit {
+                threshold: NonZeroU32::new(10).unwrap(),
+                window: Duration::from_secs(3600),
+            },
+            events,
+        }
+    }
+
+    #[test]
+    fn round_trips_consumed_events() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = StateFile::new(dir.path().join("state.json"));
+        assert_eq!(file.load().unwrap(), Vec::new());
+
+        file.save(vec![consumed("a", 3), consumed("b", 10)])
+            .unwrap();
+        assert_eq!(
+            file.load().unwrap(),
+            vec![consumed("a", 3), consumed("b", 10)]
+        );
+        assert!(!temp_path(&file.path).exists());
+    }
+
+    #[test]
+    fn gives_back_the_budget_regained_since_the_snapshot() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.json");
+        let snapshot = |saved_at: f64| {
+            serde_json::json!({
+                "version": VERSION,
+                "saved_at": saved_at,
+                "buckets": [
+                    {"bucket": {"key": "a"}, "threshold": 10, "window_secs": 3600.0, "consumed": 5},
+                ],
+            })
+            .to_string()
+        };
+        let file = StateFile::new(path.clone());
+
+        // One event is regained every 360 seconds.
+        fs::write(&path, snapshot(unix_now() - 800.0)).unwrap();
+        assert_eq!(file.load().unwrap(), vec![consumed("a", 3)]);
+
+        // Older than the window.
+        fs::write(&path, snapshot(unix_now() - 3600.0)).unwrap();
+        assert_eq!(file.load().unwrap(), Vec::new());
+    }
+
+    #[test]
+    fn rejects_snapshots_saved_ahead_of_the_clock() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.json");
+        let file = StateFile::new(path.clone());
+        let snapshot = |saved_at: f64| {
+            serde_json::json!({
+                "version": VERSION,
+                "saved_at": saved_at,
+                "buckets": [
+                    {"bucket": {"key": "a"}, "threshold": 10, "window_secs": 3600.0, "consumed": 5},
+                ],
+            })
+            .to_string()
+        };
+
+        fs::write(&path, snapshot(unix_now() + 600.0)).unwrap();
+        assert!(matches!(
+            file.load(),
+            Err(StateFileError::SavedAhead { ahead_secs, .. }) if ahead_secs > 599.0
+        ));
+
+        // A restart within the same second isn't mistaken for a step.
+        fs::write(&path, snapshot(unix_now() + 0.5)).unwrap();
+        assert_eq!(file.load().unwrap(), vec![consumed("a", 5)]);
+    }
+
+    #[test]
+    fn rejects_corrupt_and_incompatible_snapshots() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.json");
+        let file = StateFile::new(path.clone());
+
+        fs::write(&path, "{\"version\": 1, \"buck").unwrap();
+        assert!(matches!(file.load(), Err(StateFileError::Parse { .. })));
+
+        fs::write(&path, r#"{"version": 99, "saved_at": 0, "buckets": []}"#).unwrap();
+        assert!(matches!(
+            file.load(),
+            Err(StateFileError::Version { version: 99, .. })
+        ));
+    }
+}
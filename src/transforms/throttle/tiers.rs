@@ -0,0 +1,220 @@
+use std::{
+    collections::HashMap,
+    num::{NonZeroU32, NonZeroU64},
+    time::Duration,
+};
+
+use governor::{clock, middleware::StateInformationMiddleware, RateLimiter};
+use serde_with::serde_as;
+use vector_config::configurable_component;
+
+use super::{limiter::KeyedRateLimiter, quota, Bucket, ConfigError};
+
+/// A soft limit, applying an action to the events of a bucket beyond its threshold.
+#[serde_as]
+#[configurable_component]
+#[derive(Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct TierConfig {
+    /// The number of events allowed for a given bucket per configured `window_secs` before `action`
+    /// applies.
+    threshold: u32,
+
+    /// The time window in which the configured `threshold` is applied, in seconds.
+    #[serde_as(as = "serde_with::DurationSeconds<f64>")]
+    window_secs: Duration,
+
+    #[configurable(derived)]
+    action: TierAction,
+}
+
+/// The action applied to events beyond the threshold of a tier.
+#[configurable_component]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+#[configurable(metadata(docs::enum_tag_description = "The action to apply."))]
+pub enum TierAction {
+    /// Pass the event.
+    Pass,
+
+    /// Pass one out of every `rate` events, and drop the others.
+    #[serde(rename = "sample_1_in_n")]
+    Sample1InN {
+        /// The rate at which events are passed, expressed as `1/N`.
+        rate: NonZeroU64,
+    },
+
+    /// Drop the event.
+    Drop,
+
+    /// Pass the event, setting the `tier` field of its `throttle` annotation to the position of the
+    /// tier in `tiers`.
+    Tag,
+}
+
+/// What to do with an event admitted by the hard limit.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TierDecision {
+    Pass,
+    Drop,
+    Tag(usize),
+}
+
+/// Checks that the tier thresholds are strictly increasing and below the hard `threshold`.
+pub fn validate(tiers: &[TierConfig], threshold: NonZeroU32) -> Result<(), ConfigError> {
+    let mut previous = 0;
+    for tier in tiers {
+        let tier_threshold = NonZeroU32::new(tier.threshold).ok_or(ConfigError::NonZero)?;
+        quota(tier.window_secs, tier_threshold)?;
+
+        if tier.threshold <= previous || tier.threshold >= threshold.get() {
+            return Err(ConfigError::TierThresholds);
+        }
+        previous = tier.threshold;
+    }
+    Ok(())
+}
+
+struct Tier<C: clock::Clock> {
+    limiter: KeyedRateLimiter<C>,
+    action: TierAction,
+    sampled: HashMap<Bucket, u64>,
+}
+
+/// The rate limiters of each tier.
+pub struct Tiers<C: clock::Clock> {
+    tiers: Vec<Tier<C>>,
+}
+
+impl<C: clock::Clock> Tiers<C> {
+    /// Builds the limiters for tiers checked with [`validate`].
+    pub fn new(tiers: &[TierConfig], clock: &C) -> Self {
+        let tiers = tiers
+            .iter()
+            .map(|tier| {
+                let threshold = NonZeroU32::new(tier.threshold).expect("tiers are validated");
+                let quota = quota(tier.window_secs, threshold).expect("tiers are validated");
+                Tier {
+                    limiter: RateLimiter::dashmap_with_clock(quota, clock)
+                        .with_middleware::<StateInformationMiddleware>(),
+                    action: tier.action,
+                    sampled: HashMap::new(),
+                }
+            })
+            .collect();
+        Self { tiers }
+    }
+
+    /// Decides what to do with an event for `bucket`.
+    ///
+    /// The action of the tier with the highest exhausted threshold applies. Every tier counts the
+    /// event against its own budget, even once a higher tier is exhausted: with different windows,
+    /// a lower tier isn't necessarily exhausted along with a higher one.
+    pub fn check_key(&mut self, bucket: &Bucket) -> TierDecision {
+        let mut exhausted = None;
+        for (index, tier) in self.tiers.iter_mut().enumerate() {
+            if tier.limiter.check_key(bucket).is_err() {
+                exhausted = Some((index, tier));
+            }
+        }
+
+        match exhausted {
+            None => TierDecision::Pass,
+            Some((index, tier)) => match tier.action {
+                TierAction::Pass => TierDecision::Pass,
+                TierAction::Drop => TierDecision::Drop,
+                TierAction::Tag => TierDecision::Tag(index),
+                TierAction::Sample1InN { rate } => {
+                    let count = tier.sampled.entry(bucket.clone()).or_default();
+                    let decision = if *count % rate.get() == 0 {
+                        TierDecision::Pass
+                    } else {
+                        TierDecision::Drop
+                    };
+                    *count += 1;
+                    decision
+                }
+            },
+        }
+    }
+
+    pub fn retain_recent(&mut self) {
+        for tier in &mut self.tiers {
+            tier.limiter.retain_recent();
+            tier.sampled.clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use governor::clock::FakeRelativeClock;
+
+    use super::*;
+
+    fn tier(threshold: u32) -> TierConfig {
+        TierConfig {
+            threshold,
+            window_secs: Duration::from_secs(1),
+            action: TierAction::Drop,
+        }
+    }
+
+    #[test]
+    fn validate_requires_increasing_thresholds() {
+        let threshold = NonZeroU32::new(10).unwrap();
+
+        assert!(validate(&[tier(2), tier(5)], threshold).is_ok());
+        assert!(matches!(
+            validate(&[tier(5), tier(5)], threshold),
+            Err(ConfigError::TierThresholds)
+        ));
+        assert!(matches!(
+            validate(&[tier(5), tier(2)], threshold),
+            Err(ConfigError::TierThresholds)
+        ));
+        assert!(matches!(
+            validate(&[tier(10)], threshold),
+            Err(ConfigError::TierThresholds)
+        ));
+        assert!(matches!(
+            validate(&[tier(0)], threshold),
+            Err(ConfigError::NonZero)
+        ));
+    }
+
+    #[test]
+    fn lower_tiers_count_events_beyond_higher_ones() {
+        // The lower tier has the longer window, so it outlasts the higher one being exhausted.
+        let tiers = [
+            TierConfig {
+                threshold: 2,
+                window_secs: Duration::from_secs(20),
+                action: TierAction::Tag,
+            },
+            TierConfig {
+                threshold: 3,
+                window_secs: Duration::from_secs(3),
+                action: TierAction::Drop,
+            },
+        ];
+        let clock = FakeRelativeClock::default();
+        let mut tiers = Tiers::new(&tiers, &clock);
+        let bucket = Bucket::Key(None);
+        let mut check = |advance_millis| {
+            clock.advance(Duration::from_millis(advance_millis));
+            tiers.check_key(&bucket)
+        };
+
+        assert_eq!(check(0), TierDecision::Pass);
+        assert_eq!(check(0), TierDecision::Pass);
+        assert_eq!(check(0), TierDecision::Tag(0));
+        // Exhausts the higher tier right before the lower one regains an event.
+        assert_eq!(check(9_500), TierDecision::Tag(0));
+        assert_eq!(check(0), TierDecision::Tag(0));
+        assert_eq!(check(0), TierDecision::Tag(0));
+        // Dropped by the higher tier, this event still takes the one the lower tier regained.
+        assert_eq!(check(600), TierDecision::Drop);
+        assert_eq!(check(600), TierDecision::Tag(0));
+    }
+}
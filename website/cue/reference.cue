@@ -416,6 +416,14 @@ _values: {
 			description: "A [Datadog Search](\(urls.datadog_search_syntax)) query string."
 			example:     #"*stack"#
 		},
+		{
+			name:        "throttle_exceeded"
+			description: """
+				Whether the `key` of the event, a template, is currently over the budget of the `throttle`
+				transform published under the name given by `throttle`, with its `register_as` option.
+				"""
+			example:     #"{ throttle = "api_quota", key = "{{ service }}" }"#
+		},
 	]
 
 	options: {
@@ -39,6 +39,34 @@ base: components: sinks: socket: configuration: {
 		required:      true
 		type: string: examples: ["92.12.333.224:5000", "https://somehost:5000"]
 	}
+	block_above_sendq_bytes: {
+		description: """
+			The number of bytes queued in the socket above which sending pauses.
+
+			Before each event is sent, the bytes sent on the socket but not read by the peer yet are
+			checked, and sending waits while there are more than this, so that a slow reader slows down
+			the sink before writes start blocking. The queued bytes are only known on Linux, and this
+			is ignored on other platforms.
+			"""
+		relevant_when: "mode = \"unix\""
+		required:      false
+		type: uint: {
+			examples: [
+				65536,
+			]
+			unit: "bytes"
+		}
+	}
+	connect_timeout_secs: {
+		description: """
+			The maximum time to wait for a connection to the socket to be established.
+
+			If unset, connecting waits indefinitely.
+			"""
+		relevant_when: "mode = \"unix\""
+		required:      false
+		type: uint: unit: "seconds"
+	}
 	encoding: {
 		description: "Configures how events are encoded into raw bytes."
 		required:    true
@@ -178,6 +206,18 @@ base: components: sinks: socket: configuration: {
 			}
 		}
 	}
+	error_log_every_n_attempts: {
+		description: """
+			How often a failed connection attempt is logged while the socket stays unreachable.
+
+			Failed attempts are always logged when the kind of error changes, and otherwise only every
+			this many attempts, the others being only counted. Once connected again, the duration of
+			the outage and the number of failed attempts are logged.
+			"""
+		relevant_when: "mode = \"unix\""
+		required:      false
+		type: uint: default: 10
+	}
 	framing: {
 		description:   "Framing configuration."
 		relevant_when: "mode = \"tcp\" or mode = \"unix\""
@@ -238,6 +278,28 @@ base: components: sinks: socket: configuration: {
 		required:      true
 		type: string: examples: ["/path/to/socket"]
 	}
+	peer_gid: {
+		description: """
+			The group ID the process listening on the socket must run as.
+
+			Checked along with `peer_uid`.
+			"""
+		relevant_when: "mode = \"unix\""
+		required:      false
+		type: uint: examples: [1000]
+	}
+	peer_uid: {
+		description: """
+			The user ID the process listening on the socket must run as.
+
+			After connecting, the credentials of the peer are checked, and the connection is retried
+			with the usual backoff if they don't match. This prevents sending data to another process
+			that took over the path of the socket.
+			"""
+		relevant_when: "mode = \"unix\""
+		required:      false
+		type: uint: examples: [1000]
+	}
 	send_buffer_bytes: {
 		description: """
 			The size of the socket's send buffer.
@@ -253,6 +315,18 @@ base: components: sinks: socket: configuration: {
 			unit: "bytes"
 		}
 	}
+	shutdown_timeout_secs: {
+		description: """
+			The maximum time to wait, once there are no more events to send, for the pending writes to
+			be flushed and the connection to be shut down.
+
+			If the peer doesn't read the remaining data in time, the connection is dropped and the
+			events not flushed yet are lost. If unset, shutting down waits indefinitely.
+			"""
+		relevant_when: "mode = \"unix\""
+		required:      false
+		type: uint: unit: "seconds"
+	}
 	tls: {
 		description:   "Configures the TLS options for incoming/outgoing connections."
 		relevant_when: "mode = \"tcp\""
@@ -349,4 +423,16 @@ base: components: sinks: socket: configuration: {
 			}
 		}
 	}
+	wait_for_socket: {
+		description: """
+			Wait for the socket to be created, if it doesn't exist yet.
+
+			Instead of retrying the connection with a backoff, the directory of the socket is watched
+			until the socket appears, and the healthcheck passes with a warning in the meantime. Once
+			the sink has connected, a socket that disappears is retried with the usual backoff.
+			"""
+		relevant_when: "mode = \"unix\""
+		required:      false
+		type: bool: default: false
+	}
 }
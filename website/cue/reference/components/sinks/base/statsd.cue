@@ -75,6 +75,16 @@ base: components: sinks: statsd: configuration: {
 			}
 		}
 	}
+	connect_timeout_secs: {
+		description: """
+			The maximum time to wait for a connection to the socket to be established.
+
+			If unset, connecting waits indefinitely.
+			"""
+		relevant_when: "mode = \"unix\""
+		required:      false
+		type: uint: unit: "seconds"
+	}
 	default_namespace: {
 		description: """
 			Sets the default namespace for any metrics sent.
@@ -114,6 +124,28 @@ base: components: sinks: statsd: configuration: {
 		required:      true
 		type: string: examples: ["/path/to/socket"]
 	}
+	peer_gid: {
+		description: """
+			The group ID the process listening on the socket must run as.
+
+			Checked along with `peer_uid`.
+			"""
+		relevant_when: "mode = \"unix\""
+		required:      false
+		type: uint: examples: [1000]
+	}
+	peer_uid: {
+		description: """
+			The user ID the process listening on the socket must run as.
+
+			After connecting, the credentials of the peer are checked, and the connection is retried
+			with the usual backoff if they don't match. This prevents sending data to another process
+			that took over the path of the socket.
+			"""
+		relevant_when: "mode = \"unix\""
+		required:      false
+		type: uint: examples: [1000]
+	}
 	send_buffer_bytes: {
 		description: """
 			The size of the socket's send buffer.
@@ -129,6 +161,18 @@ base: components: sinks: statsd: configuration: {
 			unit: "bytes"
 		}
 	}
+	shutdown_timeout_secs: {
+		description: """
+			The maximum time to wait, once there are no more events to send, for the pending writes to
+			be flushed and the connection to be shut down.
+
+			If the peer doesn't read the remaining data in time, the connection is dropped and the
+			events not flushed yet are lost. If unset, shutting down waits indefinitely.
+			"""
+		relevant_when: "mode = \"unix\""
+		required:      false
+		type: uint: unit: "seconds"
+	}
 	tls: {
 		description:   "Configures the TLS options for incoming/outgoing connections."
 		relevant_when: "mode = \"tcp\""
@@ -225,4 +269,16 @@ base: components: sinks: statsd: configuration: {
 			}
 		}
 	}
+	wait_for_socket: {
+		description: """
+			Wait for the socket to be created, if it doesn't exist yet.
+
+			Instead of retrying the connection with a backoff, the directory of the socket is watched
+			until the socket appears, and the healthcheck passes with a warning in the meantime. Once
+			the sink has connected, a socket that disappears is retried with the usual backoff.
+			"""
+		relevant_when: "mode = \"unix\""
+		required:      false
+		type: bool: default: false
+	}
 }
@@ -68,8 +68,16 @@ components: sinks: socket: {
 	}
 
 	telemetry: metrics: {
-		connection_errors_total: components.sources.internal_metrics.output.metrics.connection_errors_total
-		processed_bytes_total:   components.sources.internal_metrics.output.metrics.processed_bytes_total
-		processed_events_total:  components.sources.internal_metrics.output.metrics.processed_events_total
+		connect_duration_seconds:           components.sources.internal_metrics.output.metrics.connect_duration_seconds
+		connection_errors_suppressed_total: components.sources.internal_metrics.output.metrics.connection_errors_suppressed_total
+		connection_errors_total:            components.sources.internal_metrics.output.metrics.connection_errors_total
+		connection_established_total:       components.sources.internal_metrics.output.metrics.connection_established_total
+		connection_failed_total:            components.sources.internal_metrics.output.metrics.connection_failed_total
+		connection_outage_seconds:          components.sources.internal_metrics.output.metrics.connection_outage_seconds
+		processed_bytes_total:              components.sources.internal_metrics.output.metrics.processed_bytes_total
+		processed_events_total:             components.sources.internal_metrics.output.metrics.processed_events_total
+		reconnects_total:                   components.sources.internal_metrics.output.metrics.reconnects_total
+		unix_socket_connection_state:       components.sources.internal_metrics.output.metrics.unix_socket_connection_state
+		unix_socket_sendq_bytes:            components.sources.internal_metrics.output.metrics.unix_socket_sendq_bytes
 	}
 }
@@ -1,6 +1,17 @@
 package metadata
 
 base: components: sources: datadog_agent: configuration: {
+	acknowledgement_timeout_secs: {
+		description: """
+			The maximum number of seconds to wait for the events of a request to be acknowledged.
+
+			When the events aren't acknowledged in time, the request is answered with a 504 status so
+			that the agent retries it. By default, the source waits until the events are acknowledged.
+			Only applies when acknowledgements are enabled.
+			"""
+		required: false
+		type: uint: unit: "seconds"
+	}
 	acknowledgements: {
 		deprecated: true
 		description: """
@@ -31,6 +42,98 @@ base: components: sources: datadog_agent: configuration: {
 		required: true
 		type: string: examples: ["0.0.0.0:80", "localhost:80"]
 	}
+	allowed_api_keys: {
+		description: """
+			The API keys allowed to send data to the component.
+
+			If set, requests without an API key, or with an API key not in this list, are rejected. The
+			API key is taken from the `/v1/input/<API_KEY>` path, the `dd-api-key` query parameter, or
+			the `DD-API-KEY` header, in that order.
+			"""
+		required: false
+		type: array: {
+			default: []
+			items: type: string: {}
+		}
+	}
+	annotate_order: {
+		description: """
+			If this is set to `true`, logs are numbered in the order they were sent in within their
+			request, with a `request_seq` metadata field starting at `0`.
+
+			The logs of a request are always sent on in their original order, including the events
+			decoded from the frames of a single message, but later components may reorder them. The
+			number lets consumers detect and repair that.
+			"""
+		required: false
+		type: bool: default: false
+	}
+	api_key_routing: {
+		description: """
+			The outputs dedicated to the logs sent with given API keys, when `multiple_outputs` is
+			enabled.
+
+			Each entry maps an API key, or the hex-encoded SHA-256 digest of one prefixed with
+			`sha256:`, to the name of an output. For a source component named `agent`, logs sent with a
+			key mapped to `prod` can then be configured as input to other components by specifying
+			`agent.logs.prod`. Logs sent with other keys, or without one, are still sent to
+			`agent.logs`, and outputs dedicated to a `ddsource` take precedence.
+			"""
+		required: false
+		type: object: options: "*": {
+			description: "An API key, or the SHA-256 digest of one, and the name of its output."
+			required:    true
+			type: string: {}
+		}
+	}
+	cors_allowed_origins: {
+		description: """
+			The origins allowed to send logs from a browser.
+
+			When set, CORS preflight requests to the logs routes are answered, and responses to
+			allowed origins include the appropriate CORS headers. Each origin is of the form
+			`scheme://host[:port]`, or `*` to allow any origin.
+			"""
+		required: false
+		type: array: {
+			default: []
+			items: type: string: examples: ["https://example.com"]
+		}
+	}
+	ddsource_outputs: {
+		description: "The `ddsource` values given a dedicated output when `map_ddsource_to_output` is enabled."
+		required:    false
+		type: array: {
+			default: []
+			items: type: string: examples: ["nginx", "postgres"]
+		}
+	}
+	decode_pool: {
+		description: "Settings of the workers decompressing and parsing the bodies of log requests."
+		required:    false
+		type: object: options: {
+			max_queued_requests: {
+				description: """
+					The maximum number of log requests waiting for a worker.
+
+					Requests received while the queue is full are rejected with a `429 Too Many Requests`
+					response, which the agent retries, instead of being buffered.
+					"""
+				required: false
+				type: uint: default: 256
+			}
+			workers: {
+				description: """
+					The maximum number of log requests decoded at once.
+
+					Decoding runs on dedicated threads, so that large payloads don't delay accepting other
+					requests. Defaults to the number of available CPUs.
+					"""
+				required: false
+				type: uint: examples: [4]
+			}
+		}
+	}
 	decoding: {
 		description: "Configures how events are decoded from raw bytes."
 		required:    false
@@ -81,20 +184,59 @@ base: components: sources: datadog_agent: configuration: {
 		}
 	}
 	disable_logs: {
-		description: "If this is set to `true`, logs are not accepted by the component."
-		required:    false
+		description: """
+			If this is set to `true`, logs are not accepted by the component.
+
+			The `logs` output is then omitted when `multiple_outputs` is enabled, and requests sent to
+			the logs routes are answered with a `404 Not Found` explaining that they're disabled.
+			"""
+		required: false
 		type: bool: default: false
 	}
 	disable_metrics: {
-		description: "If this is set to `true`, metrics are not accepted by the component."
-		required:    false
+		description: """
+			If this is set to `true`, metrics are not accepted by the component.
+
+			The `metrics` output is then omitted when `multiple_outputs` is enabled, and requests sent to
+			the metrics routes are answered with a `404 Not Found` explaining that they're disabled.
+			"""
+		required: false
 		type: bool: default: false
 	}
 	disable_traces: {
-		description: "If this is set to `true`, traces are not accepted by the component."
-		required:    false
+		description: """
+			If this is set to `true`, traces are not accepted by the component.
+
+			The `traces` output is then omitted when `multiple_outputs` is enabled, and requests sent to
+			the traces routes are answered with a `404 Not Found` explaining that they're disabled.
+			"""
+		required: false
 		type: bool: default: false
 	}
+	extract_trace_context: {
+		description: """
+			If this is set to `true`, the `dd.trace_id` and `dd.span_id` fields of logs are copied to
+			their metadata, as `trace_id` and `span_id`.
+
+			The fields are read from logs decoded as objects, or parsed from the message of logs left as
+			bytes by the codec. IDs may be integers or strings of digits, and are written as strings.
+			Missing or malformed IDs are ignored, and the fields themselves are left untouched.
+			"""
+		required: false
+		type: bool: default: false
+	}
+	failed_request_dump_path: {
+		description: """
+			The directory to dump the bodies of log requests that fail to decode to.
+
+			Each dump is a pair of files sharing a unique name: the body as received, with a `.body`
+			extension, and a JSON file with a `.json` extension containing the path, headers, and error
+			of the request. This is meant to help debug encoding issues on the agent side, and doesn't
+			change the response to the request.
+			"""
+		required: false
+		type: string: examples: ["/var/lib/vector/datadog_agent/failed_requests"]
+	}
 	framing: {
 		description: """
 			Framing configuration.
@@ -186,6 +328,399 @@ base: components: sources: datadog_agent: configuration: {
 			}
 		}
 	}
+	health_failure_threshold: {
+		description: """
+			The number of consecutive requests whose events fail to be delivered after which the
+			`GET /health` route reports the source as unhealthy.
+
+			The route answers with a 200 status while the source is healthy, and a 503 status once this
+			many requests in a row failed, until one succeeds, or once the source is shutting down. It
+			doesn't require an API key, and is meant for liveness and readiness probes.
+			"""
+		required: false
+		type: uint: default: 3
+	}
+	include_request_metadata: {
+		description: """
+			If this is set to `true`, logs are enriched with details about the request that sent them.
+
+			The address of the client, and the `DD-Agent-Version` and `User-Agent` headers, are added
+			to each log as `remote_addr`, `agent_version`, and `user_agent`, respectively.
+			"""
+		required: false
+		type: bool: default: false
+	}
+	keepalive: {
+		description: "HTTP keep-alive settings of the `datadog_agent` source."
+		required:    false
+		type: object: options: max_connection_age_secs: {
+			description: """
+				The maximum time a connection is kept open for, in seconds.
+
+				The first response sent on a connection older than this has a `Connection: close` header,
+				and the connection is closed once it's sent. Clients then reconnect, which spreads the load
+				across the instances behind a load balancer. By default, connections are kept open for as
+				long as clients use them.
+				"""
+			required: false
+			type: uint: {
+				examples: [300]
+				unit: "seconds"
+			}
+		}
+	}
+	logs_listener: {
+		description: "Serves logs on a dedicated listener instead of the one configured with `address`."
+		required:    false
+		type: object: options: {
+			address: {
+				description: """
+					The socket address to accept connections on.
+
+					It _must_ include a port.
+					"""
+				required: true
+				type: string: examples: ["0.0.0.0:8081"]
+			}
+			tls: {
+				description: "Configures the TLS options for incoming/outgoing connections."
+				required:    false
+				type: object: options: {
+					alpn_protocols: {
+						description: """
+							Sets the list of supported ALPN protocols.
+
+							Declare the supported ALPN protocols, which are used during negotiation with peer. They are prioritized in the order
+							that they are defined.
+							"""
+						required: false
+						type: array: items: type: string: examples: ["h2"]
+					}
+					ca_file: {
+						description: """
+							Absolute path to an additional CA certificate file.
+
+							The certificate must be in the DER or PEM (X.509) format. Additionally, the certificate can be provided as an inline string in PEM format.
+							"""
+						required: false
+						type: string: examples: ["/path/to/certificate_authority.crt"]
+					}
+					crt_file: {
+						description: """
+							Absolute path to a certificate file used to identify this server.
+
+							The certificate must be in DER, PEM (X.509), or PKCS#12 format. Additionally, the certificate can be provided as
+							an inline string in PEM format.
+
+							If this is set, and is not a PKCS#12 archive, `key_file` must also be set.
+							"""
+						required: false
+						type: string: examples: ["/path/to/host_certificate.crt"]
+					}
+					enabled: {
+						description: """
+							Whether or not to require TLS for incoming or outgoing connections.
+
+							When enabled and used for incoming connections, an identity certificate is also required. See `tls.crt_file` for
+							more information.
+							"""
+						required: false
+						type: bool: {}
+					}
+					key_file: {
+						description: """
+							Absolute path to a private key file used to identify this server.
+
+							The key must be in DER or PEM (PKCS#8) format. Additionally, the key can be provided as an inline string in PEM format.
+							"""
+						required: false
+						type: string: examples: ["/path/to/host_certificate.key"]
+					}
+					key_pass: {
+						description: """
+							Passphrase used to unlock the encrypted key file.
+
+							This has no effect unless `key_file` is set.
+							"""
+						required: false
+						type: string: examples: ["${KEY_PASS_ENV_VAR}", "PassWord1"]
+					}
+					verify_certificate: {
+						description: """
+							Enables certificate verification.
+
+							If enabled, certificates must not be expired and must be issued by a trusted
+							issuer. This verification operates in a hierarchical manner, checking that the leaf certificate (the
+							certificate presented by the client/server) is not only valid, but that the issuer of that certificate is also valid, and
+							so on until the verification process reaches a root certificate.
+
+							Relevant for both incoming and outgoing connections.
+
+							Do NOT set this to `false` unless you understand the risks of not verifying the validity of certificates.
+							"""
+						required: false
+						type: bool: {}
+					}
+					verify_hostname: {
+						description: """
+							Enables hostname verification.
+
+							If enabled, the hostname used to connect to the remote host must be present in the TLS certificate presented by
+							the remote host, either as the Common Name or as an entry in the Subject Alternative Name extension.
+
+							Only relevant for outgoing connections.
+
+							Do NOT set this to `false` unless you understand the risks of not verifying the remote hostname.
+							"""
+						required: false
+						type: bool: {}
+					}
+				}
+			}
+		}
+	}
+	map_ddsource_to_output: {
+		description: """
+			If this is set to `true`, and `multiple_outputs` is enabled, logs are sent to a dedicated
+			output for their `ddsource`, if it's listed in `ddsource_outputs`.
+
+			For a source component named `agent`, logs with a `ddsource` of `nginx` can then be
+			configured as input to other components by specifying `agent.logs.nginx`. Other logs are
+			still sent to `agent.logs`.
+			"""
+		required: false
+		type: bool: default: false
+	}
+	max_body_bytes: {
+		description: """
+			The maximum size of the body of a log request, in bytes, as received.
+
+			Larger requests are rejected with a `413 Payload Too Large` response as soon as the body
+			crosses the limit, without reading the rest of it. This also applies to bodies sent with
+			chunked transfer encoding. By default, bodies of any size are accepted.
+			"""
+		required: false
+		type: uint: unit: "bytes"
+	}
+	max_dump_bytes: {
+		description: """
+			The maximum number of bytes of a body to dump.
+
+			Longer bodies are truncated.
+			"""
+		required: false
+		type: uint: {
+			default: 1048576
+			unit:    "bytes"
+		}
+	}
+	max_dumps_per_minute: {
+		description: "The maximum number of requests dumped per minute."
+		required:    false
+		type: uint: default: 10
+	}
+	max_message_bytes: {
+		description: """
+			The maximum size of the message of a single log, in bytes.
+
+			Messages larger than this are handled according to `on_oversized` before being decoded. By
+			default, messages of any size are accepted.
+			"""
+		required: false
+		type: uint: unit: "bytes"
+	}
+	metrics_listener: {
+		description: "Serves metrics on a dedicated listener instead of the one configured with `address`."
+		required:    false
+		type: object: options: {
+			address: {
+				description: """
+					The socket address to accept connections on.
+
+					It _must_ include a port.
+					"""
+				required: true
+				type: string: examples: ["0.0.0.0:8081"]
+			}
+			tls: {
+				description: "Configures the TLS options for incoming/outgoing connections."
+				required:    false
+				type: object: options: {
+					alpn_protocols: {
+						description: """
+							Sets the list of supported ALPN protocols.
+
+							Declare the supported ALPN protocols, which are used during negotiation with peer. They are prioritized in the order
+							that they are defined.
+							"""
+						required: false
+						type: array: items: type: string: examples: ["h2"]
+					}
+					ca_file: {
+						description: """
+							Absolute path to an additional CA certificate file.
+
+							The certificate must be in the DER or PEM (X.509) format. Additionally, the certificate can be provided as an inline string in PEM format.
+							"""
+						required: false
+						type: string: examples: ["/path/to/certificate_authority.crt"]
+					}
+					crt_file: {
+						description: """
+							Absolute path to a certificate file used to identify this server.
+
+							The certificate must be in DER, PEM (X.509), or PKCS#12 format. Additionally, the certificate can be provided as
+							an inline string in PEM format.
+
+							If this is set, and is not a PKCS#12 archive, `key_file` must also be set.
+							"""
+						required: false
+						type: string: examples: ["/path/to/host_certificate.crt"]
+					}
+					enabled: {
+						description: """
+							Whether or not to require TLS for incoming or outgoing connections.
+
+							When enabled and used for incoming connections, an identity certificate is also required. See `tls.crt_file` for
+							more information.
+							"""
+						required: false
+						type: bool: {}
+					}
+					key_file: {
+						description: """
+							Absolute path to a private key file used to identify this server.
+
+							The key must be in DER or PEM (PKCS#8) format. Additionally, the key can be provided as an inline string in PEM format.
+							"""
+						required: false
+						type: string: examples: ["/path/to/host_certificate.key"]
+					}
+					key_pass: {
+						description: """
+							Passphrase used to unlock the encrypted key file.
+
+							This has no effect unless `key_file` is set.
+							"""
+						required: false
+						type: string: examples: ["${KEY_PASS_ENV_VAR}", "PassWord1"]
+					}
+					verify_certificate: {
+						description: """
+							Enables certificate verification.
+
+							If enabled, certificates must not be expired and must be issued by a trusted
+							issuer. This verification operates in a hierarchical manner, checking that the leaf certificate (the
+							certificate presented by the client/server) is not only valid, but that the issuer of that certificate is also valid, and
+							so on until the verification process reaches a root certificate.
+
+							Relevant for both incoming and outgoing connections.
+
+							Do NOT set this to `false` unless you understand the risks of not verifying the validity of certificates.
+							"""
+						required: false
+						type: bool: {}
+					}
+					verify_hostname: {
+						description: """
+							Enables hostname verification.
+
+							If enabled, the hostname used to connect to the remote host must be present in the TLS certificate presented by
+							the remote host, either as the Common Name or as an entry in the Subject Alternative Name extension.
+
+							Only relevant for outgoing connections.
+
+							Do NOT set this to `false` unless you understand the risks of not verifying the remote hostname.
+							"""
+						required: false
+						type: bool: {}
+					}
+				}
+			}
+		}
+	}
+	multiline: {
+		description: """
+			Splits log messages into their lines, or joins consecutive log messages, before they are
+			decoded.
+
+			The logs decoded from a split or joined message get the reserved attributes of the message
+			they were split from, or of the first message they were joined from.
+			"""
+		required: false
+		type: object: options: {
+			condition_mode: {
+				description: """
+					Aggregation mode.
+
+					This setting must be configured in conjunction with `condition_pattern`.
+					"""
+				required: true
+				type: string: enum: {
+					continue_past: """
+						All consecutive lines matching this pattern, plus one additional line, are included in the group.
+
+						This is useful in cases where a log message ends with a continuation marker, such as a backslash, indicating
+						that the following line is part of the same message.
+						"""
+					continue_through: """
+						All consecutive lines matching this pattern are included in the group.
+
+						The first line (the line that matched the start pattern) does not need to match the `ContinueThrough` pattern.
+
+						This is useful in cases such as a Java stack trace, where some indicator in the line (such as a leading
+						whitespace) indicates that it is an extension of the proceeding line.
+						"""
+					halt_before: """
+						All consecutive lines not matching this pattern are included in the group.
+
+						This is useful where a log line contains a marker indicating that it begins a new message.
+						"""
+					halt_with: """
+						All consecutive lines, up to and including the first line matching this pattern, are included in the group.
+
+						This is useful where a log line ends with a termination marker, such as a semicolon.
+						"""
+				}
+			}
+			condition_pattern: {
+				description: """
+					Regular expression pattern that is used to determine whether or not more lines should be read.
+
+					This setting must be configured in conjunction with `condition_mode`.
+					"""
+				required: true
+				type: string: examples: ["^[\\s]+", "^(INFO|ERROR) "]
+			}
+			mode: {
+				description: "What to do with the lines of log messages."
+				required:    false
+				type: string: {
+					default: "off"
+					enum: {
+						join: """
+							Join consecutive messages of a request into a single message, each of them as a line of it.
+
+							Only messages from the same `hostname`, `service`, and `ddsource` are joined. This is useful
+							when the agent splits a logical event, such as a stack trace, into several messages.
+							"""
+						off: "Leave messages as they are."
+						split: """
+							Split each message into a message for each group of its lines.
+
+							This is useful when the agent sends several logical events, such as the lines of a stack
+							trace meant to be read separately, as a single message.
+							"""
+					}
+				}
+			}
+			start_pattern: {
+				description: "Regular expression pattern that is used to match the start of a new message."
+				required:    true
+				type: string: examples: ["^[^\\s]", "^(INFO|ERROR) "]
+			}
+		}
+	}
 	multiple_outputs: {
 		description: """
 			If this is set to `true` logs, metrics, and traces are sent to different outputs.
@@ -197,6 +732,153 @@ base: components: sources: datadog_agent: configuration: {
 		required: false
 		type: bool: default: false
 	}
+	normalize_service_names: {
+		description: """
+			If this is set to `true`, the `service` and `ddsource` of logs are converted to lowercase.
+
+			When this changes a value, the value as received is kept in the metadata of the log, as
+			`service_original` or `ddsource_original`. Logs are routed by `ddsource` after it is
+			converted.
+			"""
+		required: false
+		type: bool: default: false
+	}
+	on_decode_error: {
+		description: """
+			What to do with a request when one of its log messages fails to be decoded by `framing` and
+			`decoding`.
+			"""
+		required: false
+		type: string: {
+			default: "skip_message"
+			enum: {
+				fail_request: "Reject the whole request with a `400 Bad Request` response, which the Agent retries."
+				skip_message: """
+					Skip what couldn't be decoded, and accept the rest of the request.
+
+					A frame that fails to be parsed is skipped. When the framing itself fails, the rest of the
+					message is skipped, since where its next frame starts is unknown.
+					"""
+			}
+		}
+	}
+	on_oversized: {
+		description: "What to do with messages larger than `max_message_bytes`."
+		required:    false
+		type: string: {
+			default: "truncate"
+			enum: {
+				drop: "Drop the log."
+				pass: "Pass the message through unchanged."
+				truncate: """
+					Truncate the message to `max_message_bytes`, ending it with `truncation_marker`.
+
+					The cut never splits a UTF-8 character, and the log is flagged with a `truncated` metadata
+					field set to `true`.
+					"""
+			}
+		}
+	}
+	origin_as_tag: {
+		description: """
+			If this is set to `true`, the origin of logs sent with an `X-Datadog-Origin` header is also
+			appended to their `ddtags`, as an `origin:<value>` tag.
+
+			The origin is always added to the metadata of the logs as `origin`, whether this is set or
+			not. Logs sent without the header are left untouched.
+			"""
+		required: false
+		type: bool: default: false
+	}
+	promote_conflict: {
+		description: "What to do with a promoted tag whose key is already a field of the log."
+		required:    false
+		type: string: {
+			default: "keep_existing"
+			enum: {
+				keep_existing: "Keep the existing field, and don't promote the tag."
+				overwrite:     "Overwrite the existing field with the tag."
+				rename: """
+					Promote the tag to a field named after its key with a `_tag` suffix instead, such as
+					`env_tag`.
+					"""
+			}
+		}
+	}
+	promote_tags: {
+		description: """
+			The keys of the tags to promote from `ddtags` to top-level fields of logs.
+
+			A tag such as `env:prod` is written to an `env` field set to `prod`. Tags without a value
+			aren't promoted, and only the first value of a key is promoted. With the `vector` log
+			namespace, tags are only promoted to logs decoded as objects.
+			"""
+		required: false
+		type: array: {
+			default: []
+			items: type: string: examples: ["env", "version"]
+		}
+	}
+	remove_promoted: {
+		description: """
+			If this is set to `true`, the tags whose key is in `promote_tags` are removed from `ddtags`.
+
+			This includes the tags that weren't written to the log because of `promote_conflict`.
+			"""
+		required: false
+		type: bool: default: false
+	}
+	reserved_fields: {
+		description: """
+			The reserved attributes of logs inserted into each event.
+
+			With the `vector` log namespace, they're inserted into the metadata of the event instead.
+			Attributes left out are dropped, which keeps events smaller when they aren't needed. The
+			`source_type` and ingest timestamp are always inserted. Defaults to all of them.
+			"""
+		required: false
+		type: array: {
+			default: ["status", "timestamp", "hostname", "service", "ddsource", "ddtags"]
+			items: type: string: enum: {
+				ddsource:  "The `ddsource` of the log."
+				ddtags:    "The `ddtags` of the log."
+				hostname:  "The `hostname` of the log."
+				service:   "The `service` of the log."
+				status:    "The `status` of the log."
+				timestamp: "The `timestamp` of the log."
+			}
+		}
+	}
+	service_api_key_map_file: {
+		description: """
+			The path to a file mapping the `service` of logs to the Datadog API key to send them with.
+
+			The file is a YAML (or JSON) mapping of services to API keys, such as `checkout: <api key>`.
+			The key of the service of each log, once normalized by `normalize_service_names`, is stored
+			with the log as if it had been sent with the request, so that the `datadog_logs` sink sends
+			it with that key. Which key wins when the request has one too is set by
+			`service_api_key_precedence`.
+
+			The file is watched for changes, and the new keys apply to subsequent requests. If the file
+			can't be read or parsed, the previously loaded keys are kept.
+			"""
+		required: false
+		type: string: examples: ["/etc/vector/service_api_keys.yaml"]
+	}
+	service_api_key_precedence: {
+		description: """
+			Which API key a log is stored with, when both its service is in `service_api_key_map_file`
+			and its request has a key stored with `store_api_key`.
+			"""
+		required: false
+		type: string: {
+			default: "service"
+			enum: {
+				request: "The key of the request. The key of the service only applies to requests without one."
+				service: "The key of the service, from `service_api_key_map_file`."
+			}
+		}
+	}
 	store_api_key: {
 		description: """
 			If this is set to `true`, when incoming events contain a Datadog API key, it is
@@ -300,4 +982,151 @@ base: components: sources: datadog_agent: configuration: {
 			}
 		}
 	}
+	traces_listener: {
+		description: "Serves traces on a dedicated listener instead of the one configured with `address`."
+		required:    false
+		type: object: options: {
+			address: {
+				description: """
+					The socket address to accept connections on.
+
+					It _must_ include a port.
+					"""
+				required: true
+				type: string: examples: ["0.0.0.0:8081"]
+			}
+			tls: {
+				description: "Configures the TLS options for incoming/outgoing connections."
+				required:    false
+				type: object: options: {
+					alpn_protocols: {
+						description: """
+							Sets the list of supported ALPN protocols.
+
+							Declare the supported ALPN protocols, which are used during negotiation with peer. They are prioritized in the order
+							that they are defined.
+							"""
+						required: false
+						type: array: items: type: string: examples: ["h2"]
+					}
+					ca_file: {
+						description: """
+							Absolute path to an additional CA certificate file.
+
+							The certificate must be in the DER or PEM (X.509) format. Additionally, the certificate can be provided as an inline string in PEM format.
+							"""
+						required: false
+						type: string: examples: ["/path/to/certificate_authority.crt"]
+					}
+					crt_file: {
+						description: """
+							Absolute path to a certificate file used to identify this server.
+
+							The certificate must be in DER, PEM (X.509), or PKCS#12 format. Additionally, the certificate can be provided as
+							an inline string in PEM format.
+
+							If this is set, and is not a PKCS#12 archive, `key_file` must also be set.
+							"""
+						required: false
+						type: string: examples: ["/path/to/host_certificate.crt"]
+					}
+					enabled: {
+						description: """
+							Whether or not to require TLS for incoming or outgoing connections.
+
+							When enabled and used for incoming connections, an identity certificate is also required. See `tls.crt_file` for
+							more information.
+							"""
+						required: false
+						type: bool: {}
+					}
+					key_file: {
+						description: """
+							Absolute path to a private key file used to identify this server.
+
+							The key must be in DER or PEM (PKCS#8) format. Additionally, the key can be provided as an inline string in PEM format.
+							"""
+						required: false
+						type: string: examples: ["/path/to/host_certificate.key"]
+					}
+					key_pass: {
+						description: """
+							Passphrase used to unlock the encrypted key file.
+
+							This has no effect unless `key_file` is set.
+							"""
+						required: false
+						type: string: examples: ["${KEY_PASS_ENV_VAR}", "PassWord1"]
+					}
+					verify_certificate: {
+						description: """
+							Enables certificate verification.
+
+							If enabled, certificates must not be expired and must be issued by a trusted
+							issuer. This verification operates in a hierarchical manner, checking that the leaf certificate (the
+							certificate presented by the client/server) is not only valid, but that the issuer of that certificate is also valid, and
+							so on until the verification process reaches a root certificate.
+
+							Relevant for both incoming and outgoing connections.
+
+							Do NOT set this to `false` unless you understand the risks of not verifying the validity of certificates.
+							"""
+						required: false
+						type: bool: {}
+					}
+					verify_hostname: {
+						description: """
+							Enables hostname verification.
+
+							If enabled, the hostname used to connect to the remote host must be present in the TLS certificate presented by
+							the remote host, either as the Common Name or as an entry in the Subject Alternative Name extension.
+
+							Only relevant for outgoing connections.
+
+							Do NOT set this to `false` unless you understand the risks of not verifying the remote hostname.
+							"""
+						required: false
+						type: bool: {}
+					}
+				}
+			}
+		}
+	}
+	truncation_marker: {
+		description: """
+			The marker appended to messages truncated by `on_oversized`.
+
+			The marker counts towards `max_message_bytes`, so it must be shorter than it for truncated
+			messages to keep any of their content.
+			"""
+		required: false
+		type: string: default: "...[truncated]"
+	}
+	trusted_proxies: {
+		description: """
+			The proxies trusted to report the address of the client.
+
+			When a request comes from one of these addresses, the client address recorded with
+			`include_request_metadata` is taken from the `X-Forwarded-For` header instead. Each entry is
+			an IP address or a CIDR block.
+			"""
+		required: false
+		type: array: {
+			default: []
+			items: type: string: examples: ["10.0.0.0/8", "192.168.1.1"]
+		}
+	}
+	verbose_responses: {
+		description: """
+			If this is set to `true`, successful responses to log requests have a JSON body counting
+			the log messages accepted and rejected, such as `{"accepted": 124, "rejected": 0}`.
+
+			Messages dropped as they fail to decode, or as they're larger than `max_message_bytes` with
+			`on_oversized` set to `drop`, are counted as rejected. A request that isn't valid JSON is
+			still rejected entirely. By default, the body is empty, like the responses of the Datadog
+			intake.
+			"""
+		required: false
+		type: bool: default: false
+	}
 }
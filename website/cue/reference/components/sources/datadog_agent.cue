@@ -126,6 +126,13 @@ components: sources: datadog_agent: {
 						examples: ["java"]
 					}
 				}
+				ddsource_original: {
+					description: "The source field as received, before `normalize_service_names` converted it to lowercase. Only set when the conversion changed it."
+					required:    false
+					type: string: {
+						examples: ["Java"]
+					}
+				}
 				ddtags: {
 					description: "The comma separated tags list extracted from the event."
 					required:    true
@@ -133,6 +140,32 @@ components: sources: datadog_agent: {
 						examples: ["env:prod,region:ap-east-1"]
 					}
 				}
+				origin: {
+					description: "The origin of the event, from the `X-Datadog-Origin` header of the request. Only set when the header is present."
+					required:    false
+					type: string: {
+						examples: ["agent-pipeline"]
+					}
+				}
+				request_seq: {
+					description: "The position of the log within its request, starting at `0`. Only set when `annotate_order` is enabled."
+					required:    false
+					type: uint: {
+						examples: [0, 1]
+					}
+				}
+				service_original: {
+					description: "The service field as received, before `normalize_service_names` converted it to lowercase. Only set when the conversion changed it."
+					required:    false
+					type: string: {
+						examples: ["MyApp"]
+					}
+				}
+				truncated: {
+					description: "Set to `true` when the message was truncated because it was larger than `max_message_bytes`."
+					required:    false
+					type: bool: {}
+				}
 			}
 		}
 		metrics: {
@@ -206,6 +239,27 @@ components: sources: datadog_agent: {
 				```
 				"""
 		}
+		health_checks: {
+			title: "Health checks"
+			body: """
+				The source serves a `GET /health` route on each of its listeners, meant for liveness and
+				readiness probes. It doesn't require an API key. It answers with a 200 status while the
+				source can deliver events, and with a 503 status once `health_failure_threshold` requests
+				in a row failed to be delivered, or once the source is shutting down. The JSON body holds
+				the `state` of the source, the number of `consecutive_failures`, and the `last_error`.
+				"""
+		}
+		response_codes: {
+			title: "Response codes"
+			body: """
+				Requests that can't be decoded are answered with a 4xx status, and aren't retried by the
+				Datadog Agent. When acknowledgements are enabled, requests whose events couldn't be
+				delivered, because Vector is shutting down or a sink failed to deliver them, are answered
+				with a 503 status and a `Retry-After` header. Requests whose events aren't acknowledged
+				within `acknowledgement_timeout_secs` are answered with a 504 status. The Datadog Agent
+				retries both.
+				"""
+		}
 		trace_support: {
 			title: "Trace support caveats"
 			body: """
@@ -219,11 +273,17 @@ components: sources: datadog_agent: {
 	}
 
 	telemetry: metrics: {
-		component_discarded_events_total:     components.sources.internal_metrics.output.metrics.component_discarded_events_total
-		component_errors_total:               components.sources.internal_metrics.output.metrics.component_errors_total
-		component_received_bytes_total:       components.sources.internal_metrics.output.metrics.component_received_bytes_total
-		component_received_event_bytes_total: components.sources.internal_metrics.output.metrics.component_received_event_bytes_total
-		component_received_events_total:      components.sources.internal_metrics.output.metrics.component_received_events_total
-		events_in_total:                      components.sources.internal_metrics.output.metrics.events_in_total
+		component_discarded_events_total:               components.sources.internal_metrics.output.metrics.component_discarded_events_total
+		component_errors_total:                         components.sources.internal_metrics.output.metrics.component_errors_total
+		component_received_bytes_total:                 components.sources.internal_metrics.output.metrics.component_received_bytes_total
+		component_received_event_bytes_total:           components.sources.internal_metrics.output.metrics.component_received_event_bytes_total
+		component_received_events_total:                components.sources.internal_metrics.output.metrics.component_received_events_total
+		datadog_agent_decompression_ratio:              components.sources.internal_metrics.output.metrics.datadog_agent_decompression_ratio
+		datadog_agent_failed_request_dump_errors_total: components.sources.internal_metrics.output.metrics.datadog_agent_failed_request_dump_errors_total
+		datadog_agent_failed_request_dumps_total:       components.sources.internal_metrics.output.metrics.datadog_agent_failed_request_dumps_total
+		datadog_agent_message_size_bytes:               components.sources.internal_metrics.output.metrics.datadog_agent_message_size_bytes
+		datadog_agent_messages_per_request:             components.sources.internal_metrics.output.metrics.datadog_agent_messages_per_request
+		datadog_agent_oversized_messages_total:         components.sources.internal_metrics.output.metrics.datadog_agent_oversized_messages_total
+		events_in_total:                                components.sources.internal_metrics.output.metrics.events_in_total
 	}
 }
@@ -84,6 +84,12 @@ components: sources: internal_metrics: {
 			default_namespace: "vector"
 			tags:              _internal_metrics_tags
 		}
+		connect_duration_seconds: {
+			description:       "The time taken to establish a connection, including the backoff between failed attempts."
+			type:              "histogram"
+			default_namespace: "vector"
+			tags:              _internal_metrics_tags
+		}
 		connection_errors_total: {
 			description:       "The total number of connection errors for this Vector instance."
 			type:              "counter"
@@ -96,12 +102,24 @@ components: sources: internal_metrics: {
 			default_namespace: "vector"
 			tags:              _internal_metrics_tags
 		}
+		connection_errors_suppressed_total: {
+			description:       "The total number of failed connection attempts that weren't logged, as the same error was logged recently."
+			type:              "counter"
+			default_namespace: "vector"
+			tags:              _internal_metrics_tags
+		}
 		connection_failed_total: {
 			description:       "The total number of times a connection has failed."
 			type:              "counter"
 			default_namespace: "vector"
 			tags:              _internal_metrics_tags
 		}
+		connection_outage_seconds: {
+			description:       "The time between the first failed connection attempt and the connection being established again."
+			type:              "histogram"
+			default_namespace: "vector"
+			tags:              _internal_metrics_tags
+		}
 		connection_send_errors_total: {
 			description:       "The total number of errors sending data via the connection."
 			type:              "counter"
@@ -591,6 +609,60 @@ components: sources: internal_metrics: {
 			default_namespace: "vector"
 			tags:              _component_tags & {output: _output}
 		}
+		datadog_agent_decompression_ratio: {
+			description:       "The ratio of decompressed to compressed size of the last compressed request received from a Datadog Agent."
+			type:              "gauge"
+			default_namespace: "vector"
+			tags: _component_tags & {
+				endpoint: _datadog_agent_endpoint
+			}
+		}
+		datadog_agent_failed_request_dump_errors_total: {
+			description:       "The number of bodies of requests that failed to decode that couldn't be dumped."
+			type:              "counter"
+			default_namespace: "vector"
+			tags:              _component_tags
+		}
+		datadog_agent_failed_request_dumps_total: {
+			description:       "The number of bodies of requests that failed to decode dumped to `failed_request_dump_path`."
+			type:              "counter"
+			default_namespace: "vector"
+			tags:              _component_tags
+		}
+		datadog_agent_message_size_bytes: {
+			description:       "The size of each log message received from a Datadog Agent."
+			type:              "histogram"
+			default_namespace: "vector"
+			tags: _component_tags & {
+				endpoint:   _datadog_agent_endpoint
+				compressed: _datadog_agent_compressed
+			}
+		}
+		datadog_agent_messages_per_request: {
+			description:       "The number of messages in each request received from a Datadog Agent."
+			type:              "histogram"
+			default_namespace: "vector"
+			tags: _component_tags & {
+				endpoint:   _datadog_agent_endpoint
+				compressed: _datadog_agent_compressed
+			}
+		}
+		datadog_agent_oversized_messages_total: {
+			description:       "The number of log messages received from a Datadog Agent that were larger than `max_message_bytes`."
+			type:              "counter"
+			default_namespace: "vector"
+			tags: _component_tags & {
+				action: {
+					description: "What was done with the message."
+					required:    true
+					enum: {
+						drop:     "The log was dropped."
+						pass:     "The message was passed through unchanged."
+						truncate: "The message was truncated."
+					}
+				}
+			}
+		}
 		datadog_logs_received_in_total: {
 			description:       "Number of Datadog logs received."
 			type:              "counter"
@@ -897,6 +969,12 @@ components: sources: internal_metrics: {
 			default_namespace: "vector"
 			tags:              _component_tags
 		}
+		reconnects_total: {
+			description:       "The total number of times a connection has been established after a previous one was lost."
+			type:              "counter"
+			default_namespace: "vector"
+			tags:              _internal_metrics_tags
+		}
 		request_errors_total: {
 			description:       "The total number of requests errors for this component."
 			type:              "counter"
@@ -1035,12 +1113,30 @@ components: sources: internal_metrics: {
 			default_namespace: "vector"
 			tags:              _component_tags
 		}
+		throttle_key_cardinality_limit_reached_total: {
+			description:       "The total number of windows in which the `throttle` transform reached its `max_unique_keys`."
+			type:              "counter"
+			default_namespace: "vector"
+			tags:              _component_tags
+		}
 		timestamp_parse_errors_total: {
 			description:       "The total number of errors encountered parsing [RFC 3339](\(urls.rfc_3339)) timestamps."
 			type:              "counter"
 			default_namespace: "vector"
 			tags:              _component_tags
 		}
+		unix_socket_connection_state: {
+			description:       "The state of the connection to a Unix socket: `0` when disconnected, `1` when connecting, `2` when connected, and `3` when sending."
+			type:              "gauge"
+			default_namespace: "vector"
+			tags:              _internal_metrics_tags
+		}
+		unix_socket_sendq_bytes: {
+			description:       "The bytes sent on a Unix socket but not read by the peer yet, sampled before each event is sent. Only reported on Linux."
+			type:              "histogram"
+			default_namespace: "vector"
+			tags:              _internal_metrics_tags
+		}
 		uptime_seconds: {
 			description:       "The total number of seconds the Vector instance has been up."
 			type:              "gauge"
@@ -1192,6 +1288,23 @@ components: sources: internal_metrics: {
 			required:    true
 			examples: ["file", "http", "honeycomb", "splunk_hec"]
 		}
+		_datadog_agent_compressed: {
+			description: "Whether the request body was compressed."
+			required:    true
+			enum: {
+				"true":  "The request body was compressed."
+				"false": "The request body was not compressed."
+			}
+		}
+		_datadog_agent_endpoint: {
+			description: "The kind of data received from the Datadog Agent."
+			required:    true
+			enum: {
+				logs:    "Logs."
+				metrics: "Metrics."
+				traces:  "Traces."
+			}
+		}
 		_endpoint: {
 			description: "The absolute path of originating file."
 			required:    true
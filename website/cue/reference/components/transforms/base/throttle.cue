@@ -1,11 +1,168 @@
 package metadata
 
 base: components: transforms: throttle: configuration: {
+	acknowledge_dropped: {
+		description: """
+			Whether or not to acknowledge rate limited events as delivered.
+
+			When end-to-end acknowledgements are enabled, events dropped by this transform are marked
+			as delivered if this is set to `true`. Otherwise, they are marked as failed, and sources
+			that support it report the failure back to their clients.
+			"""
+		required: false
+		type: bool: default: true
+	}
+	alert_clear_ratio: {
+		description: """
+			The fraction of events dropped below which a raised alert is cleared, between 0 and
+			`alert_drop_ratio`.
+
+			Defaults to half of `alert_drop_ratio`, so that a fraction hovering around it doesn't
+			repeatedly raise and clear the alert.
+			"""
+		required: false
+		type: float: {}
+	}
+	alert_drop_ratio: {
+		description: """
+			The fraction of events dropped above which an alert is raised, between 0 and 1.
+
+			Once the fraction of events dropped by the transform stayed above this ratio for
+			`alert_window_secs`, a warning is logged and the `throttle_alert_active` gauge is set to 1.
+			The fraction is checked every other `window_secs`.
+			"""
+		required: false
+		type: float: examples: [0.5]
+	}
+	alert_window_secs: {
+		description: """
+			How long the fraction of events dropped must stay above `alert_drop_ratio` before an alert
+			is raised, in seconds.
+
+			Defaults to `window_secs`. Only applies if `alert_drop_ratio` is set.
+			"""
+		required: false
+		type: float: {}
+	}
+	annotate_admitted: {
+		description: """
+			Whether or not to annotate admitted events with the state of their bucket.
+
+			The `throttle` metadata field of each admitted log is set to an object holding its `key`,
+			the `threshold`, and the approximate number of events `remaining` after it was admitted.
+			"""
+		required: false
+		type: bool: default: false
+	}
+	annotate_as_field: {
+		description: """
+			Whether or not to write the annotation to the `throttle` field of the log instead of its
+			metadata.
+
+			Only applies if `annotate_admitted` is `true`.
+			"""
+		required: false
+		type: bool: default: false
+	}
+	dedupe_field: {
+		description: """
+			The name of the log field whose value identifies retransmitted events.
+
+			Events whose rendered value was already seen for their key within `dedupe_ttl_secs` are
+			duplicates: they are handled according to `duplicate_action`, without counting against the
+			threshold. Events for which the value can't be rendered are never duplicates.
+			"""
+		required: false
+		type: string: {
+			examples: ["{{ message_id }}"]
+			syntax: "template"
+		}
+	}
+	dedupe_max_values: {
+		description: """
+			The maximum number of values of `dedupe_field` remembered at once, across all keys.
+
+			Once reached, the oldest value is forgotten to make room for the next one.
+			"""
+		required: false
+		type: uint: default: 1000
+	}
+	dedupe_ttl_secs: {
+		description: """
+			How long a value of `dedupe_field` is remembered after it was first seen, in seconds.
+
+			Defaults to `window_secs`.
+			"""
+		required: false
+		type: float: {}
+	}
+	dry_run: {
+		description: """
+			Whether or not to only simulate throttling, without dropping or delaying any event.
+
+			The limiters are applied as usual, but the events that would be dropped are passed through
+			to the default output, with the `throttle.would_throttle` metadata field set to `true` and
+			`throttle.key` to the key of their bucket. With the `backpressure` and `queue` over-limit
+			actions, the events that would be held are passed through the same way. The counters of
+			dropped events are emitted with a `simulated` tag set to `true`, so setting this back to
+			`false` enforces exactly what was simulated.
+			"""
+		required: false
+		type: bool: default: false
+	}
+	duplicate_action: {
+		description: "What to do with duplicate events, as identified by `dedupe_field`."
+		required:    false
+		type: string: {
+			default: "pass"
+			enum: {
+				drop: "Drop the event."
+				pass: "Pass the event through."
+			}
+		}
+	}
 	exclude: {
-		description: "A logical condition used to exclude events from sampling."
+		description: """
+			A logical condition used to exclude events from sampling.
+
+			Excluded events are passed through without counting against the threshold. VRL conditions
+			can read the state of the event's bucket from the `%throttle.key`, `%throttle.remaining`,
+			and `%throttle.threshold` metadata fields.
+			"""
 		required:    false
 		type: condition: {}
 	}
+	grace_consumes_budget: {
+		description: """
+			Whether or not the events allowed by `grace_events_per_key` count against the `threshold`.
+
+			When they do, a burst of a new key is allowed `grace_events_per_key` events, or `threshold`
+			events if more, and the budget of the key is the same afterwards as without the grace.
+			Otherwise, the grace comes on top of the `threshold`.
+			"""
+		required: false
+		type: bool: default: true
+	}
+	grace_events_per_key: {
+		description: """
+			The number of events of a new key allowed regardless of its `threshold`.
+
+			A key is new when it wasn't seen for `grace_ttl_secs`. Its first events then pass through,
+			which keeps the startup messages of a new service even when they come as a burst. Once past
+			them, the `threshold` applies as usual. Keys beyond `max_unique_keys` aren't given any grace.
+			"""
+		required: false
+		type: uint: {}
+	}
+	grace_ttl_secs: {
+		description: """
+			How long a key must not be seen for to be new again, in seconds.
+
+			Defaults to `window_secs`. Only applies if `grace_events_per_key` is set.
+			"""
+		required: false
+		type: float: {}
+	}
 	key_field: {
 		description: """
 			The name of the log field whose value is hashed to determine if the event should be
@@ -14,13 +171,253 @@ base: components: transforms: throttle: configuration: {
 			Each unique key creates a bucket of related events to be rate limited separately. If
 			left unspecified, or if the event doesn't have `key_field`, then the event is not rate
 			limited separately.
+
+			Paths starting with `%` are read from the metadata of the event, which is where the
+			`vector` log namespace puts the fields set by sources, such as
+			`{{ %datadog_agent.service }}`.
 			"""
 		required: false
 		type: string: {
-			examples: ["{{ message }}", "{{ hostname }}"]
+			examples: ["{{ message }}", "{{ hostname }}", "{{ %datadog_agent.service }}"]
 			syntax: "template"
 		}
 	}
+	limits: {
+		description: """
+			Additional limits, each applied to the buckets of its own `key_field`.
+
+			An event is only admitted if it's within `threshold` and within each of these limits. The
+			budget under every limit is checked before any of it is consumed, so an event rejected by one
+			limit doesn't count against the others. The check reads the state of each bucket as of its
+			last admitted event, so an event may be rejected one replenishment early.
+
+			Events excluded by `exclude` or let through by `grace_events_per_key` don't count against
+			these limits. Only applies to the `drop` over-limit action.
+			"""
+		required: false
+		type: array: {
+			default: []
+			items: type: object: options: {
+				key_field: {
+					description: """
+						The name of the log field whose value determines the bucket of the event under this limit.
+
+						Events without it share a single bucket.
+						"""
+					required: true
+					type: string: {
+						examples: ["{{ hostname }}"]
+						syntax: "template"
+					}
+				}
+				threshold: {
+					description: "The number of events allowed for a given bucket per configured `window_secs`."
+					required:    true
+					type: uint: {}
+				}
+				window_secs: {
+					description: "The time window in which the configured `threshold` is applied, in seconds."
+					required:    true
+					type: float: unit: "seconds"
+				}
+			}
+		}
+	}
+	max_queue_bytes: {
+		description: """
+			The maximum size of the events held by the `backpressure` and `queue` over-limit actions, or
+			by `shed_upstream`, in bytes, across all buckets.
+
+			The size of an event is its estimated in-memory size. By default, only `max_queue_events`
+			bounds the queue.
+			"""
+		required: false
+		type: uint: {}
+	}
+	max_queue_events: {
+		description: """
+			The maximum number of events held by the `backpressure` and `queue` over-limit actions, or
+			by `shed_upstream`, across all buckets.
+			"""
+		required:    false
+		type: uint: default: 1000
+	}
+	max_unique_keys: {
+		description: """
+			The maximum number of keys tracked at once.
+
+			Once reached, events whose key isn't already tracked share a single overflow bucket, limited
+			by `overflow_threshold`, instead of getting a bucket of their own. Tracked keys keep their
+			bucket, and are no longer tracked once they haven't been seen for `window_secs`.
+			"""
+		required: false
+		type: uint: {}
+	}
+	on_condition_error: {
+		description: """
+			What to do with events for which the `exclude` condition fails to evaluate.
+
+			Evaluation errors are reported as internal errors whatever this is set to.
+			"""
+		required: false
+		type: string: {
+			default: "throttle"
+			enum: {
+				drop:     "Drop the event."
+				exclude:  "Exclude the event from throttling, as if the condition was true."
+				throttle: "Throttle the event, as if the condition was false."
+			}
+		}
+	}
+	over_limit_action: {
+		description: "What to do with events exceeding the threshold."
+		required:    false
+		type: string: {
+			default: "drop"
+			enum: {
+				backpressure: """
+					Hold the event until it fits within the threshold of its bucket.
+
+					Events of other buckets keep being processed, and events of a bucket keep their order. Once
+					`max_queue_events` or `max_queue_bytes` are held, no further events are taken in until some
+					are released, which applies backpressure to upstream components. Once the input ends, the
+					held events are released right away, so that they don't hold up shutting down.
+					"""
+				drop: "Drop the event."
+				queue: """
+					Queue the event until it fits within the threshold of its bucket.
+
+					Events of other buckets keep being processed, and events of a bucket keep their order.
+					Queued events are held, along with their acknowledgement, until they are released or
+					dropped because the queue is full.
+					"""
+			}
+		}
+	}
+	overflow_threshold: {
+		description: """
+			The number of events allowed for the overflow bucket per configured `window_secs`.
+
+			Defaults to `threshold`. Only applies if `max_unique_keys` is set.
+			"""
+		required: false
+		type: uint: {}
+	}
+	queue_full_action: {
+		description: """
+			What to do with events once the queue is full.
+
+			Only applies to the `queue` over-limit action.
+			"""
+		required: false
+		type: string: {
+			default: "drop_newest"
+			enum: {
+				drop_newest: "Drop the incoming event."
+				drop_oldest: "Drop the oldest queued events to make room for the incoming event."
+			}
+		}
+	}
+	quota_file: {
+		description: """
+			The path to a file containing per-key thresholds.
+
+			The file is a YAML (or JSON) mapping of keys to thresholds. Keys may be exact values of the
+			rendered `key_field` or glob patterns such as `team-*`. Exact keys take precedence, and
+			patterns are tried in the order they appear. Keys not matching any entry use `threshold`.
+
+			A key may also map to an object with a `threshold` and a `window_secs`, to apply its
+			threshold over its own window instead of `window_secs`. Each key may only appear once.
+
+			The file is watched for changes, and the new thresholds apply to subsequent events. If the
+			file can't be read or parsed, the previously loaded thresholds are kept.
+			"""
+		required: false
+		type: string: examples: ["/etc/vector/throttle_quotas.yaml"]
+	}
+	register_as: {
+		description: """
+			The name to publish the state of the buckets under, for `throttle_exceeded` conditions.
+
+			Conditions referencing this name report whether the bucket of a key is currently out of
+			budget, without counting against it. Each name can only be used by a single throttle, and
+			stops matching once its throttle is removed.
+			"""
+		required: false
+		type: string: examples: ["api_quota"]
+	}
+	reroute_dropped: {
+		description: """
+			Whether or not to send the events dropped by this transform to a `dropped` output, instead of
+			discarding them.
+
+			This covers the events exceeding the threshold, along with those dropped by a tier,
+			`duplicate_action`, or `on_condition_error`. Admitted events are sent to the default output.
+			Rerouted events keep their acknowledgement, so `acknowledge_dropped` doesn't apply to them.
+			"""
+		required: false
+		type: bool: default: false
+	}
+	shed_drop_ratio: {
+		description: """
+			The fraction of events dropped above which `shed_upstream` slows down the intake, between 0
+			and 1.
+			"""
+		required: false
+		type: float: default: 0.9
+	}
+	shed_upstream: {
+		description: """
+			Whether or not to slow down the intake of events while most of them are dropped.
+
+			Once the fraction of events dropped over a `window_secs` is above `shed_drop_ratio`, events
+			over their limit are held until their bucket allows them rather than dropped, and once
+			`max_queue_events` or `max_queue_bytes` are held, no further events are taken in until some
+			are released. This applies backpressure to upstream components, so that upstream buffers and
+			sources shed the excess events, rather than carrying them through the topology only for this
+			transform to drop them. Events of other keys keep being processed until the held events fill
+			up the queue. Shedding stops once nothing was dropped or held for a `window_secs`, and the
+			events still held once the input ends are dropped.
+
+			Only applies to the `drop` over-limit action, and not in dry run.
+			"""
+		required: false
+		type: bool: default: false
+	}
+	spread_replenishment: {
+		description: """
+			Whether or not to stagger the replenishment of the budget of each key.
+
+			Keys saturating their threshold at the same time otherwise regain their budget at the same
+			time, and send synchronized bursts downstream. When set, each key starts its budget partially
+			spent, by an amount derived from a hash of the key, so that the budget of different keys
+			replenishes at different points of the window. Each key is still allowed `threshold` events
+			per window once past its first one.
+			"""
+		required: false
+		type: bool: default: false
+	}
+	state_path: {
+		description: """
+			The path to a file to persist the state of the buckets to, so that it survives restarts.
+
+			The number of events each bucket consumed is snapshotted to the file every
+			`state_snapshot_interval_secs`, and once the input of the transform ends. On startup, the
+			last snapshot is restored, minus the budget buckets regained since it was taken. If the file
+			can't be read or parsed, or was saved ahead of the system clock as the clock stepped
+			backwards since, a warning is logged and the transform starts with full budgets.
+			"""
+		required: false
+		type: string: examples: ["/var/lib/vector/throttle_state.json"]
+	}
+	state_snapshot_interval_secs: {
+		description: "How often the state of the buckets is snapshotted to `state_path`, in seconds."
+		required:    false
+		type: float: {
+			default: 60.0
+			unit:    "seconds"
+		}
+	}
 	threshold: {
 		description: """
 			The number of events allowed for a given bucket per configured `window_secs`.
@@ -30,8 +427,66 @@ base: components: transforms: throttle: configuration: {
 		required: true
 		type: uint: {}
 	}
+	tiers: {
+		description: """
+			Soft limits applying an action to events before they reach the `threshold`.
+
+			Each tier counts the events of a bucket over its own window, and the action of the tier with
+			the highest exceeded threshold applies. Tier thresholds must be strictly increasing, and below
+			`threshold`, which still applies to every event.
+			"""
+		required: false
+		type: array: {
+			default: []
+			items: type: object: options: {
+				action: {
+					description: "The action applied to events beyond the threshold of a tier."
+					required:    true
+					type: object: options: {
+						rate: {
+							description:   "The rate at which events are passed, expressed as `1/N`."
+							relevant_when: "type = \"sample_1_in_n\""
+							required:      true
+							type: uint: {}
+						}
+						type: {
+							description: "The action to apply."
+							required:    true
+							type: string: enum: {
+								drop:          "Drop the event."
+								pass:          "Pass the event."
+								sample_1_in_n: "Pass one out of every `rate` events, and drop the others."
+								tag: """
+									Pass the event, setting the `tier` field of its `throttle` annotation to the position of the
+									tier in `tiers`.
+									"""
+							}
+						}
+					}
+				}
+				threshold: {
+					description: """
+						The number of events allowed for a given bucket per configured `window_secs` before `action`
+						applies.
+						"""
+					required: true
+					type: uint: {}
+				}
+				window_secs: {
+					description: "The time window in which the configured `threshold` is applied, in seconds."
+					required:    true
+					type: float: unit: "seconds"
+				}
+			}
+		}
+	}
 	window_secs: {
-		description: "The time window in which the configured `threshold` is applied, in seconds."
+		description: """
+			The time window in which the configured `threshold` is applied, in seconds.
+
+			Must be between 0.01 seconds and a year, and long enough for each of the `threshold` events to
+			be at least a nanosecond apart.
+			"""
 		required:    true
 		type: float: unit: "seconds"
 	}
@@ -32,8 +32,22 @@ components: transforms: throttle: {
 		traces:  false
 	}
 
+	outputs: [
+		components._default_output,
+		{
+			name: "dropped"
+			description: """
+				When `reroute_dropped` is set to `true`, the events this transform would drop are sent
+				to the `dropped` output instead, unmodified. For a transform component named `foo`,
+				this output can be accessed by specifying `foo.dropped` as the input to another
+				component.
+				"""
+		},
+	]
+
 	telemetry: metrics: {
-		events_discarded_total: components.sources.internal_metrics.output.metrics.events_discarded_total
+		events_discarded_total:                       components.sources.internal_metrics.output.metrics.events_discarded_total
+		throttle_key_cardinality_limit_reached_total: components.sources.internal_metrics.output.metrics.throttle_key_cardinality_limit_reached_total
 	}
 
 	examples: [